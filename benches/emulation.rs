@@ -0,0 +1,100 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use emu::frame::Frame;
+use emu::nes::Nes;
+use emu::ppu::NesPPU;
+use emu::render;
+use emu::rom::Rom;
+
+// Benchmarks for the hot paths, to get before/after numbers for performance work:
+// instruction dispatch, the PPU's scanline bookkeeping, drawing a full frame with
+// sprites and save states. criterion keeps the last run in target/criterion and
+// reports the change against it.
+// Usage: cargo bench (add -- NAME to run the benchmarks whose name contains NAME)
+
+// A loop of common instructions over a few addressing modes
+fn dispatch_program() -> Vec<u8> {
+    vec![
+        0xA9, 0x10, // LDA #$10
+        0x85, 0x20, // STA $20
+        0xA2, 0x05, // LDX #$05
+        0xB5, 0x1B, // LDA $1B,X
+        0x69, 0x01, // ADC #$01
+        0x9D, 0x00, 0x03, // STA $0300,X
+        0xE6, 0x21, // INC $21
+        0xCA, // DEX
+        0xD0, 0xF7, // BNE back to LDA $1B,X
+        0x4C, 0x00, 0x80, // JMP $8000
+    ]
+}
+
+fn running_nes() -> Nes<'static> {
+    let mut nes = Nes::new(Rom::_new_test(dispatch_program()).unwrap(), |_ppu, _joypad1| {}).unwrap();
+    nes.cpu.pc = 0x8000;
+    nes
+}
+
+// 64 sprites over a background of varied tiles, with rendering on
+fn busy_ppu() -> NesPPU {
+    let mut ppu = NesPPU::_new_empty_rom();
+    for (i, byte) in ppu.vram.iter_mut().enumerate() {
+        *byte = (i * 7) as u8;
+    }
+    for (i, color) in ppu.palette_table.iter_mut().enumerate() {
+        *color = (i * 3) as u8 & 0x3F;
+    }
+    for sprite in 0..64 {
+        let (x, y) = ((sprite % 8) * 30, (sprite / 8) * 28);
+        ppu.oam_data[sprite * 4..sprite * 4 + 4].copy_from_slice(&[y as u8, sprite as u8, (sprite % 4) as u8 | 0x40, x as u8]);
+    }
+    ppu.write_mask(0x1E);
+    ppu
+}
+
+fn cpu(c: &mut Criterion) {
+    let mut nes = running_nes();
+    c.bench_function("cpu/1000 instructions", |b| {
+        b.iter(|| {
+            for _ in 0..1000 {
+                nes.step();
+            }
+        })
+    });
+    c.bench_function("nes/run_frame", |b| b.iter(|| black_box(nes.run_frame())));
+}
+
+fn ppu(c: &mut Criterion) {
+    let mut ppu = busy_ppu();
+    c.bench_function("ppu/262 scanlines", |b| {
+        b.iter(|| {
+            for _ in 0..262 {
+                ppu.tick(341);
+                black_box(ppu.end_scanline());
+            }
+        })
+    });
+}
+
+fn render(c: &mut Criterion) {
+    let ppu = busy_ppu();
+    let mut frame = Frame::new();
+    c.bench_function("render/frame with 64 sprites", |b| {
+        b.iter(|| {
+            render::render(&ppu, &mut frame);
+            black_box(&frame);
+        })
+    });
+}
+
+fn save_state(c: &mut Criterion) {
+    let mut nes = running_nes();
+    nes.run_frame();
+    let state = nes.cpu.save_state();
+    c.bench_function("state/save", |b| b.iter(|| black_box(nes.cpu.save_state())));
+    c.bench_function("state/load", |b| b.iter(|| nes.cpu.load_state(black_box(&state)).unwrap()));
+}
+
+criterion_group!(benches, cpu, ppu, render, save_state);
+criterion_main!(benches);
@@ -0,0 +1,25 @@
+use std::env;
+
+use emu::rom::Rom;
+
+// Prints the first bytes of a rom's PRG data as they'd appear from $8000
+// Usage: cargo run --example dump_prg -- PATH_TO_ROM [COUNT]
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: cargo run --example dump_prg -- PATH_TO_ROM [COUNT]");
+        std::process::exit(1);
+    }
+
+    let count: usize = match args.get(2) {
+        Some(count) => count.parse().expect("COUNT must be a number"),
+        None => 80,
+    };
+
+    let bytes: Vec<u8> = std::fs::read(&args[1]).unwrap();
+    let rom = Rom::new(&bytes).unwrap();
+
+    for (i, byte) in rom.prg_rom.iter().enumerate().take(count) {
+        println!("${:04X}: {:02X}", 0x8000 + i, byte);
+    }
+}
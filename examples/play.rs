@@ -0,0 +1,74 @@
+use std::cell::Cell;
+use std::env;
+
+use emu::nes::{default_keyboard_map, ControllerPort, NesBuilder, Region};
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+
+// The smallest complete frontend, built on NesBuilder
+// Usage: cargo run --example play -- PATH_TO_ROM
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: cargo run --example play -- PATH_TO_ROM");
+        std::process::exit(1);
+    }
+
+    // init sdl2
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window("Play", 256 * 3, 240 * 3)
+        .position_centered()
+        .build().unwrap();
+
+    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(PixelFormatEnum::RGB24, 256, 240).unwrap();
+
+    let frame_done = Cell::new(false);
+    let mut nes = NesBuilder::new()
+        .rom(&args[1])
+        .region(Region::Ntsc)
+        .video_sink(|frame| {
+            texture.update(None, &frame.data, 256 * 3).unwrap();
+            canvas.copy(&texture, None, None).unwrap();
+            canvas.present();
+            frame_done.set(true);
+        })
+        .controller(ControllerPort::One, default_keyboard_map())
+        .build()
+        .unwrap();
+
+    loop {
+        nes.step();
+        if !frame_done.replace(false) {
+            continue;
+        }
+
+        // Input is checked once per frame, right after it is shown
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return,
+
+                Event::KeyDown { keycode: Some(keycode), .. } => {
+                    nes.key_event(&keycode.name(), true);
+                }
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    nes.key_event(&keycode.name(), false);
+                }
+
+                _ => { /* do nothing */ },
+            }
+        }
+    }
+}
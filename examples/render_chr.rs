@@ -0,0 +1,69 @@
+use std::env;
+
+use emu::rom::Rom;
+use emu::frame::Frame;
+use emu::nes::Nes;
+use emu::render::render_pattern_table;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+
+// Shows every tile in one pattern table of a rom while the game runs, so tiles
+// built in CHR RAM and bank switches show up as they happen
+// Usage: cargo run --example render_chr -- PATH_TO_ROM [BANK]
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: cargo run --example render_chr -- PATH_TO_ROM [BANK]");
+        std::process::exit(1);
+    }
+
+    let bank: u16 = match args.get(2) {
+        Some(bank) => bank.parse().ok().filter(|&bank| bank <= 1).expect("BANK must be 0 or 1"),
+        None => 0,
+    };
+
+    // init sdl2
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window("Texture viewer", (256.0 * 3.0) as u32, (240.0 * 3.0) as u32)
+        .position_centered()
+        .build().unwrap();
+
+    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    canvas.set_scale(3.0, 3.0).unwrap();
+
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(PixelFormatEnum::RGB24, 256, 240).unwrap();
+
+    //load the game
+    let bytes: Vec<u8> = std::fs::read(&args[1]).unwrap();
+    let rom = Rom::new(&bytes).unwrap();
+    let mut nes = Nes::new(rom, |_ppu, _joypad1| {}).unwrap();
+
+    // Redraw the tiles every frame the game runs (present_vsync keeps this near 60fps)
+    loop {
+        for event in event_pump.poll_iter() {
+            match event {
+            Event::Quit { .. }
+            | Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => std::process::exit(0),
+            _ => { /* do nothing */ }
+            }
+        }
+
+        nes.run_frame();
+        let mut tile_frame = Frame::new();
+        render_pattern_table(&nes.cpu.bus.ppu, bank, &mut tile_frame);
+
+        texture.update(None, &tile_frame.data, 256 * 3).unwrap();
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+    }
+}
@@ -1,16 +1,10 @@
-mod cpu;
-mod rom;
-mod bus;
-mod ppu;
-
-use std::path::Path;
+use std::env;
 use std::error::Error;
 
-use crate::cpu::CPU;
-use crate::rom::Rom;
-use crate::ppu::NesPPU;
-
-use rand::Rng;
+use emu::cpu::CPU;
+use emu::bus::Bus;
+use emu::rom::Rom;
+use emu::entropy::EntropyDevice;
 use sdl2::event::Event;
 use sdl2::EventPump;
 use sdl2::keyboard::Keycode;
@@ -79,7 +73,15 @@ fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
     }
 }
 
+// Runs the 6502 snake demo (examples/snake.nes), drawing its $0200-$05FF screen buffer
+// Usage: cargo run --example snake -- examples/snake.nes
 fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: cargo run --example snake -- PATH_TO_SNAKE_ROM");
+        std::process::exit(1);
+    }
+
     // init sdl2
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
@@ -99,28 +101,27 @@ fn main() -> Result<(), Box<dyn Error>> {
     // NEW GAME LOAD CODE
     
     // Load the ROM file into a 'Rom' object
-    let rom_path_string = "/home/briyoda/Projects/Rust/NES Emulator/EMU/src/snake.nes";
-    let rom_path: &Path = Path::new(rom_path_string);
-    let rom_contents = std::fs::read(rom_path).unwrap_or_else(|err| {
+    let rom_contents = std::fs::read(&args[1]).unwrap_or_else(|err| {
         eprintln!("Error: {}", err);
         panic!("Panic due to bad file load");
     });
     let cartridge = Rom::new(&rom_contents.clone())?;
 
+    // The snake game reads a random byte from $FE for apple placement
+    let mut bus = Bus::new(cartridge, |_, _| {})?;
+    bus.attach_entropy(EntropyDevice::new(None, Some(0xFE)));
+
     // Create the CPU and prepare the game
-    let mut cpu: CPU = CPU::new(cartridge);
+    let mut cpu: CPU = CPU::new(bus);
     print_hex(&rom_contents.clone());
     cpu.reset();
 
     let mut screen_state = [0 as u8; 32 * 3 * 32];
-    let mut rng = rand::thread_rng();
 
     // run the game cycle
     cpu.run_with_callback(move |cpu| {
         handle_user_input(cpu, &mut event_pump);
 
-        cpu.mem_write(0xfe, rng.gen_range(1..16));
-
         if read_screen_state(cpu, &mut screen_state) {
             texture.update(None, &screen_state, 32 * 3).unwrap();
 
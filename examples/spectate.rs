@@ -0,0 +1,88 @@
+use std::env;
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use emu::frame::{Frame, PixelFormat};
+use emu::spectate::read_frame;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+
+// Watches a game streamed with -spectate. Frames are read on a thread of their
+// own so the window stays responsive while waiting for the next one.
+// Usage: cargo run --example spectate -- HOST:PORT
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: cargo run --example spectate -- HOST:PORT");
+        std::process::exit(1);
+    }
+
+    let mut stream = TcpStream::connect(&args[1]).unwrap();
+    let (sender, frames) = mpsc::channel();
+    thread::spawn(move || {
+        let mut frame: Option<Frame> = None;
+        loop {
+            if let Err(e) = read_frame(&mut stream, &mut frame) {
+                eprintln!("{}", e);
+                return;
+            }
+            let shown = frame.as_ref().unwrap();
+            let mut copy = Frame::with_size(shown.width(), shown.height(), shown.format());
+            copy.data.copy_from_slice(&shown.data);
+            if sender.send(copy).is_err() {
+                return;
+            }
+        }
+    });
+
+    // init sdl2
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window(&format!("Watching {}", args[1]), 256 * 3, 240 * 3)
+        .position_centered()
+        .resizable()
+        .build().unwrap();
+
+    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let creator = canvas.texture_creator();
+    let mut texture = None;
+    let mut shape = None;
+
+    loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return,
+                _ => { /* do nothing */ },
+            }
+        }
+
+        // Only the newest frame matters
+        if let Some(frame) = frames.try_iter().last() {
+            let frame_shape = (frame.width(), frame.height(), frame.format());
+            if shape != Some(frame_shape) {
+                let format = match frame.format() {
+                    PixelFormat::Rgb24 => PixelFormatEnum::RGB24,
+                    PixelFormat::Rgba8888 => PixelFormatEnum::RGBA32,
+                };
+                texture = Some(creator.create_texture_streaming(format, frame.width() as u32, frame.height() as u32).unwrap());
+                shape = Some(frame_shape);
+            }
+            let texture = texture.as_mut().unwrap();
+            texture.update(None, &frame.data, frame.pitch()).unwrap();
+            canvas.copy(texture, None, None).unwrap();
+            canvas.present();
+        } else {
+            thread::sleep(Duration::from_millis(2));
+        }
+    }
+}
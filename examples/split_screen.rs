@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use emu::frame::Frame;
+use emu::joypad::{Joypad, JoypadButton};
+use emu::nes::Nes;
+use emu::ppu::NesPPU;
+use emu::render;
+use emu::rom::Rom;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+
+// Runs a console on its own thread. Finished frames are sent back to the window
+// and the shared button state is copied into the joypad once per frame.
+fn spawn_console(rom_path: String, buttons: Arc<AtomicU8>) -> Receiver<Vec<u8>> {
+    // Bounded so the emulator waits for the window instead of running ahead
+    let (sender, receiver) = mpsc::sync_channel(1);
+
+    thread::spawn(move || {
+        let bytes: Vec<u8> = std::fs::read(&rom_path).unwrap();
+        let rom = Rom::new(&bytes).unwrap();
+        let mut frame = Frame::new();
+
+        let mut nes = Nes::new(rom, move |ppu: &NesPPU, joypad1: &mut Joypad| {
+            render::render(ppu, &mut frame);
+            if sender.send(frame.data.clone()).is_err() {
+                return; // window closed
+            }
+
+            let pressed = buttons.load(Ordering::Relaxed);
+            for button in JoypadButton::all().iter() {
+                joypad1.set_button_pressed_status(button, pressed & button.bits() != 0);
+            }
+        }).unwrap();
+        nes.run_with_callback(|_| {});
+    });
+
+    receiver
+}
+
+// Runs two roms side by side in one window, both driven by the keyboard
+// Usage: cargo run --example split_screen -- PATH_TO_ROM_1 PATH_TO_ROM_2
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!("Usage: cargo run --example split_screen -- PATH_TO_ROM_1 PATH_TO_ROM_2");
+        std::process::exit(1);
+    }
+
+    // init sdl2
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window("Split screen", (512.0 * 2.0) as u32, (240.0 * 2.0) as u32)
+        .position_centered()
+        .build().unwrap();
+
+    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    canvas.set_scale(2.0, 2.0).unwrap();
+
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(PixelFormatEnum::RGB24, 256, 240).unwrap();
+
+    let mut key_map = HashMap::new();
+    key_map.insert(Keycode::Down, JoypadButton::DOWN);
+    key_map.insert(Keycode::Up, JoypadButton::UP);
+    key_map.insert(Keycode::Right, JoypadButton::RIGHT);
+    key_map.insert(Keycode::Left, JoypadButton::LEFT);
+    key_map.insert(Keycode::Space, JoypadButton::SELECT);
+    key_map.insert(Keycode::Return, JoypadButton::START);
+    key_map.insert(Keycode::A, JoypadButton::BUTTON_A);
+    key_map.insert(Keycode::S, JoypadButton::BUTTON_B);
+
+    let buttons = Arc::new(AtomicU8::new(0));
+    let consoles = [
+        spawn_console(args[1].clone(), buttons.clone()),
+        spawn_console(args[2].clone(), buttons.clone()),
+    ];
+
+    loop {
+        for (i, console) in consoles.iter().enumerate() {
+            let data = console.recv().expect("Emulator thread stopped");
+            texture.update(None, &data, 256 * 3).unwrap();
+            canvas.copy(&texture, None, Rect::new(256 * i as i32, 0, 256, 240)).unwrap();
+        }
+        canvas.present();
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return,
+
+                Event::KeyDown { keycode: Some(keycode), .. } => {
+                    if let Some(key) = key_map.get(&keycode) {
+                        buttons.fetch_or(key.bits(), Ordering::Relaxed);
+                    }
+                }
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(key) = key_map.get(&keycode) {
+                        buttons.fetch_and(!key.bits(), Ordering::Relaxed);
+                    }
+                }
+
+                _ => { /* do nothing */ },
+            }
+        }
+    }
+}
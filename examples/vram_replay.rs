@@ -0,0 +1,108 @@
+use std::env;
+use std::fs;
+
+use emu::frame::Frame;
+use emu::ppu::NesPPU;
+use emu::render;
+use emu::rom::Rom;
+use emu::vram_log::VramLog;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+
+// Replays the writes of every frame up to and including `frame` into a fresh PPU.
+// Pattern writes go to the rom's CHR RAM, so games with CHR RAM show up too.
+fn replay(rom: &Rom, log: &VramLog, frame: u64, background_table: u8) -> NesPPU {
+    let mut ppu = NesPPU::new(rom.generate_mapper().unwrap());
+    ppu.oam_dma_write(&[0xFF; 256]); // every sprite below the screen
+    ppu.write_to_ctrl(background_table << 4);
+
+    for write in log.writes().iter().take_while(|write| write.frame <= frame) {
+        ppu.write_to_ppu_addr((write.addr >> 8) as u8);
+        ppu.write_to_ppu_addr((write.addr & 0xFF) as u8);
+        ppu.write_to_data(write.data);
+    }
+    ppu
+}
+
+// Steps through a VRAM write log saved with -vram-log, showing the screen as the
+// game had drawn it by the end of each frame. Right/Left move a frame, B swaps the
+// background pattern table. The writes of each frame shown are printed.
+// Usage: cargo run --example vram_replay -- PATH_TO_ROM PATH_TO_LOG
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!("Usage: cargo run --example vram_replay -- PATH_TO_ROM PATH_TO_LOG");
+        std::process::exit(1);
+    }
+
+    let rom = Rom::new(&fs::read(&args[1]).unwrap()).unwrap();
+    let log = VramLog::parse(&fs::read_to_string(&args[2]).unwrap()).unwrap();
+    let mut frames: Vec<u64> = log.writes().iter().map(|write| write.frame).collect();
+    frames.dedup();
+    if frames.is_empty() {
+        eprintln!("The log has no writes");
+        std::process::exit(1);
+    }
+
+    // init sdl2
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window("VRAM replay", 256 * 3, 240 * 3)
+        .position_centered()
+        .build().unwrap();
+
+    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(PixelFormatEnum::RGB24, 256, 240).unwrap();
+
+    let mut shown = 0;
+    let mut background_table = 0;
+    let mut redraw = true;
+
+    loop {
+        if redraw {
+            let frame_number = frames[shown];
+            println!("-- frame {} ({} of {})", frame_number, shown + 1, frames.len());
+            for write in log.writes().iter().filter(|write| write.frame == frame_number) {
+                println!("{}", write);
+            }
+
+            let ppu = replay(&rom, &log, frame_number, background_table);
+            let mut frame = Frame::new();
+            render::render(&ppu, &mut frame);
+            texture.update(None, &frame.data, 256 * 3).unwrap();
+            canvas.copy(&texture, None, None).unwrap();
+            canvas.present();
+            redraw = false;
+        }
+
+        match event_pump.wait_event() {
+            Event::Quit { .. }
+            | Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => return,
+
+            Event::KeyDown { keycode: Some(Keycode::Right), .. } if shown + 1 < frames.len() => {
+                shown += 1;
+                redraw = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::Left), .. } if shown > 0 => {
+                shown -= 1;
+                redraw = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::B), .. } => {
+                background_table ^= 1;
+                redraw = true;
+            }
+
+            _ => { /* do nothing */ },
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use bitflags::bitflags;
+
+// Hardware details that cost time to emulate or that only a few games notice. The
+// config picks one of three profiles ("accuracy = fast|balanced|accurate"), and the
+// bus and CPU check the flags at the places they matter.
+//
+// A dot accurate PPU and MMC3 style A12 filtering would belong here too, but this
+// core draws a line at a time and has no mapper watching A12 yet.
+
+bitflags! {
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct Accuracy: u8 {
+        // Indexed reads that cross a page, and every indexed store and
+        // read-modify-write, first read the address before the high byte is fixed
+        const DUMMY_READS    = 0b0000_0001;
+        // Unmapped reads return the last byte on the CPU bus instead of 0, and reads
+        // of the PPU's write-only registers return its register latch
+        const OPEN_BUS       = 0b0000_0010;
+        // The PPU register latch fades back to 0 when nothing refreshes it
+        const OPEN_BUS_DECAY = 0b0000_0100;
+        // A DMC fetch landing on a controller read loses a bit (see Bus::mem_read)
+        const DPCM_GLITCH    = 0b0000_1000;
+        // $2007 reads and writes while the PPU is drawing step its address the way
+        // the renderer does (coarse X and Y) rather than by 1 or 32
+        const PPUDATA_DURING_RENDERING = 0b0001_0000;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccuracyProfile {
+    Fast,
+    Balanced,
+    Accurate,
+}
+
+impl AccuracyProfile {
+    pub fn parse(value: &str) -> Result<AccuracyProfile, String> {
+        match value {
+            "fast" => Ok(AccuracyProfile::Fast),
+            "balanced" => Ok(AccuracyProfile::Balanced),
+            "accurate" => Ok(AccuracyProfile::Accurate),
+            _ => Err(format!("Accuracy must be fast, balanced or accurate but got \"{}\"", value)),
+        }
+    }
+
+    pub fn flags(&self) -> Accuracy {
+        match self {
+            AccuracyProfile::Fast => Accuracy::empty(),
+            AccuracyProfile::Balanced => Accuracy::DUMMY_READS | Accuracy::OPEN_BUS,
+            AccuracyProfile::Accurate => Accuracy::all(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_profiles() {
+        assert_eq!(AccuracyProfile::parse("fast").unwrap().flags(), Accuracy::empty());
+        assert!(AccuracyProfile::parse("balanced").unwrap().flags().contains(Accuracy::OPEN_BUS));
+        assert!(!AccuracyProfile::Balanced.flags().contains(Accuracy::DPCM_GLITCH));
+        assert_eq!(AccuracyProfile::parse("accurate").unwrap().flags(), Accuracy::all());
+        assert!(AccuracyProfile::parse("exact").is_err());
+    }
+}
@@ -0,0 +1,1097 @@
+// Audio processing unit: the two pulse (square wave) channels, the triangle and
+// noise channels, the DMC (delta modulated samples) and the frame counter that
+// clocks their envelopes, sweeps and length counters.
+// https://www.nesdev.org/wiki/APU
+//
+// The bus clocks the APU along with the CPU. Output is filtered and resampled
+// down to the frontend's rate (see audio.rs) and handed over in chunks through
+// the bus's audio callback (see Bus::set_audio_callback).
+//
+// The DMC plays 1 bit delta samples from PRG ROM. It can't reach the bus itself:
+// when its sample buffer empties it asks for the next byte with
+// dmc_fetch_address, and the bus reads it through the mapper, hands it over with
+// dmc_fill and halts the CPU for the fetch (see dma.rs).
+//
+// Without the apu feature nothing is mixed or resampled, the channels only run
+// for their length counters, $4015 and the IRQs.
+#![cfg_attr(not(feature = "apu"), allow(dead_code))]
+
+#[cfg(feature = "apu")]
+use crate::audio::Resampler;
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+pub const SAMPLE_RATE: u32 = 44100;
+pub const CPU_CYCLES_PER_SECOND: f32 = 1_789_773.0;
+
+// Frame counter steps in CPU cycles, quarter frames clock envelopes and half
+// frames also clock length counters and sweeps
+const QUARTER_FRAMES: [u32; 4] = [7457, 14913, 22371, 29829];
+const FOUR_STEP_LENGTH: u32 = 29830;
+const FIVE_STEP_LAST: u32 = 37281;
+const FIVE_STEP_LENGTH: u32 = 37282;
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// Noise timer periods in CPU cycles (NTSC)
+const NOISE_PERIODS: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+
+// DMC output rates in CPU cycles per bit (NTSC)
+const DMC_PERIODS: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+#[derive(Default)]
+struct Envelope {
+    start: bool,
+    divider: u8,
+    decay: u8,
+    period: u8, // also the volume when constant
+    looping: bool,
+    constant: bool,
+}
+
+impl Envelope {
+    fn write(&mut self, data: u8) {
+        self.looping = data & 0x20 != 0;
+        self.constant = data & 0x10 != 0;
+        self.period = data & 0x0F;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.period;
+        } else if self.divider == 0 {
+            self.divider = self.period;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn volume(&self) -> u8 {
+        if self.constant { self.period } else { self.decay }
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bool(self.start);
+        out.u8(self.divider);
+        out.u8(self.decay);
+        out.u8(self.period);
+        out.bool(self.looping);
+        out.bool(self.constant);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.start = input.bool()?;
+        self.divider = input.u8()?;
+        self.decay = input.u8()?;
+        self.period = input.u8()?;
+        self.looping = input.bool()?;
+        self.constant = input.bool()?;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Pulse {
+    second: bool, // pulse 2 negates its sweep with two's complement, pulse 1 with ones'
+    enabled: bool,
+    duty: u8,
+    step: u8,
+    timer_period: u16,
+    timer: u16,
+    length: u8,
+    length_halt: bool, // shares its bit with the envelope's loop flag
+    envelope: Envelope,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_reload: bool,
+    sweep_divider: u8,
+}
+
+impl Pulse {
+    fn new(second: bool) -> Self {
+        Pulse { second, ..Pulse::default() }
+    }
+
+    // One of the channel's four registers, `reg` 0-3
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.duty = data >> 6;
+                self.length_halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => {
+                self.sweep_enabled = data & 0x80 != 0;
+                self.sweep_period = (data >> 4) & 0x07;
+                self.sweep_negate = data & 0x08 != 0;
+                self.sweep_shift = data & 0x07;
+                self.sweep_reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    // Every other CPU cycle
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    fn clock_half_frame(&mut self) {
+        if self.length > 0 && !self.length_halt {
+            self.length -= 1;
+        }
+
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.sweep_muted() {
+            self.timer_period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.timer_period + change
+        } else if self.second {
+            self.timer_period.saturating_sub(change)
+        } else {
+            self.timer_period.saturating_sub(change + 1)
+        }
+    }
+
+    // Periods too short or a sweep heading past the top silence the channel
+    fn sweep_muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > 0x7FF
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.sweep_muted() || DUTY_TABLE[self.duty as usize][self.step as usize] == 0 {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+}
+
+impl Snapshot for Pulse {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bool(self.enabled);
+        out.u8(self.duty);
+        out.u8(self.step);
+        out.u16(self.timer_period);
+        out.u16(self.timer);
+        out.u8(self.length);
+        out.bool(self.length_halt);
+        self.envelope.save_state(out);
+        out.bool(self.sweep_enabled);
+        out.u8(self.sweep_period);
+        out.bool(self.sweep_negate);
+        out.u8(self.sweep_shift);
+        out.bool(self.sweep_reload);
+        out.u8(self.sweep_divider);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.enabled = input.bool()?;
+        self.duty = input.u8()?;
+        self.step = input.u8()?;
+        if self.duty as usize >= DUTY_TABLE.len() || self.step as usize >= DUTY_TABLE[0].len() {
+            return Err(format!("Save state has pulse duty {} at step {}", self.duty, self.step));
+        }
+        self.timer_period = input.u16()?;
+        self.timer = input.u16()?;
+        self.length = input.u8()?;
+        self.length_halt = input.bool()?;
+        self.envelope.load_state(input)?;
+        self.sweep_enabled = input.bool()?;
+        self.sweep_period = input.u8()?;
+        self.sweep_negate = input.bool()?;
+        self.sweep_shift = input.u8()?;
+        self.sweep_reload = input.bool()?;
+        self.sweep_divider = input.u8()?;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Triangle {
+    enabled: bool,
+    control: bool, // halts the length counter and keeps reloading the linear counter
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    timer_period: u16,
+    timer: u16,
+    step: u8,
+    length: u8,
+}
+
+impl Triangle {
+    // $4008-$400B, `reg` 0-3 ($4009 does nothing)
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.linear_reload_value = data & 0x7F;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.linear_reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    // Every CPU cycle, the triangle's timer runs twice as fast as the pulses'. The
+    // sequence stops where it is (rather than dropping to 0) when either counter
+    // runs out, which is why a stopped triangle doesn't click.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length > 0 && self.linear_counter > 0 {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn clock_half_frame(&mut self) {
+        if self.length > 0 && !self.control {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.step as usize]
+    }
+}
+
+impl Snapshot for Triangle {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bool(self.enabled);
+        out.bool(self.control);
+        out.u8(self.linear_reload_value);
+        out.u8(self.linear_counter);
+        out.bool(self.linear_reload);
+        out.u16(self.timer_period);
+        out.u16(self.timer);
+        out.u8(self.step);
+        out.u8(self.length);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.enabled = input.bool()?;
+        self.control = input.bool()?;
+        self.linear_reload_value = input.u8()?;
+        self.linear_counter = input.u8()?;
+        self.linear_reload = input.bool()?;
+        self.timer_period = input.u16()?;
+        self.timer = input.u16()?;
+        self.step = input.u8()?;
+        if self.step as usize >= TRIANGLE_SEQUENCE.len() {
+            return Err(format!("Save state has triangle step {}", self.step));
+        }
+        self.length = input.u8()?;
+        Ok(())
+    }
+}
+
+struct Noise {
+    enabled: bool,
+    short_mode: bool, // feedback from bit 6 instead of bit 1, a 93 step metallic loop
+    timer_period: u16,
+    timer: u16,
+    shift: u16, // 15 bit linear feedback shift register
+    length: u8,
+    length_halt: bool,
+    envelope: Envelope,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise {
+            enabled: false,
+            short_mode: false,
+            timer_period: NOISE_PERIODS[0],
+            timer: 0,
+            shift: 1, // all zeros would never change
+            length: 0,
+            length_halt: false,
+            envelope: Envelope::default(),
+        }
+    }
+}
+
+impl Noise {
+    // $400C-$400F, `reg` 0-3 ($400D does nothing)
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.length_halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            2 => {
+                self.short_mode = data & 0x80 != 0;
+                self.timer_period = NOISE_PERIODS[(data & 0x0F) as usize];
+            }
+            3 => {
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.envelope.start = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    // Every CPU cycle, the periods are in CPU cycles
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    fn clock_half_frame(&mut self) {
+        if self.length > 0 && !self.length_halt {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+}
+
+impl Snapshot for Noise {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bool(self.enabled);
+        out.bool(self.short_mode);
+        out.u16(self.timer_period);
+        out.u16(self.timer);
+        out.u16(self.shift);
+        out.u8(self.length);
+        out.bool(self.length_halt);
+        self.envelope.save_state(out);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.enabled = input.bool()?;
+        self.short_mode = input.bool()?;
+        self.timer_period = input.u16()?;
+        self.timer = input.u16()?;
+        self.shift = input.u16()?;
+        self.length = input.u8()?;
+        self.length_halt = input.bool()?;
+        self.envelope.load_state(input)?;
+        Ok(())
+    }
+}
+
+struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    irq: bool,
+    timer_period: u16,
+    timer: u16,
+    level: u8, // 7 bit output level
+    sample_address: u16,
+    sample_length: u16,
+    address: u16, // next byte to fetch
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Dmc {
+            irq_enabled: false,
+            looping: false,
+            irq: false,
+            timer_period: DMC_PERIODS[0],
+            timer: DMC_PERIODS[0] - 1,
+            level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            address: 0xC000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+}
+
+impl Dmc {
+    // $4010-$4013, `reg` 0-3
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                self.looping = data & 0x40 != 0;
+                self.timer_period = DMC_PERIODS[(data & 0x0F) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.level = data & 0x7F,
+            2 => self.sample_address = 0xC000 + data as u16 * 64,
+            _ => self.sample_length = data as u16 * 16 + 1,
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn fetch_address(&self) -> Option<u16> {
+        if self.buffer.is_none() && self.bytes_remaining > 0 { Some(self.address) } else { None }
+    }
+
+    fn fill(&mut self, data: u8) {
+        self.buffer = Some(data);
+        self.address = if self.address == 0xFFFF { 0x8000 } else { self.address + 1 };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // Every CPU cycle, the rates are in CPU cycles
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(data) => {
+                    self.shift = data;
+                    self.silence = false;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    // CPU cycles until the sample buffer empties and needs a fetch, None if no
+    // fetch is coming (nothing left to play, or it's already waiting for one)
+    fn cycles_until_fetch(&self) -> Option<u32> {
+        if self.buffer.is_none() || self.bytes_remaining == 0 {
+            return None;
+        }
+        Some(self.timer as u32 + 1 + (self.bits_remaining as u32 - 1) * self.timer_period as u32)
+    }
+}
+
+impl Snapshot for Dmc {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bool(self.irq_enabled);
+        out.bool(self.looping);
+        out.bool(self.irq);
+        out.u16(self.timer_period);
+        out.u16(self.timer);
+        out.u8(self.level);
+        out.u16(self.sample_address);
+        out.u16(self.sample_length);
+        out.u16(self.address);
+        out.u16(self.bytes_remaining);
+        out.bool(self.buffer.is_some());
+        out.u8(self.buffer.unwrap_or(0));
+        out.u8(self.shift);
+        out.u8(self.bits_remaining);
+        out.bool(self.silence);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.irq_enabled = input.bool()?;
+        self.looping = input.bool()?;
+        self.irq = input.bool()?;
+        self.timer_period = input.u16()?;
+        self.timer = input.u16()?;
+        self.level = input.u8()?;
+        self.sample_address = input.u16()?;
+        self.sample_length = input.u16()?;
+        self.address = input.u16()?;
+        self.bytes_remaining = input.u16()?;
+        let buffered = input.bool()?;
+        let byte = input.u8()?;
+        self.buffer = if buffered { Some(byte) } else { None };
+        self.shift = input.u8()?;
+        self.bits_remaining = input.u8()?;
+        self.silence = input.bool()?;
+        Ok(())
+    }
+}
+
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_cycle: u32, // CPU cycles into the frame counter's sequence
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    odd_cycle: bool,
+    #[cfg(feature = "apu")]
+    output: Resampler,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Apu::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            pulse1: Pulse::new(false),
+            pulse2: Pulse::new(true),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            frame_cycle: 0,
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            odd_cycle: false,
+            #[cfg(feature = "apu")]
+            output: Resampler::new(),
+        }
+    }
+
+    // Handles writes to $4000-$4017
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write(addr - 0x4000, data),
+            0x4004..=0x4007 => self.pulse2.write(addr - 0x4004, data),
+            0x4008..=0x400B => self.triangle.write(addr - 0x4008, data),
+            0x400C..=0x400F => self.noise.write(addr - 0x400C, data),
+            0x4010..=0x4013 => self.dmc.write(addr - 0x4010, data),
+            0x4015 => {
+                self.pulse1.set_enabled(data & 0x01 != 0);
+                self.pulse2.set_enabled(data & 0x02 != 0);
+                self.triangle.set_enabled(data & 0x04 != 0);
+                self.noise.set_enabled(data & 0x08 != 0);
+                self.dmc.set_enabled(data & 0x10 != 0);
+            }
+            0x4017 => {
+                self.five_step = data & 0x80 != 0;
+                self.irq_inhibit = data & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                // Restarting the sequence in 5 step mode clocks everything straight away
+                self.frame_cycle = 0;
+                if self.five_step {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // The reset button silences every channel and restarts the frame counter in
+    // the mode it was in
+    pub fn reset(&mut self) {
+        self.write(0x4015, 0);
+        self.frame_irq = false;
+        self.frame_cycle = 0;
+    }
+
+    // Back to the power on state, keeping the output rate, filter state and
+    // unplayed samples
+    #[cfg(feature = "apu")]
+    pub fn power_cycle(&mut self) {
+        *self = Apu { output: std::mem::take(&mut self.output), ..Apu::new() };
+    }
+
+    #[cfg(not(feature = "apu"))]
+    pub fn power_cycle(&mut self) {
+        *self = Apu::new();
+    }
+
+    // Handles $4015 reads, which acknowledge the frame IRQ
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_irq = false;
+        status
+    }
+
+    pub fn peek_status(&self) -> u8 {
+        let mut status = 0;
+        if self.pulse1.length > 0 {
+            status |= 0x01;
+        }
+        if self.pulse2.length > 0 {
+            status |= 0x02;
+        }
+        if self.triangle.length > 0 {
+            status |= 0x04;
+        }
+        if self.noise.length > 0 {
+            status |= 0x08;
+        }
+        if self.dmc.bytes_remaining > 0 {
+            status |= 0x10;
+        }
+        if self.frame_irq {
+            status |= 0x40;
+        }
+        if self.dmc.irq {
+            status |= 0x80;
+        }
+        status
+    }
+
+    // The frame counter's and the DMC's IRQ outputs, level triggered like the cart's
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    // The address of the byte the DMC wants next, if its sample buffer is empty
+    pub fn dmc_fetch_address(&self) -> Option<u16> {
+        self.dmc.fetch_address()
+    }
+
+    // Hands the DMC the byte it asked for
+    pub fn dmc_fill(&mut self, data: u8) {
+        self.dmc.fill(data);
+    }
+
+    // CPU cycles until the DMC next wants a byte, None if it won't without a write
+    pub fn cycles_until_dmc_fetch(&self) -> Option<u32> {
+        self.dmc.cycles_until_fetch()
+    }
+
+    // CPU cycles until the frame counter next raises its IRQ, None if it won't
+    pub fn cycles_until_frame_irq(&self) -> Option<u32> {
+        if self.five_step || self.irq_inhibit {
+            return None;
+        }
+        let irq_cycle = QUARTER_FRAMES[3];
+        if self.frame_cycle < irq_cycle {
+            Some(irq_cycle - self.frame_cycle)
+        } else {
+            Some(FOUR_STEP_LENGTH - self.frame_cycle + irq_cycle)
+        }
+    }
+
+    // Samples a second, nudged by the frontend's rate control
+    #[cfg(feature = "apu")]
+    pub fn set_output_rate(&mut self, rate: f64) {
+        self.output.set_output_rate(rate);
+    }
+
+    #[cfg(feature = "apu")]
+    pub fn buffered(&self) -> usize {
+        self.output.buffered()
+    }
+
+    // Samples made since the last call, silence is 0.0
+    #[cfg(feature = "apu")]
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.output.take_samples()
+    }
+
+    // Without the apu feature no samples are made, only the registers and IRQs
+    #[cfg(not(feature = "apu"))]
+    pub fn set_output_rate(&mut self, _rate: f64) {}
+
+    #[cfg(not(feature = "apu"))]
+    pub fn buffered(&self) -> usize {
+        0
+    }
+
+    #[cfg(not(feature = "apu"))]
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        vec![]
+    }
+
+    pub fn clock(&mut self, cpu_cycles: usize) {
+        for _ in 0..cpu_cycles {
+            self.clock_frame_counter();
+            self.triangle.clock_timer();
+            self.noise.clock_timer();
+            self.dmc.clock_timer();
+            if self.odd_cycle {
+                self.pulse1.clock_timer();
+                self.pulse2.clock_timer();
+            }
+            self.odd_cycle = !self.odd_cycle;
+
+            #[cfg(feature = "apu")]
+            self.output.push(self.mix());
+        }
+    }
+
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        let cycle = self.frame_cycle;
+        if cycle == QUARTER_FRAMES[0] || cycle == QUARTER_FRAMES[2] {
+            self.clock_quarter_frame();
+        } else if cycle == QUARTER_FRAMES[1] {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        } else if !self.five_step && cycle == QUARTER_FRAMES[3] {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+            if !self.irq_inhibit {
+                self.frame_irq = true;
+            }
+        } else if self.five_step && cycle == FIVE_STEP_LAST {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        }
+
+        let length = if self.five_step { FIVE_STEP_LENGTH } else { FOUR_STEP_LENGTH };
+        if self.frame_cycle >= length {
+            self.frame_cycle = 0;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    // Nonlinear mix from the nesdev wiki: the pulses make 0.0 to about 0.26 and
+    // the triangle, noise and DMC up to about 0.74 between them
+    #[cfg(feature = "apu")]
+    fn mix(&self) -> f32 {
+        let pulses = (self.pulse1.output() + self.pulse2.output()) as f32;
+        let pulse_out = if pulses == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulses + 100.0) };
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.level as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 { 0.0 } else { 159.79 / (1.0 / tnd + 100.0) };
+        pulse_out + tnd_out
+    }
+}
+
+impl Snapshot for Apu {
+    // Channels and frame counter, not the resampler: samples already made still play
+    fn save_state(&self, out: &mut StateWriter) {
+        self.pulse1.save_state(out);
+        self.pulse2.save_state(out);
+        self.triangle.save_state(out);
+        self.noise.save_state(out);
+        self.dmc.save_state(out);
+        out.u32(self.frame_cycle);
+        out.bool(self.five_step);
+        out.bool(self.irq_inhibit);
+        out.bool(self.frame_irq);
+        out.bool(self.odd_cycle);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.pulse1.load_state(input)?;
+        self.pulse2.load_state(input)?;
+        self.triangle.load_state(input)?;
+        self.noise.load_state(input)?;
+        self.dmc.load_state(input)?;
+        self.frame_cycle = input.u32()?;
+        self.five_step = input.bool()?;
+        self.irq_inhibit = input.bool()?;
+        self.frame_irq = input.bool()?;
+        self.odd_cycle = input.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "apu")]
+    #[test]
+    fn test_pulse_plays_until_length_runs_out() {
+        let mut apu = Apu::new();
+        apu.write(0x4017, 0x40); // no frame IRQ
+        apu.write(0x4015, 0x01);
+        apu.write(0x4000, 0b1001_1111); // 50% duty, constant volume 15, length counting
+        apu.write(0x4002, 0xFD); // ~440 Hz
+        apu.write(0x4003, 0b0000_1000); // length index 1: 254 half frames
+        assert_eq!(apu.peek_status() & 0x01, 0x01);
+
+        // A second of audio, the square wave swings both ways around silence (the
+        // output filters take out the idle triangle's constant level)
+        apu.clock(CPU_CYCLES_PER_SECOND as usize);
+        let samples = apu.take_samples();
+        assert!((samples.len() as i64 - SAMPLE_RATE as i64).abs() <= 1);
+        let high = samples.iter().filter(|&&sample| sample > 0.005).count();
+        assert!(high > samples.len() / 3 && high < samples.len() * 2 / 3, "{} of {}", high, samples.len());
+
+        // 254 half frames at 120 a second is just over 2 seconds
+        apu.clock(CPU_CYCLES_PER_SECOND as usize * 2);
+        assert_eq!(apu.peek_status() & 0x01, 0);
+        assert!(apu.take_samples().iter().rev().take(100).all(|&sample| sample.abs() < 1e-5));
+    }
+
+    #[test]
+    fn test_load_state_rejects_bad_sequencer_steps() {
+        let mut pulse = Pulse::new(false);
+        let mut out = StateWriter::new();
+        pulse.save_state(&mut out);
+        let mut state = out.finish();
+        state[2] = 8; // step
+        assert!(pulse.load_state(&mut StateReader::new(&state)).is_err());
+        state[2] = 7;
+        state[1] = 4; // duty
+        assert!(pulse.load_state(&mut StateReader::new(&state)).is_err());
+        state[1] = 3;
+        assert!(pulse.load_state(&mut StateReader::new(&state)).is_ok());
+
+        let mut triangle = Triangle::default();
+        let mut out = StateWriter::new();
+        triangle.save_state(&mut out);
+        let mut state = out.finish();
+        state[9] = 32; // step
+        assert!(triangle.load_state(&mut StateReader::new(&state)).is_err());
+        state[9] = 31;
+        assert!(triangle.load_state(&mut StateReader::new(&state)).is_ok());
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut apu = Apu::new();
+        apu.clock(FOUR_STEP_LENGTH as usize);
+        assert!(apu.irq());
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert!(!apu.irq());
+
+        assert_eq!(apu.cycles_until_frame_irq(), Some(FOUR_STEP_LENGTH - 1));
+        apu.clock(FOUR_STEP_LENGTH as usize - 1);
+        assert!(apu.irq());
+
+        apu.write(0x4017, 0x40);
+        assert_eq!(apu.cycles_until_frame_irq(), None);
+        apu.clock(FOUR_STEP_LENGTH as usize * 2);
+        assert!(!apu.irq());
+    }
+
+    #[cfg(feature = "apu")]
+    #[test]
+    fn test_triangle_stops_with_its_linear_counter() {
+        let mut apu = Apu::new();
+        apu.write(0x4017, 0x40);
+        apu.write(0x4015, 0x04);
+        apu.write(0x4008, 0x10); // linear counter 16 quarter frames, counting
+        apu.write(0x400A, 0x7E); // ~440 Hz
+        apu.write(0x400B, 0x08);
+        assert_eq!(apu.peek_status() & 0x04, 0x04);
+
+        // Ramps up and down while the linear counter lasts (16 quarter frames is ~67ms)
+        apu.clock(CPU_CYCLES_PER_SECOND as usize / 20);
+        let samples = apu.take_samples();
+        let (low, high) = samples.iter().fold((f32::MAX, 0.0f32), |(low, high), &s| (low.min(s), high.max(s)));
+        assert!(high - low > 0.2, "{} to {}", low, high);
+
+        // Then holds its last step, a flat line
+        apu.clock(CPU_CYCLES_PER_SECOND as usize / 10);
+        let samples = apu.take_samples();
+        assert!(samples.iter().rev().take(100).all(|&sample| sample == samples[samples.len() - 1]));
+    }
+
+    #[test]
+    fn test_noise_lfsr_modes() {
+        let mut noise = Noise::default();
+        let steps = |noise: &mut Noise| {
+            let start = noise.shift;
+            (1..=40000).find(|_| {
+                noise.timer = 0;
+                noise.clock_timer();
+                noise.shift == start
+            })
+        };
+        assert_eq!(steps(&mut noise), Some(32767));
+        noise.write(2, 0x80);
+        assert_eq!(steps(&mut noise), Some(93));
+
+        // Silent while the shift register's low bit is set
+        noise.set_enabled(true);
+        noise.write(0, 0x1F);
+        noise.write(3, 0x08);
+        noise.shift = 0b10;
+        assert_eq!(noise.output(), 15);
+        noise.shift = 0b11;
+        assert_eq!(noise.output(), 0);
+    }
+
+    #[test]
+    fn test_dmc_bits_move_the_level() {
+        let mut dmc = Dmc::default();
+        dmc.write(0, 0x0F); // 54 cycles a bit
+        dmc.write(1, 0x40);
+        dmc.write(3, 0x00); // 1 byte
+        dmc.set_enabled(true);
+        assert_eq!(dmc.fetch_address(), Some(0xC000));
+        dmc.fill(0b0000_1111);
+        assert_eq!(dmc.fetch_address(), None);
+
+        // The byte goes to the shift register once the silent first 8 bits are out,
+        // then each 1 bit adds 2 and each 0 takes 2 away
+        while dmc.buffer.is_some() {
+            dmc.clock_timer();
+        }
+        for _ in 0..4 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.level, 0x40 + 4 * 2);
+        for _ in 0..4 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.level, 0x40);
+    }
+
+    #[test]
+    fn test_sweep_mutes_past_the_top() {
+        let mut pulse = Pulse::new(false);
+        pulse.write(2, 0xFF);
+        pulse.write(3, 0x07); // period $7FF
+        pulse.write(1, 0b1000_0001); // sweep up by period >> 1
+        assert!(pulse.sweep_muted());
+        pulse.write(1, 0b1000_1001); // down instead
+        assert!(!pulse.sweep_muted());
+        assert_eq!(pulse.sweep_target(), 0x7FF - 0x3FF - 1);
+    }
+}
@@ -0,0 +1,80 @@
+use std::path::{Path, PathBuf};
+
+// Attract mode: plays every rom in a folder for a while, then moves on to the next
+// one, forever. Meant for kiosk setups, and a good workout for building and tearing
+// down consoles at runtime.
+//
+// A demo input script (see script_input.rs) with the same name as the rom and the
+// ".input" extension, e.g. smb.nes + smb.input, is played back if present.
+
+pub const DEMO_INPUT_EXTENSION: &str = "input";
+
+pub struct AttractPlaylist {
+    roms: Vec<PathBuf>,
+    next: usize,
+}
+
+impl AttractPlaylist {
+    // Every .nes file in the folder, in name order
+    pub fn from_dir(dir: &Path) -> Result<AttractPlaylist, String> {
+        let entries = std::fs::read_dir(dir).map_err(|e| format!("Could not read rom folder {}: {}", dir.display(), e))?;
+
+        let mut roms: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("nes")))
+            .collect();
+        if roms.is_empty() {
+            return Err(format!("No .nes files found in {}", dir.display()));
+        }
+        roms.sort();
+
+        Ok(AttractPlaylist { roms, next: 0 })
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    // Next rom to play, wrapping back to the first after the last
+    pub fn next_rom(&mut self) -> &Path {
+        let rom = &self.roms[self.next];
+        self.next = (self.next + 1) % self.roms.len();
+        rom
+    }
+}
+
+// The rom's demo input script, if there is one
+pub fn demo_input_path(rom: &Path) -> Option<PathBuf> {
+    let path = rom.with_extension(DEMO_INPUT_EXTENSION);
+    if path.is_file() { Some(path) } else { None }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_playlist_cycles_in_name_order() {
+        let dir = std::env::temp_dir().join(format!("attract_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["b.nes", "a.NES", "notes.txt", "b.input"] {
+            std::fs::write(dir.join(name), []).unwrap();
+        }
+
+        let mut playlist = AttractPlaylist::from_dir(&dir).unwrap();
+        assert_eq!(playlist.len(), 2);
+        assert!(playlist.next_rom().ends_with("a.NES"));
+        assert!(playlist.next_rom().ends_with("b.nes"));
+        assert!(playlist.next_rom().ends_with("a.NES"));
+
+        assert_eq!(demo_input_path(&dir.join("b.nes")), Some(dir.join("b.input")));
+        assert_eq!(demo_input_path(&dir.join("a.NES")), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use std::f32::consts::PI;
+
+use crate::apu::{CPU_CYCLES_PER_SECOND, SAMPLE_RATE};
+
+// Turns the APU's output, one level every CPU cycle (~1.79MHz), into samples at
+// the frontend's rate (44.1kHz, nudged by rate control, see sync.rs). On the way
+// it goes through the same filters as the console's audio out:
+//
+//   low-pass 14kHz    every CPU cycle, also keeps what's above the output's
+//                     Nyquist frequency from aliasing back down
+//   average           all the levels that make up one output sample
+//   high-pass 90Hz    at the output rate, these two take out the DC level the
+//   high-pass 440Hz   mixer sits at, so silence is 0.0 and samples swing both ways
+//
+// https://www.nesdev.org/wiki/APU_Mixer
+
+// One pole filter coefficients, from the cutoff and the rate it runs at
+fn low_pass_alpha(cutoff: f32, rate: f32) -> f32 {
+    let rc = 1.0 / (2.0 * PI * cutoff);
+    let dt = 1.0 / rate;
+    dt / (rc + dt)
+}
+
+fn high_pass_alpha(cutoff: f32, rate: f32) -> f32 {
+    let rc = 1.0 / (2.0 * PI * cutoff);
+    let dt = 1.0 / rate;
+    rc / (rc + dt)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct HighPass {
+    alpha: f32,
+    last_in: f32,
+    last_out: f32,
+}
+
+impl HighPass {
+    fn new(cutoff: f32) -> Self {
+        HighPass { alpha: high_pass_alpha(cutoff, SAMPLE_RATE as f32), last_in: 0.0, last_out: 0.0 }
+    }
+
+    fn filter(&mut self, sample: f32) -> f32 {
+        self.last_out = self.alpha * (self.last_out + sample - self.last_in);
+        self.last_in = sample;
+        self.last_out
+    }
+}
+
+pub struct Resampler {
+    output_rate: f64, // samples a second
+    sample_clock: f64, // fraction of an output sample the levels since the last one make
+    low_pass_alpha: f32,
+    low_pass: f32,
+    sum: f32,
+    count: u32,
+    high_pass_90: HighPass,
+    high_pass_440: HighPass,
+    samples: Vec<f32>,
+}
+
+impl Default for Resampler {
+    fn default() -> Self {
+        Resampler::new()
+    }
+}
+
+impl Resampler {
+    pub fn new() -> Self {
+        Resampler {
+            output_rate: SAMPLE_RATE as f64,
+            sample_clock: 0.0,
+            low_pass_alpha: low_pass_alpha(14_000.0, CPU_CYCLES_PER_SECOND),
+            low_pass: 0.0,
+            sum: 0.0,
+            count: 0,
+            high_pass_90: HighPass::new(90.0),
+            high_pass_440: HighPass::new(440.0),
+            samples: vec![],
+        }
+    }
+
+    pub fn set_output_rate(&mut self, rate: f64) {
+        self.output_rate = rate;
+    }
+
+    // The APU's level for one CPU cycle
+    pub fn push(&mut self, level: f32) {
+        self.low_pass += self.low_pass_alpha * (level - self.low_pass);
+        self.sum += self.low_pass;
+        self.count += 1;
+        self.sample_clock += self.output_rate / CPU_CYCLES_PER_SECOND as f64;
+        if self.sample_clock >= 1.0 {
+            self.sample_clock -= 1.0;
+            let sample = self.sum / self.count as f32;
+            let sample = self.high_pass_440.filter(self.high_pass_90.filter(sample));
+            self.samples.push(sample);
+            self.sum = 0.0;
+            self.count = 0;
+        }
+    }
+
+    pub fn buffered(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A second of a square wave at some frequency, full scale 0.0 to 1.0
+    fn square_wave(resampler: &mut Resampler, frequency: f32) -> Vec<f32> {
+        let half_period = CPU_CYCLES_PER_SECOND / frequency / 2.0;
+        for cycle in 0..CPU_CYCLES_PER_SECOND as usize {
+            let high = ((cycle as f32 / half_period) as usize).is_multiple_of(2);
+            resampler.push(if high { 1.0 } else { 0.0 });
+        }
+        resampler.take_samples()
+    }
+
+    #[test]
+    fn test_filters() {
+        // A steady level is taken out: silence is 0.0 whatever the mixer's DC level
+        let mut resampler = Resampler::new();
+        for _ in 0..CPU_CYCLES_PER_SECOND as usize / 10 {
+            resampler.push(0.5);
+        }
+        let samples = resampler.take_samples();
+        assert!((samples.len() as i64 - SAMPLE_RATE as i64 / 10).abs() <= 1);
+        assert!(samples.last().unwrap().abs() < 1e-3);
+
+        // Tones in the middle of the range come through about whole, swinging both ways
+        let samples = square_wave(&mut Resampler::new(), 2000.0);
+        let peak = samples.iter().skip(1000).fold(0.0f32, |peak, &s| peak.max(s.abs()));
+        assert!(peak > 0.45 && peak < 0.6, "{}", peak);
+
+        // Ones close to the output's Nyquist frequency are mostly filtered out
+        let samples = square_wave(&mut Resampler::new(), 20_000.0);
+        let peak = samples.iter().skip(1000).fold(0.0f32, |peak, &s| peak.max(s.abs()));
+        assert!(peak < 0.3, "{}", peak);
+    }
+}
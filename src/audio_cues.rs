@@ -0,0 +1,199 @@
+use bitflags::bitflags;
+
+use crate::frame::Frame;
+use crate::osd::draw_text;
+
+// Visual stand-ins for sound, for players who can't hear it: when an APU channel
+// starts a note a badge for that channel lights up in the top right corner for a
+// moment. Games signal a lot with sound alone (a noise burst for an enemy off
+// screen, a jingle when a timer runs low), so "audio_cues = noise,dmc" shows just
+// the channels those come from, or "all" shows every channel.
+//
+// Notes are picked up from the APU registers as the CPU writes them (the tap), so
+// this works without any sound being produced:
+//   $4003/$4007/$400B/$400F  reload a channel's length counter, which starts a note
+//   $4015                    enables channels, and starts a DMC sample if bit 4 is set
+// Notes on a disabled channel, or with the volume (or the triangle's linear
+// counter) set to 0, are silent and don't count.
+
+const CUE_FRAMES: u8 = 12; // how long a badge stays lit after a note starts
+const BADGE_WIDTH: usize = 15;
+const BADGE_HEIGHT: usize = 9;
+
+bitflags! {
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct ApuChannels: u8 {
+        const PULSE1   = 0b0000_0001;
+        const PULSE2   = 0b0000_0010;
+        const TRIANGLE = 0b0000_0100;
+        const NOISE    = 0b0000_1000;
+        const DMC      = 0b0001_0000;
+    }
+}
+
+const CHANNEL_NAMES: [(&str, ApuChannels); 5] = [
+    ("pulse1", ApuChannels::PULSE1),
+    ("pulse2", ApuChannels::PULSE2),
+    ("triangle", ApuChannels::TRIANGLE),
+    ("noise", ApuChannels::NOISE),
+    ("dmc", ApuChannels::DMC),
+];
+
+// Badge label and color for each channel, in the order they're drawn
+const BADGES: [(ApuChannels, &str, (u8, u8, u8)); 5] = [
+    (ApuChannels::PULSE1, "SQ1", (0x20, 0x60, 0xD0)),
+    (ApuChannels::PULSE2, "SQ2", (0x20, 0x90, 0xD0)),
+    (ApuChannels::TRIANGLE, "TRI", (0x20, 0xA0, 0x40)),
+    (ApuChannels::NOISE, "NOI", (0xD0, 0x40, 0x20)),
+    (ApuChannels::DMC, "DMC", (0xA0, 0x30, 0xB0)),
+];
+
+impl ApuChannels {
+    // "all", "off", or a list like "noise,dmc"
+    pub fn parse(value: &str) -> Result<ApuChannels, String> {
+        match value {
+            "all" => return Ok(ApuChannels::all()),
+            "off" | "none" => return Ok(ApuChannels::empty()),
+            _ => {}
+        }
+
+        let mut channels = ApuChannels::empty();
+        for name in value.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+            let (_, channel) = CHANNEL_NAMES
+                .iter()
+                .find(|(channel_name, _)| *channel_name == name)
+                .ok_or(format!("Unknown APU channel \"{}\"", name))?;
+            channels |= *channel;
+        }
+        Ok(channels)
+    }
+}
+
+// Watches CPU writes to the APU registers for notes starting, see the top of the file
+pub struct ApuTap {
+    registers: [u8; 0x14], // last value written to $4000-$4013
+    enabled: ApuChannels, // from $4015
+    triggered: ApuChannels, // notes started since the last take_triggered
+}
+
+impl Default for ApuTap {
+    fn default() -> Self {
+        ApuTap::new()
+    }
+}
+
+impl ApuTap {
+    pub fn new() -> Self {
+        ApuTap { registers: [0; 0x14], enabled: ApuChannels::empty(), triggered: ApuChannels::empty() }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4013 => {
+                self.registers[(addr - 0x4000) as usize] = data;
+                let started = match addr {
+                    0x4003 if self.volume_on(0x4000) => ApuChannels::PULSE1,
+                    0x4007 if self.volume_on(0x4004) => ApuChannels::PULSE2,
+                    0x400B if self.registers[0x08] & 0x7F != 0 => ApuChannels::TRIANGLE,
+                    0x400F if self.volume_on(0x400C) => ApuChannels::NOISE,
+                    _ => ApuChannels::empty(),
+                };
+                self.triggered |= started & self.enabled;
+            }
+            0x4015 => {
+                self.enabled = ApuChannels::from_bits_truncate(data);
+                self.triggered |= self.enabled & ApuChannels::DMC;
+            }
+            _ => {}
+        }
+    }
+
+    // Channels that started a note since the last call
+    pub fn take_triggered(&mut self) -> ApuChannels {
+        std::mem::replace(&mut self.triggered, ApuChannels::empty())
+    }
+
+    // A constant volume of 0 is silent, the envelope is assumed to make sound
+    fn volume_on(&self, control: u16) -> bool {
+        let value = self.registers[(control - 0x4000) as usize];
+        value & 0x10 == 0 || value & 0x0F != 0
+    }
+}
+
+pub struct CueOverlay {
+    channels: ApuChannels, // the channels to show
+    lit: [u8; 5], // frames each badge has left, in BADGES order
+}
+
+impl CueOverlay {
+    pub fn new(channels: ApuChannels) -> Self {
+        CueOverlay { channels, lit: [0; 5] }
+    }
+
+    // Call once per frame with the channels that started notes during it
+    pub fn update(&mut self, triggered: ApuChannels) {
+        for (lit, (channel, _, _)) in self.lit.iter_mut().zip(BADGES.iter()) {
+            if triggered.contains(*channel) && self.channels.contains(*channel) {
+                *lit = CUE_FRAMES;
+            } else {
+                *lit = lit.saturating_sub(1);
+            }
+        }
+    }
+
+    pub fn lit(&self) -> ApuChannels {
+        BADGES
+            .iter()
+            .zip(self.lit.iter())
+            .filter(|(_, lit)| **lit > 0)
+            .fold(ApuChannels::empty(), |lit, ((channel, _, _), _)| lit | *channel)
+    }
+
+    // Lit badges in a row along the top right corner
+    pub fn draw(&self, frame: &mut Frame) {
+        let mut x = 256 - 2 - BADGE_WIDTH;
+        for ((_, label, color), lit) in BADGES.iter().zip(self.lit.iter()).rev() {
+            if *lit == 0 {
+                continue;
+            }
+            for y in 2..2 + BADGE_HEIGHT {
+                for dx in 0..BADGE_WIDTH {
+                    frame.set_pixel(false, x + dx, y, *color);
+                }
+            }
+            draw_text(frame, x + 2, 4, label, (0xFF, 0xFF, 0xFF));
+            x -= BADGE_WIDTH + 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_notes_light_badges() {
+        let mut tap = ApuTap::new();
+        tap.write(0x400C, 0x1F); // noise at constant volume 15
+        tap.write(0x400F, 0x08);
+        assert_eq!(tap.take_triggered(), ApuChannels::empty()); // not enabled yet
+
+        tap.write(0x4015, 0x0F);
+        tap.write(0x400F, 0x08);
+        tap.write(0x4000, 0x10); // pulse 1 at constant volume 0
+        tap.write(0x4003, 0x08);
+        assert_eq!(tap.take_triggered(), ApuChannels::NOISE);
+        assert_eq!(tap.take_triggered(), ApuChannels::empty());
+
+        let mut overlay = CueOverlay::new(ApuChannels::parse("noise, dmc").unwrap());
+        overlay.update(ApuChannels::NOISE | ApuChannels::PULSE1);
+        assert_eq!(overlay.lit(), ApuChannels::NOISE);
+        for _ in 0..CUE_FRAMES {
+            overlay.update(ApuChannels::empty());
+        }
+        assert_eq!(overlay.lit(), ApuChannels::empty());
+
+        assert_eq!(ApuChannels::parse("all").unwrap(), ApuChannels::all());
+        assert!(ApuChannels::parse("noise,bass").is_err());
+    }
+}
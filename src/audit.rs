@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// Write audit: counts the writes a run made that the emulator dropped or didn't
+// fully act on, by address, so when a game doesn't work it's easier to tell what
+// it was waiting on. Turned on with "audit = on" in the config or -audit; the
+// summary prints when the emulator closes (or after a -headless run).
+//
+//   sound       $4000-$4013, $4015, $4017 in a build without the apu feature,
+//               where only the length counters and IRQs run
+//   read-only   $2002
+//   expansion   $4020-$5FFF, extra hardware on the cart that only a few of the
+//               mappers here have (see mapping/mod.rs)
+//   prg ram     $6000-$7FFF on a board with no PRG RAM
+//   unmapped    anything nothing answers to, like the CPU test registers
+//
+// Lots of sound writes only mean the game has music. Expansion or PRG RAM writes
+// usually mean a mapper feature is missing. Unmapped ones are often a CPU bug.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IgnoredWrite {
+    Sound,
+    ReadOnly,
+    Expansion,
+    PrgRam,
+    Unmapped,
+}
+
+impl IgnoredWrite {
+    fn describe(&self) -> &'static str {
+        match self {
+            IgnoredWrite::Sound => "sound registers, only heard in builds with the apu feature",
+            IgnoredWrite::ReadOnly => "read-only registers",
+            IgnoredWrite::Expansion => "cartridge expansion area, usually a mapper feature that isn't emulated",
+            IgnoredWrite::PrgRam => "PRG RAM the board doesn't have",
+            IgnoredWrite::Unmapped => "unmapped addresses, often a sign the CPU went astray",
+        }
+    }
+}
+
+const ADDRESSES_LISTED: usize = 8; // the busiest few per kind
+
+#[derive(Default)]
+pub struct WriteAudit {
+    writes: BTreeMap<(IgnoredWrite, u16), u64>,
+}
+
+impl WriteAudit {
+    pub fn new() -> Self {
+        WriteAudit::default()
+    }
+
+    pub fn record(&mut self, kind: IgnoredWrite, addr: u16) {
+        *self.writes.entry((kind, addr)).or_insert(0) += 1;
+    }
+
+    pub fn count(&self, kind: IgnoredWrite) -> u64 {
+        self.writes.iter().filter(|((k, _), _)| *k == kind).map(|(_, count)| count).sum()
+    }
+
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        if self.writes.is_empty() {
+            let _ = writeln!(out, "Write audit: every write was handled");
+            return out;
+        }
+        let total: u64 = self.writes.values().sum();
+        let _ = writeln!(out, "Write audit: {} writes ignored", total);
+        let mut kinds: Vec<IgnoredWrite> = self.writes.keys().map(|(kind, _)| *kind).collect();
+        kinds.dedup();
+        for kind in kinds {
+            let _ = writeln!(out, "  {:>8}  {}", self.count(kind), kind.describe());
+            let mut addresses: Vec<(u16, u64)> =
+                self.writes.iter().filter(|((k, _), _)| *k == kind).map(|((_, addr), count)| (*addr, *count)).collect();
+            addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            for (addr, count) in addresses.iter().take(ADDRESSES_LISTED) {
+                let _ = writeln!(out, "            ${:04X} x{}", addr, count);
+            }
+            if addresses.len() > ADDRESSES_LISTED {
+                let _ = writeln!(out, "            and {} more addresses", addresses.len() - ADDRESSES_LISTED);
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,321 @@
+use std::sync::mpsc;
+
+use crate::bus::Mem;
+use crate::joypad::{Joypad, JoypadButton};
+use crate::nes::Nes;
+use crate::ppu::NesPPU;
+use crate::rom::Rom;
+use crate::script_input::{ScriptAction, ScriptCommand, ScriptInput};
+use crate::testrom::{self, TestOutput};
+
+// Declarative regression tests for homebrew, run headless by the nes-test binary.
+// A test file is a small subset of TOML:
+//
+//   rom = "mygame.nes"          # relative to the test file
+//
+//   [[input]]
+//   frame = 120
+//   press = "start"             # or release = "start"
+//
+//   [[assert]]
+//   frame = 300
+//   addr = 0x00D0
+//   equals = 0x05
+//   name = "lives after the first level"   # optional, shown when it fails
+//
+//   [[test_rom]]
+//   frame = 3000                # blargg style test roms must report a pass by then
+//
+// Inputs are applied like an input script (see script_input.rs). Assertions are
+// checked once their frame has been drawn, at the start of its vblank. A test rom's
+// $6000 result (see testrom.rs) is checked after them, pressing reset if it asks.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assertion {
+    pub frame: u64,
+    pub addr: u16,
+    pub equals: u8,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestSpec {
+    pub rom: String,
+    pub inputs: Vec<ScriptCommand>,
+    pub asserts: Vec<Assertion>,
+    pub test_rom: Option<u64>, // frame the test rom must have passed by
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    Memory { assertion: Assertion, actual: u8 },
+    // None when the rom never wrote a result
+    TestRom { frame: u64, output: Option<TestOutput> },
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Failure::Memory { assertion, actual } => {
+                write!(f, "frame {}: ${:04X} was ${:02X}, expected ${:02X}", assertion.frame, assertion.addr, actual, assertion.equals)?;
+                if let Some(name) = assertion.name.as_ref() {
+                    write!(f, " ({})", name)?;
+                }
+                Ok(())
+            }
+            Failure::TestRom { frame, output: None } => write!(f, "frame {}: the test rom never wrote a result to $6000", frame),
+            Failure::TestRom { frame, output: Some(output) } => {
+                write!(f, "frame {}: {}", frame, output.to_string().replace('\n', "\n  "))
+            }
+        }
+    }
+}
+
+enum Value {
+    Int(u64),
+    Str(String),
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    if let Some(quoted) = text.strip_prefix('"') {
+        let string = quoted.strip_suffix('"').ok_or(format!("Unterminated string {}", text))?;
+        return Ok(Value::Str(string.to_string()));
+    }
+    let text = text.replace('_', "");
+    let number = match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    number.map(Value::Int).map_err(|_| format!("Expected a number or a quoted string but got {}", text))
+}
+
+// Drops a trailing comment, leaving # inside strings alone
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+#[derive(Default)]
+struct Table {
+    frame: Option<u64>,
+    addr: Option<u16>,
+    equals: Option<u8>,
+    name: Option<String>,
+    button: Option<(String, bool)>,
+}
+
+impl TestSpec {
+    pub fn parse(text: &str) -> Result<TestSpec, String> {
+        let mut rom = None;
+        let mut tables: Vec<(&str, Table)> = vec![];
+
+        for (number, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let at_line = |e: String| format!("Line {}: {}", number + 1, e);
+
+            if let Some(header) = line.strip_prefix("[[").and_then(|rest| rest.strip_suffix("]]")) {
+                match header.trim() {
+                    "input" => tables.push(("input", Table::default())),
+                    "assert" => tables.push(("assert", Table::default())),
+                    "test_rom" => tables.push(("test_rom", Table::default())),
+                    other => return Err(at_line(format!("Unknown table [[{}]]", other))),
+                }
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or(at_line(format!("Expected key = value but got \"{}\"", line)))?;
+            let (key, value) = (key.trim(), parse_value(value.trim()).map_err(at_line)?);
+            let Some((kind, table)) = tables.last_mut() else {
+                match (key, value) {
+                    ("rom", Value::Str(path)) => rom = Some(path),
+                    _ => return Err(at_line(format!("Unknown setting \"{}\"", key))),
+                }
+                continue;
+            };
+
+            match (*kind, key, value) {
+                (_, "frame", Value::Int(frame)) => table.frame = Some(frame),
+                ("input", "press", Value::Str(button)) => table.button = Some((button, true)),
+                ("input", "release", Value::Str(button)) => table.button = Some((button, false)),
+                ("assert", "addr", Value::Int(addr)) if addr <= 0xFFFF => table.addr = Some(addr as u16),
+                ("assert", "equals", Value::Int(equals)) if equals <= 0xFF => table.equals = Some(equals as u8),
+                ("assert", "name", Value::Str(name)) => table.name = Some(name),
+                _ => return Err(at_line(format!("Bad or unknown key \"{}\" in [[{}]]", key, kind))),
+            }
+        }
+
+        let mut spec = TestSpec {
+            rom: rom.ok_or("The test doesn't name a rom".to_string())?,
+            inputs: vec![],
+            asserts: vec![],
+            test_rom: None,
+        };
+        for (kind, table) in tables {
+            let frame = table.frame.ok_or(format!("An [[{}]] is missing its frame", kind))?;
+            if kind == "test_rom" {
+                if spec.test_rom.replace(frame).is_some() {
+                    return Err("Only one [[test_rom]] is allowed".to_string());
+                }
+            } else if kind == "input" {
+                let (label, pressed) = table.button.ok_or("An [[input]] needs press or release".to_string())?;
+                let button = JoypadButton::from_label(&label).ok_or(format!("Unknown button \"{}\"", label))?;
+                spec.inputs.push(ScriptCommand { frame, action: ScriptAction::Button { button, pressed } });
+            } else {
+                spec.asserts.push(Assertion {
+                    frame,
+                    addr: table.addr.ok_or("An [[assert]] is missing its addr".to_string())?,
+                    equals: table.equals.ok_or("An [[assert]] is missing equals".to_string())?,
+                    name: table.name,
+                });
+            }
+        }
+        spec.asserts.sort_by_key(|assertion| assertion.frame);
+        Ok(spec)
+    }
+
+    // Runs the test on `rom` and returns the assertions that didn't hold, or an
+    // error for a rom that can't run at all
+    pub fn run(&self, rom: Rom) -> Result<Vec<Failure>, String> {
+        let (sender, receiver) = mpsc::channel();
+        for command in self.inputs.iter() {
+            sender.send(*command).unwrap();
+        }
+        drop(sender);
+        let mut input = ScriptInput::from_channel(receiver);
+        input.read_to_end();
+
+        // Input goes in from here rather than the frame callback, which only runs
+        // for games that turn NMI on
+        let mut nes = Nes::new(rom, |_ppu: &NesPPU, _joypad1: &mut Joypad| {})?;
+        let mut frame = nes.cpu.bus.ppu.timing().frame;
+        let mut console_event = input.apply(frame, nes.cpu.bus.joypad1_mut());
+
+        let mut failures = vec![];
+        for assertion in self.asserts.iter() {
+            loop {
+                let timing = nes.cpu.bus.ppu.timing();
+                if timing.frame > assertion.frame || (timing.frame == assertion.frame && timing.scanline >= 241) {
+                    break;
+                }
+                nes.step();
+                if nes.cpu.bus.ppu.timing().frame != frame {
+                    if let Some(event) = console_event.take() {
+                        event.perform(&mut nes.cpu);
+                    }
+                    frame = nes.cpu.bus.ppu.timing().frame;
+                    console_event = input.apply(frame, nes.cpu.bus.joypad1_mut());
+                }
+            }
+
+            let actual = nes.cpu.bus.mem_peek(assertion.addr);
+            if actual != assertion.equals {
+                failures.push(Failure::Memory { assertion: assertion.clone(), actual });
+            }
+        }
+
+        if let Some(frame) = self.test_rom {
+            let output = testrom::run_test_rom(&mut nes, frame);
+            if !output.as_ref().is_some_and(|output| output.passed()) {
+                failures.push(Failure::TestRom { frame, output });
+            }
+        }
+        Ok(failures)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SPEC: &str = r#"
+        rom = "counter.nes"  # made up below
+
+        [[input]]
+        frame = 1
+        press = "a"
+
+        [[assert]]
+        frame = 3
+        addr = 0x0010
+        equals = 0x01
+        name = "A seen"
+
+        [[assert]]
+        frame = 2
+        addr = 0x0011
+        equals = 7
+    "#;
+
+    #[test]
+    fn test_parse() {
+        let spec = TestSpec::parse(SPEC).unwrap();
+        assert_eq!(spec.rom, "counter.nes");
+        assert_eq!(spec.inputs.len(), 1);
+        assert!(matches!(spec.inputs[0].action, ScriptAction::Button { pressed: true, .. }));
+        assert_eq!(spec.asserts[0].frame, 2); // sorted by frame
+        assert_eq!(spec.asserts[1].name.as_deref(), Some("A seen"));
+
+        assert!(TestSpec::parse("[[assert]]\nframe = 1\naddr = 0x10").is_err());
+        assert!(TestSpec::parse("rom = \"a.nes\"\n[[input]]\nframe = 1\npress = \"turbo\"").is_err());
+        assert!(TestSpec::parse("[[assert]]\nframe = 1").is_err()); // no rom
+    }
+
+    #[test]
+    fn test_run_reports_failures() {
+        // Strobes the controller and stores button A's bit in $10, forever
+        let mut program = vec![
+            0xA9, 0x01, 0x8D, 0x16, 0x40, // LDA #$01 / STA $4016
+            0xA9, 0x00, 0x8D, 0x16, 0x40, // LDA #$00 / STA $4016
+            0xAD, 0x16, 0x40,             // LDA $4016
+            0x29, 0x01,                   // AND #$01
+            0x85, 0x10,                   // STA $10
+            0x4C, 0x00, 0x80,             // JMP $8000
+        ];
+        program.resize(0x4000, 0xEA);
+        program[0x3FFC] = 0x00;
+        program[0x3FFD] = 0x80;
+
+        // A is pressed on frame 1 and seen by frame 3, $11 is never written
+        let failures = TestSpec::parse(SPEC).unwrap().run(Rom::_new_test(program).unwrap()).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].to_string(), "frame 2: $0011 was $00, expected $07");
+    }
+
+    // Reports `code` through $6000 with "done" as the text, then spins
+    fn test_rom_program(code: u8) -> Vec<u8> {
+        let mut program = vec![];
+        for (addr, value) in [(0x6004u16, b'd'), (0x6005, b'o'), (0x6006, b'n'), (0x6007, b'e'), (0x6008, 0),
+            (0x6001, 0xDE), (0x6002, 0xB0), (0x6003, 0x61), (0x6000, code)] {
+            program.extend([0xA9, value, 0x8D, addr as u8, (addr >> 8) as u8]); // LDA #value / STA addr
+        }
+        let end = 0x8000 + program.len() as u16;
+        program.extend([0x4C, end as u8, (end >> 8) as u8]); // JMP to itself
+        program.resize(0x4000, 0xEA);
+        program[0x3FFC] = 0x00;
+        program[0x3FFD] = 0x80;
+        program
+    }
+
+    #[test]
+    fn test_test_rom_status() {
+        let spec = TestSpec::parse("rom = \"cpu.nes\"\n[[test_rom]]\nframe = 5").unwrap();
+        assert_eq!(spec.test_rom, Some(5));
+        assert!(TestSpec::parse("rom = \"cpu.nes\"\n[[test_rom]]\nframe = 5\n[[test_rom]]\nframe = 6").is_err());
+
+        assert!(spec.run(Rom::_new_test(test_rom_program(0)).unwrap()).unwrap().is_empty());
+
+        let failures = spec.run(Rom::_new_test(test_rom_program(3)).unwrap()).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].to_string(), "frame 5: done\n  Result: failed with code 3");
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+
+// Which parts of the cartridge the CPU and PPU currently see, for debugging bank
+// switching. Each entry is the offset into PRG or CHR ROM that a window starts at:
+//
+//   prg -> the 8 KB windows at $8000, $A000, $C000 and $E000
+//   chr -> the 4 KB pattern tables at PPU $0000 and $1000
+//
+// The bus refreshes it after every write to the mapper's registers (see
+// Bus::bank_mapping), and `-banks` shows it on screen.
+
+pub const PRG_WINDOWS: [u16; 4] = [0x8000, 0xA000, 0xC000, 0xE000];
+pub const CHR_WINDOWS: [u16; 2] = [0x0000, 0x1000];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BankMapping {
+    pub prg: [usize; 4],
+    pub chr: [usize; 2],
+}
+
+impl BankMapping {
+    // A mapping split into 16 KB PRG and 4 KB CHR banks, the way MMC1 and most
+    // simpler boards switch them
+    pub fn from_16k(prg_first: usize, prg_second: usize, chr: [usize; 2]) -> Self {
+        BankMapping {
+            prg: [prg_first, prg_first + 0x2000, prg_second, prg_second + 0x2000],
+            chr,
+        }
+    }
+
+    // The panel, one line for each side, e.g.
+    //   "PRG 8000:00000 A000:02000 C000:1C000 E000:1E000"
+    //   "CHR 0000:00000 1000:01000"
+    pub fn lines(&self) -> [String; 2] {
+        let windows = |addrs: &[u16], offsets: &[usize]| {
+            addrs.iter().zip(offsets).map(|(addr, offset)| format!(" {:04X}:{:05X}", addr, offset)).collect::<String>()
+        };
+        [
+            format!("PRG{}", windows(&PRG_WINDOWS, &self.prg)),
+            format!("CHR{}", windows(&CHR_WINDOWS, &self.chr)),
+        ]
+    }
+}
+
+impl fmt::Display for BankMapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [prg, chr] = self.lines();
+        write!(f, "{}  {}", prg, chr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::{Bus, Mem};
+    use crate::rom::Rom;
+
+    // MMC1 cart with 128 KB of PRG and 32 KB of CHR
+    fn mmc1_rom() -> Rom {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 8, 4, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        raw.resize(16 + 8 * 0x4000 + 4 * 0x2000, 0);
+        Rom::new(&raw).unwrap()
+    }
+
+    fn mmc1_write(bus: &mut Bus, addr: u16, value: u8) {
+        for bit in 0..5 {
+            bus.mem_write(addr, (value >> bit) & 1);
+        }
+    }
+
+    #[test]
+    fn test_mapping_follows_register_writes() {
+        let mut bus = Bus::new(mmc1_rom(), |_, _| {}).unwrap();
+        // Power on: bank 0 at $8000, last bank fixed at $C000
+        assert_eq!(bus.bank_mapping(), BankMapping::from_16k(0, 0x1C000, [0, 0x1000]));
+        assert_eq!(bus.bank_switches(), 0);
+
+        mmc1_write(&mut bus, 0xE000, 3); // PRG bank 3
+        mmc1_write(&mut bus, 0xC000, 5); // CHR bank 5 at $1000, unused in 8 KB mode
+        mmc1_write(&mut bus, 0x8000, 0x1C); // 4 KB CHR banks, PRG mode 3
+
+        let mapping = bus.bank_mapping();
+        assert_eq!(mapping.prg, [0xC000, 0xE000, 0x1C000, 0x1E000]);
+        assert_eq!(mapping.chr, [0, 0x5000]);
+        assert_eq!(bus.bank_switches(), 2); // the CHR bank 1 write changed nothing visible
+        assert_eq!(mapping.lines()[1], "CHR 0000:00000 1000:05000");
+    }
+}
@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+
+// Battery backed PRG RAM, kept in game.sav next to the rom. The rules:
+//
+//   - the file is read once at power on, before the game runs
+//   - the RAM is written back when it has changed, at most once a second and on exit
+//   - a savestate carries its own copy of PRG RAM and loading one replaces the RAM,
+//     but that copy is only a snapshot: it isn't written to the .sav until the game
+//     itself writes PRG RAM after the load. Loading an old state and quitting leaves
+//     the save file as it was.
+//   - the first write after a state load moves the old file to game.sav.bak first,
+//     so a state loaded by mistake can always be undone
+//
+// The bus counts PRG RAM writes (Bus::prg_ram_writes), which is how a change made
+// by the game is told apart from one made by a state load.
+
+pub struct BatterySave {
+    path: PathBuf,
+    on_disk: Vec<u8>, // what the file holds, to skip writing unchanged RAM
+    held_at: Option<u64>, // PRG RAM write count when a state was loaded, while the RAM is held back
+    backup_pending: bool,
+}
+
+impl BatterySave {
+    pub fn path_for(rom_path: &Path) -> PathBuf {
+        rom_path.with_extension("sav")
+    }
+
+    // Opens the save file, which doesn't have to exist yet
+    pub fn open(path: &Path) -> Result<BatterySave, String> {
+        let on_disk = if path.exists() {
+            std::fs::read(path).map_err(|e| format!("Could not read save {}: {}", path.display(), e))?
+        } else {
+            vec![]
+        };
+        Ok(BatterySave { path: path.to_path_buf(), on_disk, held_at: None, backup_pending: false })
+    }
+
+    // The saved RAM to load at power on, empty for a new game
+    pub fn contents(&self) -> &[u8] {
+        &self.on_disk
+    }
+
+    // Call after a savestate has replaced PRG RAM
+    pub fn state_loaded(&mut self, prg_ram_writes: u64) {
+        self.held_at = Some(prg_ram_writes);
+        self.backup_pending = true;
+    }
+
+    // Writes `ram` to the file if it changed and may be written (see above).
+    // Returns whether the file was written.
+    pub fn flush(&mut self, ram: &[u8], prg_ram_writes: u64) -> Result<bool, String> {
+        if self.held_at.is_some_and(|held_at| prg_ram_writes == held_at) || ram == self.on_disk {
+            return Ok(false);
+        }
+        self.held_at = None;
+
+        if self.backup_pending && self.path.exists() {
+            let backup = self.path.with_extension("sav.bak");
+            std::fs::copy(&self.path, &backup).map_err(|e| format!("Could not back up save to {}: {}", backup.display(), e))?;
+        }
+        self.backup_pending = false;
+
+        std::fs::write(&self.path, ram).map_err(|e| format!("Could not write save {}: {}", self.path.display(), e))?;
+        self.on_disk = ram.to_vec();
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_state_load_does_not_reach_the_file_until_the_game_writes() {
+        let dir = std::env::temp_dir().join(format!("battery_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = BatterySave::path_for(&dir.join("zelda.nes"));
+        let _ = std::fs::remove_file(&path);
+
+        let mut save = BatterySave::open(&path).unwrap();
+        assert!(save.contents().is_empty());
+        assert!(save.flush(&[1, 2, 3], 3).unwrap());
+        assert!(!save.flush(&[1, 2, 3], 3).unwrap()); // unchanged
+
+        // An old state puts its RAM back, the file keeps the newer save
+        save.state_loaded(3);
+        assert!(!save.flush(&[9, 9, 9], 3).unwrap());
+        assert_eq!(BatterySave::open(&path).unwrap().contents(), &[1, 2, 3]);
+
+        // Once the game writes, the state's RAM becomes the save and the old one is kept
+        assert!(save.flush(&[9, 9, 8], 4).unwrap());
+        assert_eq!(std::fs::read(&path).unwrap(), vec![9, 9, 8]);
+        assert_eq!(std::fs::read(path.with_extension("sav.bak")).unwrap(), vec![1, 2, 3]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,53 @@
+use std::env;
+use std::path::Path;
+
+use emu::autotest::TestSpec;
+use emu::rom::Rom;
+
+// Runs one test file, Ok(false) when an assertion failed
+fn run(path: &str) -> Result<bool, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let spec = TestSpec::parse(&text)?;
+
+    // The rom path is relative to the test file
+    let rom_path = Path::new(path).parent().unwrap_or(Path::new("")).join(&spec.rom);
+    let bytes = std::fs::read(&rom_path).map_err(|e| format!("Could not read {}: {}", rom_path.display(), e))?;
+    let rom = Rom::new(&bytes)?;
+
+    let failures = spec.run(rom)?;
+    for failure in failures.iter() {
+        println!("  {}", failure);
+    }
+    Ok(failures.is_empty())
+}
+
+// Runs declarative rom tests headless (see src/autotest.rs for the format).
+// Usage: cargo run --bin nes-test -- TEST_FILE...
+// Exits with 1 if any test fails or can't be run.
+fn main() {
+    let paths: Vec<String> = env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("Usage: cargo run --bin nes-test -- TEST_FILE...");
+        std::process::exit(1);
+    }
+
+    let mut failed = 0;
+    for path in paths.iter() {
+        match run(path) {
+            Ok(true) => println!("PASS {}", path),
+            Ok(false) => {
+                println!("FAIL {}", path);
+                failed += 1;
+            }
+            Err(e) => {
+                println!("ERROR {}: {}", path, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{} of {} tests passed", paths.len() - failed, paths.len());
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
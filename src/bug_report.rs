@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+
+use crate::cpu::CPU;
+use crate::frame::Frame;
+use crate::render;
+
+// Everything useful for a bug report in one file, written with F7 in the frontend
+// so it can be attached to an issue as is. The bundle is a plain tar archive
+// (readable with tar or 7-Zip, no extra dependencies to write it) holding:
+//
+//   report.txt      emulator version, rom name, CRC32 and mapper, frame, CPU registers
+//   config.cfg      the config file in use, if there is one
+//   screenshot.bmp  the picture as the PPU has it right now
+//   trace.txt       CPU registers for the last TRACE_RING_SIZE instructions
+//   interrupts.txt  the interrupt log (see interrupt_log.rs)
+//   ram.bin         the 2KB of CPU RAM
+//   prg_ram.bin     cartridge RAM, for carts that have it
+//   state.bin       a save state of the whole console (see state.rs)
+
+pub const TRACE_RING_SIZE: usize = 256;
+const BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TracedInstruction {
+    pc: u16,
+    opcode: u8,
+    reg_a: u8,
+    reg_x: u8,
+    reg_y: u8,
+    status: u8,
+    sp: u8,
+}
+
+// The last few instructions run, cheap enough to record all the time: registers
+// are copied and only formatted when a report is made
+pub struct TraceRing {
+    instructions: VecDeque<TracedInstruction>,
+    capacity: usize,
+}
+
+impl TraceRing {
+    pub fn new(capacity: usize) -> Self {
+        TraceRing { instructions: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    // Call before each instruction runs
+    pub fn record(&mut self, cpu: &CPU) {
+        if self.instructions.len() == self.capacity {
+            self.instructions.pop_front();
+        }
+        self.instructions.push_back(TracedInstruction {
+            pc: cpu.pc,
+            opcode: cpu.mem_peek(cpu.pc),
+            reg_a: cpu.reg_a,
+            reg_x: cpu.reg_x,
+            reg_y: cpu.reg_y,
+            status: cpu.status,
+            sp: cpu.sp,
+        });
+    }
+
+    // One instruction per line, oldest first
+    pub fn dump(&self) -> String {
+        self.instructions
+            .iter()
+            .map(|i| format!("{:04X}  {:02X}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}\n", i.pc, i.opcode, i.reg_a, i.reg_x, i.reg_y, i.status, i.sp))
+            .collect()
+    }
+}
+
+pub struct BugReport {
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl BugReport {
+    // Collects the report from the running game. `rom_info` names the rom and
+    // `config` is the config file's text, if one was loaded.
+    pub fn collect(cpu: &CPU, trace: &TraceRing, rom_info: &str, config: Option<&str>) -> BugReport {
+        let mut report = BugReport { files: vec![] };
+
+        let timing = cpu.bus.ppu.timing();
+        let summary = format!(
+            "EMU {}\n{}\nframe {} scanline {} dot {}\nPC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}\nbanks: {}\n",
+            env!("CARGO_PKG_VERSION"), rom_info, timing.frame, timing.scanline, timing.dot,
+            cpu.pc, cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.status, cpu.sp, cpu.bus.bank_mapping()
+        );
+        report.add("report.txt", summary.into_bytes());
+        if let Some(config) = config {
+            report.add("config.cfg", config.as_bytes().to_vec());
+        }
+
+        let mut frame = Frame::new();
+        render::render(&cpu.bus.ppu, &mut frame);
+        report.add("screenshot.bmp", frame.to_bmp());
+
+        report.add("trace.txt", trace.dump().into_bytes());
+        report.add("interrupts.txt", cpu.bus.interrupt_log().dump().into_bytes());
+        report.add("ram.bin", (0..0x0800).map(|addr| cpu.mem_peek(addr)).collect());
+        if let Some(prg_ram) = cpu.bus.cart_prg_ram() {
+            report.add("prg_ram.bin", prg_ram);
+        }
+        report.add("state.bin", cpu.save_state());
+        report
+    }
+
+    pub fn add(&mut self, name: &str, contents: Vec<u8>) {
+        self.files.push((name.to_string(), contents));
+    }
+
+    // The files as a ustar archive
+    pub fn to_tar(&self) -> Vec<u8> {
+        let mut tar = vec![];
+        for (name, contents) in self.files.iter() {
+            tar.extend_from_slice(&tar_header(name, contents.len()));
+            tar.extend_from_slice(contents);
+            tar.resize(tar.len().next_multiple_of(BLOCK_SIZE), 0);
+        }
+        // Two empty blocks end the archive
+        tar.resize(tar.len() + 2 * BLOCK_SIZE, 0);
+        tar
+    }
+}
+
+fn tar_header(name: &str, size: usize) -> [u8; BLOCK_SIZE] {
+    let mut header = [0; BLOCK_SIZE];
+    let mut field = |offset: usize, value: &[u8]| header[offset..offset + value.len()].copy_from_slice(value);
+    field(0, name.as_bytes());
+    field(100, b"0000644\0"); // mode
+    field(108, b"0000000\0"); // uid
+    field(116, b"0000000\0"); // gid
+    field(124, format!("{:011o}\0", size).as_bytes());
+    field(136, b"00000000000\0"); // mtime
+    field(148, b"        "); // checksum, counted as spaces
+    field(156, b"0"); // regular file
+    field(257, b"ustar\0");
+    field(263, b"00");
+
+    let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tar_layout() {
+        let mut report = BugReport { files: vec![] };
+        report.add("report.txt", b"hello".to_vec());
+        report.add("ram.bin", vec![0xAA; 600]);
+        let tar = report.to_tar();
+
+        // header + 1 data block, header + 2 data blocks, 2 end blocks
+        assert_eq!(tar.len(), 7 * BLOCK_SIZE);
+        assert_eq!(&tar[0..10], b"report.txt");
+        assert_eq!(&tar[124..136], b"00000000005\0");
+        assert_eq!(&tar[512..517], b"hello");
+        assert_eq!(&tar[1024..1031], b"ram.bin");
+        assert_eq!(&tar[257..262], b"ustar");
+
+        let mut unsummed = tar[0..BLOCK_SIZE].to_vec();
+        unsummed[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = unsummed.iter().map(|&byte| byte as u32).sum();
+        assert_eq!(&tar[148..156], format!("{:06o}\0 ", checksum).as_bytes());
+    }
+}
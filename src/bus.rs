@@ -3,12 +3,34 @@ use std::{cell::RefCell, rc::Rc};
 
 use crate::{mapper::Mapper, ppu::NesPPU, rom::{Mirroring, Rom}};
 use crate::joypad::Joypad;
+use crate::quirks::Quirks;
+use crate::accuracy::{Accuracy, AccuracyProfile};
+use crate::bus_mode::BusMode;
+use crate::audit::{IgnoredWrite, WriteAudit};
+use crate::banks::BankMapping;
+use crate::dma::DmaController;
+use crate::desync::{ClockStamp, DesyncChecker};
+use crate::entropy::{EntropyDevice, ENTROPY_REGISTER};
+use crate::rtc::{RtcDevice, RTC_LAST_REGISTER, RTC_LATCH};
+use crate::startup::StartupState;
+use crate::state::{Snapshot, StateReader, StateWriter};
+use crate::family_keyboard::FamilyKeyboard;
+use crate::zapper::Zapper;
+use crate::apu::Apu;
+use crate::audio_cues::ApuTap;
+use crate::interrupt_log::{InterruptEvent, InterruptLog, LoggedInterrupt, INTERRUPT_LOG_SIZE};
+use crate::scheduler::{EventKind, Scheduler, MASTER_CYCLES_PER_CPU_CYCLE, MASTER_CYCLES_PER_SCANLINE};
 
 const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
 const ROM_MEM_START: u16 = 0x6000;
 const ROM_MEM_END: u16 = 0xFFFF;
+// The PPU register latch holds a value for about 600ms before fading out
+const PPU_LATCH_DECAY_FRAMES: u64 = 36;
+const AUDIO_CHUNK: usize = 512; // samples handed to the audio callback at a time
+
+type AudioCallback<'call> = Box<dyn FnMut(&[f32]) + 'call>;
 
 // Generates a dummy rom for when a rom isn't needed
 fn _test_rom_gen() -> Rom {
@@ -19,8 +41,12 @@ fn _test_rom_gen() -> Rom {
         prg_rom: prg_rom,
         chr_rom: chr_rom,
         is_chr_ram: false,
+        has_battery: false,
         mapper_id: 0,
         screen_mirroring: Mirroring::HORIZONTAL,
+        crc: 0,
+        quirks: Quirks::empty(),
+        dip_switches: 0,
     }
 }
 
@@ -29,53 +55,665 @@ pub struct Bus<'call> {
     joypad1: Joypad,
     pub ppu: NesPPU,
     pub mapper: Rc<RefCell<dyn Mapper>>,
+    rom: Rom, // for building the cartridge afresh on a power cycle
     cycles: usize,
+    scheduler: Scheduler,
+    dma: DmaController,
+    register_writes: u64, // PPU/APU/IO register writes since power on
+    bank_mapping: BankMapping, // refreshed after every mapper register write
+    bank_switches: u64, // mapper register writes that changed bank_mapping
+    prg_ram_writes: u64, // CPU writes to $6000-$7FFF since power on
+    controller_open_bus: u8, // upper bits of controller reads, see Quirks::JOYPAD_OPEN_BUS
+    accuracy: Accuracy,
+    bus_mode: BusMode,
+    audit: Option<WriteAudit>, // see audit.rs, off unless asked for
+    startup: StartupState, // what memory holds at power on
+    data_bus: u8, // last byte read or written by the CPU, for open bus reads
+    ppu_latch: u8, // last byte written to or read from a PPU register
+    ppu_latch_frame: u64, // frame the latch was last refreshed on
+    entropy: Option<EntropyDevice>,
+    rtc: Option<RtcDevice>,
+    family_keyboard: Option<FamilyKeyboard>, // on the expansion port
+    zapper: Option<Zapper>, // in controller port 2
+    microphone: bool, // the Famicom's controller 2 mic hears something, see microphone.rs
+    interrupt_log: InterruptLog,
+    apu_tap: ApuTap, // notes started, for the audio cue overlay
+    pub apu: Apu,
+    nmi_line: bool, // the PPU's NMI output when last looked at
+    nmi_edge: bool, // the NMI output went active and the CPU hasn't taken it yet
+    desync: DesyncChecker, // only consulted in debug builds
     gameloop_callback: Box<dyn FnMut(&NesPPU, &mut Joypad) + 'call>,
+    audio_callback: Option<AudioCallback<'call>>,
 }
 
 impl<'a> Bus<'a> {
-    pub fn new<'call, F>(rom: Rom, gameloop_callback: F) -> Bus<'call>
+    pub fn new<'call, F>(rom: Rom, gameloop_callback: F) -> Result<Bus<'call>, String>
     where
         F: FnMut(&NesPPU, &mut Joypad) + 'call,
     {
-        let mapper = rom.generate_mapper();
+        let mapper = rom.generate_mapper()?;
+        let controller_open_bus = if rom.quirks.contains(Quirks::JOYPAD_OPEN_BUS) { 0x40 } else { 0 };
+        let bank_mapping = mapper.borrow().bank_mapping();
         let ppu = NesPPU::new(mapper.clone());
 
-        Bus {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(MASTER_CYCLES_PER_SCANLINE, EventKind::PpuScanlineEnd);
+        let apu = Apu::new();
+        if let Some(cycles) = apu.cycles_until_frame_irq() {
+            scheduler.schedule(cycles as u64 * MASTER_CYCLES_PER_CPU_CYCLE, EventKind::ApuFrameIrq);
+        }
+
+        Ok(Bus {
             cpu_vram: [0; 2048],
             joypad1: Joypad::new(),
             ppu: ppu,
             mapper: mapper,
+            rom,
             cycles: 0,
+            scheduler,
+            dma: DmaController::new(),
+            register_writes: 0,
+            bank_mapping,
+            bank_switches: 0,
+            prg_ram_writes: 0,
+            controller_open_bus,
+            accuracy: AccuracyProfile::Balanced.flags(),
+            bus_mode: BusMode::Permissive,
+            audit: None,
+            startup: StartupState::Zeroed,
+            data_bus: 0,
+            ppu_latch: 0,
+            ppu_latch_frame: 0,
+            entropy: None,
+            rtc: None,
+            family_keyboard: None,
+            zapper: None,
+            microphone: false,
+            interrupt_log: InterruptLog::new(INTERRUPT_LOG_SIZE),
+            apu_tap: ApuTap::new(),
+            apu,
+            nmi_line: false,
+            nmi_edge: false,
+            desync: DesyncChecker::new(),
             gameloop_callback: Box::from(gameloop_callback),
+            audio_callback: None,
+        })
+    }
+
+    // The console's reset button, see CPU::reset_button. Memory and mapper
+    // registers are left alone.
+    pub fn reset(&mut self) {
+        self.ppu.reset();
+        self.apu.reset();
+        self.schedule_frame_irq();
+        self.fetch_dmc_sample();
+        self.dma = DmaController::new();
+        self.nmi_line = false;
+        self.nmi_edge = false;
+    }
+
+    // Pulls the cartridge out and puts `rom` in with the power on, leaving the old one
+    // in `rom` (or `rom` untouched when it can't go in). RAM, VRAM, OAM, the palette
+    // and the CPU carry on as they were, the new cartridge starts from power on.
+    // Whatever the CPU was running now comes from the new PRG ROM, which is the
+    // point: multi-rom loaders and crash studies.
+    pub fn swap_cartridge(&mut self, rom: &mut Rom) -> Result<(), String> {
+        self.mapper = rom.generate_mapper()?;
+        self.ppu.mapper = self.mapper.clone();
+        self.bank_mapping = self.mapper.borrow().bank_mapping();
+        self.controller_open_bus = if rom.quirks.contains(Quirks::JOYPAD_OPEN_BUS) { 0x40 } else { 0 };
+        std::mem::swap(&mut self.rom, rom);
+        Ok(())
+    }
+
+    // CRC32 of the cartridge that's in, see Rom::crc
+    pub fn cart_crc(&self) -> u32 {
+        self.rom.crc
+    }
+
+    // Switching the console off and on, see CPU::power_cycle. RAM, VRAM and the
+    // cartridge start over (battery backed RAM excepted) while the clocks keep
+    // counting, so frame numbers carry on from before.
+    pub fn power_cycle(&mut self) {
+        let battery_ram = self.cart_battery_ram().filter(|_| self.rom.has_battery);
+        self.mapper = self.rom.generate_mapper().expect("The cartridge that's in was built once already");
+        if let Some(data) = battery_ram {
+            self.load_cart_battery_ram(&data);
+        }
+        self.bank_mapping = self.mapper.borrow().bank_mapping();
+        self.ppu.power_cycle(self.mapper.clone());
+        self.apu.power_cycle();
+        self.schedule_frame_irq();
+        self.fetch_dmc_sample();
+
+        self.cpu_vram = [0; 2048];
+        self.fill_startup_memory();
+        self.dma = DmaController::new();
+        self.apu_tap = ApuTap::new();
+        self.data_bus = 0;
+        self.ppu_latch = 0;
+        self.nmi_line = false;
+        self.nmi_edge = false;
+    }
+
+    // Called with every AUDIO_CHUNK or so samples the APU makes (see apu.rs)
+    pub fn set_audio_callback<F>(&mut self, audio_callback: F)
+    where
+        F: FnMut(&[f32]) + 'a,
+    {
+        self.audio_callback = Some(Box::new(audio_callback));
+    }
+
+    // Plug an entropy device into the bus (see entropy.rs)
+    pub fn attach_entropy(&mut self, device: EntropyDevice) {
+        self.entropy = Some(device);
+    }
+
+    pub fn entropy(&self) -> Option<&EntropyDevice> {
+        self.entropy.as_ref()
+    }
+
+    // Plug a clock into the bus (see rtc.rs)
+    pub fn attach_rtc(&mut self, device: RtcDevice) {
+        self.rtc = Some(device);
+    }
+
+    pub fn rtc(&self) -> Option<&RtcDevice> {
+        self.rtc.as_ref()
+    }
+
+    pub fn attach_family_keyboard(&mut self, keyboard: FamilyKeyboard) {
+        self.family_keyboard = Some(keyboard);
+    }
+
+    pub fn family_keyboard_mut(&mut self) -> Option<&mut FamilyKeyboard> {
+        self.family_keyboard.as_mut()
+    }
+
+    pub fn attach_zapper(&mut self, zapper: Zapper) {
+        self.zapper = Some(zapper);
+    }
+
+    pub fn zapper_mut(&mut self) -> Option<&mut Zapper> {
+        self.zapper.as_mut()
+    }
+
+    pub fn set_microphone(&mut self, hearing: bool) {
+        self.microphone = hearing;
+    }
+
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    pub fn interrupt_log(&self) -> &InterruptLog {
+        &self.interrupt_log
+    }
+
+    pub fn apu_tap_mut(&mut self) -> &mut ApuTap {
+        &mut self.apu_tap
+    }
+
+    // Stamps `event` with the current CPU cycle and PPU position
+    pub fn log_interrupt(&mut self, event: InterruptEvent) {
+        let timing = self.ppu.timing();
+        self.interrupt_log.record(LoggedInterrupt {
+            cycle: self.cycles,
+            frame: timing.frame,
+            scanline: timing.scanline,
+            dot: timing.dot,
+            event,
+        });
+    }
+
+    pub fn joypad1(&self) -> &Joypad {
+        &self.joypad1
+    }
+
+    pub fn joypad1_mut(&mut self) -> &mut Joypad {
+        &mut self.joypad1
+    }
+
+    pub fn dma(&self) -> &DmaController {
+        &self.dma
+    }
+
+    pub fn dma_mut(&mut self) -> &mut DmaController {
+        &mut self.dma
+    }
+
+    // Off by default since real games work around it, turned on to check those
+    // workarounds or to match a TAS made on hardware accurate emulators
+    pub fn set_dpcm_glitch(&mut self, enabled: bool) {
+        self.accuracy.set(Accuracy::DPCM_GLITCH, enabled);
+    }
+
+    // See accuracy.rs, the balanced profile unless set
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.accuracy = accuracy;
+        self.ppu.set_rendering_increment(accuracy.contains(Accuracy::PPUDATA_DURING_RENDERING));
+    }
+
+    pub fn accuracy(&self) -> Accuracy {
+        self.accuracy
+    }
+
+    // See startup.rs, zeroed unless set. Set before the game runs, as it fills
+    // RAM, VRAM and OAM straight away.
+    pub fn set_startup_state(&mut self, startup: StartupState) {
+        self.startup = startup;
+        self.fill_startup_memory();
+    }
+
+    pub fn startup_state(&self) -> StartupState {
+        self.startup
+    }
+
+    fn fill_startup_memory(&mut self) {
+        self.startup.fill(&mut self.cpu_vram, &mut self.ppu.vram, &mut self.ppu.oam_data);
+    }
+
+    // See bus_mode.rs, permissive unless set
+    pub fn set_bus_mode(&mut self, bus_mode: BusMode) {
+        self.bus_mode = bus_mode;
+    }
+
+    // See audit.rs. Turning it on starts a fresh count
+    pub fn set_audit(&mut self, on: bool) {
+        self.audit = if on { Some(WriteAudit::new()) } else { None };
+    }
+
+    pub fn audit(&self) -> Option<&WriteAudit> {
+        self.audit.as_ref()
+    }
+
+    fn audit_write(&mut self, kind: IgnoredWrite, addr: u16) {
+        if let Some(audit) = self.audit.as_mut() {
+            audit.record(kind, addr);
+        }
+    }
+
+    // Running count of writes to $2000-$4017, used to tell a busy game from a hung one
+    pub fn register_writes(&self) -> u64 {
+        self.register_writes
+    }
+
+    pub fn bank_mapping(&self) -> BankMapping {
+        self.bank_mapping
+    }
+
+    pub fn bank_switches(&self) -> u64 {
+        self.bank_switches
+    }
+
+    // Lets the battery save tell the game changing its save from a savestate doing it
+    pub fn prg_ram_writes(&self) -> u64 {
+        self.prg_ram_writes
+    }
+
+    // Writes internal RAM (or one of its mirrors) or the cartridge's PRG RAM without
+    // going through mem_write, so nothing is counted or clocked. Returns false for any
+    // other address, and for $6000-$7FFF on carts without PRG RAM.
+    pub fn mem_poke(&mut self, addr: u16, data: u8) -> bool {
+        match addr {
+            RAM ..= RAM_MIRRORS_END => {
+                self.cpu_vram[(addr & 0b00000111_11111111) as usize] = data;
+                true
+            }
+            0x6000..=0x7FFF => {
+                let mut mapper = self.mapper.borrow_mut();
+                let Some(ram) = mapper.prg_ram_mut() else {
+                    return false;
+                };
+                let len = ram.len();
+                ram[(addr - 0x6000) as usize % len] = data;
+                true
+            }
+            _ => false,
         }
     }
 
+    // Runs the rest of the system for `cycles` CPU cycles. The cycles are run in
+    // batches that end on the next scheduled event, so every event fires with the
+    // PPU and mapper at exactly its cycle, same as ticking one cycle at a time
     pub fn tick(&mut self, cycles: usize) {
-        // println!("bus cycles: {}", self.cycles);
-        self.cycles += cycles;
+        // Refresh the entropy feed byte for roms that poll RAM for randomness
+        if let Some(entropy) = self.entropy.as_mut()
+            && let Some(addr) = entropy.feed_addr()
+        {
+            self.cpu_vram[(addr & 0b00000111_11111111) as usize] = entropy.read();
+        }
 
+        let mut remaining = cycles;
+        while remaining > 0 {
+            let step = match self.scheduler.next_at() {
+                Some(at) => {
+                    let until = at.saturating_sub(self.scheduler.now()).div_ceil(MASTER_CYCLES_PER_CPU_CYCLE);
+                    (until as usize).clamp(1, remaining)
+                }
+                None => remaining,
+            };
+            self.advance(step);
+            remaining -= step;
+        }
 
-        // Read NMI status before and after a ppu clock cycle to see
-        // if we just entered VBlank -> meaning a screen is ready to be rendered
-        let nmi_before = self.ppu.trigger_nmi;
-        for _ in 0..3 {
-            self.ppu.tick(cycles); // ppu ticks 3 times faster than CPU
+        // The instruction that requested a DMA is done, the CPU now halts for the transfer
+        if self.dma.pending() {
+            self.run_dma();
         }
-        let nmi_after = self.ppu.trigger_nmi;
+    }
 
-        // Call the gameloop function which will handle rendering other possible inputs
-        if !nmi_before && nmi_after {
+    // A single CPU cycle, for callers that step the system cycle by cycle
+    pub fn tick_cycle(&mut self) {
+        self.tick(1);
+    }
+
+    // One batch of CPU cycles with no scheduled event before its last cycle
+    fn advance(&mut self, cycles: usize) {
+        self.cycles += cycles;
+        let vblank_before = self.ppu.peek_status() & 0x80 != 0;
+        let irq_before = self.irq_line();
+        self.ppu.tick(cycles * 3); // ppu ticks 3 times faster than CPU
+        self.cart_clock(cycles);
+        self.apu.clock(cycles);
+        if self.apu.buffered() >= AUDIO_CHUNK {
+            let samples = self.apu.take_samples();
+            if let Some(audio_callback) = self.audio_callback.as_mut() {
+                audio_callback(&samples);
+            }
+        }
+        self.scheduler.advance(cycles as u64 * MASTER_CYCLES_PER_CPU_CYCLE);
+
+        while let Some(event) = self.scheduler.pop_due() {
+            match event.kind {
+                EventKind::PpuScanlineEnd => {
+                    let scanline = self.ppu.timing().scanline;
+                    let frame_done = self.ppu.end_scanline();
+                    self.cart_scanline(scanline);
+                    if cfg!(debug_assertions) {
+                        self.check_clocks(frame_done);
+                    }
+                    self.scheduler.schedule(event.at + MASTER_CYCLES_PER_SCANLINE, EventKind::PpuScanlineEnd);
+                }
+                EventKind::ApuFrameIrq => self.schedule_frame_irq(),
+                EventKind::DmcFetch => self.fetch_dmc_sample(),
+            }
+        }
+        self.log_interrupt_changes(vblank_before, irq_before);
+
+        // NMI going active at the start of vblank means a screen is ready to be rendered,
+        // call the gameloop function which will handle rendering other possible inputs
+        if self.sample_nmi_line() {
             (self.gameloop_callback)(&self.ppu, &mut self.joypad1);
         }
     }
 
-    pub fn poll_nmi_status(&mut self) -> bool {
-        let output = self.ppu.get_nmi_status();
-        if output { 
-            // println!("bus nmi poll gets true");
+    // Keeps an ApuFrameIrq event on the cycle the APU's frame IRQ will next go up
+    fn schedule_frame_irq(&mut self) {
+        self.scheduler.cancel(EventKind::ApuFrameIrq);
+        if let Some(cycles) = self.apu.cycles_until_frame_irq() {
+            self.scheduler.schedule_in(cycles as u64 * MASTER_CYCLES_PER_CPU_CYCLE, EventKind::ApuFrameIrq);
+        }
+    }
+
+    // Reads the byte the DMC is waiting for, if it is, through the mapper. The
+    // CPU is halted for it on its next read (see dma.rs).
+    fn fetch_dmc_sample(&mut self) {
+        if let Some(addr) = self.apu.dmc_fetch_address() {
+            let data = self.cart_read(addr);
+            self.apu.dmc_fill(data);
+            self.dma.request_dmc_fetch();
+        }
+        self.scheduler.cancel(EventKind::DmcFetch);
+        if let Some(cycles) = self.apu.cycles_until_dmc_fetch() {
+            self.scheduler.schedule_in(cycles as u64 * MASTER_CYCLES_PER_CPU_CYCLE, EventKind::DmcFetch);
+        }
+    }
+
+    // Debug builds: panics as soon as the CPU and PPU clocks drift apart (see desync.rs)
+    fn check_clocks(&mut self, frame_done: bool) {
+        self.desync.scanline_ended();
+        if !frame_done {
+            return;
+        }
+        let timing = self.ppu.timing();
+        let now = ClockStamp {
+            cpu_cycles: self.cycles as u64,
+            master_cycles: self.scheduler.now(),
+            frame: timing.frame,
+            scanline: timing.scanline,
+            dot: timing.dot,
+        };
+        if let Err(e) = self.desync.frame_ended(now) {
+            panic!("CPU/PPU desync: {}", e);
+        }
+    }
+
+    // Watches the PPU's NMI output for it going active, which latches an NMI for the
+    // CPU. Called whenever the output can change: vblank starting or ending, $2000
+    // writes and $2002 reads. Returns whether it just went active.
+    fn sample_nmi_line(&mut self) -> bool {
+        let line = self.ppu.nmi_line();
+        let rose = line && !self.nmi_line;
+        self.nmi_line = line;
+        if rose {
+            self.nmi_edge = true;
+            self.log_interrupt(InterruptEvent::NmiTriggered);
+        }
+        rose
+    }
+
+    fn log_interrupt_changes(&mut self, vblank_before: bool, irq_before: bool) {
+        let vblank = self.ppu.peek_status() & 0x80 != 0;
+        if vblank != vblank_before {
+            self.log_interrupt(if vblank { InterruptEvent::VblankSet } else { InterruptEvent::VblankClear });
+        }
+        let irq = self.irq_line();
+        if irq != irq_before {
+            self.log_interrupt(if irq { InterruptEvent::IrqAsserted } else { InterruptEvent::IrqCleared });
+        }
+    }
+
+    fn run_dma(&mut self) {
+        let dmc_stall = self.dma.take_dmc_stall();
+        if dmc_stall > 0 {
+            self.tick(dmc_stall);
+        }
+
+        let Some(transfer) = self.dma.take_oam(self.scheduler.now()) else {
+            return;
+        };
+
+        // Each read lands on its own cycle, so whatever the clock drives (the APU,
+        // mapper IRQ counters) sees it when the hardware would
+        self.tick(transfer.wait_cycles);
+        for i in 0..=255u8 {
+            let data = self.mem_read(transfer.source(i));
+            self.tick(1);
+            self.ppu.oam_data_write(data);
+            self.tick(1);
+        }
+    }
+
+    // Bit 2 of $4016
+    fn microphone_bit(&self) -> u8 {
+        (self.microphone as u8) << 2
+    }
+
+    // The bus's only ways into the mapper, each borrowing it for the one call (see mapper.rs)
+    fn cart_read(&self, addr: u16) -> u8 {
+        self.mapper.borrow().cpu_read(addr)
+    }
+
+    fn cart_clock(&mut self, cycles: usize) {
+        self.mapper.borrow_mut().cpu_clock(cycles);
+    }
+
+    fn cart_scanline(&mut self, scanline: u16) {
+        self.mapper.borrow_mut().on_scanline(scanline);
+    }
+
+    fn cart_irq(&self) -> bool {
+        self.mapper.borrow().irq_pending()
+    }
+
+    // A copy of the cartridge's PRG RAM, None when it has none
+    pub fn cart_prg_ram(&self) -> Option<Vec<u8>> {
+        self.mapper.borrow().prg_ram().map(|ram| ram.to_vec())
+    }
+
+    // Fills PRG RAM from `data` (a save file or a savestate), false when there's no RAM
+    pub fn load_cart_prg_ram(&mut self, data: &[u8]) -> bool {
+        let mut mapper = self.mapper.borrow_mut();
+        let Some(ram) = mapper.prg_ram_mut() else {
+            return false;
+        };
+        let len = ram.len().min(data.len());
+        ram[..len].copy_from_slice(&data[..len]);
+        true
+    }
+
+    // Just the battery backed bytes of PRG RAM, what a .sav file holds
+    pub fn cart_battery_ram(&self) -> Option<Vec<u8>> {
+        let mapper = self.mapper.borrow();
+        let ram = mapper.prg_ram()?;
+        Some(mapper.battery_ranges().into_iter().flat_map(|range| ram[range].to_vec()).collect())
+    }
+
+    // Puts a .sav file's bytes back into the battery backed ranges, false when there's no RAM
+    pub fn load_cart_battery_ram(&mut self, data: &[u8]) -> bool {
+        let mut mapper = self.mapper.borrow_mut();
+        let ranges = mapper.battery_ranges();
+        let Some(ram) = mapper.prg_ram_mut() else {
+            return false;
+        };
+        let mut data = data;
+        for range in ranges {
+            let len = range.len().min(data.len());
+            ram[range.start..range.start + len].copy_from_slice(&data[..len]);
+            data = &data[len..];
+        }
+        true
+    }
+
+    // Level triggered, the CPU takes it whenever its I flag is clear
+    pub fn poll_irq_status(&self) -> bool {
+        self.irq_line()
+    }
+
+    // The cart and the APU's frame counter share the CPU's IRQ line
+    fn irq_line(&self) -> bool {
+        self.cart_irq() || self.apu.irq()
+    }
+
+    fn cart_write(&mut self, addr: u16, data: u8) {
+        if addr < ROM_MEM_START {
+            if !self.mapper.borrow_mut().expansion_write(addr, data) {
+                self.audit_write(IgnoredWrite::Expansion, addr);
+            }
+            return;
+        }
+        if addr < 0x8000 && self.audit.is_some() && self.mapper.borrow().prg_ram().is_none() {
+            self.audit_write(IgnoredWrite::PrgRam, addr);
+        }
+        self.mapper.borrow_mut().cpu_write(addr, data);
+
+        // $8000 and up are the mapper's registers
+        if addr < 0x8000 {
+            self.prg_ram_writes += 1;
+        } else {
+            let mapping = self.mapper.borrow().bank_mapping();
+            if mapping != self.bank_mapping {
+                self.bank_mapping = mapping;
+                self.bank_switches += 1;
+            }
+        }
+    }
+
+    // What a read of a write-only PPU register (or the low bits of $2002) sees
+    fn ppu_open_bus(&self) -> u8 {
+        let age = self.ppu.timing().frame.saturating_sub(self.ppu_latch_frame);
+        if self.accuracy.contains(Accuracy::OPEN_BUS_DECAY) && age >= PPU_LATCH_DECAY_FRAMES {
+            0
+        } else {
+            self.ppu_latch
         }
-        output
+    }
+
+    fn refresh_ppu_latch(&mut self, data: u8) {
+        self.ppu_latch = data;
+        self.ppu_latch_frame = self.ppu.timing().frame;
+    }
+
+    // Unmapped addresses: whatever was last on the bus, or 0 in the fast profile
+    fn open_bus(&self) -> u8 {
+        if self.accuracy.contains(Accuracy::OPEN_BUS) { self.data_bus } else { 0 }
+    }
+
+    // Takes the NMI latched by the last rising edge, if there is one
+    pub fn poll_nmi(&mut self) -> bool {
+        let taken = std::mem::take(&mut self.nmi_edge);
+        if taken {
+            self.log_interrupt(InterruptEvent::NmiTaken);
+        }
+        taken
+    }
+}
+
+impl Snapshot for Bus<'_> {
+    // Everything the console holds, see state.rs. Starts with the rom's CRC so a
+    // state can't be loaded on another game.
+    fn save_state(&self, out: &mut StateWriter) {
+        out.u32(self.rom.crc);
+        out.bytes(&self.cpu_vram);
+        self.joypad1.save_state(out);
+        self.ppu.save_state(out);
+        self.mapper.borrow().save_state(out);
+        self.apu.save_state(out);
+        self.dma.save_state(out);
+        self.scheduler.save_state(out);
+        self.desync.save_state(out);
+        out.usize(self.cycles);
+        for counter in [self.register_writes, self.bank_switches, self.prg_ram_writes, self.ppu_latch_frame] {
+            out.u64(counter);
+        }
+        out.u8(self.data_bus);
+        out.u8(self.ppu_latch);
+        out.bool(self.nmi_line);
+        out.bool(self.nmi_edge);
+        out.optional(self.entropy.as_ref());
+        out.optional(self.rtc.as_ref());
+        out.optional(self.zapper.as_ref());
+        out.optional(self.family_keyboard.as_ref());
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        let crc = input.u32()?;
+        if crc != self.rom.crc {
+            return Err(format!("Save state is for another game (CRC {:08X}, this one is {:08X})", crc, self.rom.crc));
+        }
+        input.bytes_into(&mut self.cpu_vram)?;
+        self.joypad1.load_state(input)?;
+        self.ppu.load_state(input)?;
+        self.mapper.borrow_mut().load_state(input)?;
+        self.apu.load_state(input)?;
+        self.dma.load_state(input)?;
+        self.scheduler.load_state(input)?;
+        self.desync.load_state(input)?;
+        self.cycles = input.usize()?;
+        for counter in [&mut self.register_writes, &mut self.bank_switches, &mut self.prg_ram_writes, &mut self.ppu_latch_frame] {
+            *counter = input.u64()?;
+        }
+        self.data_bus = input.u8()?;
+        self.ppu_latch = input.u8()?;
+        self.nmi_line = input.bool()?;
+        self.nmi_edge = input.bool()?;
+        input.optional(self.entropy.as_mut(), "entropy device")?;
+        input.optional(self.rtc.as_mut(), "clock")?;
+        input.optional(self.zapper.as_mut(), "Zapper")?;
+        input.optional(self.family_keyboard.as_mut(), "Family BASIC keyboard")?;
+        self.bank_mapping = self.mapper.borrow().bank_mapping();
+        Ok(())
     }
 }
 
@@ -91,41 +729,81 @@ pub trait Mem {
 
 // 
 
-impl Mem for Bus<'_> {
-    fn mem_read(&mut self, addr: u16) -> u8 {
+impl Bus<'_> {
+    fn bus_read(&mut self, addr: u16) -> u8 {
+        let dmc_fetch = self.dma.take_dmc_fetch();
+        let open_bus = self.accuracy.contains(Accuracy::OPEN_BUS);
+
         match addr {
             RAM ..= RAM_MIRRORS_END => {
                 let mirrored_addr = addr & 0b00000111_11111111;
                 self.cpu_vram[mirrored_addr as usize]
             }
             0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 | 0x4014 => {
-                panic!("Attempt to read from write-only PPU address 0x{:04X}", addr);
+                self.bus_mode.suspicious(|| format!("read of write-only register ${:04X}", addr));
+                match addr {
+                    0x4014 => self.open_bus(),
+                    _ if open_bus => self.ppu_open_bus(),
+                    _ => 0,
+                }
+            }
+            0x2002 => {
+                let status = self.ppu.read_status();
+                let data = if open_bus { (status & 0xE0) | (self.ppu_open_bus() & 0x1F) } else { status };
+                self.log_interrupt(InterruptEvent::StatusRead(data));
+                self.sample_nmi_line(); // clearing vblank drops the NMI output
+                data
+            }
+            0x2004 | 0x2007 => {
+                let data = if addr == 0x2004 { self.ppu.oam_data_read() } else { self.ppu.read_data() };
+                self.refresh_ppu_latch(data);
+                data
             }
-            0x2002 => self.ppu.read_status(),
-            0x2004 => self.ppu.oam_data_read(),
-            0x2007 => self.ppu.read_data(),
             0x2008 ..= PPU_REGISTERS_MIRRORS_END => {
                 // Recall function with address properly mirrored
                 // println!("PPU MIRROR");
-                let mirrored_addr = addr &0b0010000_00000111;
-                self.mem_read(mirrored_addr)
-            }
-            ROM_MEM_START ..= ROM_MEM_END => {
-                self.mapper.borrow().cpu_read(addr)
+                let mirrored_addr = addr & 0b00100000_00000111;
+                self.bus_read(mirrored_addr)
             }
+            ROM_MEM_START ..= ROM_MEM_END => self.cart_read(addr),
+            0x4015 => self.apu.read_status() | (self.data_bus & 0x20), // bit 5 isn't driven
             0x4016 => {
-                self.joypad1.read()
+                // The halted cycles of a DMC fetch repeat the read, clocking the
+                // controller's shift register once more so a button bit is skipped
+                if dmc_fetch && self.accuracy.contains(Accuracy::DPCM_GLITCH) {
+                    self.joypad1.read();
+                }
+                self.joypad1.read() | self.controller_open_bus | self.microphone_bit()
             }
             0x4017 => {
-                // this is controller 2 which is not implemented yet
-                0
+                // Controller 2's port (only the Zapper so far) and the expansion port share the register
+                let zapper = match self.zapper.as_mut() {
+                    Some(zapper) => zapper.read(&self.ppu),
+                    None => 0,
+                };
+                self.controller_open_bus | zapper | self.family_keyboard.as_ref().map_or(0, |keyboard| keyboard.read())
+            }
+            ENTROPY_REGISTER => {
+                self.entropy.as_mut().map_or(0, |entropy| entropy.read())
             }
+            RTC_LATCH ..= RTC_LAST_REGISTER => match self.rtc.as_ref() {
+                Some(rtc) => rtc.read(addr),
+                None => self.open_bus(),
+            },
             _ => {
-                // println!("Attempted to read memory at unknown address 0x{:04X}", addr);
-                0
+                self.bus_mode.suspicious(|| format!("read of unmapped address ${:04X}", addr));
+                self.open_bus()
             }
         }
     }
+}
+
+impl Mem for Bus<'_> {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let data = self.bus_read(addr);
+        self.data_bus = data;
+        data
+    }
 
     fn mem_peek(&self, addr: u16) -> u8 {
         match addr {
@@ -133,57 +811,75 @@ impl Mem for Bus<'_> {
                 let mirrored_addr = addr & 0b00000111_11111111;
                 self.cpu_vram[mirrored_addr as usize]
             }
-            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 | 0x4014 => {
-                println!("Attempt to read from write-only PPU address 0x{:04X}", addr);
-                0x00
-            }
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 if self.accuracy.contains(Accuracy::OPEN_BUS) => self.ppu_open_bus(),
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 => 0,
+            0x4014 => self.open_bus(),
+            0x2002 if self.accuracy.contains(Accuracy::OPEN_BUS) => (self.ppu.peek_status() & 0xE0) | (self.ppu_open_bus() & 0x1F),
             0x2002 => self.ppu.peek_status(),
             0x2004 => self.ppu.oam_data_read(),
             0x2007 => self.ppu.peek_data(),
             0x2008 ..= PPU_REGISTERS_MIRRORS_END => {
                 // Recall function with address properly mirrored
-                let mirrored_addr = addr &0b0010000_00000111;
+                let mirrored_addr = addr & 0b00100000_00000111;
                 self.mem_peek(mirrored_addr)
             }
-            ROM_MEM_START ..= ROM_MEM_END => {
-                self.mapper.borrow().cpu_read(addr)
-            }
+            ROM_MEM_START ..= ROM_MEM_END => self.cart_read(addr),
+            0x4015 => self.apu.peek_status() | (self.data_bus & 0x20),
             0x4016 => {
-                self.joypad1.peek()
+                self.joypad1.peek() | self.controller_open_bus | self.microphone_bit()
             }
             0x4017 => {
-                // this is controller 2 which is not implemented yet
-                0
+                // Controller 2's port (only the Zapper so far) and the expansion port share the register
+                let zapper = self.zapper.as_ref().map_or(0, |zapper| zapper.peek(&self.ppu));
+                self.controller_open_bus | zapper | self.family_keyboard.as_ref().map_or(0, |keyboard| keyboard.read())
             }
-            _ => {
-                // println!("Attempted to read memory at unknown address 0x{:04X}", addr);
-                0
+            ENTROPY_REGISTER => {
+                self.entropy.as_ref().map_or(0, |entropy| entropy.peek())
             }
+            RTC_LATCH ..= RTC_LAST_REGISTER => match self.rtc.as_ref() {
+                Some(rtc) => rtc.read(addr),
+                None => self.open_bus(),
+            },
+            _ => self.open_bus(),
         }
     }
 
     fn mem_read_u16(&mut self, addr: u16) -> u16 {
         let lo = self.mem_read(addr) as u16;
-        let hi = self.mem_read(addr + 1) as u16;
+        let hi = self.mem_read(addr.wrapping_add(1)) as u16;
         (hi << 8) | lo
     }
 
     fn mem_peek_u16(&self, addr: u16) -> u16 {
         let lo = self.mem_peek(addr) as u16;
-        let hi = self.mem_peek(addr + 1) as u16;
+        let hi = self.mem_peek(addr.wrapping_add(1)) as u16;
         (hi << 8) | lo
     }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
+        // PPU register mirrors come back round as $2000-$2007 and are counted then
+        if matches!(addr, 0x2000..=0x2007 | 0x4000..=0x4017) {
+            self.register_writes += 1;
+        }
+        self.data_bus = data;
+        if (0x2000..=PPU_REGISTERS_MIRRORS_END).contains(&addr) {
+            self.refresh_ppu_latch(data);
+        }
+
         match addr {
             RAM ..= RAM_MIRRORS_END => {
                 let mirrored_addr = addr & 0b00000111_11111111;
                 self.cpu_vram[mirrored_addr as usize] = data;
             }
-            0x2000 => self.ppu.write_to_ctrl(data),
+            0x2000 => {
+                // Turning NMI on during vblank raises it straight away
+                self.ppu.write_to_ctrl(data);
+                self.sample_nmi_line();
+            }
             0x2001 => self.ppu.write_mask(data),
             0x2002 => {
-                panic!("Attempt to write to read only PPU address 0x{:04X}", addr);
+                self.bus_mode.suspicious(|| "write to read-only register $2002".to_string());
+                self.audit_write(IgnoredWrite::ReadOnly, addr);
             }
             0x2003 => self.ppu.oam_addr_write(data),
             0x2004 => self.ppu.oam_data_write(data),
@@ -191,35 +887,44 @@ impl Mem for Bus<'_> {
             0x2006 => self.ppu.write_to_ppu_addr(data),
             0x2007 => self.ppu.write_to_data(data),
             0x2008 ..= PPU_REGISTERS_MIRRORS_END => {
-                let mirrored_addr = addr &0b0010000_00000111;
+                let mirrored_addr = addr & 0b00100000_00000111;
                 self.mem_write(mirrored_addr, data);
             }
-            ROM_MEM_START ..= ROM_MEM_END => {
-                self.mapper.borrow_mut().cpu_write(addr, data);
-            }
-            0x4000 | 0x4001 | 0x4002 | 0x4003 | 0x4006 | 0x4005 | 0x4007 | 0x4004 => {
-                // APU IGNORE
-            }
-            0x4014 => {
-                let cpu_addr = (data as u16) << 8;
-                let mut data = [0u8; 256];
-
-                for i in 0..256u16 {
-                    data[i as usize] = self.mem_read(cpu_addr + i);
+            ROM_MEM_START ..= ROM_MEM_END => self.cart_write(addr, data),
+            0x4000 ..= 0x4013 | 0x4015 => {
+                if !cfg!(feature = "apu") {
+                    self.audit_write(IgnoredWrite::Sound, addr);
+                }
+                self.apu_tap.write(addr, data);
+                self.apu.write(addr, data);
+                if matches!(addr, 0x4010 ..= 0x4013 | 0x4015) {
+                    self.fetch_dmc_sample();
                 }
-                self.ppu.oam_dma_write(&data);
-
-                // to do: handle added cycles due to this action as seen on nesdev wiki for 0x4014
             }
+            0x4014 => self.dma.start_oam(data),
             0x4016 => {
                 self.joypad1.write(data);
+                if let Some(keyboard) = self.family_keyboard.as_mut() {
+                    keyboard.write(data);
+                }
             }
             0x4017 => {
-                // this is controller 2 which is not implemented yet
+                // Controller 2's strobe is the $4016 write, the Zapper in its port doesn't need one
+                if !cfg!(feature = "apu") {
+                    self.audit_write(IgnoredWrite::Sound, addr);
+                }
+                self.apu.write(addr, data);
+                self.schedule_frame_irq();
+            }
+            RTC_LATCH => {
+                if let Some(rtc) = self.rtc.as_mut() {
+                    rtc.latch(self.cycles as u64);
+                }
             }
+            0x4020 ..= 0x5FFF => self.cart_write(addr, data),
             _ => {
-                // println!("Attempted to write memory at unknown address 0x{:04X}", addr);
-                // println!("^^ Above message is likely due to the lack of APU")
+                self.bus_mode.suspicious(|| format!("write of ${:02X} to unmapped address ${:04X}", data, addr));
+                self.audit_write(IgnoredWrite::Unmapped, addr);
             }
         }
     }
@@ -229,7 +934,7 @@ impl Mem for Bus<'_> {
         let lo = (data & 0x00ff) as u8;
 
         self.mem_write(addr, lo);
-        self.mem_write(addr + 1, hi);
+        self.mem_write(addr.wrapping_add(1), hi);
     }
 }
 
@@ -237,11 +942,269 @@ impl Mem for Bus<'_> {
 mod test {
     use super::*;
     use crate::rom::{test};
+    use crate::joypad::JoypadButton;
+    use crate::rtc::RtcSource;
+    use crate::dma::DMC_DMA_CYCLES;
 
     #[test]
     fn test_mem_read_write_to_ram() {
-        let mut bus = Bus::new(test::_test_rom(), |_ppu, _joypad1| {});
+        let mut bus = Bus::new(test::_test_rom(), |_ppu, _joypad1| {}).unwrap();
         bus.mem_write(0x01, 0x55);
         assert_eq!(bus.mem_read(0x01), 0x55);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_entropy_register_is_seeded() {
+        let mut bus_a = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        let mut bus_b = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        bus_a.attach_entropy(EntropyDevice::new(Some(99), None));
+        bus_b.attach_entropy(EntropyDevice::new(Some(99), None));
+
+        for _ in 0..16 {
+            let value = bus_a.mem_read(ENTROPY_REGISTER);
+            assert_eq!(value, bus_b.mem_read(ENTROPY_REGISTER));
+            assert_eq!(bus_a.mem_peek(ENTROPY_REGISTER), value);
+        }
+    }
+
+    #[test]
+    fn test_rtc_registers() {
+        let mut bus = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        bus.mem_write(0x0010, 0x77);
+        assert_eq!(bus.mem_read(RTC_LATCH), 0x77); // nothing attached, open bus
+
+        bus.attach_rtc(RtcDevice::new(RtcSource::Emulated { start: 1709251199 }));
+        bus.mem_write(RTC_LATCH, 0);
+        assert_eq!(bus.mem_read(0x401B), 23);
+        assert_eq!(bus.mem_peek(0x401E), 24);
+    }
+
+    // Strobes the controller and reads all 8 buttons, with a DMC fetch landing on read `fetch_on`
+    fn read_buttons_with_fetch(bus: &mut Bus, fetch_on: usize) -> u8 {
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        let mut bits = 0;
+        for i in 0..8 {
+            if i == fetch_on {
+                bus.dma_mut().request_dmc_fetch();
+            }
+            bits |= (bus.mem_read(0x4016) & 1) << i;
+        }
+        bits
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut bus = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        bus.mem_write(0x0010, 0x5A);
+        assert_eq!(bus.mem_read(0x0010), 0x5A);
+        assert_eq!(bus.mem_read(0x5000), 0x5A); // unmapped, last byte on the bus
+
+        // Write-only PPU registers and the low bits of $2002 read the register latch
+        bus.mem_write(0x2001, 0x1E);
+        assert_eq!(bus.mem_read(0x2003), 0x1E);
+        assert_eq!(bus.mem_read(0x2002) & 0x1F, 0x1E);
+
+        bus.set_accuracy(AccuracyProfile::Fast.flags());
+        assert_eq!(bus.mem_read(0x5000), 0);
+
+        // The latch fades once nothing has refreshed it for a while
+        bus.set_accuracy(AccuracyProfile::Accurate.flags());
+        assert_eq!(bus.mem_peek(0x2005), 0x1E);
+        while bus.ppu.timing().frame < PPU_LATCH_DECAY_FRAMES {
+            bus.tick(1000);
+        }
+        assert_eq!(bus.mem_peek(0x2005), 0);
+    }
+
+    #[test]
+    fn test_permissive_bus_ignores_suspicious_accesses() {
+        let mut bus = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        bus.set_accuracy(AccuracyProfile::Fast.flags());
+        assert_eq!(bus.mem_read(0x2000), 0);
+        assert_eq!(bus.mem_read(0x4014), 0);
+        bus.mem_write(0x2002, 0xFF);
+        bus.mem_write(0x401F, 0xFF);
+        bus.mem_write(0x8000, 0xFF); // NROM's PRG ROM
+    }
+
+    #[test]
+    #[should_panic(expected = "Strict bus: read of write-only register $2005")]
+    fn test_strict_bus_stops_on_suspicious_accesses() {
+        let mut bus = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        bus.set_bus_mode(BusMode::Strict);
+        assert_eq!(bus.mem_peek(0x2005), 0); // peeks are never suspicious
+        bus.mem_read(0x2005);
+    }
+
+    #[test]
+    fn test_audit_counts_ignored_writes() {
+        let mut bus = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        bus.mem_write(0x2002, 0xFF); // before the audit starts
+        bus.set_audit(true);
+        bus.mem_write(0x2002, 0xFF);
+        bus.mem_write(0x401F, 0xFF);
+        bus.mem_write(0x401F, 0xFF);
+        bus.mem_write(0x5000, 0xFF);
+        bus.mem_write(0x6000, 0xFF); // NROM has PRG RAM
+        bus.mem_write(0x0000, 0xFF);
+
+        let audit = bus.audit().unwrap();
+        assert_eq!(audit.count(IgnoredWrite::ReadOnly), 1);
+        assert_eq!(audit.count(IgnoredWrite::Unmapped), 2);
+        assert_eq!(audit.count(IgnoredWrite::Expansion), 1);
+        assert_eq!(audit.count(IgnoredWrite::PrgRam), 0);
+        let summary = audit.summary();
+        assert!(summary.contains("4 writes ignored"), "{}", summary);
+        assert!(summary.contains("$401F x2"), "{}", summary);
+    }
+
+    #[test]
+    fn test_audit_skips_expansion_registers_the_board_has() {
+        let mut rom = Rom::_new_test(vec![]).unwrap();
+        rom.mapper_id = 28; // Action 53, register select at $5000-$5FFF
+        let mut bus = Bus::new(rom, |_ppu, _joypad1| {}).unwrap();
+        bus.set_audit(true);
+        bus.mem_write(0x5000, 0x81);
+        bus.mem_write(0x4800, 0xFF);
+        assert_eq!(bus.audit().unwrap().count(IgnoredWrite::Expansion), 1);
+    }
+
+    #[test]
+    fn test_mirrored_register_writes_count_once() {
+        let mut bus = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        bus.mem_write(0x2001, 0);
+        bus.mem_write(0x3FF9, 0); // $2001 again
+        bus.mem_write(0x4015, 0);
+        assert_eq!(bus.register_writes(), 3);
+    }
+
+    #[test]
+    fn test_interrupt_log_orders_events() {
+        let mut bus = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        while bus.ppu.peek_status() & 0x80 == 0 {
+            bus.tick(1);
+        }
+        // NMI turned on during vblank is raised by the write
+        bus.mem_write(0x2000, 0x80);
+        bus.mem_read(0x2002);
+        assert!(bus.poll_nmi());
+
+        let events: Vec<InterruptEvent> = bus.interrupt_log().events().map(|logged| logged.event).collect();
+        assert_eq!(events, vec![
+            InterruptEvent::VblankSet,
+            InterruptEvent::NmiTriggered,
+            InterruptEvent::StatusRead(0x80),
+            InterruptEvent::NmiTaken,
+        ]);
+        assert_eq!(bus.interrupt_log().events().next().unwrap().scanline, 241);
+    }
+
+    #[test]
+    fn test_batched_ticks_match_single_cycles() {
+        let mut batched = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        let mut stepped = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        batched.mem_write(0x2000, 0x80);
+        stepped.mem_write(0x2000, 0x80);
+
+        // Vblank and the NMI land mid-batch but are logged on their own cycle
+        batched.tick(40000);
+        for _ in 0..40000 {
+            stepped.tick_cycle();
+        }
+        let batched_events: Vec<LoggedInterrupt> = batched.interrupt_log().events().copied().collect();
+        let stepped_events: Vec<LoggedInterrupt> = stepped.interrupt_log().events().copied().collect();
+        assert!(!batched_events.is_empty());
+        assert_eq!(batched_events, stepped_events);
+        assert_eq!(batched.ppu.timing(), stepped.ppu.timing());
+    }
+
+    #[test]
+    fn test_nmi_fires_on_edges() {
+        let mut bus = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        bus.mem_write(0x2000, 0x80);
+        while bus.ppu.peek_status() & 0x80 == 0 {
+            bus.tick(1);
+        }
+        assert!(bus.poll_nmi());
+
+        // Staying in vblank with NMI on doesn't fire again
+        bus.tick(100);
+        assert!(!bus.poll_nmi());
+
+        // Turning NMI off and on again during vblank is a new edge
+        bus.mem_write(0x2000, 0x00);
+        bus.mem_write(0x2000, 0x80);
+        assert!(bus.poll_nmi());
+
+        // After $2002 clears vblank, enabling NMI again has nothing to fire on
+        bus.mem_write(0x2000, 0x00);
+        bus.mem_read(0x2002);
+        bus.mem_write(0x2000, 0x80);
+        assert!(!bus.poll_nmi());
+    }
+
+    #[test]
+    fn test_battery_ram_round_trip() {
+        let mut bus = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        bus.mem_write(0x6000, 0x12);
+        bus.mem_write(0x7FFF, 0x34);
+        let save = bus.cart_battery_ram().unwrap();
+        assert_eq!((save.len(), save[0], save[0x1FFF]), (0x2000, 0x12, 0x34));
+
+        let mut other = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        assert!(other.load_cart_battery_ram(&save));
+        assert_eq!(other.mem_read(0x7FFF), 0x34);
+    }
+
+    #[test]
+    fn test_dpcm_glitch_deletes_a_bit() {
+        let mut bus = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        bus.joypad1.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+        bus.joypad1.set_button_pressed_status(JoypadButton::START, true);
+
+        // Default: the fetch has no effect on the read
+        assert_eq!(read_buttons_with_fetch(&mut bus, 2), 0b0000_1001);
+
+        // Glitch: bit 2 is skipped, so START shifts down and the last read is past the end (1)
+        bus.set_dpcm_glitch(true);
+        assert_eq!(read_buttons_with_fetch(&mut bus, 2), 0b1000_0101);
+    }
+
+    #[test]
+    fn test_dmc_fetches_samples_and_raises_irq() {
+        // 17 bytes of sample at $C000 (the 16kB bank is mirrored there)
+        let mut bus = Bus::new(Rom::_new_test(vec![0xFF; 17]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        bus.mem_write(0x4017, 0x40); // no frame IRQ
+        bus.mem_write(0x4010, 0x8F); // IRQ on, 54 cycles a bit
+        bus.mem_write(0x4012, 0x00);
+        bus.mem_write(0x4013, 0x01);
+        bus.mem_write(0x4015, 0x10);
+
+        // The first byte is fetched straight away and halts the CPU's next read
+        assert!(bus.mem_read(0x0000) == 0 && bus.dma().pending());
+        bus.tick(1);
+        assert_eq!(bus.dma().stalled_cycles(), DMC_DMA_CYCLES as u64);
+
+        // A byte every 8 * 54 cycles after the first bits go out, the IRQ comes with the last
+        bus.tick(6000);
+        assert_eq!(bus.mem_peek(0x4015) & 0x90, 0x10);
+        assert!(!bus.poll_irq_status());
+        bus.tick(2000);
+        assert_eq!(bus.mem_peek(0x4015) & 0x90, 0x80);
+        assert!(bus.poll_irq_status());
+
+        // Writing $4015 acknowledges it
+        bus.mem_write(0x4015, 0x00);
+        assert!(!bus.poll_irq_status());
+    }
+
+    #[test]
+    fn test_microphone_bit() {
+        let mut bus = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        assert_eq!(bus.mem_read(0x4016) & 0x04, 0);
+        bus.set_microphone(true);
+        assert_eq!(bus.mem_read(0x4016) & 0x04, 0x04);
+        assert_eq!(bus.mem_peek(0x4016) & 0x04, 0x04);
+    }
+}
@@ -0,0 +1,31 @@
+// What the bus does with accesses a game shouldn't make: reading a write-only
+// register, writing $2002, touching an address nothing answers to.
+//
+//   permissive  what the hardware does: open bus for reads, writes are dropped.
+//               For playing games, some of which do these things by accident.
+//   strict      stops the emulator with a message naming the address, for
+//               working on the emulator, where they usually mean a CPU bug
+//
+// Peeks (debuggers, cheats, the bug report) never count as suspicious.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusMode {
+    Permissive,
+    Strict,
+}
+
+impl BusMode {
+    pub fn parse(value: &str) -> Result<BusMode, String> {
+        match value {
+            "permissive" => Ok(BusMode::Permissive),
+            "strict" => Ok(BusMode::Strict),
+            _ => Err(format!("Bus mode must be permissive or strict but got \"{}\"", value)),
+        }
+    }
+
+    // Called with a description of each suspicious access
+    pub fn suspicious(&self, what: impl FnOnce() -> String) {
+        if *self == BusMode::Strict {
+            panic!("Strict bus: {}", what());
+        }
+    }
+}
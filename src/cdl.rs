@@ -0,0 +1,243 @@
+use std::fmt::Write;
+
+use crate::banks::BankMapping;
+use crate::cpu::{AddressingMode, CPU, OPCODE_TABLE};
+
+// Code/data logger: marks which bytes of PRG ROM the game ran as code or read as
+// data, and which CHR tiles it put on screen, over a play session. Addresses go
+// through the live bank mapping (see banks.rs), so every bank of a bank switched
+// cart is logged separately.
+//
+//   log_instruction   before every instruction: the instruction's bytes are code,
+//                     and a read from $8000-$FFFF it's about to make is data
+//   log_frame         once a frame: the tiles the nametables and sprites use
+//
+// Data reads are worked out from the addressing mode before the instruction
+// runs, so reads the CPU makes on its own (vectors, DMA) aren't counted.
+//
+// report() sums it up per bank and lists the regions nothing touched, for rom
+// hackers looking for free space and for checking tests reach every bank.
+// to_fceux_cdl() writes the same marks in FCEUX's .cdl format.
+pub const CODE: u8 = 0b01;
+pub const DATA: u8 = 0b10;
+
+const PRG_REPORT_BANK: usize = 0x4000;
+const CHR_REPORT_BANK: usize = 0x1000;
+const TILE_SIZE: usize = 16;
+const MIN_UNUSED_REGION: usize = 256; // smaller gaps aren't worth listing
+
+// Instructions whose operand address isn't read as data
+const NOT_READS: [&str; 10] = ["STA", "STX", "STY", "*SAX", "*SHA", "*SHX", "*SHY", "*TAS", "JMP", "JSR"];
+
+pub struct CodeDataLog {
+    prg: Vec<u8>,
+    chr_tiles: Vec<bool>,
+}
+
+impl CodeDataLog {
+    pub fn new(prg_len: usize, chr_len: usize) -> Self {
+        CodeDataLog { prg: vec![0; prg_len], chr_tiles: vec![false; chr_len / TILE_SIZE] }
+    }
+
+    pub fn log_instruction(&mut self, cpu: &CPU) {
+        let mapping = cpu.bus.bank_mapping();
+        let Some(opcode) = OPCODE_TABLE.get(&cpu.mem_peek(cpu.pc)) else {
+            return;
+        };
+        for i in 0..opcode.bytes as u16 {
+            self.mark_prg(&mapping, cpu.pc.wrapping_add(i), CODE);
+        }
+
+        let operand = cpu.pc.wrapping_add(1);
+        let target = match opcode.addressing_mode {
+            // JMP ($xxxx) reads its pointer
+            AddressingMode::Indirect => Some(cpu.mem_peek_u16(operand)),
+            _ if NOT_READS.contains(&opcode.code) => None,
+            AddressingMode::Absolute => Some(cpu.mem_peek_u16(operand)),
+            AddressingMode::Absolute_X => Some(cpu.mem_peek_u16(operand).wrapping_add(cpu.reg_x as u16)),
+            AddressingMode::Absolute_Y => Some(cpu.mem_peek_u16(operand).wrapping_add(cpu.reg_y as u16)),
+            AddressingMode::Indirect_Y => {
+                let pointer = cpu.mem_peek(operand);
+                let base = u16::from_le_bytes([cpu.mem_peek(pointer as u16), cpu.mem_peek(pointer.wrapping_add(1) as u16)]);
+                Some(base.wrapping_add(cpu.reg_y as u16))
+            }
+            AddressingMode::Indirect_X => {
+                let pointer = cpu.mem_peek(operand).wrapping_add(cpu.reg_x);
+                Some(u16::from_le_bytes([cpu.mem_peek(pointer as u16), cpu.mem_peek(pointer.wrapping_add(1) as u16)]))
+            }
+            _ => None,
+        };
+        if let Some(target) = target {
+            self.mark_prg(&mapping, target, DATA);
+            if matches!(opcode.addressing_mode, AddressingMode::Indirect) {
+                self.mark_prg(&mapping, target.wrapping_add(1), DATA);
+            }
+        }
+    }
+
+    pub fn log_frame(&mut self, cpu: &CPU) {
+        let ppu = &cpu.bus.ppu;
+        let mapping = cpu.bus.bank_mapping();
+        let mask = ppu.peek_mask();
+
+        if mask & 0x08 != 0 {
+            let bank = ppu.ctrl.get_background_bank_val();
+            for table in [0, 0x400] {
+                for &tile in &ppu.vram[table..table + 0x3C0] {
+                    self.mark_tile(&mapping, bank + tile as u16 * TILE_SIZE as u16);
+                }
+            }
+        }
+
+        if mask & 0x10 != 0 {
+            for sprite in ppu.oam_data.chunks(4) {
+                if sprite[0] >= 0xEF {
+                    continue; // off the bottom, how games hide sprites
+                }
+                let tile = sprite[1] as u16;
+                if ppu.ctrl.is_sprite_size() {
+                    let bank = (tile & 1) * 0x1000;
+                    self.mark_tile(&mapping, bank + (tile & 0xFE) * TILE_SIZE as u16);
+                    self.mark_tile(&mapping, bank + (tile | 1) * TILE_SIZE as u16);
+                } else {
+                    let bank = if ppu.ctrl.is_sprite_pattern_addr() { 0x1000 } else { 0 };
+                    self.mark_tile(&mapping, bank + tile * TILE_SIZE as u16);
+                }
+            }
+        }
+    }
+
+    fn mark_prg(&mut self, mapping: &BankMapping, addr: u16, kind: u8) {
+        if addr < 0x8000 || self.prg.is_empty() {
+            return;
+        }
+        let window = (addr - 0x8000) as usize / 0x2000;
+        let offset = (mapping.prg[window] + (addr & 0x1FFF) as usize) % self.prg.len();
+        self.prg[offset] |= kind;
+    }
+
+    fn mark_tile(&mut self, mapping: &BankMapping, addr: u16) {
+        if self.chr_tiles.is_empty() {
+            return;
+        }
+        let offset = mapping.chr[(addr as usize / 0x1000) & 1] + (addr & 0x0FFF) as usize;
+        let tile = (offset / TILE_SIZE) % self.chr_tiles.len();
+        self.chr_tiles[tile] = true;
+    }
+
+    pub fn prg_marks(&self, offset: usize) -> u8 {
+        self.prg[offset]
+    }
+
+    pub fn tile_displayed(&self, tile: usize) -> bool {
+        self.chr_tiles[tile]
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let percent = |count: usize, total: usize| 100.0 * count as f64 / total.max(1) as f64;
+
+        let _ = writeln!(out, "PRG ROM: {} KB", self.prg.len() / 1024);
+        let _ = writeln!(out, "  bank  offset   code    data    unused");
+        for (bank, bytes) in self.prg.chunks(PRG_REPORT_BANK).enumerate() {
+            let code = bytes.iter().filter(|&&marks| marks & CODE != 0).count();
+            let data = bytes.iter().filter(|&&marks| marks == DATA).count();
+            let unused = bytes.iter().filter(|&&marks| marks == 0).count();
+            let _ = writeln!(
+                out,
+                "  {:>4}  {:06X}  {:5.1}%  {:5.1}%  {:5.1}%",
+                bank,
+                bank * PRG_REPORT_BANK,
+                percent(code, bytes.len()),
+                percent(data, bytes.len()),
+                percent(unused, bytes.len())
+            );
+        }
+        let _ = writeln!(out, "  unreferenced regions:");
+        for (start, end) in unused_regions(self.prg.iter().map(|&marks| marks == 0), MIN_UNUSED_REGION) {
+            let _ = writeln!(out, "    {:06X}-{:06X} ({} bytes)", start, end - 1, end - start);
+        }
+
+        let tiles_per_bank = CHR_REPORT_BANK / TILE_SIZE;
+        let displayed = self.chr_tiles.iter().filter(|&&shown| shown).count();
+        let _ = writeln!(out, "CHR: {} of {} tiles displayed", displayed, self.chr_tiles.len());
+        let _ = writeln!(out, "  bank  offset   displayed");
+        for (bank, tiles) in self.chr_tiles.chunks(tiles_per_bank).enumerate() {
+            let shown = tiles.iter().filter(|&&shown| shown).count();
+            let _ = writeln!(out, "  {:>4}  {:06X}  {:3} of {}", bank, bank * CHR_REPORT_BANK, shown, tiles.len());
+        }
+        let _ = writeln!(out, "  tiles never displayed:");
+        for (start, end) in unused_regions(self.chr_tiles.iter().map(|&shown| !shown), 1) {
+            let _ = writeln!(out, "    {:06X}-{:06X} (tiles {}-{})", start * TILE_SIZE, end * TILE_SIZE - 1, start, end - 1);
+        }
+        out
+    }
+
+    // FCEUX's layout: one byte per PRG ROM byte (bit 0 code, bit 1 data), then one
+    // per CHR byte (bit 0 drawn), so the log works with tools that read those
+    pub fn to_fceux_cdl(&self) -> Vec<u8> {
+        let mut out = self.prg.clone();
+        for &shown in &self.chr_tiles {
+            out.extend([if shown { 0x01 } else { 0x00 }; TILE_SIZE]);
+        }
+        out
+    }
+}
+
+// Runs of true at least `min` long, as start..end
+fn unused_regions(unused: impl Iterator<Item = bool>, min: usize) -> Vec<(usize, usize)> {
+    let mut regions = vec![];
+    let mut start = None;
+    let mut len = 0;
+    for (i, is_unused) in unused.enumerate() {
+        len = i + 1;
+        match (is_unused, start) {
+            (true, None) => start = Some(i),
+            (false, Some(from)) => {
+                if i - from >= min {
+                    regions.push((from, i));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(from) = start
+        && len - from >= min
+    {
+        regions.push((from, len));
+    }
+    regions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::rom::Rom;
+
+    #[test]
+    fn test_code_and_data_are_logged() {
+        let program = vec![
+            0xAD, 0x00, 0x90, // LDA $9000
+            0x8D, 0x00, 0x02, // STA $0200
+            0x4C, 0x06, 0x80, // JMP $8006
+        ];
+        let mut nes = Nes::new(Rom::_new_test(program).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.pc = 0x8000;
+        let mut cdl = CodeDataLog::new(0x4000, 0x2000);
+        for _ in 0..10 {
+            cdl.log_instruction(&nes.cpu);
+            nes.step();
+        }
+
+        assert!((0..9).all(|offset| cdl.prg_marks(offset) == CODE));
+        assert_eq!(cdl.prg_marks(9), 0);
+        assert_eq!(cdl.prg_marks(0x1000), DATA);
+
+        let report = cdl.report();
+        assert!(report.contains("000009-000FFF"), "{}", report);
+        assert!(report.contains("001001-003FFF"), "{}", report);
+        assert_eq!(cdl.to_fceux_cdl().len(), 0x4000 + 0x2000);
+    }
+}
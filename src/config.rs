@@ -0,0 +1,277 @@
+use std::path::Path;
+
+use crate::frameskip::FrameSkipMode;
+use crate::accuracy::{Accuracy, AccuracyProfile};
+use crate::bus_mode::BusMode;
+use crate::practice::PracticeMode;
+use crate::ntsc_palette::{NtscSettings, PaletteSource};
+use crate::quirks::Quirks;
+use crate::sync::SyncSettings;
+use crate::timestretch::SpeedAudioMode;
+use crate::vsync::VsyncMode;
+use crate::display::DisplayTransform;
+use crate::focus::BackgroundMode;
+use crate::audio_cues::ApuChannels;
+use crate::input_device::InputMode;
+use crate::microphone::MicSource;
+use crate::strings::DEFAULT_LOCALE;
+use crate::startup::StartupState;
+
+// User settings loaded from a plain "key = value" text file.
+// Lines starting with '#' are comments. Missing keys keep their defaults and
+// command line flags are applied on top of whatever the file sets.
+//
+// Example emu.cfg:
+//   # apply game.ips / game.bps found next to the rom
+//   auto_patch = true
+//   # off, auto, or skip drawing N out of M frames (e.g. 1/2)
+//   frame_skip = auto
+//   # on, off (frame limiter only), adaptive or paced (vsync + frame limiter)
+//   vsync = on
+//   # turn the picture for cabinets: rotate 0/90/180/270, flip none/horizontal/vertical/both
+//   rotate = 0
+//   flip = none
+//   # nudge the audio rate by up to +-0.5% to keep audio and video in sync
+//   dynamic_rate = on
+//   dynamic_rate_max_delta = 0.005
+//   # stretch keeps the pitch in slow motion / fast forward, pitch lets it follow the speed
+//   speed_audio = stretch
+//   # when the window loses focus: run, pause, or throttle to 10% speed
+//   background = run
+//   # who drives controller 1: auto (last used keyboard or gamepad), keyboard or gamepad
+//   input = auto
+//   # language of on screen messages, anything but en is read from lang/LOCALE.txt
+//   locale = en
+//   # flash a badge when these sound channels start a note: off, all, or a list like noise,dmc
+//   audio_cues = off
+//   # plug the Family BASIC keyboard into the expansion port, typing on it with the host keyboard
+//   family_keyboard = off
+//   # plug the Zapper light gun into controller port 2, aimed and fired with the mouse
+//   zapper = off
+//   # the Famicom's controller 2 microphone: off, key (hold microphone_key) or capture
+//   # (the host microphone, louder than microphone_threshold from 0.0 to 1.0)
+//   microphone = off
+//   microphone_key = M
+//   microphone_threshold = 0.2
+//   # warn when the game seems to have hung
+//   watchdog = on
+//   # fast, balanced or accurate, see accuracy.rs
+//   accuracy = balanced
+//   # lose controller bits to DMC fetches like real hardware does (accurate turns it on)
+//   dpcm_glitch = off
+//   # permissive, or strict to stop on accesses a game shouldn't make, see bus_mode.rs
+//   bus_mode = permissive
+//   # off, on (F1 marks a checkpoint, F2 goes back to it) or input to show buttons too
+//   practice = off
+//   # count writes the emulator ignores and sum them up on exit, see audit.rs
+//   audit = off
+//   # what RAM, VRAM and OAM hold at power on: zero, pattern, random or random:SEED
+//   startup = zero
+//   # auto uses the built-in table, or none / a list like four_screen,joypad_open_bus
+//   quirks = auto
+//   # builtin, or ntsc to work the colors out from the video signal with these knobs
+//   palette = builtin
+//   ntsc_hue = 0
+//   ntsc_saturation = 1.0
+//   ntsc_contrast = 1.0
+//   ntsc_brightness = 0.0
+//   # DIP switches on carts that have them (0-15), the NWC timer runs 5:00 + 18.75s per step
+//   dip_switches = 0
+
+pub const DEFAULT_CONFIG_PATH: &str = "emu.cfg";
+
+pub struct Config {
+    pub auto_patch: bool,
+    pub frame_skip: FrameSkipMode,
+    pub vsync: VsyncMode,
+    pub display: DisplayTransform,
+    pub sync: SyncSettings,
+    pub speed_audio: SpeedAudioMode,
+    pub background: BackgroundMode,
+    pub input: InputMode,
+    pub locale: String,
+    pub audio_cues: ApuChannels,
+    pub family_keyboard: bool,
+    pub zapper: bool,
+    pub microphone: MicSource,
+    pub microphone_key: String, // SDL key name
+    pub microphone_threshold: f32,
+    pub watchdog: bool,
+    pub quirks: Option<Quirks>, // None looks the rom up in the quirks table
+    pub accuracy: AccuracyProfile,
+    pub dpcm_glitch: Option<bool>, // None leaves it to the accuracy profile
+    pub bus_mode: BusMode,
+    pub audit: bool,
+    pub practice: PracticeMode,
+    pub startup: StartupState,
+    pub dip_switches: u8,
+    pub palette: PaletteSource,
+    pub ntsc: NtscSettings,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            auto_patch: true,
+            frame_skip: FrameSkipMode::Off,
+            vsync: VsyncMode::On,
+            display: DisplayTransform::default(),
+            sync: SyncSettings::default(),
+            speed_audio: SpeedAudioMode::Stretch,
+            background: BackgroundMode::Run,
+            input: InputMode::Auto,
+            locale: DEFAULT_LOCALE.to_string(),
+            audio_cues: ApuChannels::empty(),
+            family_keyboard: false,
+            zapper: false,
+            microphone: MicSource::Off,
+            microphone_key: "M".to_string(),
+            microphone_threshold: 0.2,
+            watchdog: true,
+            quirks: None,
+            accuracy: AccuracyProfile::Balanced,
+            dpcm_glitch: None,
+            bus_mode: BusMode::Permissive,
+            audit: false,
+            practice: PracticeMode::Off,
+            startup: StartupState::Zeroed,
+            dip_switches: 0,
+            palette: PaletteSource::Builtin,
+            ntsc: NtscSettings::default(),
+        }
+    }
+}
+
+fn parse_number(key: &str, value: &str, range: std::ops::RangeInclusive<f32>) -> Result<f32, String> {
+    value.parse().ok().filter(|value| range.contains(value))
+        .ok_or(format!("\"{}\" must be a number from {} to {} but got \"{}\"", key, range.start(), range.end(), value))
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "on" | "yes" | "1" => Ok(true),
+        "false" | "off" | "no" | "0" => Ok(false),
+        _ => Err(format!("Expected true/false for \"{}\" but got \"{}\"", key, value)),
+    }
+}
+
+impl Config {
+    // The accuracy profile's flags with any settings that override it
+    pub fn accuracy_flags(&self) -> Accuracy {
+        let mut flags = self.accuracy.flags();
+        if let Some(dpcm_glitch) = self.dpcm_glitch {
+            flags.set(Accuracy::DPCM_GLITCH, dpcm_glitch);
+        }
+        flags
+    }
+
+    // Loads the config file, falling back to defaults when it doesn't exist
+    pub fn load(path: &Path) -> Result<Config, String> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read config {}: {}", path.display(), e))?;
+        Config::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(format!("Config line {} is not in \"key = value\" form", line_num + 1))?;
+            config.set(key.trim(), value.trim())?;
+        }
+
+        Ok(config)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "auto_patch" => self.auto_patch = parse_bool(key, value)?,
+            "frame_skip" => self.frame_skip = FrameSkipMode::parse(value)?,
+            "vsync" => self.vsync = VsyncMode::parse(value)?,
+            "rotate" => self.display.rotation = DisplayTransform::parse_rotation(value)?,
+            "flip" => self.display.set_flip(value)?,
+            "audio_cues" => self.audio_cues = ApuChannels::parse(value)?,
+            "family_keyboard" => self.family_keyboard = parse_bool(key, value)?,
+            "zapper" => self.zapper = parse_bool(key, value)?,
+            "microphone" => self.microphone = MicSource::parse(value)?,
+            "microphone_key" => self.microphone_key = value.to_string(),
+            "microphone_threshold" => self.microphone_threshold = parse_number(key, value, 0.0..=1.0)?,
+            "watchdog" => self.watchdog = parse_bool(key, value)?,
+            "accuracy" => self.accuracy = AccuracyProfile::parse(value)?,
+            "dpcm_glitch" => self.dpcm_glitch = Some(parse_bool(key, value)?),
+            "bus_mode" => self.bus_mode = BusMode::parse(value)?,
+            "audit" => self.audit = parse_bool(key, value)?,
+            "practice" => self.practice = PracticeMode::parse(value)?,
+            "startup" => self.startup = StartupState::parse(value)?,
+            "quirks" => self.quirks = if value == "auto" { None } else { Some(Quirks::parse(value)?) },
+            "dip_switches" => {
+                self.dip_switches = value.parse().ok().filter(|&value| value <= 15)
+                    .ok_or(format!("\"{}\" must be a number from 0 to 15 but got \"{}\"", key, value))?;
+            }
+            "palette" => self.palette = PaletteSource::parse(value)?,
+            "ntsc_hue" => self.ntsc.hue = parse_number(key, value, -180.0..=180.0)?,
+            "ntsc_saturation" => self.ntsc.saturation = parse_number(key, value, 0.0..=3.0)?,
+            "ntsc_contrast" => self.ntsc.contrast = parse_number(key, value, 0.0..=3.0)?,
+            "ntsc_brightness" => self.ntsc.brightness = parse_number(key, value, -1.0..=1.0)?,
+            "dynamic_rate" => self.sync.dynamic_rate = parse_bool(key, value)?,
+            "speed_audio" => self.speed_audio = SpeedAudioMode::parse(value)?,
+            "background" => self.background = BackgroundMode::parse(value)?,
+            "input" => self.input = InputMode::parse(value)?,
+            "locale" => self.locale = value.to_string(),
+            "dynamic_rate_max_delta" => {
+                let delta: f64 = value.parse().map_err(|_| format!("Expected a number for \"{}\" but got \"{}\"", key, value))?;
+                if !(0.0..0.05).contains(&delta) {
+                    return Err(format!("\"{}\" must be between 0 and 0.05", key));
+                }
+                self.sync.max_delta = delta;
+            }
+            _ => println!("Ignoring unknown config key \"{}\"", key),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_overrides_defaults() {
+        let config = Config::parse("# comment\n\nauto_patch = off\nframe_skip = 1/2\n").unwrap();
+        assert!(!config.auto_patch);
+        assert_eq!(config.frame_skip, FrameSkipMode::Fixed { skip: 1, out_of: 2 });
+        assert_eq!(config.quirks, None);
+
+        let config = Config::parse("quirks = four_screen\nvsync = paced").unwrap();
+        assert_eq!(config.quirks, Some(Quirks::FOUR_SCREEN));
+        assert_eq!(config.vsync, VsyncMode::Paced);
+        assert_eq!(Config::parse("background = pause").unwrap().background, BackgroundMode::Pause);
+        assert_eq!(Config::parse("input = gamepad").unwrap().input, InputMode::Gamepad);
+        assert_eq!(Config::parse("audio_cues = noise,dmc").unwrap().audio_cues, ApuChannels::NOISE | ApuChannels::DMC);
+
+        let config = Config::parse("accuracy = accurate\ndpcm_glitch = off").unwrap();
+        assert_eq!(config.accuracy_flags(), Accuracy::all() - Accuracy::DPCM_GLITCH);
+
+        let config = Config::parse("palette = ntsc\nntsc_hue = -15\nntsc_saturation = 1.2").unwrap();
+        assert_eq!(config.palette, PaletteSource::Ntsc);
+        assert_eq!(config.ntsc, NtscSettings { hue: -15.0, saturation: 1.2, ..NtscSettings::default() });
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_lines() {
+        assert!(Config::parse("auto_patch").is_err());
+        assert!(Config::parse("auto_patch = maybe").is_err());
+        assert!(Config::parse("dynamic_rate_max_delta = 0.5").is_err());
+        assert!(Config::parse("ntsc_saturation = lots").is_err());
+        assert!(Config::parse("ntsc_hue = 270").is_err());
+    }
+}
@@ -1,7 +1,10 @@
 use once_cell::sync::Lazy;
 use std::collections::{HashMap, HashSet};
 
+use crate::accuracy::Accuracy;
 use crate::bus::{Bus, Mem};
+use crate::interrupt_log::InterruptEvent;
+use crate::state::{Snapshot, StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
 
 pub struct CPU<'a> {
     // Registers
@@ -88,6 +91,11 @@ pub static PAGE_CROSSERS: Lazy<HashSet<u8>> = Lazy::new(|| {
     set.insert(0xF9);
     set.insert(0xF1);
 
+    // Unofficial NOP, LAX and LAS
+    for code in [0x1C, 0x3C, 0x5C, 0x7C, 0xDC, 0xFC, 0xBF, 0xB3, 0xBB] {
+        set.insert(code);
+    }
+
     set
 });
 
@@ -251,8 +259,8 @@ pub static OPCODE_TABLE: Lazy<HashMap<u8, OpCode>> = Lazy::new(|| {
     map.insert(0xC8, OpCode::new(0xC8, "INY", 1, 2, AddressingMode::NoneAddressing));
 
     // JMP
-    map.insert(0x4C, OpCode::new(0x4C, "JMP", 3, 3, AddressingMode::Immediate));
-    map.insert(0x6C, OpCode::new(0x6C, "JMP", 3, 5, AddressingMode::Absolute));
+    map.insert(0x4C, OpCode::new(0x4C, "JMP", 3, 3, AddressingMode::Absolute));
+    map.insert(0x6C, OpCode::new(0x6C, "JMP", 3, 5, AddressingMode::Indirect));
 
     // JSR
     map.insert(0x20, OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute));
@@ -329,10 +337,10 @@ pub static OPCODE_TABLE: Lazy<HashMap<u8, OpCode>> = Lazy::new(|| {
     map.insert(0x85, OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage));
     map.insert(0x95, OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPage_X));
     map.insert(0x8D, OpCode::new(0x8D, "STA", 3, 4, AddressingMode::Absolute));
-    map.insert(0x9D, OpCode::new(0x9D, "STA", 3, 4, AddressingMode::Absolute_X));
-    map.insert(0x99, OpCode::new(0x99, "STA", 3, 4, AddressingMode::Absolute_Y));
+    map.insert(0x9D, OpCode::new(0x9D, "STA", 3, 5, AddressingMode::Absolute_X));
+    map.insert(0x99, OpCode::new(0x99, "STA", 3, 5, AddressingMode::Absolute_Y));
     map.insert(0x81, OpCode::new(0x81, "STA", 2, 6, AddressingMode::Indirect_X));
-    map.insert(0x91, OpCode::new(0x91, "STA", 2, 5, AddressingMode::Indirect_Y));
+    map.insert(0x91, OpCode::new(0x91, "STA", 2, 6, AddressingMode::Indirect_Y));
 
     // STX
     map.insert(0x86, OpCode::new(0x86, "STX", 2, 3, AddressingMode::ZeroPage));
@@ -359,10 +367,158 @@ pub static OPCODE_TABLE: Lazy<HashMap<u8, OpCode>> = Lazy::new(|| {
     // TYA
     map.insert(0x98, OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing));
 
+    // Unofficial opcodes, named with a * the way nestest's log does
+
+    // Unofficial NOPs, some read their operand and take as long as a load
+    map.insert(0x1A, OpCode::new(0x1A, "*NOP", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0x3A, OpCode::new(0x3A, "*NOP", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0x5A, OpCode::new(0x5A, "*NOP", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0x7A, OpCode::new(0x7A, "*NOP", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0xDA, OpCode::new(0xDA, "*NOP", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0xFA, OpCode::new(0xFA, "*NOP", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0x80, OpCode::new(0x80, "*NOP", 2, 2, AddressingMode::Immediate));
+    map.insert(0x82, OpCode::new(0x82, "*NOP", 2, 2, AddressingMode::Immediate));
+    map.insert(0x89, OpCode::new(0x89, "*NOP", 2, 2, AddressingMode::Immediate));
+    map.insert(0xC2, OpCode::new(0xC2, "*NOP", 2, 2, AddressingMode::Immediate));
+    map.insert(0xE2, OpCode::new(0xE2, "*NOP", 2, 2, AddressingMode::Immediate));
+    map.insert(0x04, OpCode::new(0x04, "*NOP", 2, 3, AddressingMode::ZeroPage));
+    map.insert(0x44, OpCode::new(0x44, "*NOP", 2, 3, AddressingMode::ZeroPage));
+    map.insert(0x64, OpCode::new(0x64, "*NOP", 2, 3, AddressingMode::ZeroPage));
+    map.insert(0x14, OpCode::new(0x14, "*NOP", 2, 4, AddressingMode::ZeroPage_X));
+    map.insert(0x34, OpCode::new(0x34, "*NOP", 2, 4, AddressingMode::ZeroPage_X));
+    map.insert(0x54, OpCode::new(0x54, "*NOP", 2, 4, AddressingMode::ZeroPage_X));
+    map.insert(0x74, OpCode::new(0x74, "*NOP", 2, 4, AddressingMode::ZeroPage_X));
+    map.insert(0xD4, OpCode::new(0xD4, "*NOP", 2, 4, AddressingMode::ZeroPage_X));
+    map.insert(0xF4, OpCode::new(0xF4, "*NOP", 2, 4, AddressingMode::ZeroPage_X));
+    map.insert(0x0C, OpCode::new(0x0C, "*NOP", 3, 4, AddressingMode::Absolute));
+    map.insert(0x1C, OpCode::new(0x1C, "*NOP", 3, 4, AddressingMode::Absolute_X));
+    map.insert(0x3C, OpCode::new(0x3C, "*NOP", 3, 4, AddressingMode::Absolute_X));
+    map.insert(0x5C, OpCode::new(0x5C, "*NOP", 3, 4, AddressingMode::Absolute_X));
+    map.insert(0x7C, OpCode::new(0x7C, "*NOP", 3, 4, AddressingMode::Absolute_X));
+    map.insert(0xDC, OpCode::new(0xDC, "*NOP", 3, 4, AddressingMode::Absolute_X));
+    map.insert(0xFC, OpCode::new(0xFC, "*NOP", 3, 4, AddressingMode::Absolute_X));
+
+    // LAX (LDA and LDX in one)
+    map.insert(0xA7, OpCode::new(0xA7, "*LAX", 2, 3, AddressingMode::ZeroPage));
+    map.insert(0xB7, OpCode::new(0xB7, "*LAX", 2, 4, AddressingMode::ZeroPage_Y));
+    map.insert(0xAF, OpCode::new(0xAF, "*LAX", 3, 4, AddressingMode::Absolute));
+    map.insert(0xBF, OpCode::new(0xBF, "*LAX", 3, 4, AddressingMode::Absolute_Y));
+    map.insert(0xA3, OpCode::new(0xA3, "*LAX", 2, 6, AddressingMode::Indirect_X));
+    map.insert(0xB3, OpCode::new(0xB3, "*LAX", 2, 5, AddressingMode::Indirect_Y));
+
+    // LXA (LAX immediate, unstable on hardware)
+    map.insert(0xAB, OpCode::new(0xAB, "*LXA", 2, 2, AddressingMode::Immediate));
+
+    // SAX (stores A & X)
+    map.insert(0x87, OpCode::new(0x87, "*SAX", 2, 3, AddressingMode::ZeroPage));
+    map.insert(0x97, OpCode::new(0x97, "*SAX", 2, 4, AddressingMode::ZeroPage_Y));
+    map.insert(0x8F, OpCode::new(0x8F, "*SAX", 3, 4, AddressingMode::Absolute));
+    map.insert(0x83, OpCode::new(0x83, "*SAX", 2, 6, AddressingMode::Indirect_X));
+
+    // SBC (the same as the official $E9)
+    map.insert(0xEB, OpCode::new(0xEB, "*SBC", 2, 2, AddressingMode::Immediate));
+
+    // DCP (DEC then CMP)
+    map.insert(0xC7, OpCode::new(0xC7, "*DCP", 2, 5, AddressingMode::ZeroPage));
+    map.insert(0xD7, OpCode::new(0xD7, "*DCP", 2, 6, AddressingMode::ZeroPage_X));
+    map.insert(0xCF, OpCode::new(0xCF, "*DCP", 3, 6, AddressingMode::Absolute));
+    map.insert(0xDF, OpCode::new(0xDF, "*DCP", 3, 7, AddressingMode::Absolute_X));
+    map.insert(0xDB, OpCode::new(0xDB, "*DCP", 3, 7, AddressingMode::Absolute_Y));
+    map.insert(0xC3, OpCode::new(0xC3, "*DCP", 2, 8, AddressingMode::Indirect_X));
+    map.insert(0xD3, OpCode::new(0xD3, "*DCP", 2, 8, AddressingMode::Indirect_Y));
+
+    // ISB (INC then SBC)
+    map.insert(0xE7, OpCode::new(0xE7, "*ISB", 2, 5, AddressingMode::ZeroPage));
+    map.insert(0xF7, OpCode::new(0xF7, "*ISB", 2, 6, AddressingMode::ZeroPage_X));
+    map.insert(0xEF, OpCode::new(0xEF, "*ISB", 3, 6, AddressingMode::Absolute));
+    map.insert(0xFF, OpCode::new(0xFF, "*ISB", 3, 7, AddressingMode::Absolute_X));
+    map.insert(0xFB, OpCode::new(0xFB, "*ISB", 3, 7, AddressingMode::Absolute_Y));
+    map.insert(0xE3, OpCode::new(0xE3, "*ISB", 2, 8, AddressingMode::Indirect_X));
+    map.insert(0xF3, OpCode::new(0xF3, "*ISB", 2, 8, AddressingMode::Indirect_Y));
+
+    // SLO (ASL then ORA)
+    map.insert(0x07, OpCode::new(0x07, "*SLO", 2, 5, AddressingMode::ZeroPage));
+    map.insert(0x17, OpCode::new(0x17, "*SLO", 2, 6, AddressingMode::ZeroPage_X));
+    map.insert(0x0F, OpCode::new(0x0F, "*SLO", 3, 6, AddressingMode::Absolute));
+    map.insert(0x1F, OpCode::new(0x1F, "*SLO", 3, 7, AddressingMode::Absolute_X));
+    map.insert(0x1B, OpCode::new(0x1B, "*SLO", 3, 7, AddressingMode::Absolute_Y));
+    map.insert(0x03, OpCode::new(0x03, "*SLO", 2, 8, AddressingMode::Indirect_X));
+    map.insert(0x13, OpCode::new(0x13, "*SLO", 2, 8, AddressingMode::Indirect_Y));
+
+    // RLA (ROL then AND)
+    map.insert(0x27, OpCode::new(0x27, "*RLA", 2, 5, AddressingMode::ZeroPage));
+    map.insert(0x37, OpCode::new(0x37, "*RLA", 2, 6, AddressingMode::ZeroPage_X));
+    map.insert(0x2F, OpCode::new(0x2F, "*RLA", 3, 6, AddressingMode::Absolute));
+    map.insert(0x3F, OpCode::new(0x3F, "*RLA", 3, 7, AddressingMode::Absolute_X));
+    map.insert(0x3B, OpCode::new(0x3B, "*RLA", 3, 7, AddressingMode::Absolute_Y));
+    map.insert(0x23, OpCode::new(0x23, "*RLA", 2, 8, AddressingMode::Indirect_X));
+    map.insert(0x33, OpCode::new(0x33, "*RLA", 2, 8, AddressingMode::Indirect_Y));
+
+    // SRE (LSR then EOR)
+    map.insert(0x47, OpCode::new(0x47, "*SRE", 2, 5, AddressingMode::ZeroPage));
+    map.insert(0x57, OpCode::new(0x57, "*SRE", 2, 6, AddressingMode::ZeroPage_X));
+    map.insert(0x4F, OpCode::new(0x4F, "*SRE", 3, 6, AddressingMode::Absolute));
+    map.insert(0x5F, OpCode::new(0x5F, "*SRE", 3, 7, AddressingMode::Absolute_X));
+    map.insert(0x5B, OpCode::new(0x5B, "*SRE", 3, 7, AddressingMode::Absolute_Y));
+    map.insert(0x43, OpCode::new(0x43, "*SRE", 2, 8, AddressingMode::Indirect_X));
+    map.insert(0x53, OpCode::new(0x53, "*SRE", 2, 8, AddressingMode::Indirect_Y));
+
+    // RRA (ROR then ADC)
+    map.insert(0x67, OpCode::new(0x67, "*RRA", 2, 5, AddressingMode::ZeroPage));
+    map.insert(0x77, OpCode::new(0x77, "*RRA", 2, 6, AddressingMode::ZeroPage_X));
+    map.insert(0x6F, OpCode::new(0x6F, "*RRA", 3, 6, AddressingMode::Absolute));
+    map.insert(0x7F, OpCode::new(0x7F, "*RRA", 3, 7, AddressingMode::Absolute_X));
+    map.insert(0x7B, OpCode::new(0x7B, "*RRA", 3, 7, AddressingMode::Absolute_Y));
+    map.insert(0x63, OpCode::new(0x63, "*RRA", 2, 8, AddressingMode::Indirect_X));
+    map.insert(0x73, OpCode::new(0x73, "*RRA", 2, 8, AddressingMode::Indirect_Y));
+
+    // ANC (AND, then N is copied into C)
+    map.insert(0x0B, OpCode::new(0x0B, "*ANC", 2, 2, AddressingMode::Immediate));
+    map.insert(0x2B, OpCode::new(0x2B, "*ANC", 2, 2, AddressingMode::Immediate));
+
+    // ALR (AND then LSR A)
+    map.insert(0x4B, OpCode::new(0x4B, "*ALR", 2, 2, AddressingMode::Immediate));
+
+    // ARR (AND then ROR A, with odd C and V)
+    map.insert(0x6B, OpCode::new(0x6B, "*ARR", 2, 2, AddressingMode::Immediate));
+
+    // AXS (X = A & X minus the operand, without borrow)
+    map.insert(0xCB, OpCode::new(0xCB, "*AXS", 2, 2, AddressingMode::Immediate));
+
+    // XAA (unstable on hardware)
+    map.insert(0x8B, OpCode::new(0x8B, "*XAA", 2, 2, AddressingMode::Immediate));
+
+    // SHY, SHX, SHA and TAS store a register ANDed with the high byte of the address + 1
+    map.insert(0x9C, OpCode::new(0x9C, "*SHY", 3, 5, AddressingMode::Absolute_X));
+
+    map.insert(0x9E, OpCode::new(0x9E, "*SHX", 3, 5, AddressingMode::Absolute_Y));
+
+    map.insert(0x9F, OpCode::new(0x9F, "*SHA", 3, 5, AddressingMode::Absolute_Y));
+    map.insert(0x93, OpCode::new(0x93, "*SHA", 2, 6, AddressingMode::Indirect_Y));
+
+    map.insert(0x9B, OpCode::new(0x9B, "*TAS", 3, 5, AddressingMode::Absolute_Y));
+
+    // LAS (memory & SP into A, X and SP)
+    map.insert(0xBB, OpCode::new(0xBB, "*LAS", 3, 4, AddressingMode::Absolute_Y));
+
+    // JAM (locks up the CPU until reset)
+    map.insert(0x02, OpCode::new(0x02, "*JAM", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0x12, OpCode::new(0x12, "*JAM", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0x22, OpCode::new(0x22, "*JAM", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0x32, OpCode::new(0x32, "*JAM", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0x42, OpCode::new(0x42, "*JAM", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0x52, OpCode::new(0x52, "*JAM", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0x62, OpCode::new(0x62, "*JAM", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0x72, OpCode::new(0x72, "*JAM", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0x92, OpCode::new(0x92, "*JAM", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0xB2, OpCode::new(0xB2, "*JAM", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0xD2, OpCode::new(0xD2, "*JAM", 1, 2, AddressingMode::NoneAddressing));
+    map.insert(0xF2, OpCode::new(0xF2, "*JAM", 1, 2, AddressingMode::NoneAddressing));
+
     map
 });
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
    Immediate,
@@ -372,7 +528,7 @@ pub enum AddressingMode {
    Absolute,
    Absolute_X,
    Absolute_Y,
-   _Indirect,
+   Indirect,
    Indirect_X,
    Indirect_Y,
    NoneAddressing,
@@ -420,25 +576,18 @@ impl<'a> CPU<'a> {
                 let output = addr.wrapping_add(self.reg_y as u16);
                 output
             },
-            AddressingMode::_Indirect => {
+            AddressingMode::Indirect => {
                 let output = self.mem_read_u16(self.pc);
                 output
             }
             AddressingMode::Indirect_X => {
                 let addr = self.mem_read(self.pc);
                 let ptr = addr.wrapping_add(self.reg_x);
-
-                let low = self.mem_read(ptr as u16);
-                let high = self.mem_read(ptr.wrapping_add(1) as u16);
-                let output = (high as u16) << 8 | (low as u16);
-                output
+                self.zero_page_read_u16(ptr)
             },
             AddressingMode::Indirect_Y => {
                 let addr = self.mem_read(self.pc);
-
-                let low = self.mem_read(addr as u16);
-                let high = self.mem_read((addr as u8).wrapping_add(1) as u16);
-                let ptr = (high as u16) << 8 | (low as u16);
+                let ptr = self.zero_page_read_u16(addr);
                 let output = ptr.wrapping_add(self.reg_y as u16);
                 output
             }
@@ -491,7 +640,7 @@ impl<'a> CPU<'a> {
                 // print!("{:04X} = ", output);
                 output
             },
-            AddressingMode::_Indirect => {
+            AddressingMode::Indirect => {
                 let output = self.mem_peek_u16(old_pc);
                 // print!("({:04X}) @ ", output);
                 output
@@ -562,14 +711,11 @@ impl<'a> CPU<'a> {
         self.sp = self.sp.wrapping_sub(1);
     }
 
-    // Move SP 1 to make room for 2 byes, push, move SP 1 more to free byte
+    // High byte first so the low byte ends up on top. Pushed one byte at a time
+    // so SP wraps within page $01 like the real stack
     fn stack_push_u16(&mut self, data: u16) {
-        self.sp = self.sp.wrapping_sub(1);
-        let full_sp = 0x0100 + (self.sp as u16);
-
-        self.mem_write_u16(full_sp , data);
-
-        self.sp = self.sp.wrapping_sub(1);
+        self.stack_push((data >> 8) as u8);
+        self.stack_push((data & 0x00FF) as u8);
     }
 
     fn stack_read(&mut self) -> u8 {
@@ -578,16 +724,26 @@ impl<'a> CPU<'a> {
         output
     }
 
-    fn stack_read_u16(&mut self) -> u16 {
-        let full_sp: u16 = 0x0100 + (self.sp as u16);
-        let output = self.mem_read_u16(full_sp);
-        output
-    }
-
     fn stack_pop(&mut self) {
         self.sp = self.sp.wrapping_add(1);
     }
 
+    // Pulls low then high byte, wrapping within page $01 (SP $FF -> $00)
+    fn stack_pull_u16(&mut self) -> u16 {
+        self.stack_pop();
+        let low = self.stack_read() as u16;
+        self.stack_pop();
+        let high = self.stack_read() as u16;
+        high << 8 | low
+    }
+
+    // A pointer stored in the zero page: the high byte of $FF comes from $00
+    fn zero_page_read_u16(&mut self, ptr: u8) -> u16 {
+        let low = self.mem_read(ptr as u16) as u16;
+        let high = self.mem_read(ptr.wrapping_add(1) as u16) as u16;
+        high << 8 | low
+    }
+
     // Debug function when no controller input is needed
     pub fn _run_rom(&mut self) {
         self.reset();
@@ -601,13 +757,81 @@ impl<'a> CPU<'a> {
     pub fn reset(&mut self) {
         self.reg_a = 0;
         self.reg_x = 0;
-        self.status = 0b0010_0000;
+        self.status = 0b0010_0100; // interrupts start off disabled
         self.sp = 0xFF;
 
         self.pc = self.mem_read_u16(0xFFFC);
         // self.pc = 0x8000; // for testing
     }
 
+    // A save state of the whole console, see state.rs
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = StateWriter::new();
+        for &byte in STATE_MAGIC {
+            out.u8(byte);
+        }
+        out.u8(STATE_VERSION);
+        for register in [self.reg_a, self.reg_x, self.reg_y, self.status, self.sp] {
+            out.u8(register);
+        }
+        out.u16(self.pc);
+        out.usize(self.extra_cycles);
+        self.bus.save_state(&mut out);
+        out.finish()
+    }
+
+    // A state that fails to read part way through is undone, leaving the console
+    // where it was
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let mut input = StateReader::new(state);
+        let mut magic = [0; 4];
+        for byte in magic.iter_mut() {
+            *byte = input.u8()?;
+        }
+        if &magic != STATE_MAGIC {
+            return Err("Not a save state".to_string());
+        }
+        let version = input.u8()?;
+        if version != STATE_VERSION {
+            return Err(format!("Save state is version {}, this emulator reads version {}", version, STATE_VERSION));
+        }
+        let before = self.save_state();
+        let loaded = self.load_registers_and_bus(&mut input).and_then(|()| input.finish());
+        if loaded.is_err() {
+            let mut undo = StateReader::new(&before[STATE_MAGIC.len() + 1..]);
+            self.load_registers_and_bus(&mut undo).expect("Restoring the state from before a failed load");
+        }
+        loaded
+    }
+
+    fn load_registers_and_bus(&mut self, input: &mut StateReader) -> Result<(), String> {
+        for register in [&mut self.reg_a, &mut self.reg_x, &mut self.reg_y, &mut self.status, &mut self.sp] {
+            *register = input.u8()?;
+        }
+        self.pc = input.u16()?;
+        self.extra_cycles = input.usize()?;
+        self.bus.load_state(input)
+    }
+
+    // Pressing the console's reset button. A, X and Y keep their values, the stack
+    // pointer drops by 3 (the CPU goes through the motions of an interrupt without
+    // writing) and RAM is untouched, so games can tell a reset from a power on.
+    pub fn reset_button(&mut self) {
+        self.bus.reset();
+        self.sp = self.sp.wrapping_sub(3);
+        self.status |= 0b0000_0100;
+        self.extra_cycles = 0;
+        self.pc = self.mem_read_u16(0xFFFC);
+    }
+
+    // Switching the console off and on again, see Bus::power_cycle
+    pub fn power_cycle(&mut self) {
+        self.bus.power_cycle();
+        self.reg_y = 0;
+        self.extra_cycles = 0;
+        self.reset();
+    }
+
     fn conditional_cycle_check(&mut self, addr: u16, offset: u8) {
         if self.is_page_cross(addr, offset) {
             self.extra_cycles += 2
@@ -637,10 +861,7 @@ impl<'a> CPU<'a> {
             },
             AddressingMode::Indirect_Y => {
                 let addr = self.mem_read(self.pc);
-
-                let low = self.mem_read(addr as u16);
-                let high = self.mem_read((addr as u8).wrapping_add(1) as u16);
-                let ptr = (high as u16) << 8 | (low as u16);
+                let ptr = self.zero_page_read_u16(addr);
                 if self.is_page_cross(ptr, self.reg_y) {
                     return 1;
                 }
@@ -652,6 +873,27 @@ impl<'a> CPU<'a> {
         return 0;
     }
 
+    // Indexed addressing adds the index to the low byte first and fixes the high byte
+    // a cycle later, reading from the half-fixed address in between. Reads only do
+    // it when a page is crossed, stores and read-modify-writes always do.
+    fn dummy_read(&mut self, opscode: u8, mode: &AddressingMode) {
+        let (base, index) = match mode {
+            AddressingMode::Absolute_X => (self.mem_peek_u16(self.pc), self.reg_x),
+            AddressingMode::Absolute_Y => (self.mem_peek_u16(self.pc), self.reg_y),
+            AddressingMode::Indirect_Y => {
+                let ptr = self.mem_peek(self.pc);
+                let low = self.mem_peek(ptr as u16);
+                let high = self.mem_peek(ptr.wrapping_add(1) as u16);
+                ((high as u16) << 8 | (low as u16), self.reg_y)
+            }
+            _ => return,
+        };
+        if PAGE_CROSSERS.contains(&opscode) && !self.is_page_cross(base, index) {
+            return;
+        }
+        self.mem_read((base & 0xFF00) | (base.wrapping_add(index as u16) & 0x00FF));
+    }
+
     fn interrupt_nmi(&mut self) {
         self.stack_push_u16(self.pc);
         let mut flag = self.status.clone();
@@ -678,130 +920,177 @@ impl<'a> CPU<'a> {
         self.pc = self.mem_read_u16(0xFFFE); // Set the pc to run whatever instruction our ROM runs on NMI interrupts
     }
 
+    // IRQ from the cartridge, unlike BRK the pushed flags have the break bit clear
+    fn interrupt_hardware_irq(&mut self) {
+        self.stack_push_u16(self.pc);
+        self.stack_push((self.status & 0b1110_1111) | 0b0010_0000);
+        self.status |= 0b0000_0100; // Disable IRQ interrupts until cpu finishes
+        self.bus.log_interrupt(InterruptEvent::IrqTaken);
+
+        self.bus.tick(2);
+        self.pc = self.mem_read_u16(0xFFFE);
+    }
+
     pub fn run_with_callback<F>(&mut self, mut callback: F) 
         where
             F: FnMut(&mut CPU),
         {
             loop {
                 callback(self);
+                self.step();
+            }
+    }
 
-                let nmi_stat: bool = self.bus.poll_nmi_status();
-                // println!("nmi stat from cpu {}", nmi_stat);
-                if nmi_stat { // Check if there's an NMI interrupt and execute one
-                    // println!("Interrupt triggered!!!");
-                    self.interrupt_nmi();
-                }
+    // Runs a single instruction (handling a pending NMI first)
+    pub fn step(&mut self) {
+        let nmi_stat: bool = self.bus.poll_nmi();
+        if nmi_stat { // Check if there's an NMI interrupt and execute one
+            self.interrupt_nmi();
+        } else if self.status & 0b0000_0100 == 0 && self.bus.poll_irq_status() {
+            self.interrupt_hardware_irq();
+        }
 
-                // Read the current opcode in binary and convert using our table
-                let opscode = self.mem_read(self.pc);
-                if opscode != 0xEA {
-                    // println!("Grabbing opscode 0x{:02X} at 0x{:04X} on the pc", self.mem_read(self.pc), self.pc);
-                }
-                let op_object: &OpCode = OPCODE_TABLE.get(&opscode).unwrap();
+        // Read the current opcode in binary and convert using our table
+        let opscode = self.mem_read(self.pc);
+        if opscode != 0xEA {
+            // println!("Grabbing opscode 0x{:02X} at 0x{:04X} on the pc", self.mem_read(self.pc), self.pc);
+        }
+        let op_object: &OpCode = OPCODE_TABLE.get(&opscode).unwrap();
 
-                // self.trace_status(op_object, self.pc);
+        // self.trace_status(op_object, self.pc);
 
-                // Move the program counter to point to the next address after opscode
-                self.pc += 1;
+        // Move the program counter to point to the next address after opscode
+        self.pc += 1;
 
-                // Calculate extra cycles due to page crossing
-                if PAGE_CROSSERS.contains(&opscode) {
-                    self.extra_cycles += self.calc_page_cycles(&op_object.addressing_mode);
-                }
+        // Calculate extra cycles due to page crossing
+        if PAGE_CROSSERS.contains(&opscode) {
+            self.extra_cycles += self.calc_page_cycles(&op_object.addressing_mode);
+        }
+        if self.bus.accuracy().contains(Accuracy::DUMMY_READS) {
+            self.dummy_read(opscode, &op_object.addressing_mode);
+        }
 
-                // Match to the corresponding opscode and run that function
-                if opscode != 0xEA {
-                    // println!("Running instruction {}", op_object.code);
-                }
+        // Match to the corresponding opscode and run that function
+        if opscode != 0xEA {
+            // println!("Running instruction {}", op_object.code);
+        }
 
-                // Decides if the standard program counter increment should take place
-                // We don't increment for stuff like JMP that manually set the PC
-                let mut should_inc: bool = true;
-
-                match op_object.code {
-                    "LDA" => self.lda(&op_object.addressing_mode),
-                    "BRK" => self.brk(), // should call brk() but fails to pass test cases w/o return
-                    "TAX" => self.tax(),
-                    "INX" => self.inx(),
-                    "CLC" => self.clc(),
-                    "SEC" => self.sec(),
-                    "ASL" => self.asl(&op_object.addressing_mode),
-                    "AND" => self.and(&op_object.addressing_mode),
-                    "ADC" => self.adc(&op_object.addressing_mode),
-                    "BCC" => self.bcc(),
-                    "BCS" => self.bcs(),
-                    "BEQ" => self.beq(),
-                    "BMI" => self.bmi(),
-                    "BNE" => self.bne(),
-                    "BPL" => self.bpl(),
-                    "BIT" => self.bit(&op_object.addressing_mode),
-                    "BVC" => self.bvc(),
-                    "BVS" => self.bvs(),
-                    "CLD" => self.cld(),
-                    "CLV" => self.clv(),
-                    "CLI" => self.cli(),
-                    "CPX" => self.cpx(&op_object.addressing_mode),
-                    "CPY" => self.cpy(&op_object.addressing_mode),
-                    "CMP" => self.cmp(&op_object.addressing_mode),
-                    "LDX" => self.ldx(&op_object.addressing_mode),
-                    "LDY" => self.ldy(&op_object.addressing_mode),
-                    "DEC" => self.dec(&op_object.addressing_mode),
-                    "DEX" => self.dex(),
-                    "DEY" => self.dey(),
-                    "EOR" => self.eor(&op_object.addressing_mode),
-                    "INC" => self.inc(&op_object.addressing_mode),
-                    "INY" => self.iny(),
-                    "JMP" => {
-                        should_inc = self.jmp(&op_object.addressing_mode);
-                    },
-                    "JSR" => {
-                        should_inc = self.jsr(&op_object.addressing_mode);
-                    },
-                    "RTS" => {
-                        should_inc = self.rts();
-                    },
-                    "LSR" => self.lsr(&op_object.addressing_mode),
-                    "NOP" => {},
-                    "ORA" => self.ora(&op_object.addressing_mode),
-                    "PHA" => self.pha(),
-                    "PHP" => self.php(),
-                    "PLA" => self.pla(),
-                    "PLP" => self.plp(),
-                    "ROL" => self.rol(&op_object.addressing_mode),
-                    "ROR" => self.ror(&op_object.addressing_mode),
-                    "RTI" => {
-                        should_inc = self.rti();
-                    },
-                    "SBC" => self.sbc(&op_object.addressing_mode),
-                    "SED" => self.sed(),
-                    "SEI" => self.sei(),
-                    "STA" => self.sta(&op_object.addressing_mode),
-                    "STX" => self.stx(&op_object.addressing_mode),
-                    "STY" => self.sty(&op_object.addressing_mode),
-                    "TAY" => self.tay(),
-                    "TSX" => self.tsx(),
-                    "TXA" => self.txa(),
-                    "TXS" => self.txs(),
-                    "TYA" => self.tya(),
-                    _ => panic!("Returned op_code: \"{}\" is not yet implemented...", op_object.code)
-                }
+        // Decides if the standard program counter increment should take place
+        // We don't increment for stuff like JMP that manually set the PC
+        let mut should_inc: bool = true;
+
+        match op_object.code {
+            "LDA" => self.lda(&op_object.addressing_mode),
+            "BRK" => self.brk(), // should call brk() but fails to pass test cases w/o return
+            "TAX" => self.tax(),
+            "INX" => self.inx(),
+            "CLC" => self.clc(),
+            "SEC" => self.sec(),
+            "ASL" => self.asl(&op_object.addressing_mode),
+            "AND" => self.and(&op_object.addressing_mode),
+            "ADC" => self.adc(&op_object.addressing_mode),
+            "BCC" => self.bcc(),
+            "BCS" => self.bcs(),
+            "BEQ" => self.beq(),
+            "BMI" => self.bmi(),
+            "BNE" => self.bne(),
+            "BPL" => self.bpl(),
+            "BIT" => self.bit(&op_object.addressing_mode),
+            "BVC" => self.bvc(),
+            "BVS" => self.bvs(),
+            "CLD" => self.cld(),
+            "CLV" => self.clv(),
+            "CLI" => self.cli(),
+            "CPX" => self.cpx(&op_object.addressing_mode),
+            "CPY" => self.cpy(&op_object.addressing_mode),
+            "CMP" => self.cmp(&op_object.addressing_mode),
+            "LDX" => self.ldx(&op_object.addressing_mode),
+            "LDY" => self.ldy(&op_object.addressing_mode),
+            "DEC" => self.dec(&op_object.addressing_mode),
+            "DEX" => self.dex(),
+            "DEY" => self.dey(),
+            "EOR" => self.eor(&op_object.addressing_mode),
+            "INC" => self.inc(&op_object.addressing_mode),
+            "INY" => self.iny(),
+            "JMP" => {
+                should_inc = self.jmp(&op_object.addressing_mode);
+            },
+            "JSR" => {
+                should_inc = self.jsr(&op_object.addressing_mode);
+            },
+            "RTS" => {
+                should_inc = self.rts();
+            },
+            "LSR" => self.lsr(&op_object.addressing_mode),
+            "NOP" => {},
+            "ORA" => self.ora(&op_object.addressing_mode),
+            "PHA" => self.pha(),
+            "PHP" => self.php(),
+            "PLA" => self.pla(),
+            "PLP" => self.plp(),
+            "ROL" => self.rol(&op_object.addressing_mode),
+            "ROR" => self.ror(&op_object.addressing_mode),
+            "RTI" => {
+                should_inc = self.rti();
+            },
+            "SBC" => self.sbc(&op_object.addressing_mode),
+            "SED" => self.sed(),
+            "SEI" => self.sei(),
+            "STA" => self.sta(&op_object.addressing_mode),
+            "STX" => self.stx(&op_object.addressing_mode),
+            "STY" => self.sty(&op_object.addressing_mode),
+            "TAY" => self.tay(),
+            "TSX" => self.tsx(),
+            "TXA" => self.txa(),
+            "TXS" => self.txs(),
+            "TYA" => self.tya(),
+            "*NOP" => self.nop_read(&op_object.addressing_mode),
+            "*LAX" => self.lax(&op_object.addressing_mode),
+            "*LXA" => self.lxa(&op_object.addressing_mode),
+            "*SAX" => self.sax(&op_object.addressing_mode),
+            "*SBC" => self.sbc(&op_object.addressing_mode),
+            "*DCP" => self.dcp(&op_object.addressing_mode),
+            "*ISB" => self.isb(&op_object.addressing_mode),
+            "*SLO" => self.slo(&op_object.addressing_mode),
+            "*RLA" => self.rla(&op_object.addressing_mode),
+            "*SRE" => self.sre(&op_object.addressing_mode),
+            "*RRA" => self.rra(&op_object.addressing_mode),
+            "*ANC" => self.anc(&op_object.addressing_mode),
+            "*ALR" => self.alr(&op_object.addressing_mode),
+            "*ARR" => self.arr(&op_object.addressing_mode),
+            "*AXS" => self.axs(&op_object.addressing_mode),
+            "*XAA" => self.xaa(&op_object.addressing_mode),
+            "*SHY" => self.store_high_and(&op_object.addressing_mode, self.reg_y),
+            "*SHX" => self.store_high_and(&op_object.addressing_mode, self.reg_x),
+            "*SHA" => self.store_high_and(&op_object.addressing_mode, self.reg_a & self.reg_x),
+            "*TAS" => {
+                self.sp = self.reg_a & self.reg_x;
+                self.store_high_and(&op_object.addressing_mode, self.sp);
+            },
+            "*LAS" => self.las(&op_object.addressing_mode),
+            "*JAM" => {
+                // Stays on the opcode forever, only a reset gets out
+                self.pc = self.pc.wrapping_sub(1);
+                should_inc = false;
+            },
+            _ => panic!("Returned op_code: \"{}\" is not yet implemented...", op_object.code)
+        }
 
-                // Handle number of ticks to move
-                // println!("adding cycles base {} + extra {} to cpu cycles", op_object.cycles, self.extra_cycles);
-                self.bus.tick(op_object.cycles + self.extra_cycles);
+        // Handle number of ticks to move
+        // println!("adding cycles base {} + extra {} to cpu cycles", op_object.cycles, self.extra_cycles);
+        self.bus.tick(op_object.cycles + self.extra_cycles);
 
-                // Reset extra cycles from last instruction
-                if self.extra_cycles > 0 {
-                    self.extra_cycles = 0;
-                }
+        // Reset extra cycles from last instruction
+        if self.extra_cycles > 0 {
+            self.extra_cycles = 0;
+        }
 
-                // Increment the program counter depending on the addressing mode
-                // println!("Performing a pc increment from {} to {}", self.pc, self.pc + (op_object.bytes - 1) as u16);
-                // println!("What we add: {}", (op_object.bytes - 1) as u16);
-                if should_inc {
-                    self.pc = self.pc.wrapping_add((op_object.bytes - 1) as u16);
-                }
-            }
+        // Increment the program counter depending on the addressing mode
+        // println!("Performing a pc increment from {} to {}", self.pc, self.pc + (op_object.bytes - 1) as u16);
+        // println!("What we add: {}", (op_object.bytes - 1) as u16);
+        if should_inc {
+            self.pc = self.pc.wrapping_add((op_object.bytes - 1) as u16);
+        }
     }
 
     // Begin instruction set implementations
@@ -1090,32 +1379,16 @@ impl<'a> CPU<'a> {
 
     fn jmp(&mut self, mode: &AddressingMode) -> bool {
         let addr = self.get_opperand_address(mode);
-        // println!("Address read by opperand: 0x{:04X}", addr);
-
-        // Custom code for the 6502 error for indirect
-        if matches!(mode, &AddressingMode::Absolute) {
-            let next_addr = self.mem_read_u16(self.pc);
-
-            // println!("next addr: 0x{:04X}", next_addr);
-
-            // Only adjust if last byte is all ones of indirect address
-            if next_addr & 0x00FF == 0x00FF {
-                let bad_read_addr: u16 = next_addr & 0xFF00;
-                // println!("bad_read_addr: 0x{:04X}", bad_read_addr);
-
-                let hi: u8 = self.mem_read(bad_read_addr);
-                let lo: u8 = self.mem_read(next_addr);
-
-                let new_addr: u16 = ((hi as u16) << 8) + (lo as u16);
-                // println!("new_addr: 0x{:04X}", new_addr);
-                
-                self.pc = new_addr;
-            } else {
-                self.pc = self.mem_read_u16(addr);
+        self.pc = match mode {
+            // The 6502 doesn't carry into the pointer's high byte: JMP ($xxFF)
+            // takes the high byte of the target from $xx00
+            AddressingMode::Indirect => {
+                let lo = self.mem_read(addr);
+                let hi = self.mem_read((addr & 0xFF00) | (addr.wrapping_add(1) & 0x00FF));
+                (hi as u16) << 8 | (lo as u16)
             }
-        } else {
-            self.pc = self.mem_read_u16(addr);
-        }
+            _ => addr,
+        };
 
         // Tell program not to auto increment
         false
@@ -1140,10 +1413,7 @@ impl<'a> CPU<'a> {
 
     fn rts(&mut self) -> bool {
         // Move the stack back to the next value and read it
-        self.stack_pop();
-        let ptr: u16 = self.stack_read_u16();
-        self.stack_pop();
-        
+        let ptr: u16 = self.stack_pull_u16();
 
         let output = ptr.wrapping_add(1);
         self.pc = output;
@@ -1280,9 +1550,7 @@ impl<'a> CPU<'a> {
         self.status = self.stack_read();
 
         // Pull program counter
-        self.stack_pop();
-        self.pc = self.stack_read_u16();
-        self.stack_pop(); 
+        self.pc = self.stack_pull_u16();
 
         // Tell loop not to increment 
         false
@@ -1388,6 +1656,156 @@ impl<'a> CPU<'a> {
         self.update_z_and_n_flags(self.reg_a);
     }
 
+    // Unofficial instructions, https://www.nesdev.org/wiki/CPU_unofficial_opcodes
+
+    // NOPs with an operand still read it
+    fn nop_read(&mut self, mode: &AddressingMode) {
+        if !matches!(mode, AddressingMode::NoneAddressing) {
+            let addr = self.get_opperand_address(mode);
+            self.mem_read(addr);
+        }
+    }
+
+    fn lax(&mut self, mode: &AddressingMode) {
+        let addr = self.get_opperand_address(mode);
+        self.reg_a = self.mem_read(addr);
+        self.reg_x = self.reg_a;
+        self.update_z_and_n_flags(self.reg_a);
+    }
+
+    // Which bits of A come through depends on the chip, $EE is the usual guess
+    fn lxa(&mut self, mode: &AddressingMode) {
+        let addr = self.get_opperand_address(mode);
+        self.reg_a = (self.reg_a | 0xEE) & self.mem_read(addr);
+        self.reg_x = self.reg_a;
+        self.update_z_and_n_flags(self.reg_a);
+    }
+
+    fn xaa(&mut self, mode: &AddressingMode) {
+        let addr = self.get_opperand_address(mode);
+        self.reg_a = (self.reg_a | 0xEE) & self.reg_x & self.mem_read(addr);
+        self.update_z_and_n_flags(self.reg_a);
+    }
+
+    fn sax(&mut self, mode: &AddressingMode) {
+        let addr = self.get_opperand_address(mode);
+        self.mem_write(addr, self.reg_a & self.reg_x);
+    }
+
+    fn dcp(&mut self, mode: &AddressingMode) {
+        let addr = self.get_opperand_address(mode);
+        let output = self.mem_read(addr).wrapping_sub(1);
+        self.mem_write(addr, output);
+        self.compare(self.reg_a, output);
+    }
+
+    fn isb(&mut self, mode: &AddressingMode) {
+        let addr = self.get_opperand_address(mode);
+        let output = self.mem_read(addr).wrapping_add(1);
+        self.mem_write(addr, output);
+        self.add_carry(!output);
+    }
+
+    fn slo(&mut self, mode: &AddressingMode) {
+        let addr = self.get_opperand_address(mode);
+        let param = self.mem_read(addr);
+        self.update_c_bit(param & 0b1000_0000 != 0);
+        let output = param << 1;
+        self.mem_write(addr, output);
+        self.reg_a |= output;
+        self.update_z_and_n_flags(self.reg_a);
+    }
+
+    fn rla(&mut self, mode: &AddressingMode) {
+        let addr = self.get_opperand_address(mode);
+        let param = self.mem_read(addr);
+        let old_c = self.status & 0b0000_0001;
+        self.update_c_bit(param & 0b1000_0000 != 0);
+        let output = (param << 1) | old_c;
+        self.mem_write(addr, output);
+        self.reg_a &= output;
+        self.update_z_and_n_flags(self.reg_a);
+    }
+
+    fn sre(&mut self, mode: &AddressingMode) {
+        let addr = self.get_opperand_address(mode);
+        let param = self.mem_read(addr);
+        self.update_c_bit(param & 0b0000_0001 != 0);
+        let output = param >> 1;
+        self.mem_write(addr, output);
+        self.reg_a ^= output;
+        self.update_z_and_n_flags(self.reg_a);
+    }
+
+    fn rra(&mut self, mode: &AddressingMode) {
+        let addr = self.get_opperand_address(mode);
+        let param = self.mem_read(addr);
+        let old_c = (self.status & 0b0000_0001) << 7;
+        self.update_c_bit(param & 0b0000_0001 != 0);
+        let output = (param >> 1) | old_c;
+        self.mem_write(addr, output);
+        self.add_carry(output);
+    }
+
+    fn anc(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.update_c_bit(self.reg_a & 0b1000_0000 != 0);
+    }
+
+    fn alr(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.lsr(&AddressingMode::NoneAddressing);
+    }
+
+    // C comes from bit 6 of the result and V from bit 6 XOR bit 5
+    fn arr(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.ror(&AddressingMode::NoneAddressing);
+        let bit_6 = (self.reg_a >> 6) & 1;
+        let bit_5 = (self.reg_a >> 5) & 1;
+        self.update_c_bit(bit_6 == 1);
+        self.update_o_flag(bit_6 ^ bit_5 == 1);
+    }
+
+    fn axs(&mut self, mode: &AddressingMode) {
+        let addr = self.get_opperand_address(mode);
+        let param = self.mem_read(addr);
+        let and = self.reg_a & self.reg_x;
+        self.reg_x = and.wrapping_sub(param);
+        self.update_c_bit(and >= param);
+        self.update_z_and_n_flags(self.reg_x);
+    }
+
+    // SHY, SHX, SHA and TAS. When indexing crosses a page the value also replaces
+    // the high byte of the address it's stored to.
+    fn store_high_and(&mut self, mode: &AddressingMode, value: u8) {
+        let (base, index) = match mode {
+            AddressingMode::Absolute_X => (self.mem_read_u16(self.pc), self.reg_x),
+            AddressingMode::Absolute_Y => (self.mem_read_u16(self.pc), self.reg_y),
+            _ => {
+                let ptr = self.mem_read(self.pc);
+                let low = self.mem_read(ptr as u16);
+                let high = self.mem_read(ptr.wrapping_add(1) as u16);
+                ((high as u16) << 8 | (low as u16), self.reg_y)
+            }
+        };
+        let mut addr = base.wrapping_add(index as u16);
+        let output = value & ((base >> 8) as u8).wrapping_add(1);
+        if self.is_page_cross(base, index) {
+            addr = ((output as u16) << 8) | (addr & 0x00FF);
+        }
+        self.mem_write(addr, output);
+    }
+
+    fn las(&mut self, mode: &AddressingMode) {
+        let addr = self.get_opperand_address(mode);
+        let output = self.mem_read(addr) & self.sp;
+        self.reg_a = output;
+        self.reg_x = output;
+        self.sp = output;
+        self.update_z_and_n_flags(output);
+    }
+
     fn decrement(&mut self, value: u8) -> u8 {
         let output: i8 = (value as i8).wrapping_sub(1);
         self.update_z_and_n_flags(output as u8);
@@ -1465,15 +1883,158 @@ impl<'a> CPU<'a> {
 
 }
 
-
-
-
-
-
-
-
-
-
-
-
-
+#[cfg(test)]
+mod test {
+    use super::{AddressingMode, OPCODE_TABLE};
+    use crate::bus::Mem;
+    use crate::nes::Nes;
+    use crate::rom::Rom;
+
+    // Runs `count` instructions of a program placed at $8000
+    fn run(program: Vec<u8>, count: usize) -> Nes<'static> {
+        let mut nes = Nes::new(Rom::_new_test(program).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.pc = 0x8000;
+        for _ in 0..count {
+            nes.step();
+        }
+        nes
+    }
+
+    // The whole 6502 opcode matrix, one row per high nibble: mnemonic, addressing
+    // mode and base cycles (before page crossing and branch penalties). Modes are
+    // imp (implied, accumulator and relative all count), imm, zp, zpx, zpy, abs,
+    // abx, aby, ind, izx and izy; the byte count follows from the mode. From the
+    // nesdev wiki's CPU unofficial opcodes table. JAM never finishes, 2 stands in.
+    const REFERENCE_OPCODES: [&str; 16] = [
+        "BRK imp 7,ORA izx 6,JAM imp 2,SLO izx 8,NOP zp 3,ORA zp 3,ASL zp 5,SLO zp 5,PHP imp 3,ORA imm 2,ASL imp 2,ANC imm 2,NOP abs 4,ORA abs 4,ASL abs 6,SLO abs 6",
+        "BPL imp 2,ORA izy 5,JAM imp 2,SLO izy 8,NOP zpx 4,ORA zpx 4,ASL zpx 6,SLO zpx 6,CLC imp 2,ORA aby 4,NOP imp 2,SLO aby 7,NOP abx 4,ORA abx 4,ASL abx 7,SLO abx 7",
+        "JSR abs 6,AND izx 6,JAM imp 2,RLA izx 8,BIT zp 3,AND zp 3,ROL zp 5,RLA zp 5,PLP imp 4,AND imm 2,ROL imp 2,ANC imm 2,BIT abs 4,AND abs 4,ROL abs 6,RLA abs 6",
+        "BMI imp 2,AND izy 5,JAM imp 2,RLA izy 8,NOP zpx 4,AND zpx 4,ROL zpx 6,RLA zpx 6,SEC imp 2,AND aby 4,NOP imp 2,RLA aby 7,NOP abx 4,AND abx 4,ROL abx 7,RLA abx 7",
+        "RTI imp 6,EOR izx 6,JAM imp 2,SRE izx 8,NOP zp 3,EOR zp 3,LSR zp 5,SRE zp 5,PHA imp 3,EOR imm 2,LSR imp 2,ALR imm 2,JMP abs 3,EOR abs 4,LSR abs 6,SRE abs 6",
+        "BVC imp 2,EOR izy 5,JAM imp 2,SRE izy 8,NOP zpx 4,EOR zpx 4,LSR zpx 6,SRE zpx 6,CLI imp 2,EOR aby 4,NOP imp 2,SRE aby 7,NOP abx 4,EOR abx 4,LSR abx 7,SRE abx 7",
+        "RTS imp 6,ADC izx 6,JAM imp 2,RRA izx 8,NOP zp 3,ADC zp 3,ROR zp 5,RRA zp 5,PLA imp 4,ADC imm 2,ROR imp 2,ARR imm 2,JMP ind 5,ADC abs 4,ROR abs 6,RRA abs 6",
+        "BVS imp 2,ADC izy 5,JAM imp 2,RRA izy 8,NOP zpx 4,ADC zpx 4,ROR zpx 6,RRA zpx 6,SEI imp 2,ADC aby 4,NOP imp 2,RRA aby 7,NOP abx 4,ADC abx 4,ROR abx 7,RRA abx 7",
+        "NOP imm 2,STA izx 6,NOP imm 2,SAX izx 6,STY zp 3,STA zp 3,STX zp 3,SAX zp 3,DEY imp 2,NOP imm 2,TXA imp 2,XAA imm 2,STY abs 4,STA abs 4,STX abs 4,SAX abs 4",
+        "BCC imp 2,STA izy 6,JAM imp 2,SHA izy 6,STY zpx 4,STA zpx 4,STX zpy 4,SAX zpy 4,TYA imp 2,STA aby 5,TXS imp 2,TAS aby 5,SHY abx 5,STA abx 5,SHX aby 5,SHA aby 5",
+        "LDY imm 2,LDA izx 6,LDX imm 2,LAX izx 6,LDY zp 3,LDA zp 3,LDX zp 3,LAX zp 3,TAY imp 2,LDA imm 2,TAX imp 2,LXA imm 2,LDY abs 4,LDA abs 4,LDX abs 4,LAX abs 4",
+        "BCS imp 2,LDA izy 5,JAM imp 2,LAX izy 5,LDY zpx 4,LDA zpx 4,LDX zpy 4,LAX zpy 4,CLV imp 2,LDA aby 4,TSX imp 2,LAS aby 4,LDY abx 4,LDA abx 4,LDX aby 4,LAX aby 4",
+        "CPY imm 2,CMP izx 6,NOP imm 2,DCP izx 8,CPY zp 3,CMP zp 3,DEC zp 5,DCP zp 5,INY imp 2,CMP imm 2,DEX imp 2,AXS imm 2,CPY abs 4,CMP abs 4,DEC abs 6,DCP abs 6",
+        "BNE imp 2,CMP izy 5,JAM imp 2,DCP izy 8,NOP zpx 4,CMP zpx 4,DEC zpx 6,DCP zpx 6,CLD imp 2,CMP aby 4,NOP imp 2,DCP aby 7,NOP abx 4,CMP abx 4,DEC abx 7,DCP abx 7",
+        "CPX imm 2,SBC izx 6,NOP imm 2,ISB izx 8,CPX zp 3,SBC zp 3,INC zp 5,ISB zp 5,INX imp 2,SBC imm 2,NOP imp 2,SBC imm 2,CPX abs 4,SBC abs 4,INC abs 6,ISB abs 6",
+        "BEQ imp 2,SBC izy 5,JAM imp 2,ISB izy 8,NOP zpx 4,SBC zpx 4,INC zpx 6,ISB zpx 6,SED imp 2,SBC aby 4,NOP imp 2,ISB aby 7,NOP abx 4,SBC abx 4,INC abx 7,ISB abx 7",
+    ];
+
+    #[test]
+    fn test_opcode_table_matches_reference() {
+        let relative = ["BPL", "BMI", "BVC", "BVS", "BCC", "BCS", "BNE", "BEQ"];
+        let mut mismatches = vec![];
+        for (row, line) in REFERENCE_OPCODES.iter().enumerate() {
+            for (column, entry) in line.split(',').enumerate() {
+                let code = (row * 16 + column) as u8;
+                let [name, mode, cycles] = entry.split(' ').collect::<Vec<_>>()[..] else { panic!("bad entry {}", entry) };
+                let (mode, bytes) = match mode {
+                    "imp" if relative.contains(&name) => (AddressingMode::NoneAddressing, 2),
+                    "imp" => (AddressingMode::NoneAddressing, 1),
+                    "imm" => (AddressingMode::Immediate, 2),
+                    "zp" => (AddressingMode::ZeroPage, 2),
+                    "zpx" => (AddressingMode::ZeroPage_X, 2),
+                    "zpy" => (AddressingMode::ZeroPage_Y, 2),
+                    "abs" => (AddressingMode::Absolute, 3),
+                    "abx" => (AddressingMode::Absolute_X, 3),
+                    "aby" => (AddressingMode::Absolute_Y, 3),
+                    "ind" => (AddressingMode::Indirect, 3),
+                    "izx" => (AddressingMode::Indirect_X, 2),
+                    "izy" => (AddressingMode::Indirect_Y, 2),
+                    other => panic!("unknown mode {}", other),
+                };
+                let cycles: usize = cycles.parse().unwrap();
+
+                let Some(opcode) = OPCODE_TABLE.get(&code) else {
+                    mismatches.push(format!("${:02X} {} missing", code, name));
+                    continue;
+                };
+                if (opcode.bytes, opcode.cycles, &opcode.addressing_mode) != (bytes, cycles, &mode) || opcode.addr != code {
+                    mismatches.push(format!(
+                        "${:02X} {}: table has {} bytes {} cycles {:?}, reference {} bytes {} cycles {:?}",
+                        code, name, opcode.bytes, opcode.cycles, opcode.addressing_mode, bytes, cycles, mode
+                    ));
+                }
+            }
+        }
+        assert!(mismatches.is_empty(), "OPCODE_TABLE differs from the reference:\n{}", mismatches.join("\n"));
+    }
+
+    #[test]
+    fn test_jmp_indirect_page_wrap() {
+        // Pointer at $02FF: low byte from $02FF, high byte from $0200 (not $0300)
+        let mut nes = run(vec![0x6C, 0xFF, 0x02], 0);
+        nes.cpu.mem_write(0x02FF, 0x34);
+        nes.cpu.mem_write(0x0200, 0x12);
+        nes.cpu.mem_write(0x0300, 0x56);
+        nes.step();
+        assert_eq!(nes.cpu.pc, 0x1234);
+
+        let nes = run(vec![0x4C, 0x78, 0x56], 1);
+        assert_eq!(nes.cpu.pc, 0x5678);
+    }
+
+    #[test]
+    fn test_stack_wraps_within_page_one() {
+        // JSR with SP at $00: the return address straddles $0100 and $01FF
+        let mut nes = run(vec![0x20, 0x00, 0x90], 0);
+        nes.cpu.sp = 0x00;
+        nes.cpu.mem_write(0x0200, 0xAA);
+        nes.step();
+        assert_eq!((nes.cpu.pc, nes.cpu.sp), (0x9000, 0xFE));
+        assert_eq!((nes.cpu.mem_peek(0x0100), nes.cpu.mem_peek(0x01FF)), (0x80, 0x02));
+        assert_eq!(nes.cpu.mem_peek(0x0200), 0xAA);
+
+        // RTS (run from RAM) pulls it back across the wrap
+        nes.cpu.mem_write(0x0300, 0x60);
+        nes.cpu.pc = 0x0300;
+        nes.step();
+        assert_eq!((nes.cpu.pc, nes.cpu.sp), (0x8003, 0x00));
+
+        // ($FF),Y takes its high byte from $00
+        let mut nes = run(vec![0xB1, 0xFF], 0);
+        nes.cpu.mem_write(0x00FF, 0x34);
+        nes.cpu.mem_write(0x0000, 0x02);
+        nes.cpu.mem_write(0x0234, 0x77);
+        nes.step();
+        assert_eq!(nes.cpu.reg_a, 0x77);
+    }
+
+    #[test]
+    fn test_unofficial_loads_and_stores() {
+        // LDA #$F0 / STA $10 / LAX $10 / LDA #$3C / SAX $11
+        let nes = run(vec![0xA9, 0xF0, 0x85, 0x10, 0xA7, 0x10, 0xA9, 0x3C, 0x87, 0x11], 5);
+        assert_eq!(nes.cpu.reg_x, 0xF0);
+        assert_eq!(nes.cpu.bus.mem_peek(0x11), 0x30);
+
+        // 3 byte NOP skips its operand, JAM stays put
+        let nes = run(vec![0x0C, 0x00, 0x20, 0x02], 3);
+        assert_eq!(nes.cpu.pc, 0x8003);
+    }
+
+    #[test]
+    fn test_unofficial_read_modify_writes() {
+        // LDA #$05 / STA $10 / LDA #$04 / DCP $10: $10 becomes 4 and compares equal
+        let nes = run(vec![0xA9, 0x05, 0x85, 0x10, 0xA9, 0x04, 0xC7, 0x10], 4);
+        assert_eq!(nes.cpu.bus.mem_peek(0x10), 0x04);
+        assert_eq!(nes.cpu.status & 0b0000_0011, 0b0000_0011);
+
+        // LDA #$81 / STA $10 / LDA #$01 / SLO $10: $10 << 1 = $02, A = $03, C from bit 7
+        let nes = run(vec![0xA9, 0x81, 0x85, 0x10, 0xA9, 0x01, 0x07, 0x10], 4);
+        assert_eq!(nes.cpu.bus.mem_peek(0x10), 0x02);
+        assert_eq!(nes.cpu.reg_a, 0x03);
+        assert_eq!(nes.cpu.status & 1, 1);
+
+        // SEC / LDA #$10 / STA $10 / LDA #$20 / ISB $10: $10 = $11, A = $20 - $11
+        let nes = run(vec![0x38, 0xA9, 0x10, 0x85, 0x10, 0xA9, 0x20, 0xE7, 0x10], 5);
+        assert_eq!(nes.cpu.reg_a, 0x0F);
+
+        // LDA #$FF / LDX #$0F / AXS #$01: X = ($FF & $0F) - 1
+        let nes = run(vec![0xA9, 0xFF, 0xA2, 0x0F, 0xCB, 0x01], 3);
+        assert_eq!(nes.cpu.reg_x, 0x0E);
+    }
+}
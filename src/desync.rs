@@ -0,0 +1,126 @@
+use crate::scheduler::{MASTER_CYCLES_PER_CPU_CYCLE, PPU_DOTS_PER_SCANLINE, SCANLINES_PER_FRAME};
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// Consistency check between the CPU and PPU clocks, run by the bus at the end of
+// every frame in debug builds. Over a frame the PPU must have advanced exactly 3
+// dots per CPU cycle, the scheduler's master clock must match the CPU's cycle
+// count, and the frame must have had the region's number of scanlines. Timing
+// bugs (a tick counted twice, a scanline ended early) break one of these long
+// before they show up as a glitch on screen, so the bus panics with the numbers.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockStamp {
+    pub cpu_cycles: u64,
+    pub master_cycles: u64,
+    pub frame: u64,
+    pub scanline: u16,
+    pub dot: usize,
+}
+
+impl ClockStamp {
+    // PPU dots since power on, worked out from where the PPU is
+    fn ppu_dots(&self) -> u64 {
+        (self.frame * SCANLINES_PER_FRAME + self.scanline as u64) * PPU_DOTS_PER_SCANLINE + self.dot as u64
+    }
+}
+
+pub struct DesyncChecker {
+    last: ClockStamp,
+    scanlines: u64, // scanlines ended since the last frame ended
+}
+
+impl Default for DesyncChecker {
+    fn default() -> Self {
+        DesyncChecker::new()
+    }
+}
+
+impl DesyncChecker {
+    pub fn new() -> Self {
+        DesyncChecker {
+            last: ClockStamp { cpu_cycles: 0, master_cycles: 0, frame: 0, scanline: 0, dot: 0 },
+            scanlines: 0,
+        }
+    }
+
+    pub fn scanline_ended(&mut self) {
+        self.scanlines += 1;
+    }
+
+    // Call when a frame has just ended, with the clocks as they are now
+    pub fn frame_ended(&mut self, now: ClockStamp) -> Result<(), String> {
+        let last = std::mem::replace(&mut self.last, now);
+        let scanlines = std::mem::take(&mut self.scanlines);
+
+        if scanlines != SCANLINES_PER_FRAME {
+            return Err(format!("frame {} had {} scanlines instead of {}", last.frame, scanlines, SCANLINES_PER_FRAME));
+        }
+        let cpu_cycles = now.cpu_cycles - last.cpu_cycles;
+        let ppu_dots = now.ppu_dots() - last.ppu_dots();
+        if ppu_dots != cpu_cycles * 3 {
+            return Err(format!(
+                "frame {} ran {} CPU cycles but {} PPU dots ({} expected), from {:?} to {:?}",
+                last.frame, cpu_cycles, ppu_dots, cpu_cycles * 3, last, now
+            ));
+        }
+        if now.master_cycles != now.cpu_cycles * MASTER_CYCLES_PER_CPU_CYCLE {
+            return Err(format!(
+                "master clock is at {} but {} CPU cycles make {}",
+                now.master_cycles, now.cpu_cycles, now.cpu_cycles * MASTER_CYCLES_PER_CPU_CYCLE
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot for DesyncChecker {
+    // Saved with the clocks it checks, so a loaded state carries on from its own frame
+    fn save_state(&self, out: &mut StateWriter) {
+        out.u64(self.last.cpu_cycles);
+        out.u64(self.last.master_cycles);
+        out.u64(self.last.frame);
+        out.u16(self.last.scanline);
+        out.usize(self.last.dot);
+        out.u64(self.scanlines);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.last = ClockStamp {
+            cpu_cycles: input.u64()?,
+            master_cycles: input.u64()?,
+            frame: input.u64()?,
+            scanline: input.u16()?,
+            dot: input.usize()?,
+        };
+        self.scanlines = input.u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn end_frame(checker: &mut DesyncChecker, scanlines: u64, cpu_cycles: u64, frame: u64, dot: usize) -> Result<(), String> {
+        for _ in 0..scanlines {
+            checker.scanline_ended();
+        }
+        let master_cycles = cpu_cycles * MASTER_CYCLES_PER_CPU_CYCLE;
+        checker.frame_ended(ClockStamp { cpu_cycles, master_cycles, frame, scanline: 0, dot })
+    }
+
+    #[test]
+    fn test_catches_drift() {
+        let mut checker = DesyncChecker::new();
+        // 262 * 341 dots is 29780 and 2/3 CPU cycles, the leftover dots carry into the next frame
+        assert!(end_frame(&mut checker, 262, 29781, 1, 1).is_ok());
+        assert!(end_frame(&mut checker, 262, 29781 * 2, 2, 2).is_ok());
+
+        // A PPU dot too many
+        let error = end_frame(&mut checker, 262, 29781 * 3, 3, 4).unwrap_err();
+        assert!(error.contains("PPU dots"), "{}", error);
+
+        let error = end_frame(&mut checker, 261, 29781 * 4, 4, 4).unwrap_err();
+        assert!(error.contains("261 scanlines"), "{}", error);
+    }
+}
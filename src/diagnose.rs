@@ -0,0 +1,188 @@
+use std::fmt;
+
+use crate::bus::Mem;
+use crate::cpu::OPCODE_TABLE;
+use crate::frame::Frame;
+use crate::nes::Nes;
+use crate::ppu::MaskRegister;
+use crate::render;
+use crate::rom::Rom;
+use crate::romdb;
+
+// "Why is the screen blank?" mode. Runs a rom headless for a number of frames while
+// watching for the usual reasons a game never shows anything, then prints a checklist.
+
+// Instructions that read $2002 with absolute addressing (LDA, LDX, LDY, BIT)
+const STATUS_READ_OPCODES: [u8; 4] = [0xAD, 0xAE, 0xAC, 0x2C];
+const STUCK_LOOP_SPAN: u16 = 16; // a polling loop fits in a handful of bytes
+
+pub struct Finding {
+    pub ok: bool,
+    pub message: String,
+}
+
+pub struct Diagnosis {
+    pub frames_run: u64,
+    pub findings: Vec<Finding>,
+}
+
+impl Diagnosis {
+    fn check(&mut self, ok: bool, message: String) {
+        self.findings.push(Finding { ok, message });
+    }
+
+    pub fn problems(&self) -> impl Iterator<Item = &Finding> {
+        self.findings.iter().filter(|finding| !finding.ok)
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Blank screen checklist after {} frames:", self.frames_run)?;
+        for finding in self.findings.iter() {
+            writeln!(f, "  [{}] {}", if finding.ok { "ok" } else { "!!" }, finding.message)?;
+        }
+        if self.problems().next().is_none() {
+            writeln!(f, "No obvious cause found, the problem is likely deeper in the CPU/PPU/mapper emulation.")?;
+        }
+        Ok(())
+    }
+}
+
+// Activity seen during one frame of emulation
+#[derive(Default)]
+struct FrameActivity {
+    instructions: u64,
+    status_reads: u64,
+    pc_min: u16,
+    pc_max: u16,
+}
+
+pub fn diagnose(rom: Rom, frames: u64) -> Diagnosis {
+    let mut diagnosis = Diagnosis { frames_run: 0, findings: vec![] };
+
+    // Header vs rom database
+    match romdb::lookup(rom.crc) {
+        Some(info) if info.mapper_id != rom.mapper_id => diagnosis.check(false, format!(
+            "Header says mapper {} but {} uses mapper {} (try -fix-header)", rom.mapper_id, info.name, info.mapper_id
+        )),
+        Some(info) => diagnosis.check(true, format!("Header matches the rom database entry for {}", info.name)),
+        None => {}
+    }
+
+    let mut nes = match Nes::new(rom, |_ppu, _joypad1| {}) {
+        Ok(nes) => nes,
+        Err(e) => {
+            diagnosis.check(false, e);
+            return diagnosis;
+        }
+    };
+
+    // The reset vector has to land on real code in the bank mapped at power on
+    let reset_vector = nes.cpu.bus.mem_peek_u16(0xFFFC);
+    let first_opcode = nes.cpu.bus.mem_peek(reset_vector);
+    if reset_vector < 0x8000 {
+        diagnosis.check(false, format!("Reset vector ${:04X} points outside PRG rom (wrong bank mapped at $FFFC?)", reset_vector));
+    } else if first_opcode == 0x00 || first_opcode == 0xFF {
+        diagnosis.check(false, format!(
+            "Reset vector ${:04X} lands on ${:02X} bytes, the wrong PRG bank is probably mapped at power on", reset_vector, first_opcode
+        ));
+    } else {
+        diagnosis.check(true, format!("Reset vector ${:04X} points at code", reset_vector));
+    }
+
+    let mut rendering_enabled = false;
+    let mut nmi_enabled = false;
+    let mut last_frame = FrameActivity::default();
+    let mut current = FrameActivity { pc_min: u16::MAX, ..FrameActivity::default() };
+    let mut crashed_at: Option<(u16, u8)> = None;
+
+    while nes.cpu.bus.ppu.timing().frame < frames {
+        let pc = nes.cpu.pc;
+        let opcode = nes.cpu.bus.mem_peek(pc);
+        if OPCODE_TABLE[&opcode].code == "*JAM" {
+            crashed_at = Some((pc, opcode));
+            break;
+        }
+        if STATUS_READ_OPCODES.contains(&opcode) && nes.cpu.bus.mem_peek_u16(pc.wrapping_add(1)) == 0x2002 {
+            current.status_reads += 1;
+        }
+        current.instructions += 1;
+        current.pc_min = current.pc_min.min(pc);
+        current.pc_max = current.pc_max.max(pc);
+
+        let frame_before = nes.cpu.bus.ppu.timing().frame;
+        nes.step();
+
+        let mask = MaskRegister::from_bits_truncate(nes.cpu.bus.ppu.peek_mask());
+        rendering_enabled |= mask.intersects(MaskRegister::BACKGROUND_RENDERING | MaskRegister::SPRITE_RENDERING);
+        nmi_enabled |= nes.cpu.bus.ppu.ctrl.is_generate_nmi();
+
+        if nes.cpu.bus.ppu.timing().frame != frame_before {
+            last_frame = std::mem::replace(&mut current, FrameActivity { pc_min: u16::MAX, ..FrameActivity::default() });
+        }
+    }
+    diagnosis.frames_run = nes.cpu.bus.ppu.timing().frame;
+
+    if let Some((pc, opcode)) = crashed_at {
+        diagnosis.check(false, format!("CPU jammed on opcode ${:02X} at ${:04X} and stopped", opcode, pc));
+    }
+
+    diagnosis.check(rendering_enabled, if rendering_enabled {
+        "Rendering was enabled in PPUMASK ($2001)".to_string()
+    } else {
+        "Rendering never enabled in PPUMASK ($2001), the game hasn't finished starting up".to_string()
+    });
+
+    diagnosis.check(nmi_enabled, if nmi_enabled {
+        "NMI on vblank was enabled in PPUCTRL ($2000)".to_string()
+    } else {
+        "NMI never enabled in PPUCTRL ($2000), a game driven by its NMI handler will never draw".to_string()
+    });
+
+    // Most of the last frame spent in a tiny loop reading $2002 means it's waiting for a
+    // flag (vblank or sprite 0 hit) that never shows up
+    let polling = last_frame.instructions > 0
+        && last_frame.status_reads * 4 >= last_frame.instructions
+        && last_frame.pc_max.saturating_sub(last_frame.pc_min) < STUCK_LOOP_SPAN;
+    diagnosis.check(!polling, if polling {
+        format!("Stuck polling $2002 in a loop at ${:04X}-${:04X} (waiting on vblank or sprite 0 hit?)", last_frame.pc_min, last_frame.pc_max)
+    } else {
+        "Not stuck waiting on $2002".to_string()
+    });
+
+    let mut frame = Frame::new();
+    render::render(&nes.cpu.bus.ppu, &mut frame);
+    let first_pixel = &frame.data[0..3];
+    let single_color = frame.data.chunks(3).all(|pixel| pixel == first_pixel);
+    diagnosis.check(!single_color, if single_color {
+        "The whole screen is one color (nothing written to the nametables or palette yet)".to_string()
+    } else {
+        "The screen has something drawn on it".to_string()
+    });
+
+    diagnosis
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reports_status_polling_loop() {
+        // Reset vector at $8000 (mirrored to $FFFC), code: loop: BIT $2002 / BPL loop / JMP loop
+        let mut program = vec![0x2C, 0x02, 0x20, 0x10, 0xFB, 0x4C, 0x00, 0x80];
+        program.resize(0x4000, 0xEA);
+        program[0x3FFC] = 0x00;
+        program[0x3FFD] = 0x80;
+
+        let diagnosis = diagnose(Rom::_new_test(program).unwrap(), 3);
+        let problems: Vec<&str> = diagnosis.problems().map(|finding| finding.message.as_str()).collect();
+
+        assert_eq!(diagnosis.frames_run, 3);
+        assert!(problems.iter().any(|message| message.starts_with("Stuck polling $2002")));
+        assert!(problems.iter().any(|message| message.starts_with("Rendering never enabled")));
+        assert!(problems.iter().any(|message| message.starts_with("NMI never enabled")));
+        assert!(!problems.iter().any(|message| message.starts_with("Reset vector")));
+    }
+}
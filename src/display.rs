@@ -0,0 +1,183 @@
+// Window sizing for the frontend. The picture keeps its shape when the window is
+// resized, with black bars filling whatever is left over (letterboxing).
+// It can also be rotated and flipped for vertical arcade cabinet setups, which
+// is done when the picture is copied to the window.
+
+pub const NES_WIDTH: u32 = 256;
+pub const NES_HEIGHT: u32 = 240;
+pub const DEFAULT_SCALE: u32 = 3;
+pub const MAX_SCALE_PRESET: u32 = 5; // presets are 1x to 5x
+
+// Where the picture goes in the window, in window pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DisplayTransform {
+    pub rotation: u16, // clockwise degrees: 0, 90, 180 or 270
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+impl DisplayTransform {
+    pub fn parse_rotation(value: &str) -> Result<u16, String> {
+        match value {
+            "0" | "90" | "180" | "270" => Ok(value.parse().unwrap()),
+            _ => Err(format!("Rotation must be 0, 90, 180 or 270 but got \"{}\"", value)),
+        }
+    }
+
+    // Sets the flips from "none", "horizontal", "vertical" or "both"
+    pub fn set_flip(&mut self, value: &str) -> Result<(), String> {
+        (self.flip_horizontal, self.flip_vertical) = match value {
+            "none" => (false, false),
+            "horizontal" => (true, false),
+            "vertical" => (false, true),
+            "both" => (true, true),
+            _ => return Err(format!("Flip must be none, horizontal, vertical or both but got \"{}\"", value)),
+        };
+        Ok(())
+    }
+
+    // Turned on its side, so the picture is taller than it is wide
+    pub fn is_sideways(&self) -> bool {
+        self.rotation == 90 || self.rotation == 270
+    }
+
+    // A window size for the upright picture, turned to match the rotation
+    pub fn orient(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        if self.is_sideways() { (height, width) } else { (width, height) }
+    }
+}
+
+// Largest picture that fits the window without changing its shape, centered
+pub fn letterbox(window_width: u32, window_height: u32) -> Viewport {
+    fit(window_width, window_height, NES_WIDTH, NES_HEIGHT)
+}
+
+fn fit(window_width: u32, window_height: u32, picture_width: u32, picture_height: u32) -> Viewport {
+    let scale = (window_width as f64 / picture_width as f64).min(window_height as f64 / picture_height as f64);
+    let width = ((picture_width as f64 * scale).round() as u32).min(window_width);
+    let height = ((picture_height as f64 * scale).round() as u32).min(window_height);
+
+    Viewport {
+        x: ((window_width - width) / 2) as i32,
+        y: ((window_height - height) / 2) as i32,
+        width,
+        height,
+    }
+}
+
+// Destination rect to copy the picture to before it is rotated about its center.
+// When sideways the rotated picture is what has to fit the window, so the rect is
+// fitted with the sides swapped and then turned back.
+pub fn picture_viewport(window_width: u32, window_height: u32, transform: &DisplayTransform) -> Viewport {
+    if !transform.is_sideways() {
+        return letterbox(window_width, window_height);
+    }
+
+    let rotated = fit(window_width, window_height, NES_HEIGHT, NES_WIDTH);
+    let center_x = rotated.x + rotated.width as i32 / 2;
+    let center_y = rotated.y + rotated.height as i32 / 2;
+    Viewport {
+        x: center_x - rotated.height as i32 / 2,
+        y: center_y - rotated.width as i32 / 2,
+        width: rotated.height,
+        height: rotated.width,
+    }
+}
+
+// The NES pixel under a point in the window, None over the black bars. Undoes what
+// the copy to the window does: scaling, then flips, then the rotation about the
+// picture's center.
+pub fn window_to_picture(viewport: &Viewport, transform: &DisplayTransform, x: i32, y: i32) -> Option<(usize, usize)> {
+    let (width, height) = (viewport.width as f64, viewport.height as f64);
+    let dx = x as f64 + 0.5 - (viewport.x as f64 + width / 2.0);
+    let dy = y as f64 + 0.5 - (viewport.y as f64 + height / 2.0);
+    let (dx, dy) = match transform.rotation {
+        90 => (dy, -dx),
+        180 => (-dx, -dy),
+        270 => (-dy, dx),
+        _ => (dx, dy),
+    };
+    let dx = if transform.flip_horizontal { -dx } else { dx };
+    let dy = if transform.flip_vertical { -dy } else { dy };
+
+    let px = (dx + width / 2.0) * NES_WIDTH as f64 / width;
+    let py = (dy + height / 2.0) * NES_HEIGHT as f64 / height;
+    if px < 0.0 || py < 0.0 || px >= NES_WIDTH as f64 || py >= NES_HEIGHT as f64 {
+        return None;
+    }
+    Some((px as usize, py as usize))
+}
+
+// Window size for a whole number scale preset, None outside 1x-5x
+pub fn preset_size(scale: u32) -> Option<(u32, u32)> {
+    if (1..=MAX_SCALE_PRESET).contains(&scale) {
+        Some((NES_WIDTH * scale, NES_HEIGHT * scale))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_letterbox_keeps_shape() {
+        // Exact fit
+        assert_eq!(letterbox(768, 720), Viewport { x: 0, y: 0, width: 768, height: 720 });
+        // Wide window: bars left and right
+        assert_eq!(letterbox(1920, 1080), Viewport { x: 384, y: 0, width: 1152, height: 1080 });
+        // Tall window: bars top and bottom
+        assert_eq!(letterbox(512, 1000), Viewport { x: 0, y: 260, width: 512, height: 480 });
+    }
+
+    #[test]
+    fn test_sideways_viewport() {
+        let transform = DisplayTransform { rotation: 90, ..Default::default() };
+        // 240x256 once rotated fills a 480x512 window, so the unrotated rect is
+        // 512x480 around the same center
+        assert_eq!(picture_viewport(480, 512, &transform), Viewport { x: -16, y: 16, width: 512, height: 480 });
+        assert_eq!(picture_viewport(768, 720, &DisplayTransform::default()), letterbox(768, 720));
+
+        let mut transform = DisplayTransform::default();
+        transform.set_flip("both").unwrap();
+        assert!(transform.flip_horizontal && transform.flip_vertical);
+        assert!(transform.set_flip("sideways").is_err());
+        assert!(DisplayTransform::parse_rotation("45").is_err());
+    }
+
+    #[test]
+    fn test_window_to_picture() {
+        // 3x with bars on the sides
+        let upright = DisplayTransform::default();
+        let viewport = picture_viewport(968, 720, &upright);
+        assert_eq!(window_to_picture(&viewport, &upright, 100, 0), Some((0, 0)));
+        assert_eq!(window_to_picture(&viewport, &upright, 100 + 767, 719), Some((255, 239)));
+        assert_eq!(window_to_picture(&viewport, &upright, 50, 300), None);
+
+        // Turned clockwise, the picture's top left corner is in the window's top right
+        let turned = DisplayTransform { rotation: 90, ..Default::default() };
+        let viewport = picture_viewport(240, 256, &turned);
+        assert_eq!(window_to_picture(&viewport, &turned, 239, 0), Some((0, 0)));
+        assert_eq!(window_to_picture(&viewport, &turned, 0, 255), Some((255, 239)));
+
+        let flipped = DisplayTransform { flip_horizontal: true, ..Default::default() };
+        let viewport = picture_viewport(256, 240, &flipped);
+        assert_eq!(window_to_picture(&viewport, &flipped, 0, 10), Some((255, 10)));
+    }
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(preset_size(1), Some((256, 240)));
+        assert_eq!(preset_size(5), Some((1280, 1200)));
+        assert_eq!(preset_size(6), None);
+    }
+}
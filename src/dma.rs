@@ -0,0 +1,235 @@
+use crate::scheduler::MASTER_CYCLES_PER_CPU_CYCLE;
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// DMA unit between the CPU and the bus. While a transfer runs the CPU is halted
+// and the DMA unit owns the bus, reading 256 bytes from one CPU page and writing
+// them to OAM through $2004.
+//
+// A write to $4014 only requests the transfer, the bus runs it once the writing
+// instruction has finished (see Bus::run_dma). The CPU halts for a cycle, waits
+// one more when that lands on an odd cycle so the reads line up with even ones,
+// then each byte takes a read cycle and a write cycle: 513 or 514 cycles in all.
+// The 256 reads stay within the page, $xx00-$xxFF.
+// DMC sample fetches also go through here. Each one halts the CPU on its next
+// read for 4 cycles, and if that read is of a controller port the halted cycles
+// re-read it (the DPCM controller glitch, see Bus::mem_read).
+
+pub const OAM_DMA_CYCLES: usize = 513;
+pub const DMC_DMA_CYCLES: usize = 4;
+
+// An OAM transfer about to run, see take_oam
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OamTransfer {
+    pub page: u8,
+    pub wait_cycles: usize, // halt and alignment cycles before the first read
+}
+
+impl OamTransfer {
+    // Where byte `i` is read from
+    pub fn source(&self, i: u8) -> u16 {
+        u16::from_be_bytes([self.page, i])
+    }
+}
+
+pub struct DmaController {
+    oam_page: Option<u8>, // requested OAM transfer, high byte of the source address
+    dmc_fetch: bool, // DMC sample fetch waiting for the CPU's next read
+    dmc_stall: usize, // cycles owed by DMC fetches, charged when the instruction ends
+    stalled_cycles: u64, // CPU cycles lost to DMA since power on
+}
+
+impl Default for DmaController {
+    fn default() -> Self {
+        DmaController::new()
+    }
+}
+
+impl DmaController {
+    pub fn new() -> Self {
+        DmaController {
+            oam_page: None,
+            dmc_fetch: false,
+            dmc_stall: 0,
+            stalled_cycles: 0,
+        }
+    }
+
+    // $4014 write
+    pub fn start_oam(&mut self, page: u8) {
+        self.oam_page = Some(page);
+    }
+
+    // Called by the DMC when its sample buffer needs refilling
+    pub fn request_dmc_fetch(&mut self) {
+        self.dmc_fetch = true;
+    }
+
+    pub fn pending(&self) -> bool {
+        self.oam_page.is_some() || self.dmc_stall > 0
+    }
+
+    // Called on every CPU read, true when a DMC fetch halts the CPU on this read
+    pub fn take_dmc_fetch(&mut self) -> bool {
+        if !self.dmc_fetch {
+            return false;
+        }
+        self.dmc_fetch = false;
+        self.dmc_stall += DMC_DMA_CYCLES;
+        self.stalled_cycles += DMC_DMA_CYCLES as u64;
+        true
+    }
+
+    pub fn take_dmc_stall(&mut self) -> usize {
+        std::mem::take(&mut self.dmc_stall)
+    }
+
+    // Hands the pending transfer to the bus, given the master clock it starts on.
+    // The bus runs the wait cycles, then a read and a write cycle per byte.
+    pub fn take_oam(&mut self, now: u64) -> Option<OamTransfer> {
+        let page = self.oam_page.take()?;
+        let odd_cycle = (now / MASTER_CYCLES_PER_CPU_CYCLE) % 2 == 1;
+        let wait_cycles = 1 + odd_cycle as usize;
+        self.stalled_cycles += (OAM_DMA_CYCLES - 1 + wait_cycles) as u64;
+        Some(OamTransfer { page, wait_cycles })
+    }
+
+    pub fn stalled_cycles(&self) -> u64 {
+        self.stalled_cycles
+    }
+}
+
+impl Snapshot for DmaController {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bool(self.oam_page.is_some());
+        out.u8(self.oam_page.unwrap_or(0));
+        out.bool(self.dmc_fetch);
+        out.usize(self.dmc_stall);
+        out.u64(self.stalled_cycles);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        let requested = input.bool()?;
+        let page = input.u8()?;
+        self.oam_page = if requested { Some(page) } else { None };
+        self.dmc_fetch = input.bool()?;
+        self.dmc_stall = input.usize()?;
+        self.stalled_cycles = input.u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Mem;
+    use crate::nes::Nes;
+    use crate::frame::Frame;
+    use crate::render;
+    use crate::rom::Rom;
+
+    #[test]
+    fn test_oam_dma_copies_page_and_stalls_cpu() {
+        // LDA #$02 / STA $4014
+        let mut nes = Nes::new(Rom::_new_test(vec![0xA9, 0x02, 0x8D, 0x14, 0x40]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.pc = 0x8000;
+        for i in 0..256u16 {
+            nes.cpu.bus.mem_write(0x0200 + i, i as u8);
+        }
+
+        nes.step();
+        let before = nes.cpu.bus.scheduler().now();
+        nes.step();
+        let elapsed = (nes.cpu.bus.scheduler().now() - before) / MASTER_CYCLES_PER_CPU_CYCLE;
+
+        assert_eq!(nes.cpu.bus.ppu.oam_data[0x10], 0x10);
+        assert_eq!(nes.cpu.bus.ppu.oam_data[0xFF], 0xFF);
+        // 4 cycles for the STA, then the transfer
+        let stall = nes.cpu.bus.dma().stalled_cycles();
+        assert!(stall == 513 || stall == 514);
+        assert_eq!(elapsed, 4 + stall);
+    }
+
+    #[test]
+    fn test_odd_cycle_start_adds_alignment_cycle() {
+        let mut dma = DmaController::new();
+        dma.start_oam(0x03);
+        assert!(dma.pending());
+        assert_eq!(dma.take_oam(0), Some(OamTransfer { page: 0x03, wait_cycles: 1 }));
+        assert!(!dma.pending());
+        assert_eq!(dma.stalled_cycles(), 513);
+
+        dma.start_oam(0x03);
+        assert_eq!(dma.take_oam(MASTER_CYCLES_PER_CPU_CYCLE), Some(OamTransfer { page: 0x03, wait_cycles: 2 }));
+        assert_eq!(dma.take_oam(0), None);
+        assert_eq!(dma.stalled_cycles(), 513 + 514);
+    }
+
+    #[test]
+    fn test_oam_dma_stays_within_the_page() {
+        let transfer = OamTransfer { page: 0xFF, wait_cycles: 1 };
+        assert_eq!((transfer.source(0), transfer.source(0xFF)), (0xFF00, 0xFFFF));
+
+        // LDA #$FF / STA $4014 copies the top page of ROM, vectors included
+        let mut program = vec![0xA9, 0xFF, 0x8D, 0x14, 0x40];
+        program.resize(0x4000, 0xEA);
+        program[0x3FFF] = 0x5A;
+        let mut nes = Nes::new(Rom::_new_test(program).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.pc = 0x8000;
+        nes.step();
+        nes.step();
+        assert_eq!(nes.cpu.bus.ppu.oam_data[0x00], 0xEA);
+        assert_eq!(nes.cpu.bus.ppu.oam_data[0xFF], 0x5A);
+    }
+
+    #[test]
+    fn test_dmc_fetch_halts_next_read() {
+        let mut dma = DmaController::new();
+        assert!(!dma.take_dmc_fetch());
+
+        dma.request_dmc_fetch();
+        assert!(dma.take_dmc_fetch());
+        assert!(!dma.take_dmc_fetch());
+        assert!(dma.pending());
+        assert_eq!(dma.take_dmc_stall(), DMC_DMA_CYCLES);
+        assert!(!dma.pending());
+    }
+
+    #[test]
+    fn test_dma_and_chr_writes_while_rendering() {
+        // Renders every frame from the NMI callback while the program keeps the
+        // cartridge busy: CHR RAM writes and reads through $2007 and OAM DMA from
+        // PRG RAM, which the mapper serves
+        let mut program = vec![
+            0xA9, 0x80, 0x8D, 0x00, 0x20, // LDA #$80 / STA $2000 (NMI on)
+            0xA9, 0x1E, 0x8D, 0x01, 0x20, // LDA #$1E / STA $2001 (rendering on)
+            // loop:
+            0xA9, 0x00, 0x8D, 0x06, 0x20, // LDA #$00 / STA $2006
+            0x8D, 0x06, 0x20,             // STA $2006 (CHR address $0000)
+            0xA9, 0x55, 0x8D, 0x07, 0x20, // LDA #$55 / STA $2007
+            0xAD, 0x07, 0x20,             // LDA $2007
+            0xA9, 0x60, 0x8D, 0x14, 0x40, // LDA #$60 / STA $4014 (DMA from $6000)
+            0x4C, 0x0A, 0x80,             // JMP loop
+        ];
+        program.resize(0x4000, 0xEA);
+        program[0x3F00] = 0x40; // RTI
+        program[0x3FFA] = 0x00; // NMI vector $BF00 (the bank is mirrored up to $C000)
+        program[0x3FFB] = 0xBF;
+
+        let mut frames = 0;
+        let mut frame = Frame::new();
+        let mut nes = Nes::new(Rom::_new_test(program).unwrap(), |ppu, _joypad1| {
+            render::render(ppu, &mut frame);
+            frames += 1;
+        }).unwrap();
+        nes.cpu.pc = 0x8000;
+        nes.cpu.bus.mem_write(0x6000, 0x42);
+
+        while nes.cpu.bus.ppu.timing().frame < 3 {
+            nes.step();
+        }
+        drop(nes);
+
+        assert!(frames >= 2);
+        assert!(frame.data.iter().any(|&byte| byte != 0));
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// Register the device answers reads on. 0x4018-0x401F is the disabled APU test
+// range on retail consoles so nothing else on the bus lives there.
+pub const ENTROPY_REGISTER: u16 = 0x4018;
+
+// Optional random byte source that demo/test roms can read from.
+// Seeding the device makes every byte it produces reproducible between runs,
+// which is what headless tests want. Without a seed one is picked at random
+// (and can be read back with seed() to reproduce the run later).
+pub struct EntropyDevice {
+    state: u64,
+    seed: u64,
+    last: u8,
+    feed_addr: Option<u16>, // RAM address refreshed before every instruction (legacy demos)
+}
+
+impl EntropyDevice {
+    // Some demo roms (like the snake game) poll a zero page byte for randomness
+    // instead of a register. Passing a feed address makes the bus write a fresh
+    // value there before every instruction, like the old frontend loop did.
+    pub fn new(seed: Option<u64>, feed_addr: Option<u16>) -> Self {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64)
+        });
+        EntropyDevice {
+            state: seed,
+            seed,
+            last: 0,
+            feed_addr,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn feed_addr(&self) -> Option<u16> {
+        self.feed_addr
+    }
+
+    // Called upon ENTROPY_REGISTER reads
+    pub fn read(&mut self) -> u8 {
+        self.last = (self.next_u64() >> 56) as u8;
+        self.last
+    }
+
+    // splitmix64 (https://prng.di.unimi.it/splitmix64.c), plenty for games and
+    // small enough not to need a crate
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    // Returns the last byte handed out without advancing the sequence
+    pub fn peek(&self) -> u8 {
+        self.last
+    }
+}
+
+impl Snapshot for EntropyDevice {
+    // Where the sequence is, so a loaded state hands out the same bytes again.
+    // The seed and feed address are the frontend's settings.
+    fn save_state(&self, out: &mut StateWriter) {
+        out.u64(self.state);
+        out.u8(self.last);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.state = input.u64()?;
+        self.last = input.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_bytes() {
+        let mut a = EntropyDevice::new(Some(1234), None);
+        let mut b = EntropyDevice::new(Some(1234), None);
+
+        for _ in 0..64 {
+            assert_eq!(a.read(), b.read());
+        }
+    }
+
+    #[test]
+    fn test_peek_does_not_advance() {
+        let mut device = EntropyDevice::new(Some(7), None);
+        let value = device.read();
+
+        assert_eq!(device.peek(), value);
+        assert_eq!(device.peek(), value);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// The Family BASIC keyboard (HVC-007), plugged into the Famicom's expansion port.
+// It's a matrix of 9 rows by 2 columns of 4 keys each, scanned through the
+// controller registers:
+//
+//   $4016 write  bit 0 = back to row 0 column 0, bit 1 = column, bit 2 = keyboard on
+//   $4017 read   bits 1-4 = the 4 keys at the current row and column, 0 = pressed
+//
+// Switching from column 1 back to column 0 moves on to the next row. Reading past
+// the last row gives no keys pressed, which is how Family BASIC knows a keyboard
+// is there; with bit 2 clear the keyboard doesn't drive the bus at all.
+//
+// The host keyboard is passed through key for key. Keys are named the way SDL
+// names them (Keycode::name), laid out like this:
+//
+//   Row | Column 0 (bit 1, 2, 3, 4)       | Column 1 (bit 1, 2, 3, 4)
+//   0   | ]  [  RETURN  F8                | STOP  ¥  RSHIFT  KANA
+//   1   | ;  :  @  F7                     | ^  -  /  _
+//   2   | K  L  O  F6                     | 0  P  ,  .
+//   3   | J  U  I  F5                     | 8  9  N  M
+//   4   | H  G  Y  F4                     | 6  7  V  B
+//   5   | D  R  T  F3                     | 4  5  C  F
+//   6   | A  S  W  F2                     | 3  E  Z  X
+//   7   | CTR  Q  ESC  F1                 | 2  1  GRPH  LSHIFT
+//   8   | LEFT  RIGHT  UP  CLR HOME       | INS  DEL  SPACE  DOWN
+//
+// Keys a PC keyboard doesn't have are on nearby ones: : is ', @ is `, ^ is =,
+// ¥ is \, _ is Right Ctrl, STOP is Pause, KANA is Right Alt, GRPH is Left Alt
+// and CLR HOME is Home. Backspace is DEL as well.
+
+const ROWS: usize = 9;
+
+// Host key for each position, row by row, column 0 then 1, bit 1 first
+const MATRIX: [[&str; 8]; ROWS] = [
+    ["]", "[", "Return", "F8", "Pause", "\\", "Right Shift", "Right Alt"],
+    [";", "'", "`", "F7", "=", "-", "/", "Right Ctrl"],
+    ["K", "L", "O", "F6", "0", "P", ",", "."],
+    ["J", "U", "I", "F5", "8", "9", "N", "M"],
+    ["H", "G", "Y", "F4", "6", "7", "V", "B"],
+    ["D", "R", "T", "F3", "4", "5", "C", "F"],
+    ["A", "S", "W", "F2", "3", "E", "Z", "X"],
+    ["Left Ctrl", "Q", "Escape", "F1", "2", "1", "Left Alt", "Left Shift"],
+    ["Left", "Right", "Up", "Home", "Insert", "Delete", "Space", "Down"],
+];
+
+const ALIASES: [(&str, &str); 1] = [("Backspace", "Delete")];
+
+// Which keys are down, one bit per matrix position (row * 8 + column * 4 + bit)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyMatrix(u128);
+
+impl KeyMatrix {
+    // Presses or releases the key a host key is passed through to. Returns false
+    // for host keys the keyboard doesn't have.
+    pub fn set_host_key(&mut self, name: &str, pressed: bool) -> bool {
+        let name = ALIASES.iter().find(|(alias, _)| *alias == name).map_or(name, |(_, key)| *key);
+        let Some(position) = MATRIX.iter().flatten().position(|key| *key == name) else {
+            return false;
+        };
+        if pressed {
+            self.0 |= 1 << position;
+        } else {
+            self.0 &= !(1 << position);
+        }
+        true
+    }
+
+    pub fn release_all(&mut self) {
+        self.0 = 0;
+    }
+
+    // The 4 keys at a row and column, bit 0 for the first
+    fn keys(&self, row: usize, column: usize) -> u8 {
+        ((self.0 >> (row * 8 + column * 4)) & 0x0F) as u8
+    }
+}
+
+pub struct FamilyKeyboard {
+    keys: KeyMatrix,
+    row: usize,
+    column: usize,
+    enabled: bool,
+}
+
+impl Default for FamilyKeyboard {
+    fn default() -> Self {
+        FamilyKeyboard::new()
+    }
+}
+
+impl FamilyKeyboard {
+    pub fn new() -> Self {
+        FamilyKeyboard { keys: KeyMatrix::default(), row: 0, column: 0, enabled: false }
+    }
+
+    // The frontend hands over the host keys once per frame
+    pub fn set_keys(&mut self, keys: KeyMatrix) {
+        self.keys = keys;
+    }
+
+    // Called upon $4016 writes
+    pub fn write(&mut self, data: u8) {
+        let previous_column = self.column;
+        self.column = ((data >> 1) & 1) as usize;
+        self.enabled = data & 0x04 != 0;
+        if !self.enabled {
+            return;
+        }
+        if previous_column == 1 && self.column == 0 {
+            self.row = (self.row + 1).min(ROWS);
+        }
+        if data & 0x01 != 0 {
+            self.row = 0;
+        }
+    }
+
+    // Bits 1-4 of $4017 reads, reading has no side effects
+    pub fn read(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        if self.row >= ROWS {
+            return 0x1E;
+        }
+        (!self.keys.keys(self.row, self.column) & 0x0F) << 1
+    }
+}
+
+impl Snapshot for FamilyKeyboard {
+    // Where the scan is, the keys are whatever the player holds now
+    fn save_state(&self, out: &mut StateWriter) {
+        out.usize(self.row);
+        out.usize(self.column);
+        out.bool(self.enabled);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.row = input.usize()?.min(ROWS);
+        self.column = input.usize()? & 1;
+        self.enabled = input.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scanning_the_matrix() {
+        let mut keys = KeyMatrix::default();
+        assert!(keys.set_host_key("Return", true)); // row 0, column 0, bit 3
+        assert!(keys.set_host_key("Backspace", true)); // DEL: row 8, column 1, bit 2
+        assert!(!keys.set_host_key("F12", true));
+
+        let mut keyboard = FamilyKeyboard::new();
+        keyboard.set_keys(keys);
+        assert_eq!(keyboard.read(), 0); // off until bit 2 is written
+
+        keyboard.write(0x05); // reset to row 0, column 0
+        assert_eq!(keyboard.read(), 0x1E & !0x08);
+        keyboard.write(0x06); // column 1
+        assert_eq!(keyboard.read(), 0x1E);
+
+        // Each column 1 -> 0 switch is a new row
+        for _ in 0..8 {
+            keyboard.write(0x04);
+            keyboard.write(0x06);
+        }
+        assert_eq!(keyboard.read(), 0x1E & !0x04);
+        keyboard.write(0x04);
+        keyboard.write(0x06);
+        assert_eq!(keyboard.read(), 0x1E); // past the last row
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use crate::frameskip::NTSC_FRAME_TIME;
+
+// What the emulator does while its window doesn't have focus:
+//
+//   run      -> keep going as normal
+//   pause    -> stop emulating until the window gets focus back
+//   throttle -> keep going at 10% speed, so a game left in the background (an
+//               idle farm, a long cutscene) still moves without eating a CPU core
+//
+// Keys held when focus went away are released, since their key up events go to
+// whichever window has focus instead.
+
+const THROTTLE_SPEED: u32 = 10; // percent of normal speed
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackgroundMode {
+    Run,
+    Pause,
+    Throttle,
+}
+
+impl BackgroundMode {
+    pub fn parse(value: &str) -> Result<BackgroundMode, String> {
+        match value {
+            "run" => Ok(BackgroundMode::Run),
+            "pause" => Ok(BackgroundMode::Pause),
+            "throttle" => Ok(BackgroundMode::Throttle),
+            _ => Err(format!("Background must be run, pause or throttle but got \"{}\"", value)),
+        }
+    }
+}
+
+pub struct FocusTracker {
+    mode: BackgroundMode,
+    focused: bool,
+}
+
+impl FocusTracker {
+    pub fn new(mode: BackgroundMode) -> Self {
+        FocusTracker { mode, focused: true }
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    // Whether emulation should stop until focus comes back
+    pub fn paused(&self) -> bool {
+        !self.focused && self.mode == BackgroundMode::Pause
+    }
+
+    // The least time a frame should take right now, None to run at normal speed
+    pub fn min_frame_time(&self) -> Option<Duration> {
+        if !self.focused && self.mode == BackgroundMode::Throttle {
+            Some(NTSC_FRAME_TIME * 100 / THROTTLE_SPEED)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_background_modes() {
+        let mut pause = FocusTracker::new(BackgroundMode::parse("pause").unwrap());
+        assert!(!pause.paused());
+        pause.set_focused(false);
+        assert!(pause.paused());
+        assert_eq!(pause.min_frame_time(), None);
+        pause.set_focused(true);
+        assert!(!pause.paused());
+
+        let mut throttle = FocusTracker::new(BackgroundMode::Throttle);
+        assert_eq!(throttle.min_frame_time(), None);
+        throttle.set_focused(false);
+        assert!(!throttle.paused());
+        assert_eq!(throttle.min_frame_time(), Some(NTSC_FRAME_TIME * 10));
+
+        let mut run = FocusTracker::new(BackgroundMode::Run);
+        run.set_focused(false);
+        assert!(!run.paused() && run.min_frame_time().is_none());
+        assert!(BackgroundMode::parse("sleep").is_err());
+    }
+}
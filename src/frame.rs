@@ -1,36 +1,201 @@
+// How a frame's pixels are laid out in `data`, row by row from the top left.
+// Rgba8888 is R, G, B then an opaque A byte, what GPU frontends upload without
+// converting (SDL's PixelFormatEnum::RGBA32).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgb24,
+    Rgba8888,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgb24 => 3,
+            PixelFormat::Rgba8888 => 4,
+        }
+    }
+}
+
 pub struct Frame {
     pub data: Vec<u8>,
-    pub transparency: Vec<bool>
+    pub transparency: Vec<bool>,
+    sprite: Vec<bool>, // a sprite already claimed this pixel
+    indices: Option<Vec<u16>>, // the PPU's output per pixel, see keep_indices
+    width: usize,
+    height: usize,
+    format: PixelFormat,
 }
 
 impl Frame {
     const WIDTH: usize = 256;
     const HEIGHT: usize = 240;
 
+    // The NES screen, 256x240 RGB
     pub fn new() -> Self {
+        Frame::with_size(Frame::WIDTH, Frame::HEIGHT, PixelFormat::Rgb24)
+    }
+
+    // Any size, for debug views like all four nametables at 512x480
+    pub fn with_size(width: usize, height: usize, format: PixelFormat) -> Self {
+        let mut data = vec![0; width * height * format.bytes_per_pixel()];
+        if format == PixelFormat::Rgba8888 {
+            data.chunks_mut(4).for_each(|pixel| pixel[3] = 0xFF);
+        }
         Frame {
-            data: vec![0; Frame::WIDTH * Frame::HEIGHT * 3], // dimensions of screen * 3 colors per pixel
-            transparency: vec![true; Frame::WIDTH * Frame::HEIGHT]    
+            data,
+            transparency: vec![true; width * height],
+            sprite: vec![false; width * height],
+            indices: None,
+            width,
+            height,
+            format,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    // Bytes per row, for texture uploads
+    pub fn pitch(&self) -> usize {
+        self.width * self.format.bytes_per_pixel()
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let offset = y * self.pitch() + x * self.format.bytes_per_pixel();
+        (self.data[offset], self.data[offset + 1], self.data[offset + 2])
+    }
+
+    // Also keep the palette index of every pixel the renderer draws (see
+    // NesPPU::output_index), for comparing against reference emulators exactly
+    // and for filters that work on the signal rather than on RGB. Off by default,
+    // the overlays drawn over the picture don't show up in it.
+    pub fn keep_indices(&mut self) {
+        self.indices.get_or_insert_with(|| vec![0; self.width * self.height]);
+    }
+
+    // Row by row like `data`, None unless keep_indices was called
+    pub fn indices(&self) -> Option<&[u16]> {
+        self.indices.as_deref()
+    }
+
+    pub fn index(&self, x: usize, y: usize) -> Option<u16> {
+        self.indices.as_ref().map(|indices| indices[y * self.width + x])
+    }
+
+    // Sprite pixels, drawn after the background and in OAM order. Like the PPU's
+    // sprite multiplexer the first opaque sprite pixel wins a spot even when it's
+    // behind the background, so a later sprite can't show through there either.
+    pub fn check_and_set(&mut self, trans: bool, priority: bool, x: usize, y: usize, index: u16, color: (u8, u8, u8)) {
+        if x >= self.width || y >= self.height || trans || self.sprite[y * self.width + x] {
+            return;
+        }
+        self.sprite[y * self.width + x] = true;
+        if priority || self.transparency[y * self.width + x] {
+            self.put(x, y, color);
+            self.put_index(x, y, index);
         }
     }
 
-    pub fn check_and_set(&mut self, trans: bool, priority: bool, x: usize, y: usize, color: (u8, u8, u8)) {
-        let actual_coord = (y * (Frame::WIDTH * 3)) + (x * 3);
-        if actual_coord + 2 < self.data.len() && (priority || self.transparency[actual_coord/3]){
-            self.transparency[actual_coord/3] = trans;
-            self.data[actual_coord] = color.0;
-            self.data[actual_coord + 1] = color.1;
-            self.data[actual_coord + 2] = color.2;
+    // A background pixel from the renderer, with its palette index
+    pub fn set_indexed_pixel(&mut self, trans: bool, x: usize, y: usize, index: u16, color: (u8, u8, u8)) {
+        if x >= self.width || y >= self.height {
+            return;
         }
+        self.set_pixel(trans, x, y, color);
+        self.put_index(x, y, index);
     }
 
+    // Pixels off the edge are dropped
     pub fn set_pixel(&mut self, trans: bool, x: usize, y: usize, color: (u8, u8, u8)) {
-        let actual_coord = (y * (Frame::WIDTH * 3)) + (x * 3);
-        if actual_coord + 2 < self.data.len() {
-            self.transparency[actual_coord/3] = trans;
-            self.data[actual_coord] = color.0;
-            self.data[actual_coord + 1] = color.1;
-            self.data[actual_coord + 2] = color.2;
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        self.transparency[y * self.width + x] = trans;
+        self.sprite[y * self.width + x] = false;
+        self.put(x, y, color);
+    }
+
+    fn put_index(&mut self, x: usize, y: usize, index: u16) {
+        if let Some(indices) = self.indices.as_mut() {
+            indices[y * self.width + x] = index;
+        }
+    }
+
+    fn put(&mut self, x: usize, y: usize, color: (u8, u8, u8)) {
+        let offset = y * self.pitch() + x * self.format.bytes_per_pixel();
+        self.data[offset] = color.0;
+        self.data[offset + 1] = color.1;
+        self.data[offset + 2] = color.2;
+    }
+
+    // Encodes the frame as a 24 bit BMP file (uncompressed, so no extra dependencies)
+    pub fn to_bmp(&self) -> Vec<u8> {
+        let row_size = (self.width * 3).next_multiple_of(4); // rows are padded to 4 bytes
+        let image_size = row_size * self.height;
+        let file_size = 54 + image_size;
+
+        let mut bmp = Vec::with_capacity(file_size);
+        bmp.extend(b"BM");
+        bmp.extend((file_size as u32).to_le_bytes());
+        bmp.extend([0u8; 4]); // reserved
+        bmp.extend(54u32.to_le_bytes()); // pixel data offset
+        bmp.extend(40u32.to_le_bytes()); // info header size
+        bmp.extend((self.width as i32).to_le_bytes());
+        bmp.extend((self.height as i32).to_le_bytes()); // positive height = rows stored bottom up
+        bmp.extend(1u16.to_le_bytes()); // planes
+        bmp.extend(24u16.to_le_bytes()); // bits per pixel
+        bmp.extend(0u32.to_le_bytes()); // no compression
+        bmp.extend((image_size as u32).to_le_bytes());
+        bmp.extend([0u8; 16]); // resolution and palette info, unused
+
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+        for row in self.data.chunks(self.pitch()).rev() {
+            for pixel in row.chunks(bytes_per_pixel) {
+                bmp.extend([pixel[2], pixel[1], pixel[0]]); // BMP stores BGR
+            }
+            bmp.resize(bmp.len() + row_size - self.width * 3, 0);
         }
+        bmp
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bmp_layout() {
+        let mut frame = Frame::new();
+        frame.set_pixel(false, 0, 239, (1, 2, 3)); // bottom left is the first pixel stored
+
+        let bmp = frame.to_bmp();
+        assert_eq!(bmp.len(), 54 + 256 * 240 * 3);
+        assert_eq!(&bmp[0..2], b"BM");
+        assert_eq!(&bmp[54..57], &[3, 2, 1]);
+    }
+
+    #[test]
+    fn test_sizes_and_formats() {
+        let mut frame = Frame::with_size(5, 2, PixelFormat::Rgba8888);
+        assert_eq!(frame.pitch(), 20);
+        frame.set_pixel(false, 4, 1, (1, 2, 3));
+        frame.set_pixel(false, 5, 0, (9, 9, 9)); // off the right edge, not wrapped onto row 1
+        assert_eq!(&frame.data[36..40], &[1, 2, 3, 0xFF]);
+        assert_eq!(frame.pixel(0, 1), (0, 0, 0));
+        assert_eq!(frame.data[3], 0xFF); // opaque from the start
+
+        // 5 pixels of RGB is 15 bytes, padded to 16 in a BMP
+        let bmp = frame.to_bmp();
+        assert_eq!(bmp.len(), 54 + 16 * 2);
+        assert_eq!(&bmp[54 + 12..54 + 15], &[3, 2, 1]);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,140 @@
+use std::time::{Duration, Instant};
+
+// Frame skipping: every frame is still emulated, but drawing it to the window is
+// skipped for some of them so slow machines can keep the game at full speed.
+//
+//   off   -> draw every frame
+//   N/M   -> skip drawing N out of every M frames
+//   auto  -> pick how many out of AUTO_WINDOW frames to skip from measured frame times
+
+pub const NTSC_FRAME_TIME: Duration = Duration::from_nanos(16_639_267); // ~60.1 fps
+pub const AUTO_WINDOW: u32 = 4;
+const AUTO_MAX_SKIP: u32 = AUTO_WINDOW - 1; // always draw at least one frame per window
+const AUTO_BUDGET: f64 = 0.95; // leave a little headroom under a full frame
+const AVERAGE_WEIGHT: f64 = 0.05; // smoothing for the measured times
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameSkipMode {
+    Off,
+    Fixed { skip: u32, out_of: u32 },
+    Auto,
+}
+
+impl FrameSkipMode {
+    pub fn parse(value: &str) -> Result<FrameSkipMode, String> {
+        match value {
+            "off" | "0" => return Ok(FrameSkipMode::Off),
+            "auto" => return Ok(FrameSkipMode::Auto),
+            _ => {}
+        }
+
+        let (skip, out_of) = value
+            .split_once('/')
+            .ok_or(format!("Frame skip must be off, auto or N/M but got \"{}\"", value))?;
+        let skip: u32 = skip.trim().parse().map_err(|_| format!("Bad frame skip count \"{}\"", skip))?;
+        let out_of: u32 = out_of.trim().parse().map_err(|_| format!("Bad frame skip window \"{}\"", out_of))?;
+        if out_of == 0 || skip >= out_of {
+            return Err(format!("Frame skip {}/{} would never draw a frame", skip, out_of));
+        }
+
+        Ok(FrameSkipMode::Fixed { skip, out_of })
+    }
+}
+
+pub struct FrameSkipper {
+    mode: FrameSkipMode,
+    counter: u32,
+    auto_skip: u32,
+    emulate_secs: f64, // average time spent emulating one frame
+    render_secs: f64,  // average time spent drawing one frame (not counting vsync waits)
+}
+
+impl FrameSkipper {
+    pub fn new(mode: FrameSkipMode) -> Self {
+        FrameSkipper {
+            mode,
+            counter: 0,
+            auto_skip: 0,
+            emulate_secs: 0.0,
+            render_secs: 0.0,
+        }
+    }
+
+    // How many frames out of how many are currently being skipped
+    pub fn skipping(&self) -> (u32, u32) {
+        match self.mode {
+            FrameSkipMode::Off => (0, 1),
+            FrameSkipMode::Fixed { skip, out_of } => (skip, out_of),
+            FrameSkipMode::Auto => (self.auto_skip, AUTO_WINDOW),
+        }
+    }
+
+    // Call once per emulated frame. Returns whether that frame should be drawn.
+    pub fn should_render(&mut self) -> bool {
+        let (skip, out_of) = self.skipping();
+        let position = self.counter % out_of;
+        self.counter = self.counter.wrapping_add(1);
+        position >= skip
+    }
+
+    // Feeds back how long the last frame took to emulate and, if it was drawn, to draw.
+    // Only used by auto mode to decide how many frames need skipping.
+    pub fn record(&mut self, emulate: Duration, render: Option<Duration>) {
+        self.emulate_secs += (emulate.as_secs_f64() - self.emulate_secs) * AVERAGE_WEIGHT;
+        if let Some(render) = render {
+            self.render_secs += (render.as_secs_f64() - self.render_secs) * AVERAGE_WEIGHT;
+        }
+
+        if self.mode != FrameSkipMode::Auto {
+            return;
+        }
+
+        // Skip as few frames as possible while the average cost fits in one frame's time
+        let budget = NTSC_FRAME_TIME.as_secs_f64() * AUTO_BUDGET;
+        self.auto_skip = (0..=AUTO_MAX_SKIP)
+            .find(|skip| {
+                let drawn = (AUTO_WINDOW - skip) as f64 / AUTO_WINDOW as f64;
+                self.emulate_secs + self.render_secs * drawn <= budget
+            })
+            .unwrap_or(AUTO_MAX_SKIP);
+    }
+}
+
+// Skipped frames never wait on vsync, so sleep out the rest of the frame
+// to keep the game running at its normal speed
+pub fn throttle(frame_start: Instant) {
+    let elapsed = frame_start.elapsed();
+    if elapsed < NTSC_FRAME_TIME {
+        std::thread::sleep(NTSC_FRAME_TIME - elapsed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fixed_skip_pattern() {
+        let mut skipper = FrameSkipper::new(FrameSkipMode::parse("2/3").unwrap());
+        let drawn: Vec<bool> = (0..6).map(|_| skipper.should_render()).collect();
+        assert_eq!(drawn, vec![false, false, true, false, false, true]);
+
+        assert!(FrameSkipMode::parse("3/3").is_err());
+        assert_eq!(FrameSkipMode::parse("off").unwrap(), FrameSkipMode::Off);
+    }
+
+    #[test]
+    fn test_auto_engages_when_drawing_is_slow() {
+        let mut skipper = FrameSkipper::new(FrameSkipMode::Auto);
+        for _ in 0..200 {
+            skipper.record(Duration::from_millis(4), Some(Duration::from_millis(4)));
+        }
+        assert_eq!(skipper.skipping(), (0, AUTO_WINDOW));
+
+        // 8ms emulating + 14ms drawing only fits when half the frames are skipped
+        for _ in 0..200 {
+            skipper.record(Duration::from_millis(8), Some(Duration::from_millis(14)));
+        }
+        assert_eq!(skipper.skipping(), (2, AUTO_WINDOW));
+    }
+}
@@ -0,0 +1,40 @@
+// CRC32 (IEEE, the same one zip and No-Intro/NesCartDB use) for identifying roms
+
+const CRC32_POLY: u32 = 0xEDB88320;
+
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+// Continue a crc from a previous value so multiple slices can be hashed as one
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            if crc & 1 == 1 {
+                crc = (crc >> 1) ^ CRC32_POLY;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn test_crc32_update_matches_single_pass() {
+        let crc = crc32_update(crc32(b"1234"), b"56789");
+        assert_eq!(crc, crc32(b"123456789"));
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+
+use crate::hash::{crc32, crc32_update};
+use crate::rom::{Mirroring, NES_TAG};
+use crate::romdb::{self, RomInfo};
+
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_ROM_PAGE_SIZE: usize = 16384;
+const CHR_ROM_PAGE_SIZE: usize = 8192;
+
+// Problems the header checker knows how to detect (and fix)
+#[derive(Debug, PartialEq)]
+pub enum HeaderIssue {
+    // Bytes 7-15 were overwritten by an old dumping tool ("DiskDude!" is the classic one),
+    // which also corrupts the upper nibble of the mapper number
+    GarbageTail,
+    WrongMapper { found: u8, expected: u8 },
+    WrongMirroring { found: Mirroring, expected: Mirroring },
+}
+
+impl fmt::Display for HeaderIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderIssue::GarbageTail => write!(f, "Bytes 7-15 contain garbage (e.g. \"DiskDude!\"), cleared them"),
+            HeaderIssue::WrongMapper { found, expected } => {
+                write!(f, "Header says mapper {} but the database says mapper {}", found, expected)
+            }
+            HeaderIssue::WrongMirroring { found, expected } => {
+                write!(f, "Header says {:?} mirroring but the database says {:?}", found, expected)
+            }
+        }
+    }
+}
+
+fn header_mirroring(raw: &[u8]) -> Mirroring {
+    match (raw[6] & 0b1000 != 0, raw[6] & 0b1 != 0) {
+        (true, _) => Mirroring::FOURSCREEN,
+        (false, true) => Mirroring::VERTICAL,
+        (false, false) => Mirroring::HORIZONTAL,
+    }
+}
+
+// Hash the rom data the same way Rom::new does so the database lookup matches
+fn data_crc(raw: &[u8]) -> Option<u32> {
+    let mut prg_start = HEADER_SIZE;
+    if raw[6] & 0b100 != 0 {
+        prg_start += TRAINER_SIZE;
+    }
+    let prg_end = prg_start + raw[4] as usize * PRG_ROM_PAGE_SIZE;
+    let chr_end = prg_end + raw[5] as usize * CHR_ROM_PAGE_SIZE;
+    if chr_end > raw.len() {
+        return None;
+    }
+
+    let crc = crc32(&raw[prg_start..prg_end]);
+    Some(crc32_update(crc, &raw[prg_end..chr_end]))
+}
+
+// Returns a repaired copy of the rom file plus every issue that was fixed.
+// An empty issue list means the header was already fine.
+pub fn repair_header(raw: &[u8]) -> Result<(Vec<u8>, Vec<HeaderIssue>), String> {
+    repair_with_database(raw, &romdb::ROM_DATABASE)
+}
+
+fn repair_with_database(raw: &[u8], database: &[RomInfo]) -> Result<(Vec<u8>, Vec<HeaderIssue>), String> {
+    if raw.len() < HEADER_SIZE || raw[0..4] != NES_TAG {
+        return Err("File is not in iNES file format".to_string());
+    }
+
+    let mut fixed = raw.to_vec();
+    let mut issues = vec![];
+
+    // iNES 1.0 requires bytes 12-15 to be zero, anything there is left over from a tool
+    let is_nes2 = (fixed[7] >> 2) & 0b11 == 0b10;
+    if &fixed[7..HEADER_SIZE] == b"DiskDude!" || (!is_nes2 && fixed[12..HEADER_SIZE].iter().any(|b| *b != 0)) {
+        for byte in fixed[7..HEADER_SIZE].iter_mut() {
+            *byte = 0;
+        }
+        issues.push(HeaderIssue::GarbageTail);
+    }
+
+    let crc = data_crc(&fixed).ok_or("Rom is smaller than its header claims")?;
+    if let Some(info) = database.iter().find(|info| info.crc == crc) {
+        let found_mapper = (fixed[7] & 0b1111_0000) | (fixed[6] >> 4);
+        if found_mapper != info.mapper_id {
+            fixed[6] = (fixed[6] & 0b0000_1111) | (info.mapper_id << 4);
+            fixed[7] = (fixed[7] & 0b0000_1111) | (info.mapper_id & 0b1111_0000);
+            issues.push(HeaderIssue::WrongMapper { found: found_mapper, expected: info.mapper_id });
+        }
+
+        let found_mirroring = header_mirroring(&fixed);
+        if let Some(expected) = info.mirroring
+            && found_mirroring != expected
+        {
+            fixed[6] &= 0b1111_0110;
+            match expected {
+                Mirroring::VERTICAL => fixed[6] |= 0b0001,
+                Mirroring::FOURSCREEN => fixed[6] |= 0b1000,
+                _ => {}
+            }
+            issues.push(HeaderIssue::WrongMirroring { found: found_mirroring, expected });
+        }
+    }
+
+    Ok((fixed, issues))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rom_with_header(header: [u8; 16]) -> Vec<u8> {
+        let mut raw = header.to_vec();
+        raw.extend(std::iter::repeat_n(0xEA, PRG_ROM_PAGE_SIZE));
+        raw.extend(std::iter::repeat_n(0, CHR_ROM_PAGE_SIZE));
+        raw
+    }
+
+    #[test]
+    fn test_clean_header_is_untouched() {
+        let raw = rom_with_header([0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let (fixed, issues) = repair_header(&raw).unwrap();
+
+        assert!(issues.is_empty());
+        assert_eq!(fixed, raw);
+    }
+
+    #[test]
+    fn test_diskdude_is_cleared() {
+        let mut header = [0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x11, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        header[7..16].copy_from_slice(b"DiskDude!");
+        let raw = rom_with_header(header);
+
+        let (fixed, issues) = repair_header(&raw).unwrap();
+
+        assert_eq!(issues, vec![HeaderIssue::GarbageTail]);
+        assert_eq!(&fixed[7..16], &[0; 9]);
+        assert_eq!(fixed[6], 0x11); // low nibble of the mapper and mirroring survive
+        assert_eq!(&fixed[16..], &raw[16..]);
+    }
+
+    // A one entry database that knows the test rom's data
+    fn database_for(raw: &[u8], mapper_id: u8, mirroring: Option<Mirroring>) -> [RomInfo; 1] {
+        [RomInfo { crc: data_crc(raw).unwrap(), name: "Test", mapper_id, mirroring }]
+    }
+
+    #[test]
+    fn test_wrong_mapper_is_fixed() {
+        // Header says mapper 0 with vertical mirroring, the database says mapper 0x42
+        let raw = rom_with_header([0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let database = database_for(&raw, 0x42, None);
+
+        let (fixed, issues) = repair_with_database(&raw, &database).unwrap();
+
+        assert_eq!(issues, vec![HeaderIssue::WrongMapper { found: 0, expected: 0x42 }]);
+        assert_eq!(fixed[6], 0x21); // mirroring bit survives
+        assert_eq!(fixed[7], 0x40);
+        assert_eq!(&fixed[8..], &raw[8..]);
+    }
+
+    #[test]
+    fn test_wrong_mirroring_is_fixed() {
+        // Mapper 1 with horizontal mirroring in the header, the database says vertical
+        let raw = rom_with_header([0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let database = database_for(&raw, 1, Some(Mirroring::VERTICAL));
+
+        let (fixed, issues) = repair_with_database(&raw, &database).unwrap();
+
+        assert_eq!(issues, vec![HeaderIssue::WrongMirroring { found: Mirroring::HORIZONTAL, expected: Mirroring::VERTICAL }]);
+        assert_eq!(fixed[6], 0x11);
+        assert_eq!(&fixed[7..], &raw[7..]);
+
+        // And the other way around, down to horizontal
+        let raw = fixed;
+        let database = database_for(&raw, 1, Some(Mirroring::HORIZONTAL));
+        let (fixed, _) = repair_with_database(&raw, &database).unwrap();
+        assert_eq!(fixed[6], 0x10);
+    }
+
+    #[test]
+    fn test_not_ines_is_rejected() {
+        assert!(repair_header(&[0; 32]).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+// Which device drives controller 1. In auto mode it's whichever was used last:
+// pressing a key hands the controller to the keyboard, pressing a button on a
+// gamepad hands it to that gamepad, so players can pass it around the couch
+// without touching a setting. The frontend lets go of the buttons the old device
+// was holding and shows the new one on screen.
+//
+//   auto     -> switch to the last used device
+//   keyboard -> only the keyboard, gamepads are ignored
+//   gamepad  -> only gamepads (any of them), the keyboard is ignored
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputDevice {
+    Keyboard,
+    Gamepad(u32), // SDL instance id, stays the same while the pad is plugged in
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    Auto,
+    Keyboard,
+    Gamepad,
+}
+
+impl InputMode {
+    pub fn parse(value: &str) -> Result<InputMode, String> {
+        match value {
+            "auto" => Ok(InputMode::Auto),
+            "keyboard" => Ok(InputMode::Keyboard),
+            "gamepad" => Ok(InputMode::Gamepad),
+            _ => Err(format!("Input must be auto, keyboard or gamepad but got \"{}\"", value)),
+        }
+    }
+}
+
+pub struct InputSwitcher {
+    mode: InputMode,
+    active: InputDevice,
+}
+
+impl InputSwitcher {
+    pub fn new(mode: InputMode) -> Self {
+        InputSwitcher { mode, active: InputDevice::Keyboard }
+    }
+
+    pub fn active(&self) -> InputDevice {
+        self.active
+    }
+
+    // Whether input from a device should reach the controller at all
+    pub fn allows(&self, device: InputDevice) -> bool {
+        match (self.mode, device) {
+            (InputMode::Auto, _) => true,
+            (InputMode::Keyboard, device) => device == InputDevice::Keyboard,
+            (InputMode::Gamepad, device) => device != InputDevice::Keyboard,
+        }
+    }
+
+    // Call when a device presses a button. True if it just took over the controller.
+    pub fn pressed(&mut self, device: InputDevice) -> bool {
+        if !self.allows(device) || device == self.active {
+            return false;
+        }
+        self.active = device;
+        true
+    }
+
+    // Call when a gamepad is unplugged. True if it was the active one, which hands
+    // the controller back to the keyboard.
+    pub fn removed(&mut self, id: u32) -> bool {
+        if self.active != InputDevice::Gamepad(id) {
+            return false;
+        }
+        self.active = InputDevice::Keyboard;
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_last_used_device_takes_over() {
+        let mut auto = InputSwitcher::new(InputMode::parse("auto").unwrap());
+        assert!(!auto.pressed(InputDevice::Keyboard));
+        assert!(auto.pressed(InputDevice::Gamepad(3)));
+        assert!(!auto.pressed(InputDevice::Gamepad(3)));
+        assert!(auto.pressed(InputDevice::Gamepad(4)));
+        assert!(!auto.removed(3));
+        assert!(auto.removed(4));
+        assert_eq!(auto.active(), InputDevice::Keyboard);
+
+        let mut keyboard = InputSwitcher::new(InputMode::Keyboard);
+        assert!(!keyboard.allows(InputDevice::Gamepad(0)));
+        assert!(!keyboard.pressed(InputDevice::Gamepad(0)));
+
+        let mut gamepad = InputSwitcher::new(InputMode::Gamepad);
+        assert!(!gamepad.allows(InputDevice::Keyboard));
+        assert!(gamepad.pressed(InputDevice::Gamepad(0)));
+        assert!(InputMode::parse("mouse").is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+// The last few interrupt related events with the cycle they happened on, for
+// working out ordering bugs: whether a game read $2002 just before or just after
+// vblank was set, whether its NMI fired late, which IRQ came first. Always on (a
+// push per event), dumped with F8 in the frontend.
+
+pub const INTERRUPT_LOG_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptEvent {
+    VblankSet,
+    VblankClear,
+    NmiTriggered, // the PPU raised NMI, at vblank or from a $2000 write during it
+    NmiTaken, // the CPU started its NMI handler
+    StatusRead(u8), // a $2002 read and the value it returned
+    IrqAsserted, // the IRQ line (cart or APU frame counter) went low
+    IrqCleared,
+    IrqTaken,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoggedInterrupt {
+    pub cycle: usize, // CPU cycles since power on
+    pub frame: u64,
+    pub scanline: u16,
+    pub dot: usize,
+    pub event: InterruptEvent,
+}
+
+impl fmt::Display for LoggedInterrupt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cycle {:>10}  frame {:>6}  line {:>3} dot {:>3}  ", self.cycle, self.frame, self.scanline, self.dot)?;
+        match self.event {
+            InterruptEvent::StatusRead(value) => write!(f, "$2002 read -> {:08b}", value),
+            event => write!(f, "{:?}", event),
+        }
+    }
+}
+
+pub struct InterruptLog {
+    events: VecDeque<LoggedInterrupt>,
+    capacity: usize,
+}
+
+impl InterruptLog {
+    pub fn new(capacity: usize) -> Self {
+        InterruptLog { events: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub fn record(&mut self, event: LoggedInterrupt) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    // Oldest first
+    pub fn events(&self) -> impl Iterator<Item = &LoggedInterrupt> {
+        self.events.iter()
+    }
+
+    // One event per line, oldest first
+    pub fn dump(&self) -> String {
+        self.events.iter().map(|event| format!("{}\n", event)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(cycle: usize, event: InterruptEvent) -> LoggedInterrupt {
+        LoggedInterrupt { cycle, frame: 0, scanline: 241, dot: 0, event }
+    }
+
+    #[test]
+    fn test_keeps_the_last_events() {
+        let mut log = InterruptLog::new(2);
+        log.record(at(1, InterruptEvent::VblankSet));
+        log.record(at(2, InterruptEvent::StatusRead(0x80)));
+        log.record(at(3, InterruptEvent::NmiTaken));
+
+        let cycles: Vec<usize> = log.events().map(|event| event.cycle).collect();
+        assert_eq!(cycles, vec![2, 3]);
+        assert!(log.dump().starts_with("cycle          2"));
+        assert!(log.dump().contains("$2002 read -> 10000000"));
+    }
+}
@@ -1,8 +1,10 @@
 use bitflags::bitflags;
 
+use crate::state::{Snapshot, StateReader, StateWriter};
+
 bitflags! {
     // https://wiki.nesdev.com/w/index.php/Controller_reading_code
-    #[derive(Copy, Clone)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub struct JoypadButton: u8 {
         const RIGHT             = 0b10000000;
         const LEFT              = 0b01000000;
@@ -15,10 +17,50 @@ bitflags! {
     }
 }
 
+impl JoypadButton {
+    // Parses a button name as used in scripts and config files ("a", "start", "up", ...)
+    pub fn from_label(name: &str) -> Option<JoypadButton> {
+        match name.to_ascii_lowercase().as_str() {
+            "right" => Some(JoypadButton::RIGHT),
+            "left" => Some(JoypadButton::LEFT),
+            "down" => Some(JoypadButton::DOWN),
+            "up" => Some(JoypadButton::UP),
+            "start" => Some(JoypadButton::START),
+            "select" => Some(JoypadButton::SELECT),
+            "b" => Some(JoypadButton::BUTTON_B),
+            "a" => Some(JoypadButton::BUTTON_A),
+            _ => None,
+        }
+    }
+
+    // Names of the buttons held, spelled the way from_label reads them
+    pub fn labels(&self) -> Vec<&'static str> {
+        ["up", "down", "left", "right", "select", "start", "b", "a"]
+            .into_iter()
+            .filter(|label| self.contains(JoypadButton::from_label(label).unwrap()))
+            .collect()
+    }
+}
+
+// The controller's state as a debugger shows it, see Joypad::snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JoypadSnapshot {
+    pub buttons: JoypadButton, // held right now
+    pub latched: JoypadButton, // what the shift register holds
+    pub strobe: bool,
+    pub index: u8, // next bit out of $4016, 8 once all are read
+}
+
+// The controller's shift register loads the buttons while strobe ($4016 bit 0) is
+// high, and keeps that copy once it goes low, so a button pressed halfway through
+// the 8 reads only shows up on the next strobe. While strobe is high every read
+// returns A as it is right now.
 pub struct Joypad {
     strobe_status: bool,
     button_index: u8,
     button_status: JoypadButton,
+    latched: JoypadButton, // loaded when strobe goes low
+    injected: JoypadButton, // held by a program rather than the frontend, see Nes::set_controller_state
 }
 
 impl Joypad {
@@ -26,7 +68,9 @@ impl Joypad {
         Joypad {
             strobe_status: false,
             button_index: 0,
-            button_status: JoypadButton::from_bits_truncate(0b0000_0000)
+            button_status: JoypadButton::from_bits_truncate(0b0000_0000),
+            latched: JoypadButton::empty(),
+            injected: JoypadButton::empty(),
         }
     }
 
@@ -35,33 +79,50 @@ impl Joypad {
             self.strobe_status = true;
             self.button_index = 0;
         } else { // Writing strobe status off starts the cycle of inputs
+            if self.strobe_status {
+                self.latched = self.buttons();
+            }
             self.strobe_status = false;
         }
     }
 
     pub fn read(&mut self) -> u8 {
-        if self.button_index > 7 {
-            return 1;
-        }
-
-        // response gets a 1 or 0 depending on if the button at button_index is pressed or not
-        let response = (self.button_status.bits() & (1 << self.button_index)) >> self.button_index;
-        
-        // response not included in if statement to force a controller button A read
-        // every read if the strobe_status == true
+        let response = self.peek();
 
+        // the index stays put while strobe is high, so every read is button A
         if !self.strobe_status && self.button_index <= 7 {
             self.button_index += 1;
         }
         response
     }
 
+    // What the next $4016 read returns, without shifting
     pub fn peek(&self) -> u8 {
+        if self.strobe_status {
+            return self.buttons().contains(JoypadButton::BUTTON_A) as u8;
+        }
         if self.button_index > 7 {
             return 1;
         }
-        let response = (self.button_status.bits() & (1 << self.button_index)) >> self.button_index;
-        response
+        (self.latched.bits() >> self.button_index) & 1
+    }
+
+    pub fn snapshot(&self) -> JoypadSnapshot {
+        JoypadSnapshot {
+            buttons: self.buttons(),
+            latched: if self.strobe_status { self.buttons() } else { self.latched },
+            strobe: self.strobe_status,
+            index: self.button_index,
+        }
+    }
+
+    // What the game sees: the frontend's buttons plus any injected ones
+    pub fn buttons(&self) -> JoypadButton {
+        self.button_status | self.injected
+    }
+
+    pub fn inject(&mut self, buttons: JoypadButton) {
+        self.injected = buttons;
     }
 
     pub fn set_button_pressed_status(&mut self, button: JoypadButton, status: bool) {
@@ -71,4 +132,49 @@ impl Joypad {
             self.button_status.remove(button);
         }
     }
+}
+
+impl Snapshot for Joypad {
+    // Only the shift register, the buttons are whatever the player holds now
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bool(self.strobe_status);
+        out.u8(self.button_index);
+        out.u8(self.latched.bits());
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.strobe_status = input.bool()?;
+        self.button_index = input.u8()?;
+        self.latched = JoypadButton::from_bits_truncate(input.u8()?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reads_come_from_the_latched_buttons() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+
+        // Strobe high: A, live, however often it's read
+        joypad.write(1);
+        assert_eq!((joypad.read(), joypad.read()), (1, 1));
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_A, false);
+        assert_eq!(joypad.peek(), 0);
+        joypad.set_button_pressed_status(JoypadButton::START, true);
+
+        // Strobe low: the buttons as they were, and peeking doesn't shift
+        joypad.write(0);
+        joypad.set_button_pressed_status(JoypadButton::START, false);
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_B, true);
+        assert_eq!((joypad.peek(), joypad.peek()), (0, 0));
+        let bits: Vec<u8> = (0..9).map(|_| joypad.read()).collect();
+        assert_eq!(bits, vec![0, 0, 0, 1, 0, 0, 0, 0, 1]);
+
+        let snapshot = joypad.snapshot();
+        assert_eq!(snapshot, JoypadSnapshot { buttons: JoypadButton::BUTTON_B, latched: JoypadButton::START, strobe: false, index: 8 });
+    }
 }
\ No newline at end of file
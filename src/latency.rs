@@ -0,0 +1,301 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::frame::Frame;
+use crate::rom::NES_TAG;
+
+// Latency instrumentation for the frontend. Timestamps input events, the end of
+// emulating each frame and each present call, and keeps rolling statistics so
+// changes like vsync options or run-ahead can be measured instead of guessed at.
+//
+// Input latency here is from the moment the frontend saw an input event to the
+// present of the first frame emulated after it. Any frames the game itself takes
+// to react come on top of that.
+
+const WINDOW: usize = 120; // frames of history, ~2 seconds
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Series {
+    pub avg: Duration,
+    pub max: Duration,
+}
+
+fn summarize(samples: &VecDeque<Duration>) -> Option<Series> {
+    if samples.is_empty() {
+        return None;
+    }
+    let total: Duration = samples.iter().sum();
+    Some(Series {
+        avg: total / samples.len() as u32,
+        max: *samples.iter().max().unwrap(),
+    })
+}
+
+fn push_sample(samples: &mut VecDeque<Duration>, sample: Duration) {
+    if samples.len() == WINDOW {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+pub struct LatencyTracker {
+    pending_input: Option<Instant>, // oldest input event not yet on screen
+    emulate: VecDeque<Duration>,
+    present: VecDeque<Duration>,
+    input: VecDeque<Duration>,
+}
+
+impl Default for LatencyTracker {
+    fn default() -> Self {
+        LatencyTracker::new()
+    }
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        LatencyTracker {
+            pending_input: None,
+            emulate: VecDeque::with_capacity(WINDOW),
+            present: VecDeque::with_capacity(WINDOW),
+            input: VecDeque::with_capacity(WINDOW),
+        }
+    }
+
+    // A key press or release was received
+    pub fn input(&mut self, at: Instant) {
+        self.pending_input.get_or_insert(at);
+    }
+
+    // A frame was shown. `emulate` is how long the frame took to emulate, `present`
+    // how long drawing and presenting it took, and `presented_at` when present returned.
+    pub fn frame(&mut self, emulate: Duration, present: Duration, presented_at: Instant) {
+        push_sample(&mut self.emulate, emulate);
+        push_sample(&mut self.present, present);
+        if let Some(input_at) = self.pending_input.take() {
+            push_sample(&mut self.input, presented_at.saturating_duration_since(input_at));
+        }
+    }
+
+    pub fn emulate(&self) -> Option<Series> {
+        summarize(&self.emulate)
+    }
+
+    pub fn present(&self) -> Option<Series> {
+        summarize(&self.present)
+    }
+
+    pub fn input_to_present(&self) -> Option<Series> {
+        summarize(&self.input)
+    }
+
+    // One line for the debug overlay, e.g. "EMU 3.1 PRES 16.2 IN 21.0/33.4 MS"
+    pub fn overlay_text(&self) -> String {
+        let (Some(emulate), Some(present)) = (self.emulate(), self.present()) else {
+            return String::new();
+        };
+        let mut text = format!("EMU {:.1} PRES {:.1}", millis(emulate.avg), millis(present.avg));
+        if let Some(input) = self.input_to_present() {
+            text += &format!(" IN {:.1}/{:.1}", millis(input.avg), millis(input.max));
+        }
+        text + " MS"
+    }
+
+    // Longer version for the console
+    pub fn report(&self) -> String {
+        let line = |name: &str, series: Option<Series>| match series {
+            Some(series) => format!("{:<17} avg {:>6.2} ms  max {:>6.2} ms\n", name, millis(series.avg), millis(series.max)),
+            None => format!("{:<17} no samples\n", name),
+        };
+        line("emulate", self.emulate()) + &line("present", self.present()) + &line("input to present", self.input_to_present())
+    }
+}
+
+// Built in latency test (-latency-test): a tiny rom that turns the whole screen
+// white while any button is held and black otherwise, reading the pad in its NMI
+// handler like a game would. FlashProbe looks at the middle of each presented
+// frame the way a photodiode taped to the monitor would, and times how long a
+// press takes to light it, so the whole input pipeline is measured end to end.
+//
+//   reset  SEI / CLD / LDA #$80 / STA $2000 / LDA #$08 / STA $2001 / JMP *
+//   nmi    strobe $4016, X = $0F (black), 8 reads: any bit 0 set -> X = $30 (white)
+//          LDA $2002 / $3F00 -> $2006 / STX $2007 / $0000 -> $2006 / RTI
+const TEST_PROGRAM: [u8; 64] = [
+    0x78, 0xD8, 0xA9, 0x80, 0x8D, 0x00, 0x20, 0xA9, 0x08, 0x8D, 0x01, 0x20, 0x4C, 0x0C, 0x80, // $8000
+    0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, 0xA2, 0x0F, 0xA0, 0x08, // $800F nmi
+    0xAD, 0x16, 0x40, 0x29, 0x01, 0xF0, 0x02, 0xA2, 0x30, 0x88, 0xD0, 0xF4, // $801D read loop
+    0xAD, 0x02, 0x20, 0xA9, 0x3F, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20, 0x8E, 0x07, 0x20, // $8029
+    0x8D, 0x06, 0x20, 0x8D, 0x06, 0x20, 0x40, // $8039, RTI at $803F
+];
+const NMI: u16 = 0x800F;
+const RESET: u16 = 0x8000;
+const IRQ: u16 = 0x803F;
+
+const PROBE_X: usize = 128;
+const PROBE_Y: usize = 120;
+const LIT_LEVEL: u16 = 3 * 0x80; // sum of the channels, white is well above, black well below
+
+// The test rom as an iNES file: 16KB PRG, CHR RAM, mapper 0
+pub fn test_rom() -> Vec<u8> {
+    let mut prg = TEST_PROGRAM.to_vec();
+    prg.resize(0x4000, 0xEA);
+    for (at, vector) in [(0x3FFA, NMI), (0x3FFC, RESET), (0x3FFE, IRQ)] {
+        prg[at..at + 2].copy_from_slice(&vector.to_le_bytes());
+    }
+    let mut raw = NES_TAG.to_vec();
+    raw.extend([0x01, 0x00, 0x00, 0x00]);
+    raw.extend([0; 8]);
+    raw.extend(prg);
+    raw
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlashSample {
+    pub frames: u32, // presented frames from the press to the flash, 1 = the very next one
+    pub time: Duration,
+}
+
+#[derive(Default)]
+pub struct FlashProbe {
+    pressed: Option<(u32, Instant)>, // frames presented since, and when
+    lit: bool,
+    samples: Vec<FlashSample>,
+}
+
+impl FlashProbe {
+    pub fn new() -> Self {
+        FlashProbe::default()
+    }
+
+    // A button went down. Only counts once the screen is dark again, so held
+    // buttons and key repeat don't start new measurements.
+    pub fn press(&mut self, at: Instant) {
+        if !self.lit && self.pressed.is_none() {
+            self.pressed = Some((0, at));
+        }
+    }
+
+    // A frame was presented at `at`, returns the measurement it completes
+    pub fn observe(&mut self, frame: &Frame, at: Instant) -> Option<FlashSample> {
+        let (r, g, b) = frame.pixel(PROBE_X, PROBE_Y);
+        self.lit = r as u16 + g as u16 + b as u16 >= LIT_LEVEL;
+        let (frames, pressed_at) = self.pressed.as_mut()?;
+        *frames += 1;
+        if !self.lit {
+            return None;
+        }
+        let sample = FlashSample { frames: *frames, time: at.saturating_duration_since(*pressed_at) };
+        self.pressed = None;
+        self.samples.push(sample);
+        Some(sample)
+    }
+
+    pub fn samples(&self) -> &[FlashSample] {
+        &self.samples
+    }
+
+    pub fn report(&self) -> String {
+        let Some(first) = self.samples.first() else {
+            return "latency test: no presses measured\n".to_string();
+        };
+        let count = self.samples.len() as u32;
+        let (mut min, mut max) = (*first, *first);
+        for sample in &self.samples {
+            min = if sample.time < min.time { *sample } else { min };
+            max = if sample.time > max.time { *sample } else { max };
+        }
+        let frames: u32 = self.samples.iter().map(|sample| sample.frames).sum();
+        let time: Duration = self.samples.iter().map(|sample| sample.time).sum();
+        format!(
+            "latency test: {} presses, avg {:.2} frames {:.2} ms, min {} frames {:.2} ms, max {} frames {:.2} ms\n",
+            count,
+            frames as f64 / count as f64,
+            millis(time / count),
+            min.frames,
+            millis(min.time),
+            max.frames,
+            millis(max.time)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_input_latency_measured_to_next_present() {
+        let mut tracker = LatencyTracker::new();
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+
+        tracker.frame(ms(4), ms(10), start + ms(16));
+        assert_eq!(tracker.input_to_present(), None);
+
+        // Two inputs before the next present, the older one counts
+        tracker.input(start + ms(20));
+        tracker.input(start + ms(25));
+        tracker.frame(ms(6), ms(12), start + ms(40));
+
+        assert_eq!(tracker.input_to_present(), Some(Series { avg: ms(20), max: ms(20) }));
+        assert_eq!(tracker.emulate(), Some(Series { avg: ms(5), max: ms(6) }));
+        assert_eq!(tracker.overlay_text(), "EMU 5.0 PRES 11.0 IN 20.0/20.0 MS");
+    }
+
+    #[test]
+    fn test_flash_follows_the_press() {
+        use crate::joypad::JoypadButton;
+        use crate::nes::{ControllerPort, NesBuilder};
+        use crate::rom::Rom;
+        use std::cell::RefCell;
+
+        let rom = Rom::new(&test_rom()).unwrap();
+        let shown = RefCell::new(Frame::new());
+        let mut nes = NesBuilder::new()
+            .rom_image(rom)
+            .video_sink(|frame| shown.borrow_mut().data.copy_from_slice(&frame.data))
+            .build()
+            .unwrap();
+        let mut probe = FlashProbe::new();
+        let start = Instant::now();
+        for _ in 0..5 {
+            nes.run_frame();
+            assert_eq!(probe.observe(&shown.borrow(), start), None);
+        }
+
+        probe.press(start);
+        nes.set_controller_state(ControllerPort::One, JoypadButton::BUTTON_A).unwrap();
+        let mut sample = None;
+        for _ in 0..4 {
+            nes.run_frame();
+            sample = sample.or(probe.observe(&shown.borrow(), start));
+        }
+        // The NMI reads the pad after the frame is drawn, so it shows one frame later
+        assert_eq!(sample.map(|sample| sample.frames), Some(2));
+
+        // Held down, nothing new is measured, and the screen goes dark on release
+        probe.press(start);
+        nes.run_frame();
+        assert_eq!(probe.observe(&shown.borrow(), start), None);
+        nes.set_controller_state(ControllerPort::One, JoypadButton::empty()).unwrap();
+        nes.run_frame();
+        nes.run_frame();
+        probe.observe(&shown.borrow(), start);
+        assert!(!probe.lit);
+        assert!(probe.report().contains("1 presses"), "{}", probe.report());
+    }
+
+    #[test]
+    fn test_window_drops_old_samples() {
+        let mut tracker = LatencyTracker::new();
+        let now = Instant::now();
+        tracker.frame(Duration::from_millis(100), Duration::ZERO, now);
+        for _ in 0..WINDOW {
+            tracker.frame(Duration::from_millis(1), Duration::ZERO, now);
+        }
+        assert_eq!(tracker.emulate().unwrap().max, Duration::from_millis(1));
+    }
+}
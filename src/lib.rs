@@ -0,0 +1,83 @@
+pub mod cpu;
+pub mod rom;
+pub mod bus;
+pub mod bus_mode;
+pub mod audit;
+pub mod palette;
+pub mod ntsc_palette;
+pub mod ppu;
+pub mod vram_log;
+#[cfg(feature = "debug-tools")]
+pub mod sprite_zero;
+pub mod frame;
+pub mod render;
+pub mod joypad;
+pub mod input_device;
+pub mod family_keyboard;
+pub mod zapper;
+pub mod microphone;
+#[cfg(feature = "debug-tools")]
+pub mod trace;
+#[cfg(feature = "debug-tools")]
+pub mod memdump;
+#[cfg(feature = "debug-tools")]
+pub mod bug_report;
+pub mod interrupt_log;
+pub mod mapper;
+pub mod banks;
+pub mod mapping;
+pub mod battery;
+pub mod entropy;
+pub mod rtc;
+pub mod startup;
+pub mod state;
+pub mod savestate;
+pub mod hash;
+pub mod romdb;
+pub mod quirks;
+pub mod accuracy;
+pub mod header;
+pub mod patch;
+pub mod config;
+pub mod osd;
+pub mod apu;
+#[cfg(feature = "apu")]
+pub mod audio;
+pub mod audio_cues;
+pub mod strings;
+pub mod nes;
+pub mod scheduler;
+pub mod dma;
+pub mod desync;
+pub mod frameskip;
+pub mod vsync;
+pub mod focus;
+pub mod display;
+pub mod sync;
+pub mod timestretch;
+#[cfg(feature = "debug-tools")]
+pub mod diagnose;
+#[cfg(feature = "debug-tools")]
+pub mod watchdog;
+pub mod testrom;
+#[cfg(feature = "scripting")]
+pub mod script_input;
+#[cfg(feature = "scripting")]
+pub mod macros;
+#[cfg(feature = "netplay")]
+pub mod remote;
+#[cfg(feature = "netplay")]
+pub mod netplay;
+#[cfg(feature = "netplay")]
+pub mod spectate;
+#[cfg(feature = "scripting")]
+pub mod screenshot;
+pub mod attract;
+pub mod practice;
+#[cfg(feature = "debug-tools")]
+pub mod latency;
+#[cfg(feature = "debug-tools")]
+pub mod cdl;
+pub mod perf;
+#[cfg(feature = "scripting")]
+pub mod autotest;
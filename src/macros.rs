@@ -0,0 +1,188 @@
+use crate::joypad::JoypadButton;
+
+// Input macros: short runs of controller input (a fireball motion, a menu
+// sequence) recorded from the player and played back by pressing one key.
+// A macro is saved as text, one line per frame with the buttons held that frame:
+//
+//   down
+//   down+right
+//   right+b
+//   -            # nothing held
+//   a x10        # held for 10 frames
+//
+// Playback holds the macro's buttons on top of whatever the player is pressing.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputMacro {
+    frames: Vec<JoypadButton>,
+}
+
+impl InputMacro {
+    pub fn new(frames: Vec<JoypadButton>) -> Self {
+        InputMacro { frames }
+    }
+
+    pub fn frames(&self) -> &[JoypadButton] {
+        &self.frames
+    }
+
+    pub fn parse(text: &str) -> Result<InputMacro, String> {
+        let mut frames = vec![];
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let at_line = |e: String| format!("Macro line {}: {}", number + 1, e);
+
+            let (buttons, repeat) = match line.rsplit_once(" x") {
+                Some((buttons, count)) => {
+                    let count: usize = count.trim().parse().map_err(|_| at_line(format!("Bad repeat count \"{}\"", count)))?;
+                    (buttons.trim(), count)
+                }
+                None => (line, 1),
+            };
+
+            let mut held = JoypadButton::empty();
+            if buttons != "-" {
+                for label in buttons.split('+') {
+                    held |= JoypadButton::from_label(label.trim()).ok_or(at_line(format!("Unknown button \"{}\"", label)))?;
+                }
+            }
+            frames.extend(std::iter::repeat_n(held, repeat));
+        }
+
+        if frames.is_empty() {
+            return Err("The macro has no frames".to_string());
+        }
+        Ok(InputMacro { frames })
+    }
+
+    // The text form parse reads, with runs of the same buttons folded into "xN"
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let mut i = 0;
+        while i < self.frames.len() {
+            let held = self.frames[i];
+            let run = self.frames[i..].iter().take_while(|&&frame| frame == held).count();
+            let labels = held.labels();
+            text += if labels.is_empty() { "-".to_string() } else { labels.join("+") }.as_str();
+            if run > 1 {
+                text += &format!(" x{}", run);
+            }
+            text.push('\n');
+            i += run;
+        }
+        text
+    }
+}
+
+// Recording and playback state, driven once per frame by the frontend
+pub struct MacroDeck {
+    recording: Option<Vec<JoypadButton>>,
+    playing: Option<(InputMacro, usize)>,
+    last_recorded: Option<InputMacro>,
+}
+
+impl Default for MacroDeck {
+    fn default() -> Self {
+        MacroDeck::new()
+    }
+}
+
+impl MacroDeck {
+    pub fn new() -> Self {
+        MacroDeck { recording: None, playing: None, last_recorded: None }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    // Starts recording, or stops and returns the new macro. Leading and trailing
+    // frames with nothing held are dropped, a recording of nothing gives None.
+    pub fn toggle_recording(&mut self) -> Option<&InputMacro> {
+        let Some(frames) = self.recording.take() else {
+            self.recording = Some(vec![]);
+            return None;
+        };
+        let start = frames.iter().position(|held| !held.is_empty())?;
+        let end = frames.iter().rposition(|held| !held.is_empty()).unwrap() + 1;
+        self.last_recorded = Some(InputMacro::new(frames[start..end].to_vec()));
+        self.last_recorded.as_ref()
+    }
+
+    // Starts playing `input_macro` from the next frame, replacing one already playing
+    pub fn play(&mut self, input_macro: &InputMacro) {
+        self.playing = Some((input_macro.clone(), 0));
+    }
+
+    // Plays the macro recorded last, false if there isn't one
+    pub fn play_last(&mut self) -> bool {
+        match self.last_recorded.clone() {
+            Some(input_macro) => {
+                self.play(&input_macro);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Call when a frame ends with the buttons the player held during it. Returns
+    // the buttons playback holds for the next frame.
+    pub fn end_frame(&mut self, held: JoypadButton) -> JoypadButton {
+        if let Some(frames) = self.recording.as_mut() {
+            frames.push(held);
+        }
+
+        let Some((input_macro, next)) = self.playing.as_mut() else {
+            return JoypadButton::empty();
+        };
+        let buttons = input_macro.frames[*next];
+        *next += 1;
+        if *next == input_macro.frames.len() {
+            self.playing = None;
+        }
+        buttons
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_text_round_trip() {
+        let input_macro = InputMacro::parse("down\ndown+right # quarter circle\nright+b\n-\na x3\n").unwrap();
+        assert_eq!(input_macro.frames().len(), 7);
+        assert_eq!(input_macro.frames()[1], JoypadButton::DOWN | JoypadButton::RIGHT);
+        assert_eq!(input_macro.frames()[3], JoypadButton::empty());
+        assert_eq!(input_macro.to_text(), "down\ndown+right\nright+b\n-\na x3\n");
+        assert_eq!(InputMacro::parse(&input_macro.to_text()).unwrap(), input_macro);
+
+        assert!(InputMacro::parse("down+turbo").is_err());
+        assert!(InputMacro::parse("a xlots").is_err());
+        assert!(InputMacro::parse("# nothing").is_err());
+    }
+
+    #[test]
+    fn test_record_then_play() {
+        let mut deck = MacroDeck::new();
+        deck.toggle_recording();
+        for held in [JoypadButton::empty(), JoypadButton::DOWN, JoypadButton::RIGHT, JoypadButton::empty()] {
+            assert_eq!(deck.end_frame(held), JoypadButton::empty());
+        }
+        let recorded = deck.toggle_recording().unwrap();
+        assert_eq!(recorded.frames(), &[JoypadButton::DOWN, JoypadButton::RIGHT]);
+
+        assert!(deck.play_last());
+        assert_eq!(deck.end_frame(JoypadButton::empty()), JoypadButton::DOWN);
+        assert_eq!(deck.end_frame(JoypadButton::empty()), JoypadButton::RIGHT);
+        assert_eq!(deck.end_frame(JoypadButton::empty()), JoypadButton::empty());
+
+        // Nothing held while recording, nothing to keep
+        deck.toggle_recording();
+        deck.end_frame(JoypadButton::empty());
+        assert!(deck.toggle_recording().is_none());
+    }
+}
@@ -1,35 +1,197 @@
-mod cpu;
-mod rom;
-mod bus;
-mod palette;
-mod ppu;
-mod frame;
-mod render;
-mod joypad;
-mod trace;
-mod mapper;
-mod mapping;
 use std::collections::HashMap;
 use std::env;
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::cpu::CPU;
-use crate::bus::Bus;
-use crate::joypad::Joypad;
-use crate::rom::Rom;
-use crate::frame::Frame;
-use crate::ppu::NesPPU;
-use crate::trace::trace;
-use sdl2::event::Event;
+use emu::accuracy::AccuracyProfile;
+use emu::startup::StartupState;
+use emu::practice::{self, Practice, PracticeMode};
+use emu::bus_mode::BusMode;
+use emu::battery::BatterySave;
+use emu::savestate::{StateSlots, STATE_DIR};
+use emu::macros::{InputMacro, MacroDeck};
+use emu::mapping;
+use emu::cpu::CPU;
+use emu::entropy::EntropyDevice;
+use emu::rtc::{find_rtc_file, RtcDevice, RtcSource};
+use emu::family_keyboard::{FamilyKeyboard, KeyMatrix};
+use emu::zapper::Zapper;
+use emu::microphone::{MicLevel, MicSource};
+use emu::joypad::{self, Joypad, JoypadButton};
+use emu::input_device::{InputDevice, InputSwitcher};
+use emu::render;
+use emu::ntsc_palette::{self, PaletteSource};
+use emu::rom::Rom;
+use emu::frame::Frame;
+use emu::ppu::NesPPU;
+use emu::trace::trace;
+use emu::bug_report::{BugReport, TraceRing, TRACE_RING_SIZE};
+use emu::header::repair_header;
+use emu::config::{Config, DEFAULT_CONFIG_PATH};
+use emu::osd::{self, Osd};
+use emu::audio_cues::{ApuChannels, CueOverlay};
+use emu::strings::{Catalog, Message, LANG_DIR};
+use emu::patch::{apply_patch, find_patch};
+use emu::diagnose::diagnose;
+use emu::nes::{Granularity, Nes};
+use emu::testrom::{run_test_rom, TestStatus};
+use emu::frameskip::{self, FrameSkipMode, FrameSkipper};
+use emu::watchdog::Watchdog;
+use emu::script_input::ScriptInput;
+use emu::remote::RemoteServer;
+use emu::netplay::{ChecksumChannel, ChecksumLink, CHECKSUM_INTERVAL};
+use emu::spectate::SpectatorServer;
+use emu::nes::state_hash;
+use emu::screenshot::{self, capture};
+use emu::attract::{demo_input_path, AttractPlaylist};
+use emu::latency::{self, FlashProbe, LatencyTracker};
+use emu::banks::BankMapping;
+use emu::cdl::CodeDataLog;
+use emu::sprite_zero::{self, SpriteZeroMonitor};
+use emu::vsync::{FrameLimiter, VsyncMode};
+use emu::apu::SAMPLE_RATE;
+use emu::sync::RateController;
+use emu::focus::FocusTracker;
+use emu::display::{self, DisplayTransform, Viewport, DEFAULT_SCALE, NES_HEIGHT, NES_WIDTH};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioQueue, AudioSpecDesired};
+use sdl2::controller::{Button, GameController};
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::PixelFormatEnum;
+use sdl2::mouse::{Cursor, MouseButton, SystemCursor};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::rwops::RWops;
+use sdl2::surface::Surface;
+use sdl2::render::{Texture, WindowCanvas};
+use sdl2::video::{SwapInterval, Window};
 
 
+// Letterboxed spot for the picture in the window's current size (before rotation)
+fn picture_rect(canvas: &WindowCanvas, transform: &DisplayTransform) -> Rect {
+    let (width, height) = canvas.output_size().unwrap();
+    let Viewport { x, y, width, height } = display::picture_viewport(width, height, transform);
+    Rect::new(x, y, width, height)
+}
+
+// Copies the picture into the window with the display rotation and flips applied
+fn present_picture(canvas: &mut WindowCanvas, texture: &Texture, dest: Rect, transform: &DisplayTransform) {
+    canvas.clear();
+    canvas
+        .copy_ex(texture, None, dest, transform.rotation as f64, None, transform.flip_horizontal, transform.flip_vertical)
+        .unwrap();
+}
+
+// A little controller for the title bar and task switcher, magenta is see-through
+const WINDOW_ICON: &[u8] = include_bytes!("../assets/icon.bmp");
+
+fn set_window_icon(window: &mut Window) {
+    let icon = RWops::from_bytes(WINDOW_ICON).and_then(|mut bytes| Surface::load_bmp_rw(&mut bytes));
+    match icon {
+        Ok(mut icon) => {
+            icon.set_color_key(true, Color::RGB(0xFF, 0x00, 0xFF)).unwrap();
+            window.set_icon(icon);
+        }
+        Err(e) => println!("Could not load the window icon: {}", e),
+    }
+}
+
+// Gamepad buttons by position: the right face button is A and the bottom one B, as on the NES pad
+fn gamepad_button(button: Button) -> Option<JoypadButton> {
+    match button {
+        Button::DPadUp => Some(JoypadButton::UP),
+        Button::DPadDown => Some(JoypadButton::DOWN),
+        Button::DPadLeft => Some(JoypadButton::LEFT),
+        Button::DPadRight => Some(JoypadButton::RIGHT),
+        Button::Start => Some(JoypadButton::START),
+        Button::Back => Some(JoypadButton::SELECT),
+        Button::B => Some(JoypadButton::BUTTON_A),
+        Button::A => Some(JoypadButton::BUTTON_B),
+        _ => None,
+    }
+}
+
+// Audio is queued rather than pulled by a callback, rate control keeps the queue
+// around half of this many samples (see sync.rs)
+const AUDIO_QUEUE_SAMPLES: u32 = SAMPLE_RATE / 10;
+
+fn open_audio(sdl_context: &sdl2::Sdl) -> Result<AudioQueue<f32>, String> {
+    let spec = AudioSpecDesired { freq: Some(SAMPLE_RATE as i32), channels: Some(1), samples: Some(512) };
+    let queue = sdl_context.audio()?.open_queue::<f32, _>(None, &spec)?;
+    queue.resume();
+    Ok(queue)
+}
+
+// Feeds the host microphone's level to the Famicom one (see microphone.rs)
+struct MicMeter(MicLevel);
+
+impl AudioCallback for MicMeter {
+    type Channel = f32;
+
+    fn callback(&mut self, samples: &mut [f32]) {
+        self.0.feed(samples);
+    }
+}
+
+fn open_microphone(sdl_context: &sdl2::Sdl, level: MicLevel) -> Result<AudioDevice<MicMeter>, String> {
+    let spec = AudioSpecDesired { freq: Some(SAMPLE_RATE as i32), channels: Some(1), samples: Some(512) };
+    let device = sdl_context.audio()?.open_capture(None, &spec, |_spec| MicMeter(level))?;
+    device.resume();
+    Ok(device)
+}
+
+// Parses the rom and applies the config's quirks override, if any
+fn load_rom(bytes: Vec<u8>, config: &Config) -> Rom {
+    let mut rom = Rom::new(&bytes).unwrap();
+    if let Some(quirks) = config.quirks {
+        rom.quirks = quirks;
+    }
+    rom.dip_switches = config.dip_switches;
+    if !rom.quirks.is_empty() {
+        println!("Compatibility quirks enabled: {:?}", rom.quirks);
+    }
+    rom
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     let mut rom_path: String = "".to_string();
     let mut debug: bool = false;
+    let mut seed: Option<u64> = None;
+    let mut rtc: Option<RtcSource> = None;
+    let mut fix_header_path: Option<String> = None;
+    let mut config_path: String = DEFAULT_CONFIG_PATH.to_string();
+    let mut no_patch: bool = false;
+    let mut frame_skip: Option<FrameSkipMode> = None;
+    let mut vsync: Option<VsyncMode> = None;
+    let mut accuracy: Option<AccuracyProfile> = None;
+    let mut startup: Option<StartupState> = None;
+    let mut practice_mode: Option<PracticeMode> = None;
+    let mut load_state_slot: Option<u8> = None;
+    let mut swap_rom_path: Option<String> = None;
+    let mut diagnose_frames: Option<u64> = None;
+    let mut headless_frames: Option<u64> = None;
+    let mut input_script: Option<String> = None;
+    let mut remote_addr: Option<String> = None;
+    let mut netplay_check_addr: Option<String> = None;
+    let mut spectate_addr: Option<String> = None;
+    let mut screenshot: Option<(u64, String)> = None;
+    let mut screenshot_raw = false;
+    let mut attract: Option<(String, u64)> = None;
+    let mut show_latency: bool = false;
+    let mut latency_test: bool = false;
+    let mut show_banks: bool = false;
+    let mut vram_log_path: Option<String> = None;
+    let mut cdl_report_path: Option<String> = None;
+    let mut show_sprite_zero: bool = false;
+    let mut family_keyboard: bool = false;
+    let mut zapper: bool = false;
+    let mut list_mappers: bool = false;
+    let mut strict_bus: bool = false;
+    let mut audit: bool = false;
+    let mut macro_keys: HashMap<Keycode, InputMacro> = HashMap::new();
 
     // Process arguments
     let mut i = 1;
@@ -45,39 +207,379 @@ fn main() {
         } else if args[i] == "-debug" {
             debug = true;
             i += 1;
+        } else if args[i] == "-fix-header" {
+            i += 1;
+            fix_header_path = Some(args[i].clone());
+            i += 1;
+        } else if args[i] == "-diagnose" {
+            i += 1;
+            diagnose_frames = Some(args[i].parse().expect("Frame count must be an unsigned integer"));
+            i += 1;
+        } else if args[i] == "-headless" {
+            i += 1;
+            headless_frames = Some(args[i].parse().expect("Frame count must be an unsigned integer"));
+            i += 1;
+        } else if args[i] == "-input-script" {
+            i += 1;
+            input_script = Some(args[i].clone());
+            i += 1;
+        } else if args[i] == "-remote" {
+            i += 1;
+            remote_addr = Some(args[i].clone());
+            i += 1;
+        } else if args[i] == "-netplay-check" {
+            i += 1;
+            netplay_check_addr = Some(args[i].clone());
+            i += 1;
+        } else if args[i] == "-spectate" {
+            i += 1;
+            spectate_addr = Some(args[i].clone());
+            i += 1;
+        } else if args[i] == "-screenshot" {
+            let frame_number: u64 = args[i + 1].parse().expect("Frame number must be an unsigned integer");
+            screenshot = Some((frame_number, args[i + 2].clone()));
+            i += 3;
+        } else if args[i] == "-screenshot-raw" {
+            screenshot_raw = true;
+            i += 1;
+        } else if args[i] == "-attract" {
+            let seconds: u64 = args[i + 2].parse().expect("Seconds per game must be an unsigned integer");
+            attract = Some((args[i + 1].clone(), seconds));
+            i += 3;
+        } else if args[i] == "-latency" {
+            show_latency = true;
+            i += 1;
+        } else if args[i] == "-latency-test" {
+            latency_test = true;
+            i += 1;
+        } else if args[i] == "-banks" {
+            show_banks = true;
+            i += 1;
+        } else if args[i] == "-sprite0" {
+            show_sprite_zero = true;
+            i += 1;
+        } else if args[i] == "-strict" {
+            strict_bus = true;
+            i += 1;
+        } else if args[i] == "-audit" {
+            audit = true;
+            i += 1;
+        } else if args[i] == "-list-mappers" {
+            list_mappers = true;
+            i += 1;
+        } else if args[i] == "-family-keyboard" {
+            family_keyboard = true;
+            i += 1;
+        } else if args[i] == "-zapper" {
+            zapper = true;
+            i += 1;
+        } else if args[i] == "-vram-log" {
+            i += 1;
+            vram_log_path = Some(args[i].clone());
+            i += 1;
+        } else if args[i] == "-cdl-report" {
+            i += 1;
+            cdl_report_path = Some(args[i].clone());
+            i += 1;
+        } else if args[i] == "-macro" {
+            let key = Keycode::from_name(&args[i + 1]).expect("Unknown key name for -macro");
+            let text = std::fs::read_to_string(&args[i + 2]).expect("Could not read the macro file");
+            macro_keys.insert(key, InputMacro::parse(&text).unwrap());
+            i += 3;
+        } else if args[i] == "-config" {
+            i += 1;
+            config_path = args[i].clone();
+            i += 1;
+        } else if args[i] == "-no-patch" {
+            no_patch = true;
+            i += 1;
+        } else if args[i] == "-frameskip" {
+            i += 1;
+            frame_skip = Some(FrameSkipMode::parse(&args[i]).unwrap());
+            i += 1;
+        } else if args[i] == "-vsync" {
+            i += 1;
+            vsync = Some(VsyncMode::parse(&args[i]).unwrap());
+            i += 1;
+        } else if args[i] == "-accuracy" {
+            i += 1;
+            accuracy = Some(AccuracyProfile::parse(&args[i]).unwrap());
+            i += 1;
+        } else if args[i] == "-swap-rom" {
+            i += 1;
+            swap_rom_path = Some(args[i].clone());
+            i += 1;
+        } else if args[i] == "-loadstate" {
+            i += 1;
+            load_state_slot = Some(args[i].parse().expect("State slot must be a number from 0 to 9"));
+            i += 1;
+        } else if args[i] == "-practice" {
+            i += 1;
+            practice_mode = Some(PracticeMode::parse(&args[i]).unwrap());
+            i += 1;
+        } else if args[i] == "-startup" {
+            i += 1;
+            startup = Some(StartupState::parse(&args[i]).unwrap());
+            i += 1;
+        } else if args[i] == "-rtc" {
+            i += 1;
+            rtc = Some(RtcSource::parse(&args[i]).unwrap());
+            i += 1;
+        } else if args[i] == "-seed" {
+            i += 1;
+            seed = Some(args[i].parse().expect("Seed must be an unsigned integer"));
+            i += 1;
         } else {
             panic!("Invalid argument passed: {}", args[i])
         }
     }
 
-    if rom_path == "".to_string() {
+    // Utility mode: print which mappers are supported and exit, no rom needed
+    if list_mappers {
+        print!("{}", mapping::list());
+        return;
+    }
+
+    if rom_path.is_empty() && attract.is_none() && !latency_test {
         panic!("
             No rom path given!!! Please provide a rom path like so:\n
             \"cargo run rom PATH_TO_ROM\"
         ")
     }
 
+    // Command line flags win over the config file
+    let mut config = Config::load(Path::new(&config_path)).unwrap();
+    if no_patch {
+        config.auto_patch = false;
+    }
+    if let Some(frame_skip) = frame_skip {
+        config.frame_skip = frame_skip;
+    }
+    if let Some(vsync) = vsync {
+        config.vsync = vsync;
+    }
+    if let Some(accuracy) = accuracy {
+        config.accuracy = accuracy;
+    }
+    if let Some(mode) = practice_mode {
+        config.practice = mode;
+    }
+    if let Some(startup) = startup {
+        config.startup = startup;
+    }
+    if family_keyboard {
+        config.family_keyboard = true;
+    }
+    if zapper {
+        config.zapper = true;
+    }
+    if strict_bus {
+        config.bus_mode = BusMode::Strict;
+    }
+    if audit {
+        config.audit = true;
+    }
+    let ntsc_palettes = (config.palette == PaletteSource::Ntsc).then(|| ntsc_palette::generate(&config.ntsc));
+
+    // Utility mode: check the rom header, write a repaired copy and exit without emulating
+    if let Some(out_path) = fix_header_path {
+        let bytes: Vec<u8> = std::fs::read(&rom_path).unwrap();
+        let (fixed, issues) = repair_header(&bytes).unwrap();
+        if issues.is_empty() {
+            println!("No header problems found in {}", rom_path);
+            return;
+        }
+        for issue in issues.iter() {
+            println!("Fixed: {}", issue);
+        }
+        std::fs::write(&out_path, fixed).unwrap();
+        println!("Wrote repaired rom to {}", out_path);
+        return;
+    }
+
+    // Utility mode: run the rom headless and explain why it might not be showing anything
+    if let Some(frames) = diagnose_frames {
+        let bytes: Vec<u8> = std::fs::read(&rom_path).unwrap();
+        let rom = load_rom(bytes, &config);
+        print!("{}", diagnose(rom, frames));
+        return;
+    }
+
+    // Utility mode: run without a window, printing the result of roms that report through $6000.
+    // The exit code is the test's result code so scripts can check it.
+    if let Some(frames) = headless_frames {
+        let bytes: Vec<u8> = std::fs::read(&rom_path).unwrap();
+        let mut nes = Nes::new(load_rom(bytes, &config), |_ppu: &NesPPU, _joypad1: &mut Joypad| {}).unwrap();
+        nes.cpu.bus.set_bus_mode(config.bus_mode);
+        nes.cpu.bus.set_startup_state(config.startup);
+        nes.cpu.bus.set_audit(config.audit);
+        let output = run_test_rom(&mut nes, frames);
+        if let Some(audit) = nes.cpu.bus.audit() {
+            print!("{}", audit.summary());
+        }
+        match output {
+            Some(output) => {
+                println!("{}", output);
+                match output.status {
+                    TestStatus::Finished(code) => std::process::exit(code as i32),
+                    _ => std::process::exit(1),
+                }
+            }
+            None => println!("Ran {} frames, the rom didn't report anything at $6000", frames),
+        }
+        return;
+    }
+
+    // Utility mode: run without a window and save a single frame, e.g. for thumbnails
+    if let Some((frame_number, out_path)) = screenshot {
+        let bytes: Vec<u8> = std::fs::read(&rom_path).unwrap();
+        let input = input_script.map(|path| ScriptInput::open(&path).unwrap());
+        let (frame, info) = capture(load_rom(bytes, &config), frame_number, input).unwrap();
+        std::fs::write(&out_path, frame.to_bmp()).unwrap();
+        println!("Saved frame {} to {}", frame_number, out_path);
+        if screenshot_raw {
+            let raw_path = Path::new(&out_path).with_extension("idx");
+            let sidecar_path = Path::new(&out_path).with_extension("json");
+            std::fs::write(&raw_path, screenshot::indices_bytes(&frame)).unwrap();
+            std::fs::write(&sidecar_path, info.to_json()).unwrap();
+            println!("Saved its palette indices to {} and the PPU state to {}", raw_path.display(), sidecar_path.display());
+        }
+        return;
+    }
+
     // init SDL2
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("Texture viewer", (256.0 * 3.0) as u32, (240.0 * 3.0) as u32)
+    let (window_width, window_height) = config.display.orient((NES_WIDTH * DEFAULT_SCALE, NES_HEIGHT * DEFAULT_SCALE));
+    let mut window = video_subsystem
+        .window("Texture viewer", window_width, window_height)
         .position_centered()
+        .resizable()
         .build().unwrap();
+    set_window_icon(&mut window);
+    // Cutscenes and attract mode go minutes without a key press, keep the screen awake
+    // while a game runs. SDL lets the screensaver back on when the program exits.
+    video_subsystem.disable_screen_saver();
 
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    // Adaptive vsync is a swap interval setting, which only the OpenGL renderer has
+    if config.vsync == VsyncMode::Adaptive {
+        sdl2::hint::set("SDL_RENDER_DRIVER", "opengl");
+    }
+    let mut canvas_builder = window.into_canvas();
+    if config.vsync.uses_vsync() {
+        canvas_builder = canvas_builder.present_vsync();
+    }
+    let mut canvas = canvas_builder.build().unwrap();
+    if config.vsync == VsyncMode::Adaptive && let Err(e) = video_subsystem.gl_set_swap_interval(SwapInterval::LateSwapTearing) {
+        println!("Adaptive vsync is not available ({}), using regular vsync", e);
+    }
     let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(3.0, 3.0).unwrap();
+    let event_subsystem = sdl_context.event().unwrap();
+    let controller_subsystem = sdl_context.game_controller().unwrap();
 
     let creator = canvas.texture_creator();
     let mut texture = creator
         .create_texture_target(PixelFormatEnum::RGB24, 256, 240).unwrap();
 
-    //load the game
-    println!("Attempting to load rom at path: {}", rom_path);
-    let bytes: Vec<u8> = std::fs::read(rom_path).unwrap();
+    // Attract mode: play every rom in a folder for a while each, any key skips ahead
+    if let Some((dir, seconds)) = attract {
+        let mut playlist = AttractPlaylist::from_dir(Path::new(&dir)).unwrap();
+        let mut skipped_in_a_row = 0;
+
+        loop {
+            let path = playlist.next_rom().to_path_buf();
+            let rom = match std::fs::read(&path).map(|bytes| Rom::new(&bytes)) {
+                Ok(Ok(rom)) if rom.has_supported_mapper() => rom,
+                _ => {
+                    println!("Attract mode: skipping {}, it can't be played", path.display());
+                    skipped_in_a_row += 1;
+                    if skipped_in_a_row == playlist.len() {
+                        panic!("None of the roms in {} can be played", dir);
+                    }
+                    continue;
+                }
+            };
+            skipped_in_a_row = 0;
+
+            let mut demo = demo_input_path(&path).map(|demo_path| {
+                let mut input = ScriptInput::open(&demo_path.to_string_lossy()).unwrap();
+                input.read_to_end();
+                input
+            });
+            canvas.window_mut().set_title(&path.file_name().unwrap().to_string_lossy()).unwrap();
+
+            let skip = Cell::new(false);
+            let mut frame = Frame::new();
+            let mut nes = Nes::new(rom, |ppu: &NesPPU, joypad1: &mut Joypad| {
+                render::render(ppu, &mut frame);
+                texture.update(None, &frame.data, frame.pitch()).unwrap();
+                let dest = picture_rect(&canvas, &config.display);
+                present_picture(&mut canvas, &texture, dest, &config.display);
+                canvas.present();
+
+                if let Some(demo) = demo.as_mut() {
+                    demo.apply(ppu.timing().frame, joypad1);
+                }
+
+                for event in event_pump.poll_iter() {
+                    match event {
+                        Event::Quit { .. }
+                        | Event::KeyDown {
+                            keycode: Some(Keycode::Escape),
+                            ..
+                        } => std::process::exit(0),
+                        Event::KeyDown { .. } => skip.set(true),
+                        _ => { /* do nothing */ },
+                    }
+                }
+            }).unwrap();
+            if let Some(palettes) = ntsc_palettes {
+                nes.set_palettes(palettes);
+            }
+
+            let started = Instant::now();
+            while started.elapsed() < Duration::from_secs(seconds) && !skip.get() {
+                nes.step();
+            }
+        }
+    }
+
+    //load the game, or the built in latency test instead of one
+    let mut bytes: Vec<u8> = if latency_test {
+        rom_path = "latency test".to_string();
+        latency::test_rom()
+    } else {
+        println!("Attempting to load rom at path: {}", rom_path);
+        std::fs::read(&rom_path).unwrap()
+    };
     println!("Rom found successfully, preparing for emulation...");
-    let rom = Rom::new(&bytes).unwrap();
+
+    let osd = Rc::new(RefCell::new(Osd::new())); // shared by the frame callback and the watchdog
+    let strings = Rc::new(Catalog::load(&config.locale, Path::new(LANG_DIR)).unwrap_or_else(|e| {
+        println!("{}, using English", e);
+        Catalog::english()
+    }));
+
+    // Soft-patch the rom if a game.ips / game.bps sits next to it
+    if config.auto_patch && let Some(patch_path) = find_patch(Path::new(&rom_path)) {
+        let patch = std::fs::read(&patch_path).unwrap();
+        let patch_name = patch_path.file_name().unwrap().to_string_lossy();
+        match apply_patch(&bytes, &patch) {
+            Ok(patched) => {
+                bytes = patched;
+                osd.borrow_mut().notify(&strings.text(Message::PatchApplied, &[&patch_name]));
+            }
+            Err(e) => osd.borrow_mut().notify(&strings.text(Message::PatchFailed, &[&patch_name, &e])),
+        }
+    }
+
+    let rom = load_rom(bytes, &config);
+    let has_battery = rom.has_battery;
+    let rom_sizes = (rom.prg_rom.len(), rom.chr_rom.len());
+    let rom_info = format!("{} CRC32 {:08X} mapper {}", rom_path, rom.crc, rom.mapper_id);
+    let rom_crc = rom.crc;
+    // The other cartridge F11 swaps in with the power on, and the path for the OSD
+    let mut spare_cartridge = swap_rom_path.map(|path| (load_rom(std::fs::read(&path).unwrap(), &config), path));
 
     let mut frame = Frame::new(); // The current frame to be drawn by sdl2
 
@@ -92,32 +594,405 @@ fn main() {
     key_map.insert(Keycode::A, joypad::JoypadButton::BUTTON_A);
     key_map.insert(Keycode::S, joypad::JoypadButton::BUTTON_B);
 
+    // Buttons can also come from a script on stdin or a named pipe
+    let mut script_input = input_script.map(|path| ScriptInput::open(&path).unwrap());
+
+    // Optional HTTP server for remote inspection and control
+    let mut remote = remote_addr.map(|addr| RemoteServer::start(&addr).unwrap());
+    let mut remote_input = remote.as_mut().and_then(|remote| remote.take_input());
+    let remote_screen = remote.as_ref().map(|remote| remote.screen());
+    // Optional stream of the picture for people watching
+    let spectators = spectate_addr.map(|addr| SpectatorServer::start(&addr).unwrap());
+
+    let mut frame_skipper = FrameSkipper::new(config.frame_skip);
+    let mut last_frame_end = Instant::now();
+    let display_transform = config.display;
+    let mut picture_dest = picture_rect(&canvas, &display_transform);
+    let mut frame_limiter = if config.vsync.uses_limiter() { Some(FrameLimiter::default()) } else { None };
+    let mut focus = FocusTracker::new(config.background);
+    // Gamepads are opened as they're plugged in (SDL sends an added event for ones already there)
+    let mut input = InputSwitcher::new(config.input);
+    let mut gamepads: HashMap<u32, GameController> = HashMap::new();
+    let mut latency = if show_latency { Some(LatencyTracker::new()) } else { None };
+    let mut flash_probe = if latency_test { Some(FlashProbe::new()) } else { None };
+    // Bank panel, updated from the instruction callback after each bank switch
+    let banks = if show_banks { Some(Rc::new(Cell::new(BankMapping::default()))) } else { None };
+    let frame_banks = banks.clone();
+    let log_vram = vram_log_path.is_some();
+    let mut sprite_zero_monitor = if show_sprite_zero { Some(SpriteZeroMonitor::new()) } else { None };
+
+    // F9 records a macro, F10 plays it back, -macro keys play theirs (see macros.rs)
+    let macros = Rc::new(RefCell::new(MacroDeck::new()));
+    let frame_macros = macros.clone();
+
+    // Set by the frame callback on Escape so the save file can be written before exiting
+    let quit = Rc::new(Cell::new(false));
+    let frame_quit = quit.clone();
+
+    // Badges for sound channels starting notes (see audio_cues.rs), fed by a frame hook
+    let mut cue_overlay = if config.audio_cues.is_empty() { None } else { Some(CueOverlay::new(config.audio_cues)) };
+    let audio_cues = Rc::new(Cell::new(ApuChannels::empty()));
+    let frame_audio_cues = audio_cues.clone();
+
+    // F7 saves a bug report bundle (see bug_report.rs)
+    let save_bug_report = Rc::new(Cell::new(false));
+    let frame_save_bug_report = save_bug_report.clone();
+
+    // Resets and power cycles from the input script or the remote, done by a frame
+    // hook as the frame they're for ends (see script_input.rs)
+    let console_event = Rc::new(Cell::new(None));
+    let frame_console_event = console_event.clone();
+
+    // The host keyboard types on the Family BASIC keyboard while it's captured,
+    // Scroll Lock hands it back to controller 1 (see family_keyboard.rs)
+    let mut family_keys_captured = config.family_keyboard;
+    let family_keys = Rc::new(Cell::new(KeyMatrix::default()));
+    let frame_family_keys = family_keys.clone();
+
+    // The Zapper is aimed with the mouse and fired with its left button, handed to
+    // the bus by a frame hook (see zapper.rs). A crosshair makes aiming easier.
+    let zapper_on = config.zapper;
+    let zapper_aim = Rc::new(Cell::new(None));
+    let frame_zapper_aim = zapper_aim.clone();
+    let zapper_trigger = Rc::new(Cell::new(false));
+    let frame_zapper_trigger = zapper_trigger.clone();
+    let crosshair = Cursor::from_system(SystemCursor::Crosshair).unwrap();
+    if zapper_on {
+        crosshair.set();
+    }
+
+    // The Famicom microphone hears something while its key is held or the host
+    // microphone is loud enough, handed to the bus by a frame hook (see microphone.rs)
+    let mic_key = match config.microphone {
+        MicSource::Key => Some(Keycode::from_name(&config.microphone_key).expect("Unknown key name for microphone_key")),
+        _ => None,
+    };
+    let mic_held = Rc::new(Cell::new(false));
+    let frame_mic_held = mic_held.clone();
+    let mic_level = (config.microphone == MicSource::Capture).then(MicLevel::new);
+    let _mic_device = mic_level.clone().and_then(|level| {
+        open_microphone(&sdl_context, level)
+            .inspect_err(|e| println!("Could not open the microphone, running without it: {}", e))
+            .ok()
+    });
+
+    // F8 prints the recent interrupt events (see interrupt_log.rs)
+    let dump_interrupts = Rc::new(Cell::new(false));
+    let frame_dump_interrupts = dump_interrupts.clone();
+
+    // Practice mode: F1 marks a checkpoint and F2 retries from it, done by a frame
+    // hook since they need the whole console (see practice.rs)
+    let practice_mode = config.practice;
+    let practice = Rc::new(RefCell::new(Practice::new()));
+    let frame_practice = practice.clone();
+    let mark_checkpoint = Rc::new(Cell::new(false));
+    let frame_mark_checkpoint = mark_checkpoint.clone();
+    let retry_checkpoint = Rc::new(Cell::new(false));
+    let frame_retry_checkpoint = retry_checkpoint.clone();
+
+    // F3/F4 pick a state slot, F5 saves to it and F6 loads it, also from a frame
+    // hook (see savestate.rs)
+    let state_slots = Rc::new(RefCell::new(StateSlots::new(Path::new(STATE_DIR), rom_crc)));
+    let frame_state_slots = state_slots.clone();
+    let save_state_file = Rc::new(Cell::new(false));
+    let frame_save_state_file = save_state_file.clone();
+    let load_state_file = Rc::new(Cell::new(false));
+    let frame_load_state_file = load_state_file.clone();
+    // Set when any state replaced the console, so the battery save holds back its RAM
+    let state_loaded = Rc::new(Cell::new(false));
+    let swap_cartridge = Rc::new(Cell::new(false));
+    let frame_swap_cartridge = swap_cartridge.clone();
+    let swap_osd = osd.clone();
+    let swap_strings = strings.clone();
+    let mut cart_path = rom_path.clone(); // the cartridge that's in, for the OSD
+
     // begin game cycle
-    let bus = Bus::new(rom, move |ppu: &NesPPU, joypad1: &mut Joypad| {
-        render::render(ppu, &mut frame); // Causes PPU to process a frame and insert that data into the passed frame object
+    let frame_osd = osd.clone();
+    let frame_strings = strings.clone();
+    let mut nes = Nes::new(rom, move |ppu: &NesPPU, joypad1: &mut Joypad| {
+        let frame_start = Instant::now();
+
+        if let Some(monitor) = sprite_zero_monitor.as_mut()
+            && let Some(line) = monitor.check(ppu)
+        {
+            println!("{}", line);
+        }
+
+        if let Some(cue_overlay) = cue_overlay.as_mut() {
+            cue_overlay.update(frame_audio_cues.replace(ApuChannels::empty()));
+        }
+
+        // Skipped frames are still fully emulated, they just never reach the window
+        let render_time = if frame_skipper.should_render() {
+            render::render(ppu, &mut frame); // Causes PPU to process a frame and insert that data into the passed frame object
+            frame_osd.borrow_mut().draw(&mut frame);
+            if let Some(cue_overlay) = cue_overlay.as_ref() {
+                cue_overlay.draw(&mut frame);
+            }
+            if let Some(latency) = latency.as_ref() {
+                osd::draw_text(&mut frame, 2, 232, &latency.overlay_text(), (0xFF, 0xFF, 0x00));
+            }
+            if practice_mode != PracticeMode::Off {
+                osd::draw_text(&mut frame, 2, 224, &frame_practice.borrow().overlay_text(), (0xFF, 0xFF, 0xFF));
+            }
+            if practice_mode == PracticeMode::Input {
+                osd::draw_text(&mut frame, 2, 216, &practice::input_text(joypad1.buttons()), (0xFF, 0xFF, 0xFF));
+            }
+            if sprite_zero_monitor.is_some()
+                && let Some(hit) = ppu.sprite_zero_hit()
+            {
+                sprite_zero::draw_marker(&mut frame, hit);
+            }
+            if let Some(banks) = frame_banks.as_ref() {
+                for (row, line) in banks.get().lines().iter().enumerate() {
+                    osd::draw_text(&mut frame, 2, 2 + row * 7, line, (0x00, 0xFF, 0xFF));
+                }
+            }
+            if let Some(remote_screen) = remote_screen.as_ref() {
+                remote_screen.publish(&frame);
+            }
+            if let Some(spectators) = spectators.as_ref() {
+                spectators.publish(&frame);
+            }
 
-        // Process the frame object via SDL2
-        texture.update(None, &frame.data, 256 * 3).unwrap();
+            // Process the frame object via SDL2
+            texture.update(None, &frame.data, frame.pitch()).unwrap();
 
-        canvas.copy(&texture, None, None).unwrap();
+            present_picture(&mut canvas, &texture, picture_dest, &display_transform);
+            let render_time = frame_start.elapsed(); // measured before present so vsync waits don't count
+
+            canvas.present();
+            if let Some(latency) = latency.as_mut() {
+                let presented_at = Instant::now();
+                latency.frame(frame_start - last_frame_end, presented_at - frame_start, presented_at);
+            }
+            if let Some(flash_probe) = flash_probe.as_mut()
+                && let Some(sample) = flash_probe.observe(&frame, Instant::now())
+            {
+                let text = format!("{} FRAMES {:.1} MS", sample.frames, sample.time.as_secs_f64() * 1000.0);
+                frame_osd.borrow_mut().notify(&text);
+            }
+            Some(render_time)
+        } else {
+            None
+        };
+        frame_skipper.record(frame_start - last_frame_end, render_time);
+
+        for input in [script_input.as_mut(), remote_input.as_mut()].into_iter().flatten() {
+            if let Some(event) = input.apply(ppu.timing().frame, joypad1) {
+                frame_console_event.set(Some(event));
+            }
+        }
+
+        // Paused in the background: wait here until focus comes back. Closing the
+        // window is put back in the queue for the loop below to handle.
+        while focus.paused() {
+            match event_pump.wait_event() {
+                Event::Window { win_event: WindowEvent::FocusGained, .. } => focus.set_focused(true),
+                event @ Event::Quit { .. } => {
+                    event_subsystem.push_event(event).unwrap();
+                    break;
+                }
+                _ => {}
+            }
+        }
 
-        canvas.present();
         for event in event_pump.poll_iter() {
             match event {
+                Event::MouseMotion { x, y, .. } if zapper_on => {
+                    let viewport = Viewport { x: picture_dest.x(), y: picture_dest.y(), width: picture_dest.width(), height: picture_dest.height() };
+                    frame_zapper_aim.set(display::window_to_picture(&viewport, &display_transform, x, y));
+                }
+                Event::Window { win_event: WindowEvent::Leave, .. } if zapper_on => {
+                    frame_zapper_aim.set(None);
+                }
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, .. } if zapper_on => {
+                    frame_zapper_trigger.set(true);
+                }
+                Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } if zapper_on => {
+                    frame_zapper_trigger.set(false);
+                }
+                Event::KeyDown { keycode: Some(Keycode::ScrollLock), repeat: false, .. } if config.family_keyboard => {
+                    family_keys_captured = !family_keys_captured;
+                    joypad1.set_button_pressed_status(JoypadButton::all(), false);
+                    let mut keys = frame_family_keys.get();
+                    keys.release_all();
+                    frame_family_keys.set(keys);
+                    let message = if family_keys_captured { Message::FamilyKeyboardOn } else { Message::FamilyKeyboardOff };
+                    frame_osd.borrow_mut().notify(&frame_strings.text(message, &[]));
+                }
+                // Every key, Escape and the F keys included, is typed while captured
+                Event::KeyDown { keycode: Some(keycode), .. } if family_keys_captured => {
+                    let mut keys = frame_family_keys.get();
+                    keys.set_host_key(&keycode.name(), true);
+                    frame_family_keys.set(keys);
+                }
+                Event::KeyUp { keycode: Some(keycode), .. } if family_keys_captured => {
+                    let mut keys = frame_family_keys.get();
+                    keys.set_host_key(&keycode.name(), false);
+                    frame_family_keys.set(keys);
+                }
+
+                Event::KeyDown { keycode, repeat: false, .. } if keycode.is_some() && keycode == mic_key => {
+                    frame_mic_held.set(true);
+                }
+                Event::KeyUp { keycode, .. } if keycode.is_some() && keycode == mic_key => {
+                    frame_mic_held.set(false);
+                }
+
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } => std::process::exit(0),
+                } => {
+                    if let Some(latency) = latency.as_ref() {
+                        print!("{}", latency.report());
+                    }
+                    if let Some(flash_probe) = flash_probe.as_ref() {
+                        print!("{}", flash_probe.report());
+                    }
+                    if let Some(path) = vram_log_path.as_ref()
+                        && let Some(log) = ppu.vram_log()
+                    {
+                        match std::fs::write(path, log.to_text()) {
+                            Ok(()) => println!("Saved {} VRAM writes to {}", log.writes().len(), path),
+                            Err(e) => println!("Could not save the VRAM log to {}: {}", path, e),
+                        }
+                    }
+                    frame_quit.set(true);
+                }
+
+                Event::Window { win_event: WindowEvent::SizeChanged(..), .. } => {
+                    picture_dest = picture_rect(&canvas, &display_transform);
+                }
+                Event::Window { win_event: WindowEvent::FocusLost, .. } => {
+                    focus.set_focused(false);
+                    // The key up events for anything held now go to another window
+                    joypad1.set_button_pressed_status(JoypadButton::all(), false);
+                }
+                Event::Window { win_event: WindowEvent::FocusGained, .. } => focus.set_focused(true),
+
+                // 1-5 resize the window to that many times the NES resolution
+                Event::KeyDown { keycode: Some(keycode), .. }
+                    if (Keycode::Num1.into_i32()..=Keycode::Num5.into_i32()).contains(&keycode.into_i32()) =>
+                {
+                    let scale = (keycode.into_i32() - Keycode::Num1.into_i32() + 1) as u32;
+                    let (width, height) = display_transform.orient(display::preset_size(scale).unwrap());
+                    canvas.window_mut().set_size(width, height).unwrap();
+                    picture_dest = picture_rect(&canvas, &display_transform);
+                }
+
+                Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } => {
+                    let mut macros = frame_macros.borrow_mut();
+                    match macros.toggle_recording().cloned() {
+                        Some(recorded) => {
+                            println!("Recorded macro:\n{}", recorded.to_text());
+                            frame_osd.borrow_mut().notify(&frame_strings.text(Message::MacroRecorded, &[&recorded.frames().len()]));
+                        }
+                        None if macros.is_recording() => frame_osd.borrow_mut().notify(&frame_strings.text(Message::MacroRecording, &[])),
+                        None => frame_osd.borrow_mut().notify(&frame_strings.text(Message::MacroEmpty, &[])),
+                    }
+                }
+                Event::KeyDown { keycode: Some(Keycode::F7), repeat: false, .. } => {
+                    frame_save_bug_report.set(true);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F1), repeat: false, .. } if practice_mode != PracticeMode::Off => {
+                    frame_mark_checkpoint.set(true);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F2), repeat: false, .. } if practice_mode != PracticeMode::Off => {
+                    frame_retry_checkpoint.set(true);
+                }
+                Event::KeyDown { keycode: Some(keycode @ (Keycode::F3 | Keycode::F4)), repeat: false, .. } => {
+                    let mut slots = frame_state_slots.borrow_mut();
+                    let slot = if keycode == Keycode::F3 { slots.select_previous() } else { slots.select_next() };
+                    let message = if slots.last_used() == Some(slot) { Message::StateSlotLastUsed } else { Message::StateSlotSelected };
+                    frame_osd.borrow_mut().notify(&frame_strings.text(message, &[&slot]));
+                }
+                Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => {
+                    frame_save_state_file.set(true);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } => {
+                    frame_load_state_file.set(true);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. } => {
+                    frame_dump_interrupts.set(true);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => {
+                    frame_swap_cartridge.set(true);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F10), repeat: false, .. } if !frame_macros.borrow_mut().play_last() => {
+                    frame_osd.borrow_mut().notify(&frame_strings.text(Message::NoMacroYet, &[]));
+                }
+                Event::KeyDown { keycode: Some(keycode), repeat: false, .. } if macro_keys.contains_key(&keycode) => {
+                    frame_macros.borrow_mut().play(&macro_keys[&keycode]);
+                }
+
+                Event::ControllerDeviceAdded { which, .. } => match controller_subsystem.open(which) {
+                    Ok(gamepad) => {
+                        println!("Gamepad connected: {}", gamepad.name());
+                        gamepads.insert(gamepad.instance_id(), gamepad);
+                    }
+                    Err(e) => println!("Could not open gamepad {}: {}", which, e),
+                },
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    gamepads.remove(&which);
+                    if input.removed(which) {
+                        joypad1.set_button_pressed_status(JoypadButton::all(), false);
+                        frame_osd.borrow_mut().notify(&frame_strings.text(Message::InputKeyboard, &[]));
+                    }
+                }
+                Event::ControllerButtonDown { which, button, .. } => {
+                    let device = InputDevice::Gamepad(which);
+                    if let Some(key) = gamepad_button(button) && input.allows(device) {
+                        // A new device takes over, whatever the old one held is let go
+                        if input.pressed(device) {
+                            joypad1.set_button_pressed_status(JoypadButton::all(), false);
+                            let name = gamepads.get(&which).map_or("gamepad".to_string(), |gamepad| gamepad.name());
+                            frame_osd.borrow_mut().notify(&frame_strings.text(Message::InputGamepad, &[&name]));
+                        }
+                        joypad1.set_button_pressed_status(key, true);
+                        if let Some(latency) = latency.as_mut() {
+                            latency.input(Instant::now());
+                        }
+                        if let Some(flash_probe) = flash_probe.as_mut() {
+                            flash_probe.press(Instant::now());
+                        }
+                    }
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    if let Some(key) = gamepad_button(button) && input.active() == InputDevice::Gamepad(which) {
+                        joypad1.set_button_pressed_status(key, false);
+                        if let Some(latency) = latency.as_mut() {
+                            latency.input(Instant::now());
+                        }
+                    }
+                }
 
                 Event::KeyDown { keycode, .. } => {
-                    if let Some(key) = key_map.get(&keycode.unwrap_or(Keycode::Ampersand)) {
+                    if let Some(key) = key_map.get(&keycode.unwrap_or(Keycode::Ampersand))
+                        && input.allows(InputDevice::Keyboard)
+                    {
+                        if input.pressed(InputDevice::Keyboard) {
+                            joypad1.set_button_pressed_status(JoypadButton::all(), false);
+                            frame_osd.borrow_mut().notify(&frame_strings.text(Message::InputKeyboard, &[]));
+                        }
                         joypad1.set_button_pressed_status(*key, true);
+                        if let Some(latency) = latency.as_mut() {
+                            latency.input(Instant::now());
+                        }
+                        if let Some(flash_probe) = flash_probe.as_mut() {
+                            flash_probe.press(Instant::now());
+                        }
                     }
                 }
                 Event::KeyUp { keycode, .. } => {
-                    if let Some(key) = key_map.get(&keycode.unwrap_or(Keycode::Ampersand)) {
+                    if let Some(key) = key_map.get(&keycode.unwrap_or(Keycode::Ampersand))
+                        && input.active() == InputDevice::Keyboard
+                    {
                         joypad1.set_button_pressed_status(*key, false);
+                        if let Some(latency) = latency.as_mut() {
+                            latency.input(Instant::now());
+                        }
                     }
                 }
 
@@ -125,23 +1000,338 @@ fn main() {
             }
         }
 
-    });
+        if let Some(frame_limiter) = frame_limiter.as_mut() {
+            frame_limiter.wait();
+        } else if render_time.is_none() {
+            frameskip::throttle(last_frame_end); // the previous frame ending is when emulating this one began
+        }
+        if let Some(min_frame_time) = focus.min_frame_time() {
+            std::thread::sleep(min_frame_time.saturating_sub(last_frame_end.elapsed()));
+        }
+        last_frame_end = Instant::now();
+    }).unwrap();
+
+    nes.cpu.bus.set_accuracy(config.accuracy_flags());
+    nes.cpu.bus.set_bus_mode(config.bus_mode);
+    nes.cpu.bus.set_startup_state(config.startup);
+    nes.cpu.bus.set_audit(config.audit);
+    if let StartupState::Random(_) = config.startup {
+        println!("Startup state {0}, put \"startup {0}\" at the top of an input script to play it back the same", config.startup);
+    }
 
-    let mut cpu = CPU::new(bus);
+    // Sound, when there's a device to play it on
+    match open_audio(&sdl_context) {
+        Ok(audio_queue) => {
+            let audio_queue = Rc::new(audio_queue);
+            let frame_audio_queue = audio_queue.clone();
+            let queued_samples = move || frame_audio_queue.size() / std::mem::size_of::<f32>() as u32;
+            nes.cpu.bus.set_audio_callback(move |samples: &[f32]| {
+                // Far behind (the window was dragged, the game paused) drops samples instead of lagging
+                if audio_queue.size() / (std::mem::size_of::<f32>() as u32) < AUDIO_QUEUE_SAMPLES * 2 {
+                    audio_queue.queue_audio(samples).unwrap();
+                }
+            });
+            let mut rate = RateController::new(config.sync);
+            nes.add_callback(Granularity::Frame, move |cpu: &mut CPU| {
+                let ratio = rate.update(queued_samples() as usize, AUDIO_QUEUE_SAMPLES as usize);
+                cpu.bus.apu.set_output_rate(SAMPLE_RATE as f64 * ratio);
+            });
+        }
+        Err(e) => println!("No sound: {}", e),
+    }
+    if log_vram {
+        nes.cpu.bus.ppu.start_vram_log();
+    }
+    if let Some(palettes) = ntsc_palettes {
+        nes.set_palettes(palettes);
+    }
 
-    let mut callback_fn: Box<dyn FnMut(&mut CPU)> = if debug {
-        Box::new(|cpu: &mut CPU| {
+    // Test roms that need random bytes can read them from the entropy register
+    if let Some(seed) = seed {
+        nes.cpu.bus.attach_entropy(EntropyDevice::new(Some(seed), None));
+        println!("Entropy device attached with seed {}", nes.cpu.bus.entropy().unwrap().seed());
+    }
+
+    // Rom hacks that want the time of day ask for a clock with -rtc or a .rtc file (see rtc.rs)
+    if rtc.is_none() && let Some(rtc_path) = find_rtc_file(Path::new(&rom_path)) {
+        let source = std::fs::read_to_string(&rtc_path).unwrap();
+        rtc = Some(RtcSource::parse(&source).unwrap());
+    }
+    if let Some(source) = rtc {
+        nes.cpu.bus.attach_rtc(RtcDevice::new(source));
+        println!("Clock attached: {:?}", source);
+    }
+
+    if config.microphone != MicSource::Off {
+        let threshold = config.microphone_threshold;
+        nes.add_callback(Granularity::Frame, move |cpu: &mut CPU| {
+            let loud = mic_level.as_ref().is_some_and(|level| level.level() > threshold);
+            cpu.bus.set_microphone(mic_held.get() || loud);
+        });
+    }
+
+    if zapper_on {
+        nes.cpu.bus.attach_zapper(Zapper::new());
+        nes.add_callback(Granularity::Frame, move |cpu: &mut CPU| {
+            if let Some(zapper) = cpu.bus.zapper_mut() {
+                zapper.aim(zapper_aim.get());
+                zapper.set_trigger(zapper_trigger.get());
+            }
+        });
+    }
+
+    if config.family_keyboard {
+        nes.cpu.bus.attach_family_keyboard(FamilyKeyboard::new());
+        nes.add_callback(Granularity::Frame, move |cpu: &mut CPU| {
+            if let Some(keyboard) = cpu.bus.family_keyboard_mut() {
+                keyboard.set_keys(family_keys.get());
+            }
+        });
+    }
+
+    // Battery saves (see battery.rs for when the file is written)
+    let mut battery = None;
+    if has_battery {
+        let save = BatterySave::open(&BatterySave::path_for(Path::new(&rom_path))).unwrap();
+        if !save.contents().is_empty() && nes.cpu.bus.load_cart_battery_ram(save.contents()) {
+            println!("Loaded battery save for {}", rom_path);
+        }
+        battery = Some(save);
+    }
+
+    // Only what was asked for gets a callback, so a plain run calls nothing per instruction
+    // but the bug report's trace ring, which just copies the registers
+    let trace_ring = Rc::new(RefCell::new(TraceRing::new(TRACE_RING_SIZE)));
+    let instruction_trace_ring = trace_ring.clone();
+    nes.add_callback(Granularity::Instruction, move |cpu: &mut CPU| instruction_trace_ring.borrow_mut().record(cpu));
+    if debug {
+        nes.add_callback(Granularity::Instruction, |cpu: &mut CPU| {
             println!("{}", trace(cpu));
-            println!("MORE PPU DATA: VBLANK: {} CTRL: {:08b}, STATUS: {:08b}", cpu.bus.ppu.trigger_nmi, cpu.bus.ppu.ctrl.bits(), cpu.bus.ppu.peek_status());
-        })
-    } else {
-        Box::new(|_: &mut CPU| {
+            println!("MORE PPU DATA: NMI: {} CTRL: {:08b}, STATUS: {:08b}", cpu.bus.ppu.nmi_line(), cpu.bus.ppu.ctrl.bits(), cpu.bus.ppu.peek_status());
+        });
+    }
 
-        })
-    };
+    if let Some(remote) = remote {
+        let remote_state_loaded = state_loaded.clone();
+        nes.add_callback(Granularity::Scanline, move |cpu: &mut CPU| {
+            if remote.answer_queries(cpu) {
+                remote_state_loaded.set(true);
+            }
+        });
+    }
+
+    // Swap state hashes with a netplay peer and warn when the games drift apart
+    if let Some(addr) = netplay_check_addr {
+        let link = ChecksumLink::open(&addr).unwrap();
+        let mut checksums = ChecksumChannel::new(CHECKSUM_INTERVAL);
+        let desync_osd = osd.clone();
+        let desync_strings = strings.clone();
+        nes.add_callback(Granularity::Frame, move |cpu: &mut CPU| {
+            let frame = cpu.bus.ppu.timing().frame;
+            if let Some((frame, hash)) = checksums.record_local(frame, state_hash(cpu)) {
+                link.send(frame, hash);
+            }
+            for (frame, hash) in link.receive() {
+                checksums.record_remote(frame, hash);
+            }
+            if let Some(frame) = checksums.take_desync_warning() {
+                desync_osd.borrow_mut().notify(&desync_strings.text(Message::NetplayDesync, &[&frame]));
+                eprintln!("Netplay desync: the state hashes first differed on frame {}", frame);
+            }
+        });
+    }
+
+    if let Some(banks) = banks {
+        let mut bank_switches = None;
+        nes.add_callback(Granularity::Instruction, move |cpu: &mut CPU| {
+            if bank_switches != Some(cpu.bus.bank_switches()) {
+                bank_switches = Some(cpu.bus.bank_switches());
+                banks.set(cpu.bus.bank_mapping());
+                println!("PC {:04X} bank switch: {}", cpu.pc, cpu.bus.bank_mapping());
+            }
+        });
+    }
+
+    let report_osd = osd.clone();
+    let report_strings = strings.clone();
+    let config_text = std::fs::read_to_string(&config_path).ok();
+    nes.add_callback(Granularity::Frame, move |cpu: &mut CPU| {
+        if !save_bug_report.replace(false) {
+            return;
+        }
+        let report = BugReport::collect(cpu, &trace_ring.borrow(), &rom_info, config_text.as_deref());
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        let path = format!("bug-report-{}.tar", stamp);
+        match std::fs::write(&path, report.to_tar()) {
+            Ok(()) => report_osd.borrow_mut().notify(&report_strings.text(Message::BugReportSaved, &[&path])),
+            Err(e) => report_osd.borrow_mut().notify(&report_strings.text(Message::BugReportFailed, &[&e])),
+        }
+    });
+
+    // -loadstate boots straight into a slot, after the battery save so the state's RAM wins
+    if let Some(slot) = load_state_slot {
+        match state_slots.borrow_mut().load(&mut nes.cpu, slot) {
+            Ok(true) => {
+                state_loaded.set(true);
+                osd.borrow_mut().notify(&strings.text(Message::StateLoaded, &[&slot]));
+            }
+            Ok(false) => osd.borrow_mut().notify(&strings.text(Message::StateSlotEmpty, &[&slot])),
+            Err(e) => osd.borrow_mut().notify(&strings.text(Message::StateLoadFailed, &[&e])),
+        }
+    }
+
+    let state_osd = osd.clone();
+    let state_strings = strings.clone();
+    let file_state_loaded = state_loaded.clone();
+    nes.add_callback(Granularity::Frame, move |cpu: &mut CPU| {
+        if save_state_file.replace(false) {
+            match state_slots.borrow_mut().save(cpu) {
+                Ok(slot) => state_osd.borrow_mut().notify(&state_strings.text(Message::StateSaved, &[&slot])),
+                Err(e) => state_osd.borrow_mut().notify(&state_strings.text(Message::StateSaveFailed, &[&e])),
+            }
+        }
+        if load_state_file.replace(false) {
+            let mut slots = state_slots.borrow_mut();
+            let slot = slots.selected();
+            match slots.load(cpu, slot) {
+                Ok(true) => {
+                    file_state_loaded.set(true);
+                    state_osd.borrow_mut().notify(&state_strings.text(Message::StateLoaded, &[&slot]));
+                }
+                Ok(false) => state_osd.borrow_mut().notify(&state_strings.text(Message::StateSlotEmpty, &[&slot])),
+                Err(e) => state_osd.borrow_mut().notify(&state_strings.text(Message::StateLoadFailed, &[&e])),
+            }
+        }
+    });
+
+    if practice_mode != PracticeMode::Off {
+        let practice_osd = osd.clone();
+        let practice_strings = strings.clone();
+        let practice_state_loaded = state_loaded.clone();
+        nes.add_callback(Granularity::Frame, move |cpu: &mut CPU| {
+            if mark_checkpoint.replace(false) {
+                practice.borrow_mut().mark(cpu);
+                practice_osd.borrow_mut().notify(&practice_strings.text(Message::CheckpointMarked, &[]));
+            }
+            if retry_checkpoint.replace(false) {
+                match practice.borrow_mut().retry(cpu) {
+                    Ok(true) => practice_state_loaded.set(true),
+                    Ok(false) => practice_osd.borrow_mut().notify(&practice_strings.text(Message::NoCheckpoint, &[])),
+                    Err(e) => practice_osd.borrow_mut().notify(&practice_strings.text(Message::StateLoadFailed, &[&e])),
+                }
+            }
+        });
+    }
+
+    if config.watchdog {
+        let mut watchdog = Watchdog::new();
+        nes.add_callback(Granularity::Instruction, move |cpu: &mut CPU| {
+            if let Some(warning) = watchdog.check(cpu) {
+                let (start, end) = (format!("${:04X}", warning.pc_min), format!("${:04X}", warning.pc_max));
+                osd.borrow_mut().notify(&strings.text(Message::GameStuck, &[&start, &end]));
+                println!("No PPU/APU register writes for {} frames, the loop looks like:", warning.frames);
+                for line in warning.trace.iter() {
+                    println!("  {}", line);
+                }
+            }
+        });
+    }
+
+    nes.add_callback(Granularity::Frame, move |cpu: &mut CPU| {
+        let held = cpu.bus.joypad1_mut().buttons();
+        let playback = macros.borrow_mut().end_frame(held);
+        cpu.bus.joypad1_mut().inject(playback);
+    });
+
+    if !config.audio_cues.is_empty() {
+        nes.add_callback(Granularity::Frame, move |cpu: &mut CPU| {
+            audio_cues.set(audio_cues.get() | cpu.bus.apu_tap_mut().take_triggered());
+        });
+    }
+
+    nes.add_callback(Granularity::Frame, move |cpu: &mut CPU| {
+        if let Some(event) = console_event.take() {
+            event.perform(cpu);
+        }
+    });
 
-    cpu.reset();
-    cpu.run_with_callback(move |cpu| callback_fn.as_mut()(cpu));
+    nes.add_callback(Granularity::Frame, move |cpu: &mut CPU| {
+        if dump_interrupts.replace(false) {
+            print!("Last interrupt events:\n{}", cpu.bus.interrupt_log().dump());
+        }
+    });
 
+    // Code/data log for the whole session, reported when the emulator is closed
+    if let Some(path) = cdl_report_path {
+        let cdl = Rc::new(RefCell::new(CodeDataLog::new(rom_sizes.0, rom_sizes.1)));
+        let instruction_cdl = cdl.clone();
+        nes.add_callback(Granularity::Instruction, move |cpu: &mut CPU| instruction_cdl.borrow_mut().log_instruction(cpu));
+        let cdl_quit = quit.clone();
+        nes.add_callback(Granularity::Frame, move |cpu: &mut CPU| {
+            cdl.borrow_mut().log_frame(cpu);
+            if !cdl_quit.get() {
+                return;
+            }
+            let cdl_path = Path::new(&path).with_extension("cdl");
+            let written = std::fs::write(&path, cdl.borrow().report())
+                .and_then(|()| std::fs::write(&cdl_path, cdl.borrow().to_fceux_cdl()));
+            match written {
+                Ok(()) => println!("Saved the memory map report to {} and the code/data log to {}", path, cdl_path.display()),
+                Err(e) => println!("Could not save the memory map report: {}", e),
+            }
+        });
+    }
+
+    // Battery RAM goes to disk about once a second, and the save is flushed before quitting.
+    // Cartridge swaps happen here too: the game's save is flushed before its cartridge
+    // comes out and loaded again when it goes back in, and never written while the
+    // other one is in.
+    nes.add_callback(Granularity::Frame, move |cpu: &mut CPU| {
+        let frame = cpu.bus.ppu.timing().frame;
+        if state_loaded.replace(false)
+            && let Some(battery) = battery.as_mut()
+        {
+            battery.state_loaded(cpu.bus.prg_ram_writes());
+        }
+        if swap_cartridge.replace(false)
+            && let Some((rom, path)) = spare_cartridge.as_mut()
+        {
+            if cpu.bus.cart_crc() == rom_crc
+                && let Some(battery) = battery.as_mut()
+                && let Some(ram) = cpu.bus.cart_battery_ram()
+                && let Err(e) = battery.flush(&ram, cpu.bus.prg_ram_writes())
+            {
+                println!("{}", e);
+            }
+            match cpu.bus.swap_cartridge(rom) {
+                Ok(()) => {
+                    std::mem::swap(path, &mut cart_path);
+                    swap_osd.borrow_mut().notify(&swap_strings.text(Message::CartridgeSwapped, &[&cart_path]));
+                    if cpu.bus.cart_crc() == rom_crc
+                        && let Some(battery) = battery.as_ref()
+                        && !battery.contents().is_empty()
+                    {
+                        cpu.bus.load_cart_battery_ram(battery.contents());
+                    }
+                }
+                Err(e) => swap_osd.borrow_mut().notify(&swap_strings.text(Message::CartridgeSwapFailed, &[&e])),
+            }
+        }
+        if let Some(battery) = battery.as_mut()
+            && cpu.bus.cart_crc() == rom_crc
+            && (quit.get() || frame.is_multiple_of(60))
+            && let Some(ram) = cpu.bus.cart_battery_ram()
+            && let Err(e) = battery.flush(&ram, cpu.bus.prg_ram_writes())
+        {
+            println!("{}", e);
+        }
+        if quit.get() {
+            if let Some(audit) = cpu.bus.audit() {
+                print!("{}", audit.summary());
+            }
+            std::process::exit(0);
+        }
+    });
 
+    nes.run();
 }
\ No newline at end of file
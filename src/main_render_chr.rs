@@ -1,188 +0,0 @@
-mod cpu;
-mod rom;
-mod bus;
-mod ppu;
-mod frame;
-
-use std::path::Path;
-use std::error::Error;
-
-use crate::cpu::CPU;
-use crate::rom::Rom;
-use crate::frame::Frame;
-use crate::ppu::NesPPU;
-
-use rand::Rng;
-use sdl2::event::Event;
-use sdl2::EventPump;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::pixels::PixelFormatEnum;
-
-#[rustfmt::skip]
-
-pub static SYSTEM_PALLETE: [(u8,u8,u8); 64] = [
-   (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
-   (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00), (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00),
-   (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E), (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05),
-   (0x05, 0x05, 0x05), (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
-   (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00), (0xC4, 0x62, 0x00),
-   (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55), (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21),
-   (0x09, 0x09, 0x09), (0x09, 0x09, 0x09), (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF),
-   (0xD4, 0x80, 0xFF), (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
-   (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4), (0x05, 0xFB, 0xFF),
-   (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D), (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF),
-   (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB), (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0),
-   (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
-   (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
-];
-
-
-fn show_tile(chr_rom: &Vec<u8>, bank: usize, tile_n: usize) -> Frame {
-    assert!(bank <= 1); // Ensure bank is a valid size of 0 or 1
-
-    let mut frame: Frame = Frame::new();
-    let bank = (bank * 0x1000) as usize;
-    let tile = &chr_rom[(bank + (tile_n * 16))..=(bank + (tile_n * 16) + 15)];
-
-    for y in 0..7 {
-        let mut lower = tile[y];
-        let mut upper = tile[y+8];
-
-        for x in (0..7).rev() {
-            let pal_id = (1 & upper) << 1 | (1 & lower);
-            lower = lower >> 1;
-            upper = upper >> 1;
-            let color = match pal_id {
-                0 => SYSTEM_PALLETE[0x01],
-                1 => SYSTEM_PALLETE[0x23],
-                2 => SYSTEM_PALLETE[0x27],
-                3 => SYSTEM_PALLETE[0x30],
-                _ => panic!("Somehow got invalid sprite color id???")
-            };
-            frame.set_pixel(x, y, color);
-        }
-    }
-
-    frame
-}
-
-fn show_tile_bank(chr_rom: &Vec<u8>, bank: usize) -> Frame {
-    assert!(bank <= 1); // Ensure bank is a valid size of 0 or 1
-
-    let mut frame: Frame = Frame::new();
-    let bank = (bank * 0x1000) as usize;
-
-    let mut y_offset = 0;
-    let mut x_offset = 0;
-
-    for tile in 0..255 {
-        x_offset = (tile % 16) * 9;
-        y_offset = (tile / 16) * 9;
-
-        let tile = &chr_rom[(bank + (tile * 16))..=(bank + (tile * 16) + 15)];
-
-        for y in 0..=7 {
-            let mut lower = tile[y];
-            let mut upper = tile[y+8];
-
-            for x in (0..=7).rev() {
-                let pal_id = (1 & upper) << 1 | (1 & lower);
-                lower = lower >> 1;
-                upper = upper >> 1;
-                let color = match pal_id {
-                    0 => SYSTEM_PALLETE[0x01],
-                    1 => SYSTEM_PALLETE[0x27],
-                    2 => SYSTEM_PALLETE[0x23],
-                    3 => SYSTEM_PALLETE[0x30],
-                    _ => panic!("Somehow got invalid sprite color id???")
-                };
-                frame.set_pixel(x + x_offset, y + y_offset, color);
-            }
-        }
-    }
-
-    frame
-}
-
-fn his_show_tile_bank(chr_rom: &Vec<u8>, bank: usize) ->Frame {
-    assert!(bank <= 1);
-
-    let mut frame = Frame::new();
-    let mut tile_y = 0;
-    let mut tile_x = 0;
-    let bank = (bank * 0x1000) as usize;
-
-    for tile_n in 0..255 {
-        if tile_n != 0 && tile_n % 20 == 0 {
-            tile_y += 10;
-            tile_x = 0;
-        }
-        let tile = &chr_rom[(bank + tile_n * 16)..=(bank + tile_n * 16 + 15)];
-
-        for y in 0..=7 {
-            let mut upper = tile[y];
-            let mut lower = tile[y + 8];
-
-            for x in (0..=7).rev() {
-                let value = (1 & upper) << 1 | (1 & lower);
-                upper = upper >> 1;
-                lower = lower >> 1;
-                let rgb = match value {
-                    0 => SYSTEM_PALLETE[0x01],
-                    1 => SYSTEM_PALLETE[0x23],
-                    2 => SYSTEM_PALLETE[0x27],
-                    3 => SYSTEM_PALLETE[0x30],
-                    _ => panic!("can't be"),
-                };
-                frame.set_pixel(tile_x + x, tile_y + y, rgb)
-            }
-        }
-
-        tile_x += 10;
-    }
-    frame
-}
-
-fn main() {
-    // init sdl2
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("Texture viewer", (256.0 * 3.0) as u32, (240.0 * 3.0) as u32)
-        .position_centered()
-        .build().unwrap();
-
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(3.0, 3.0).unwrap();
-
-    let creator = canvas.texture_creator();
-    let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, 256, 240).unwrap();
-
-    //load the game
-    let bytes: Vec<u8> = std::fs::read("/home/briyoda/Projects/Rust/NES_Emulator/src/pacman.nes").unwrap();
-    let rom = Rom::new(&bytes).unwrap();
-
-    // let tile_frame = show_tile(&rom.chr_rom, 1,0);
-    let tile_frame = show_tile_bank(&rom.chr_rom, 1);
-
-    texture.update(None, &tile_frame.data, 256 * 3).unwrap();
-    canvas.copy(&texture, None, None).unwrap();
-    canvas.present();
-
-    loop {
-        for event in event_pump.poll_iter() {
-            match event {
-            Event::Quit { .. }
-            | Event::KeyDown {
-                keycode: Some(Keycode::Escape),
-                ..
-            } => std::process::exit(0),
-            _ => { /* do nothing */ }
-            }
-        }
-    }
-
-}
\ No newline at end of file
@@ -1,10 +1,150 @@
+use std::ops::Range;
+
+use crate::banks::BankMapping;
 use crate::rom::Mirroring;
+use crate::state::Snapshot;
 
+// The mapper is shared between the Bus (PRG side) and the PPU (CHR side) as an
+// Rc<RefCell<dyn Mapper>>. To keep that free of double borrow panics:
+//   - only Bus::cart_read/cart_write/cart_clock/cart_irq/cart_scanline, the Bus::cart_*_ram
+//     accessors, Bus::load_cart_*_ram, Bus::mem_poke and NesPPU::chr_read/chr_write/mirroring/
+//     ppu_addr_changed borrow it
+//   - a borrow lasts for a single trait call and is never held while calling back
+//     into the bus, PPU or CPU
+//   - mapper methods never reach back out to the rest of the console
+// That way no borrow is ever alive when another one starts, whatever order the CPU,
+// DMA and renderer end up touching the cartridge in.
 
-pub trait Mapper {
+// A board sees the console through its bus lines: the CPU side ($4020-$FFFF), the
+// PPU side (pattern tables below $2000, nametables at $2000-$2FFF), the IRQ line,
+// and the PPU's address and scanlines for boards that watch them. Everything but
+// the reads and writes has a default that does nothing, so simple boards like
+// NROM (mapper0.rs) and MMC1 (mapper1.rs) only implement what they have.
+// Every board is also a Snapshot of its registers and RAM (PRG RAM, CHR RAM) for
+// save states. The ROMs aren't saved, a state only loads on the game it was made on.
+pub trait Mapper: Snapshot {
     fn cpu_read(&self, addr: u16) -> u8;
     fn cpu_write(&mut self, addr: u16, data: u8);
     fn ppu_read(&self, addr: u16) -> u8;
     fn ppu_write(&mut self, addr: u16, data: u8);
     fn get_mirroring(&self) -> Mirroring;
-}
\ No newline at end of file
+
+    // Nametable accesses, given the console's VRAM. Boards choose which 1KB each
+    // nametable shows through get_mirroring, so a mirroring change takes effect on
+    // the next access. Boards with nametable RAM or ROM of their own override these.
+    fn nametable_read(&self, addr: u16, vram: &[u8]) -> u8 {
+        vram[nametable_offset(self.get_mirroring(), addr)]
+    }
+    fn nametable_write(&mut self, addr: u16, data: u8, vram: &mut [u8]) {
+        vram[nametable_offset(self.get_mirroring(), addr)] = data;
+    }
+    // Called as each scanline ends, for boards that count them
+    fn on_scanline(&mut self, _scanline: u16) {}
+    // Called with the PPU's address when $2006 or $2007 moves it, for boards that
+    // watch the address lines
+    fn on_ppu_addr(&mut self, _addr: u16) {}
+    // Where each PRG/CHR window currently points, for the bank debug panel
+    fn bank_mapping(&self) -> BankMapping;
+
+    // Writes to $4020-$5FFF, where a few boards put extra registers. True when
+    // the board has a register at addr, so the write audit leaves it out.
+    fn expansion_write(&mut self, _addr: u16, _data: u8) -> bool {
+        false
+    }
+    // Called with the CPU cycles that just passed, for boards with timers or counters
+    fn cpu_clock(&mut self, _cycles: usize) {}
+    // The cartridge's IRQ line, held until the game acknowledges it
+    fn irq_pending(&self) -> bool {
+        false
+    }
+    // The cartridge's PRG RAM at $6000-$7FFF, None for boards without any
+    fn prg_ram(&self) -> Option<&[u8]> {
+        None
+    }
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+    // The bytes of prg_ram a battery keeps on carts that have one (the rom header
+    // says whether it does). All of it unless a board only backs part of its RAM.
+    fn battery_ranges(&self) -> Vec<Range<usize>> {
+        self.prg_ram().into_iter().map(|ram| 0..ram.len()).collect()
+    }
+}
+
+// Bank math shared by the mappers. Bank registers often have more bits than a
+// cartridge has banks, and boards only wire up the address lines they need, so a
+// bank number past the end wraps around instead of reading off the end of the rom.
+// Roms whose size isn't a power of two (some hacks and homebrew) wrap within it.
+
+// Start of `bank` in a rom of `rom_len` bytes split into `bank_size` byte banks
+pub fn bank_offset(bank: usize, bank_size: usize, rom_len: usize) -> usize {
+    let banks = (rom_len / bank_size).max(1);
+    let wrapped = bank & (banks.next_power_of_two() - 1); // the address lines the board has
+    (wrapped % banks) * bank_size
+}
+
+// Offset of the last bank, which many boards keep fixed at the top of memory
+pub fn last_bank_offset(bank_size: usize, rom_len: usize) -> usize {
+    bank_offset((rom_len / bank_size).max(1) - 1, bank_size, rom_len)
+}
+
+// Where a nametable address ($2000-$3EFF) lands in VRAM. Four screen carts add
+// 2KB so each nametable has its own 1KB.
+pub fn nametable_offset(mirroring: Mirroring, addr: u16) -> usize {
+    let index = (addr as usize - 0x2000) & 0x0FFF; // $3000-$3EFF mirrors $2000-$2EFF
+    let table = match (mirroring, index / 0x400) {
+        (Mirroring::VERTICAL, table) => table & 1,
+        (Mirroring::HORIZONTAL, table) => table >> 1,
+        (Mirroring::SINGLELOWER, _) => 0,
+        (Mirroring::SINGLEUPPER, _) => 1,
+        (Mirroring::FOURSCREEN, table) => table,
+    };
+    table * 0x400 + (index & 0x3FF)
+}
+
+// Checked when a rom is loaded, so the bank math above never sees sizes it can't handle
+pub fn check_rom_sizes(prg_len: usize, chr_len: usize) -> Result<(), String> {
+    if prg_len == 0 || !prg_len.is_multiple_of(0x2000) {
+        return Err(format!("PRG ROM must be a non-zero multiple of 8 KB but is {} bytes", prg_len));
+    }
+    if chr_len == 0 || !chr_len.is_multiple_of(0x1000) {
+        return Err(format!("CHR must be a non-zero multiple of 4 KB but is {} bytes", chr_len));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bank_offset_wraps() {
+        // 128 KB in 16 KB banks: bank 9 wraps to bank 1
+        assert_eq!(bank_offset(9, 0x4000, 0x20000), 0x4000);
+        // 48 KB isn't a power of two, bank 3 is past the end too
+        assert_eq!(bank_offset(3, 0x4000, 0xC000), 0);
+        assert_eq!(bank_offset(6, 0x4000, 0xC000), 0x8000);
+        assert_eq!(last_bank_offset(0x4000, 0xC000), 0x8000);
+        // Smaller than one bank
+        assert_eq!(bank_offset(1, 0x4000, 0x2000), 0);
+        assert_eq!(last_bank_offset(0x4000, 0x2000), 0);
+    }
+
+    #[test]
+    fn test_nametable_offsets() {
+        assert_eq!(nametable_offset(Mirroring::VERTICAL, 0x2805), 0x005);
+        assert_eq!(nametable_offset(Mirroring::VERTICAL, 0x2C05), 0x405);
+        assert_eq!(nametable_offset(Mirroring::HORIZONTAL, 0x2405), 0x005);
+        assert_eq!(nametable_offset(Mirroring::HORIZONTAL, 0x2805), 0x405);
+        assert_eq!(nametable_offset(Mirroring::SINGLEUPPER, 0x2005), 0x405);
+        assert_eq!(nametable_offset(Mirroring::FOURSCREEN, 0x2C05), 0xC05);
+        assert_eq!(nametable_offset(Mirroring::SINGLELOWER, 0x3EFF), 0x2FF); // $3EFF is $2EFF
+    }
+
+    #[test]
+    fn test_rom_size_checks() {
+        assert!(check_rom_sizes(0x4000, 0x2000).is_ok());
+        assert!(check_rom_sizes(0, 0x2000).is_err());
+        assert!(check_rom_sizes(0x4000, 0x100).is_err());
+    }
+}
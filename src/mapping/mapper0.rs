@@ -1,5 +1,7 @@
+use crate::banks::BankMapping;
 use crate::mapper::Mapper;
 use crate::rom::Mirroring;
+use crate::state::{Snapshot, StateReader, StateWriter};
 
 pub struct Mapper0 {
     pub prg_rom: Vec<u8>,
@@ -23,18 +25,37 @@ impl Mapper0 {
 
 impl Mapper0 {
     fn prg_ram_read(&self, mut addr: u16) -> u8 {
-        addr = addr & 0x0FFF;
+        addr &= 0x1FFF;
         self.prg_ram[addr as usize]
     }
+
+    fn prg_ram_write(&mut self, addr: u16, data: u8) {
+        self.prg_ram[(addr & 0x1FFF) as usize] = data;
+    }
 }
 
 // https://www.nesdev.org/wiki/NROM for details on mapping mode 0
 impl Mapper for Mapper0 {
     // Default implementations mostly for test cases
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
     fn get_mirroring(&self) -> Mirroring {
         self.mirroring.clone()
     }
 
+    // Fixed banks, a 16 KB PRG ROM shows up twice
+    fn bank_mapping(&self) -> BankMapping {
+        let prg = [0x0000, 0x2000, 0x4000, 0x6000].map(|offset| offset % self.prg_rom.len());
+        BankMapping { prg, chr: [0, 0x1000] }
+    }
+
+
     // Mapper specific
     fn cpu_read(&self, mut addr: u16) -> u8 {
         match addr {
@@ -44,19 +65,19 @@ impl Mapper for Mapper0 {
             }
             0x8000..=0xFFFF => {
                 addr -= 0x8000; // Index from 0
-                // Remember 0x4000 == 16kB (a standard size for prg)
-                if self.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-                    addr = addr % 0x4000;
-                }
-                self.prg_rom[addr as usize]
+                // 16kB (and the odd 8kB) roms are mirrored to fill the 32kB window
+                self.prg_rom[addr as usize % self.prg_rom.len()]
             }
             _ => panic!("CPU READ to invalid address MAPPER 0")
         }
     }
 
-    fn cpu_write(&mut self, _addr: u16, _data: u8) {
-        // NROM PRG ROM is read-only
-        panic!("CPU WRITE TO PRG ROM IN MAPPER 0 NOT ALLOWED (might not want to panic this)")
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        // Family Basic style PRG RAM (also where test roms report their results).
+        // NROM PRG ROM is read-only, writes to it go nowhere.
+        if let 0x6000..=0x7FFF = addr {
+            self.prg_ram_write(addr, data);
+        }
     }
 
     fn ppu_read(&self, addr: u16) -> u8 {
@@ -68,10 +89,45 @@ impl Mapper for Mapper0 {
     }
 
     fn ppu_write(&mut self, addr: u16, data: u8) {
-        if self.chr_is_ram && addr < 0x2000 {
-            self.chr_rom[addr as usize] = data;
-        } else {
+        if addr >= 0x2000 {
             panic!("Invalid ppu write address for mapper0")
         }
+        // Writes to CHR ROM go nowhere
+        if self.chr_is_ram {
+            self.chr_rom[addr as usize] = data;
+        }
     }
-}
\ No newline at end of file
+}
+
+impl Snapshot for Mapper0 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            out.bytes(&self.chr_rom);
+        }
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            input.bytes_into(&mut self.chr_rom)?;
+        }
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chr_ram_takes_writes_and_chr_rom_ignores_them() {
+        let mut mapper = Mapper0::new(vec![0; 0x4000], vec![0; 0x2000], Mirroring::VERTICAL, true);
+        mapper.ppu_write(0x0000, 0x3C);
+        mapper.ppu_write(0x1FFF, 0xC3);
+        assert_eq!((mapper.ppu_read(0x0000), mapper.ppu_read(0x1FFF)), (0x3C, 0xC3));
+
+        let mut mapper = Mapper0::new(vec![0; 0x4000], vec![0x11; 0x2000], Mirroring::VERTICAL, false);
+        mapper.ppu_write(0x0000, 0x3C);
+        assert_eq!(mapper.ppu_read(0x0000), 0x11);
+    }
+}
@@ -1,5 +1,7 @@
-use crate::mapper::Mapper;
+use crate::banks::BankMapping;
+use crate::mapper::{self, Mapper};
 use crate::rom::Mirroring;
+use crate::state::{Snapshot, StateReader, StateWriter};
 
 pub struct Mapper1 {
     pub prg_rom: Vec<u8>,
@@ -28,6 +30,7 @@ pub struct Mapper1 {
 
     mirroring: Mirroring,
     chr_is_ram: bool,
+    ram_disable: bool, // MMC1B: bit 4 of the PRG bank register disables PRG RAM
 }
 
 impl Mapper1 {
@@ -55,13 +58,21 @@ impl Mapper1 {
 
 
             mirroring: mirroring,
-            chr_is_ram: chr_is_ram
+            chr_is_ram,
+            ram_disable: false,
         };
 
         mapper.update_banks();
         mapper
         
     }
+
+    // By default PRG RAM is always enabled like on MMC1A boards, which is what most
+    // games expect. Games that need the MMC1B disable bit turn it on through a quirk.
+    pub fn with_ram_disable(mut self, ram_disable: bool) -> Self {
+        self.ram_disable = ram_disable;
+        self
+    }
 }
 
 /*
@@ -81,12 +92,39 @@ CPPMM
 */
 
 impl Mapper1 {
+    fn prg_ram_enabled(&self) -> bool {
+        !self.ram_disable || self.prg_bank & 0b0001_0000 == 0
+    }
+
     fn prg_ram_read(&self, addr: u16) -> u8 {
+        if !self.prg_ram_enabled() {
+            return 0x60 | (addr >> 8) as u8; // open bus, the high byte of the address was last on it
+        }
         self.prg_ram[addr as usize]
     }
 
     fn prg_ram_write(&mut self, addr: u16, data: u8) {
-        self.prg_ram[addr as usize] = data;
+        if self.prg_ram_enabled() {
+            self.prg_ram[addr as usize] = data;
+        }
+    }
+
+    // Offsets wrap around the ROM, which can be smaller than the banks it is
+    // switched in (an 8 KB PRG fills both halves of a 16 KB bank)
+    fn prg_offset(&self, addr: u16) -> usize {
+        let offset = match addr {
+            0x8000..=0xBFFF => self.prg_bank_offset_first + (addr - 0x8000) as usize,
+            _ => self.prg_bank_offset_second + (addr - 0xC000) as usize,
+        };
+        offset % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let offset = match addr {
+            0x0000..=0x0FFF => self.chr_bank_0_offset + addr as usize,
+            _ => self.chr_bank_1_offset + (addr - 0x1000) as usize,
+        };
+        offset % self.chr_rom.len()
     }
 
     fn update_banks(&mut self) {
@@ -112,41 +150,51 @@ impl Mapper1 {
             _ => panic!("Invalid mirroring value when updating banks in mapping mode 1")
         };
 
-        let bank = (self.prg_bank & 0b0000_1111) as usize;
+        let prg_len = self.prg_rom.len();
         let single_prg_bank_size = 0x4000; // 16 Kb
+        // SUROM and friends: past 256 KB, bit 4 of the CHR bank register picks
+        // which 256 KB half of PRG the bank number counts from
+        let outer_bank = if prg_len > 0x40000 { (self.chr_bank_0 & 0b0001_0000) as usize } else { 0 };
+        let bank = outer_bank | (self.prg_bank & 0b0000_1111) as usize;
 
         match self.prg_rom_bank_mode {
             0 | 1 => {
-                self.prg_bank_offset_first = (bank & 0b1110) * single_prg_bank_size;
-                self.prg_bank_offset_second = self.prg_bank_offset_first + 0x4000;
+                self.prg_bank_offset_first = mapper::bank_offset(bank & !1, single_prg_bank_size, prg_len);
+                self.prg_bank_offset_second = mapper::bank_offset(bank | 1, single_prg_bank_size, prg_len);
             },
             2 => {
-                // Fix first offset to beginning of prg
-                self.prg_bank_offset_first = 0;
+                // Fix first offset to the first bank (of this 256 KB half)
+                self.prg_bank_offset_first = mapper::bank_offset(outer_bank, single_prg_bank_size, prg_len);
                 // Set second to custom offset
-                self.prg_bank_offset_second = bank * single_prg_bank_size;
+                self.prg_bank_offset_second = mapper::bank_offset(bank, single_prg_bank_size, prg_len);
             },
             3 => {
                 // Switch first
-                self.prg_bank_offset_first = bank * single_prg_bank_size;
-                // Fix second to last bank of prg
-                self.prg_bank_offset_second = ((self.prg_rom.len() / 0x4000) - 1) * single_prg_bank_size;
+                self.prg_bank_offset_first = mapper::bank_offset(bank, single_prg_bank_size, prg_len);
+                // Fix second to last bank of prg (of this 256 KB half)
+                self.prg_bank_offset_second = if prg_len > 0x40000 {
+                    mapper::bank_offset(outer_bank | 0b1111, single_prg_bank_size, prg_len)
+                } else {
+                    mapper::last_bank_offset(single_prg_bank_size, prg_len)
+                };
             },
             _ => panic!("Invalid prg rom bank setting in mapping mode 1 control bit")
         }
 
+        let chr_len = self.chr_rom.len();
         let single_chr_bank_size = 0x1000;
 
         match self.chr_rom_bank_mode {
             0 => {
                 // Set first bank and second bank based off first (8KB at once)
-                self.chr_bank_0_offset = ((self.chr_bank_0 as usize) & 0b0001_1110) * single_chr_bank_size;
-                self.chr_bank_1_offset = self.chr_bank_0_offset + 0x1000;
+                let bank = (self.chr_bank_0 & 0b0001_1110) as usize;
+                self.chr_bank_0_offset = mapper::bank_offset(bank, single_chr_bank_size, chr_len);
+                self.chr_bank_1_offset = mapper::bank_offset(bank | 1, single_chr_bank_size, chr_len);
             },
             1 => {
                 // Set each bank based off their own offset value
-                self.chr_bank_0_offset = ((self.chr_bank_0 as usize) & 0b0001_1111) * single_chr_bank_size;
-                self.chr_bank_1_offset = ((self.chr_bank_1 as usize) & 0b0001_1111) * single_chr_bank_size;
+                self.chr_bank_0_offset = mapper::bank_offset((self.chr_bank_0 & 0b0001_1111) as usize, single_chr_bank_size, chr_len);
+                self.chr_bank_1_offset = mapper::bank_offset((self.chr_bank_1 & 0b0001_1111) as usize, single_chr_bank_size, chr_len);
             },
             _ => panic!("Invalid chr rom bank setting in mapping mode 1 control bit")
         }
@@ -158,10 +206,24 @@ impl Mapper1 {
 // https://www.nesdev.org/wiki/NROM for details on mapping mode 0
 impl Mapper for Mapper1 {
     // Default implementations mostly for test cases
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
     fn get_mirroring(&self) -> Mirroring {
         self.mirroring.clone()
     }
 
+    fn bank_mapping(&self) -> BankMapping {
+        let prg = [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| self.prg_offset(addr));
+        BankMapping { prg, chr: [self.chr_offset(0x0000), self.chr_offset(0x1000)] }
+    }
+
+
     // Mapper specific
     fn cpu_read(&self, mut addr: u16) -> u8 {
         match addr {
@@ -169,17 +231,7 @@ impl Mapper for Mapper1 {
                 addr = addr % 0x2000;
                 self.prg_ram_read(addr)
             }
-            0x8000..=0xBFFF => {
-                addr -= 0x8000;
-                // println!("prg_bank_offset_first 0x:{:04X}", self.prg_bank_offset_first);
-                self.prg_rom[self.prg_bank_offset_first + addr as usize]
-            }
-            0xC000..=0xFFFF => {
-                // println!("addr before offsetting: 0x{:04X}", addr);
-                addr -= 0xC000; // Decrease more due to larger size of prg_bank_offset_second being larger
-                // println!("CPU READ: prg_bank_offset: 0x{:04X}, offset from bank: 0x{:04X}.", self.prg_bank_offset_second, addr);
-                self.prg_rom[self.prg_bank_offset_second + addr as usize]
-            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
             _ => panic!("CPU READ to invalid address MAPPER 1")
         }
     }
@@ -250,33 +302,122 @@ impl Mapper for Mapper1 {
         }
     }
 
-    fn ppu_read(&self, mut addr: u16) -> u8 {
+    fn ppu_read(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x0FFF => {
-                return self.chr_rom[self.chr_bank_0_offset + addr as usize]
-            }
-            0x1000..=0x1FFF => {
-                addr -= 0x1000;
-                return self.chr_rom[self.chr_bank_1_offset + addr as usize]
-            }
+            0x0000..=0x1FFF => self.chr_rom[self.chr_offset(addr)],
             _ => panic!("attempted to read from a ppu addr >= 0x2000 in mapper 1")
         }
     }
 
-    fn ppu_write(&mut self, mut addr: u16, data: u8) {
+    fn ppu_write(&mut self, addr: u16, data: u8) {
         if self.chr_is_ram && addr < 0x2000 {
-            match addr {
-                0x0000..=0x0FFF => {
-                    self.chr_rom[self.chr_bank_0_offset + addr as usize] = data;
-                }
-                0x1000..=0x1FFF => {
-                    addr -= 0x1000;
-                    self.chr_rom[self.chr_bank_1_offset + addr as usize] = data;
+            let offset = self.chr_offset(addr);
+            self.chr_rom[offset] = data;
+        } else if addr >= 0x2000 {
+            panic!("Invalid ppu write address for mapper1")
+        }
+        // Writes to CHR ROM go nowhere
+    }
+}
+
+impl Snapshot for Mapper1 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            out.bytes(&self.chr_rom);
+        }
+        for register in [self.shift_register, self.control, self.chr_bank_0, self.chr_bank_1, self.prg_bank] {
+            out.u8(register);
+        }
+        out.usize(self.shift_count);
+        out.u8(self.prg_rom_bank_mode);
+        out.u8(self.chr_rom_bank_mode);
+        for offset in [self.prg_bank_offset_first, self.prg_bank_offset_second, self.chr_bank_0_offset, self.chr_bank_1_offset] {
+            out.usize(offset);
+        }
+        out.mirroring(self.mirroring);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            input.bytes_into(&mut self.chr_rom)?;
+        }
+        for register in [&mut self.shift_register, &mut self.control, &mut self.chr_bank_0, &mut self.chr_bank_1, &mut self.prg_bank] {
+            *register = input.u8()?;
+        }
+        self.shift_count = input.usize()?;
+        self.prg_rom_bank_mode = input.u8()?;
+        self.chr_rom_bank_mode = input.u8()?;
+        let (prg_len, chr_len) = (self.prg_rom.len(), self.chr_rom.len());
+        for (offset, len) in [
+            (&mut self.prg_bank_offset_first, prg_len),
+            (&mut self.prg_bank_offset_second, prg_len),
+            (&mut self.chr_bank_0_offset, chr_len),
+            (&mut self.chr_bank_1_offset, chr_len),
+        ] {
+            *offset = input.usize()?;
+            if *offset >= len {
+                return Err(format!("Save state has a bank offset of {:#X} in a {:#X} byte ROM", offset, len));
+            }
+        }
+        self.mirroring = input.mirroring()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // MMC1 registers are loaded a bit at a time, low bit first
+    fn write_register(mapper: &mut Mapper1, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mapper.cpu_write(addr, (value >> bit) & 1);
+        }
+    }
+
+    #[test]
+    fn test_mirroring_changes_reach_the_nametables() {
+        let mut mapper = Mapper1::new(vec![0; 0x8000], vec![0; 0x2000], Mirroring::VERTICAL, true);
+        let mut vram = [0; 0x800];
+        vram[0x005] = 1;
+        vram[0x405] = 2;
+
+        write_register(&mut mapper, 0x8000, 0b0_1110); // vertical
+        assert_eq!((mapper.nametable_read(0x2405, &vram), mapper.nametable_read(0x2805, &vram)), (2, 1));
+        write_register(&mut mapper, 0x8000, 0b0_1111); // horizontal
+        assert_eq!((mapper.nametable_read(0x2405, &vram), mapper.nametable_read(0x2805, &vram)), (1, 2));
+        write_register(&mut mapper, 0x8000, 0b0_1101); // single screen, upper
+        mapper.nametable_write(0x2000, 3, &mut vram);
+        assert_eq!(vram[0x400], 3);
+    }
+
+    #[test]
+    fn test_8k_prg_fills_every_bank() {
+        let prg: Vec<u8> = (0..0x2000).map(|i| (i >> 8) as u8).collect();
+        let mut mapper = Mapper1::new(prg, vec![0; 0x2000], Mirroring::VERTICAL, true);
+        for mode in [0b0_0000, 0b0_1000, 0b0_1100] {
+            write_register(&mut mapper, 0x8000, mode);
+            for bank in 0..4 {
+                write_register(&mut mapper, 0xE000, bank);
+                for addr in [0x8005, 0xA005, 0xC105, 0xE105, 0xFFFF] {
+                    assert_eq!(mapper.cpu_read(addr), ((addr & 0x1FFF) >> 8) as u8);
                 }
-                _ => panic!("attempted to read from a ppu addr >= 0x2000 in mapper 1")
             }
-        } else {
-            panic!("Invalid ppu write address for mapper0")
         }
+        assert_eq!(mapper.bank_mapping().prg, [0; 4]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_load_state_rejects_offsets_past_the_rom() {
+        let mut mapper = Mapper1::new(vec![0; 0x2000], vec![0; 0x2000], Mirroring::VERTICAL, true);
+        let mut out = StateWriter::new();
+        mapper.save_state(&mut out);
+        let mut state = out.finish();
+        // The offsets sit just before the mirroring byte at the end
+        let second = state.len() - 1 - 3 * 8;
+        state[second..second + 8].copy_from_slice(&0x4000usize.to_le_bytes());
+        assert!(mapper.load_state(&mut StateReader::new(&state)).is_err());
+    }
+}
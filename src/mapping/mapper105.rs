@@ -0,0 +1,341 @@
+use crate::banks::BankMapping;
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// Nintendo World Championships 1990 (mapper 105). An MMC1 with two 128KB PRG chips
+// and a countdown timer for the competition. The MMC1's CHR bank 0 register is
+// rewired, CHR is a plain 8KB of RAM:
+//
+//   4bit0
+//   -----
+//   IRAA.
+//   |||+--- 32KB bank from the first chip (when R is 0)
+//   ||+---- R: 0 = first chip in 32KB banks, 1 = second chip with normal MMC1 banking
+//   +------ I: 1 holds the timer at zero and acknowledges its IRQ, 0 lets it count
+//
+// At power on only the first 32KB is visible until I has been set and cleared once.
+// The timer raises an IRQ after (16 + DIP switches) * 2^25 CPU cycles, so from
+// 5:00 with every switch off up to about 9:41 with all four on.
+// https://www.nesdev.org/wiki/INES_Mapper_105
+const TIMER_STEP: u64 = 1 << 25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Startup {
+    Locked, // waiting for I to be set
+    Armed, // waiting for I to be cleared again
+    Running,
+}
+
+pub struct Mapper105 {
+    pub prg_rom: Vec<u8>,
+    pub prg_ram: Vec<u8>,
+    pub chr_ram: Vec<u8>,
+
+    shift_register: u8,
+    shift_count: usize,
+    control: u8,
+    chr_bank_0: u8, // IRAA. above
+    prg_bank: u8,
+
+    prg_bank_offset_first: usize,
+    prg_bank_offset_second: usize,
+    mirroring: Mirroring,
+
+    startup: Startup,
+    timer: u64,
+    timer_target: u64,
+    irq: bool,
+}
+
+impl Mapper105 {
+    pub fn new(prg_rom: Vec<u8>, dip_switches: u8) -> Self {
+        let mut mapper = Mapper105 {
+            prg_rom,
+            prg_ram: vec![0; 0x2000],
+            chr_ram: vec![0; 0x2000],
+            shift_register: 0,
+            shift_count: 0,
+            control: 0x0C,
+            chr_bank_0: 0,
+            prg_bank: 0,
+            prg_bank_offset_first: 0,
+            prg_bank_offset_second: 0,
+            mirroring: Mirroring::SINGLELOWER,
+            startup: Startup::Locked,
+            timer: 0,
+            timer_target: (16 + (dip_switches & 0x0F) as u64) * TIMER_STEP,
+            irq: false,
+        };
+        mapper.update_banks();
+        mapper
+    }
+
+    // Seconds left on the competition clock, for a frontend that wants to show it
+    pub fn seconds_left(&self) -> f64 {
+        (self.timer_target - self.timer.min(self.timer_target)) as f64 / 1_789_773.0
+    }
+
+    fn update_banks(&mut self) {
+        self.mirroring = match self.control & 0b11 {
+            0 => Mirroring::SINGLELOWER,
+            1 => Mirroring::SINGLEUPPER,
+            2 => Mirroring::VERTICAL,
+            _ => Mirroring::HORIZONTAL,
+        };
+
+        let prg_len = self.prg_rom.len();
+        if self.startup != Startup::Running {
+            self.prg_bank_offset_first = mapper::bank_offset(0, 0x4000, prg_len);
+            self.prg_bank_offset_second = mapper::bank_offset(1, 0x4000, prg_len);
+        } else if self.chr_bank_0 & 0b0_1000 == 0 {
+            // First chip, 32KB at a time
+            let bank = ((self.chr_bank_0 >> 1) & 0b11) as usize * 2;
+            self.prg_bank_offset_first = mapper::bank_offset(bank, 0x4000, prg_len);
+            self.prg_bank_offset_second = mapper::bank_offset(bank + 1, 0x4000, prg_len);
+        } else {
+            // Second chip (banks 8-15) with the usual MMC1 modes
+            let bank = 0b1000 | (self.prg_bank & 0b0111) as usize;
+            let (first, second) = match (self.control >> 2) & 0b11 {
+                0 | 1 => (bank & !1, bank | 1),
+                2 => (0b1000, bank),
+                _ => (bank, 0b1111),
+            };
+            self.prg_bank_offset_first = mapper::bank_offset(first, 0x4000, prg_len);
+            self.prg_bank_offset_second = mapper::bank_offset(second, 0x4000, prg_len);
+        }
+    }
+
+    // Wraps around images smaller than the banks they're switched in, see mapper1.rs
+    fn prg_offset(&self, addr: u16) -> usize {
+        let offset = match addr {
+            0x8000..=0xBFFF => self.prg_bank_offset_first + (addr - 0x8000) as usize,
+            _ => self.prg_bank_offset_second + (addr - 0xC000) as usize,
+        };
+        offset % self.prg_rom.len()
+    }
+
+    fn write_chr_bank_0(&mut self, value: u8) {
+        let hold_timer = value & 0b1_0000 != 0;
+        self.startup = match (self.startup, hold_timer) {
+            (Startup::Locked, true) => Startup::Armed,
+            (Startup::Armed, false) => Startup::Running,
+            (startup, _) => startup,
+        };
+        if hold_timer {
+            self.timer = 0;
+            self.irq = false;
+        }
+        self.chr_bank_0 = value;
+    }
+}
+
+impl Mapper for Mapper105 {
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn bank_mapping(&self) -> BankMapping {
+        let prg = [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| self.prg_offset(addr));
+        BankMapping { prg, chr: [0, 0x1000] }
+    }
+
+    fn cpu_clock(&mut self, cycles: usize) {
+        // The timer only runs once the game has started it
+        if self.chr_bank_0 & 0b1_0000 != 0 || self.startup != Startup::Running {
+            return;
+        }
+        self.timer += cycles as u64;
+        if self.timer >= self.timer_target {
+            self.irq = true;
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq
+    }
+
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+            _ => panic!("CPU READ to invalid address MAPPER 105")
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            self.prg_ram[(addr - 0x6000) as usize] = data;
+            return;
+        }
+
+        // MMC1 serial port, see mapper1.rs
+        if data & 0x80 != 0 {
+            self.shift_register = 0;
+            self.shift_count = 0;
+            self.control |= 0x0C;
+            self.update_banks();
+            return;
+        }
+        self.shift_register = (self.shift_register >> 1) | ((data & 1) << 4);
+        self.shift_count += 1;
+        if self.shift_count < 5 {
+            return;
+        }
+
+        let value = self.shift_register;
+        match (addr - 0x8000) / 0x2000 {
+            0 => self.control = value,
+            1 => self.write_chr_bank_0(value),
+            2 => {} // CHR bank 1 isn't connected
+            _ => self.prg_bank = value,
+        }
+        self.shift_register = 0;
+        self.shift_count = 0;
+        self.update_banks();
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr_ram[(addr & 0x1FFF) as usize]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr_ram[(addr & 0x1FFF) as usize] = data;
+    }
+}
+
+impl Snapshot for Mapper105 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.prg_ram);
+        out.bytes(&self.chr_ram);
+        for register in [self.shift_register, self.control, self.chr_bank_0, self.prg_bank] {
+            out.u8(register);
+        }
+        out.usize(self.shift_count);
+        out.usize(self.prg_bank_offset_first);
+        out.usize(self.prg_bank_offset_second);
+        out.mirroring(self.mirroring);
+        out.u8(self.startup as u8);
+        out.u64(self.timer);
+        out.u64(self.timer_target);
+        out.bool(self.irq);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.prg_ram)?;
+        input.bytes_into(&mut self.chr_ram)?;
+        for register in [&mut self.shift_register, &mut self.control, &mut self.chr_bank_0, &mut self.prg_bank] {
+            *register = input.u8()?;
+        }
+        self.shift_count = input.usize()?;
+        for offset in [&mut self.prg_bank_offset_first, &mut self.prg_bank_offset_second] {
+            *offset = input.usize()?;
+            if *offset >= self.prg_rom.len() {
+                return Err(format!("Save state has a bank offset of {:#X} in a {:#X} byte ROM", offset, self.prg_rom.len()));
+            }
+        }
+        self.mirroring = input.mirroring()?;
+        self.startup = match input.u8()? {
+            0 => Startup::Locked,
+            1 => Startup::Armed,
+            2 => Startup::Running,
+            value => return Err(format!("Save state has an unknown NWC startup step {}", value)),
+        };
+        self.timer = input.u64()?;
+        self.timer_target = input.u64()?;
+        self.irq = input.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_register(mapper: &mut Mapper105, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mapper.cpu_write(addr, (value >> bit) & 1);
+        }
+    }
+
+    // 256KB, each 16KB bank filled with its number
+    fn nwc() -> Mapper105 {
+        Mapper105::new((0..16).flat_map(|bank| vec![bank; 0x4000]).collect(), 0)
+    }
+
+    #[test]
+    fn test_unlock_and_chip_select() {
+        let mut mapper = nwc();
+        write_register(&mut mapper, 0xA000, 0b0_0100); // ignored while locked
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+
+        write_register(&mut mapper, 0xA000, 0b1_0000);
+        write_register(&mut mapper, 0xA000, 0b0_0100); // unlocked, first chip 32KB bank 2
+        assert_eq!((mapper.cpu_read(0x8000), mapper.cpu_read(0xC000)), (4, 5));
+
+        write_register(&mut mapper, 0xA000, 0b0_1000); // second chip, MMC1 mode 3
+        write_register(&mut mapper, 0xE000, 3);
+        assert_eq!((mapper.cpu_read(0x8000), mapper.cpu_read(0xC000)), (11, 15));
+    }
+
+    #[test]
+    fn test_timer_irq() {
+        let mut mapper = nwc();
+        mapper.cpu_clock(1 << 30);
+        assert!(!mapper.irq_pending()); // not started
+
+        write_register(&mut mapper, 0xA000, 0b1_0000);
+        write_register(&mut mapper, 0xA000, 0);
+        assert!((mapper.seconds_left() - 299.96).abs() < 0.01);
+        mapper.cpu_clock((16 << 25) - 1);
+        assert!(!mapper.irq_pending());
+        mapper.cpu_clock(1);
+        assert!(mapper.irq_pending());
+
+        // Setting I acknowledges it
+        write_register(&mut mapper, 0xA000, 0b1_0000);
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn test_small_images_wrap() {
+        for len in [0x4000, 0x6000] {
+            let prg: Vec<u8> = (0..len).map(|i| (i >> 12) as u8).collect();
+            let mut mapper = Mapper105::new(prg, 0);
+            assert_eq!(mapper.cpu_read(0xFFFC), ((0x3FFC % len) >> 12) as u8);
+            write_register(&mut mapper, 0xA000, 0b1_0000);
+            write_register(&mut mapper, 0xA000, 0b0_1000); // second chip, past the end
+            write_register(&mut mapper, 0xE000, 7);
+            for addr in [0x8000, 0xBFFF, 0xC000, 0xFFFF] {
+                mapper.cpu_read(addr);
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_state_rejects_bad_offsets_and_startup() {
+        let mut mapper = Mapper105::new(vec![0; 0x4000], 0);
+        let mut out = StateWriter::new();
+        mapper.save_state(&mut out);
+        let state = out.finish();
+        // Then come the two offsets, mirroring, startup and the 17 bytes of timer and IRQ
+        let startup = state.len() - 17 - 1;
+        let second = startup - 1 - 8;
+
+        let mut bad_offset = state.clone();
+        bad_offset[second..second + 8].copy_from_slice(&0x4000usize.to_le_bytes());
+        assert!(mapper.load_state(&mut StateReader::new(&bad_offset)).is_err());
+        let mut bad_startup = state.clone();
+        bad_startup[startup] = 3;
+        assert!(mapper.load_state(&mut StateReader::new(&bad_startup)).is_err());
+        assert!(mapper.load_state(&mut StateReader::new(&state)).is_ok());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::banks::BankMapping;
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// Sunsoft-1 (mapper 184): fixed PRG, and two 4KB CHR banks picked by a register
+// at $6000-$7FFF. There is no PRG RAM, that range is only the register.
+// https://www.nesdev.org/wiki/INES_Mapper_184
+pub struct Mapper184 {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    chr_bank_0_offset: usize,
+    chr_bank_1_offset: usize,
+    mirroring: Mirroring,
+}
+
+impl Mapper184 {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        let mut mapper = Mapper184 {
+            prg_rom,
+            chr_rom,
+            chr_bank_0_offset: 0,
+            chr_bank_1_offset: 0,
+            mirroring,
+        };
+        mapper.select_chr(0);
+        mapper
+    }
+
+    // .HHH .LLL, the top bit of H is always set by the hardware
+    fn select_chr(&mut self, data: u8) {
+        let low = (data & 0b0000_0111) as usize;
+        let high = ((data >> 4) & 0b0000_0111) as usize | 0b100;
+        self.chr_bank_0_offset = mapper::bank_offset(low, 0x1000, self.chr_rom.len());
+        self.chr_bank_1_offset = mapper::bank_offset(high, 0x1000, self.chr_rom.len());
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x0FFF => self.chr_bank_0_offset + addr as usize,
+            _ => self.chr_bank_1_offset + (addr - 0x1000) as usize,
+        }
+    }
+}
+
+impl Mapper for Mapper184 {
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn bank_mapping(&self) -> BankMapping {
+        let prg = [0x0000, 0x2000, 0x4000, 0x6000].map(|offset| offset % self.prg_rom.len());
+        BankMapping { prg, chr: [self.chr_bank_0_offset, self.chr_bank_1_offset] }
+    }
+
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => (addr >> 8) as u8, // write only register, open bus
+            0x8000..=0xFFFF => self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()],
+            _ => panic!("CPU READ to invalid address MAPPER 184")
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        // Anything else is PRG ROM, which is read-only
+        if (0x6000..=0x7FFF).contains(&addr) {
+            self.select_chr(data);
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        if addr < 0x2000 {
+            self.chr_rom[self.chr_offset(addr)]
+        } else {
+            panic!("Invalid ppu read address for mapper184")
+        }
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {
+        // CHR ROM only, writes go nowhere
+    }
+}
+
+impl Snapshot for Mapper184 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.usize(self.chr_bank_0_offset);
+        out.usize(self.chr_bank_1_offset);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        let chr_len = self.chr_rom.len();
+        for offset in [&mut self.chr_bank_0_offset, &mut self.chr_bank_1_offset] {
+            *offset = input.usize()?;
+            if *offset >= chr_len {
+                return Err(format!("Save state has a bank offset of {:#X} in a {:#X} byte ROM", offset, chr_len));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chr_banks() {
+        // 32KB CHR, each 4KB bank filled with its number
+        let chr_rom = (0..8).flat_map(|bank| vec![bank; 0x1000]).collect();
+        let mut mapper = Mapper184::new(vec![0; 0x8000], chr_rom, Mirroring::HORIZONTAL);
+        assert_eq!(mapper.ppu_read(0x0000), 0);
+        assert_eq!(mapper.ppu_read(0x1000), 4);
+
+        mapper.cpu_write(0x6000, 0b0010_0011);
+        assert_eq!(mapper.ppu_read(0x0000), 3);
+        assert_eq!(mapper.ppu_read(0x1FFF), 6);
+    }
+
+    #[test]
+    fn test_load_state_rejects_offsets_past_the_rom() {
+        let mut mapper = Mapper184::new(vec![0; 0x8000], vec![0; 0x2000], Mirroring::HORIZONTAL);
+        let mut out = StateWriter::new();
+        out.usize(0);
+        out.usize(0x2000);
+        assert!(mapper.load_state(&mut StateReader::new(&out.finish())).is_err());
+
+        let mut out = StateWriter::new();
+        out.usize(0);
+        out.usize(0x1000);
+        assert!(mapper.load_state(&mut StateReader::new(&out.finish())).is_ok());
+        assert_eq!(mapper.ppu_read(0x1FFF), 0);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::banks::BankMapping;
+use crate::mapper::Mapper;
+use crate::rom::Mirroring;
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// CNROM with copy protection (mapper 185): a single 8KB CHR ROM that the register
+// at $8000-$FFFF can switch off. Games like B-Wings and Mighty Bomb Jack write a
+// value that disables CHR at boot, read a few bytes back through $2007 and refuse
+// to run if they got real tile data, so disabled reads have to return junk.
+// https://www.nesdev.org/wiki/INES_Mapper_185
+const DISABLED_CHR_VALUE: u8 = 0xFF;
+
+pub struct Mapper185 {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    chr_enabled: bool,
+    mirroring: Mirroring,
+}
+
+impl Mapper185 {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        Mapper185 { prg_rom, chr_rom, chr_enabled: true, mirroring }
+    }
+
+    fn prg_read(&self, addr: u16) -> u8 {
+        self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()]
+    }
+}
+
+impl Mapper for Mapper185 {
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn bank_mapping(&self) -> BankMapping {
+        let prg = [0x0000, 0x2000, 0x4000, 0x6000].map(|offset| offset % self.prg_rom.len());
+        BankMapping { prg, chr: [0, 0x1000] }
+    }
+
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => (addr >> 8) as u8, // no PRG RAM, open bus
+            0x8000..=0xFFFF => self.prg_read(addr),
+            _ => panic!("CPU READ to invalid address MAPPER 185")
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+        // Bus conflict: the ROM drives the bus too, so only bits both agree on land
+        let value = data & self.prg_read(addr);
+        // Which values enable CHR differs between boards. The iNES 1 header can't say
+        // which one a game has, so use the rule that suits all of them (as FCEUX and
+        // Mesen do): any of the low 4 bits set, except for $13
+        self.chr_enabled = value & 0x0F != 0 && value != 0x13;
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        if addr >= 0x2000 {
+            panic!("Invalid ppu read address for mapper185")
+        }
+        if self.chr_enabled {
+            self.chr_rom[addr as usize % self.chr_rom.len()]
+        } else {
+            DISABLED_CHR_VALUE
+        }
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {
+        // CHR ROM only, writes go nowhere
+    }
+}
+
+impl Snapshot for Mapper185 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bool(self.chr_enabled);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.chr_enabled = input.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chr_disable_returns_junk() {
+        let mut prg_rom = vec![0xFF; 0x8000];
+        prg_rom[0x10] = 0x00; // the register write at $8010 conflicts with this
+        let mut mapper = Mapper185::new(prg_rom, vec![0x42; 0x2000], Mirroring::VERTICAL);
+        assert_eq!(mapper.ppu_read(0x0123), 0x42);
+
+        mapper.cpu_write(0x8000, 0x00);
+        assert_eq!(mapper.ppu_read(0x0123), DISABLED_CHR_VALUE);
+        mapper.cpu_write(0x8000, 0x13);
+        assert_eq!(mapper.ppu_read(0x0123), DISABLED_CHR_VALUE);
+        mapper.cpu_write(0x8000, 0x21);
+        assert_eq!(mapper.ppu_read(0x0123), 0x42);
+
+        // $33 ANDed with the $00 in ROM disables it again
+        mapper.cpu_write(0x8010, 0x33);
+        assert_eq!(mapper.ppu_read(0x0123), DISABLED_CHR_VALUE);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::banks::BankMapping;
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// UxROM (mapper 2): a 16KB PRG bank picked by writing $8000-$FFFF shows at $8000,
+// the last 16KB stays at $C000. CHR is 8KB of RAM on almost every board, and
+// mirroring is soldered. Mega Man, Castlevania, Contra, DuckTales...
+// UNROM has 3 bank bits and UOROM 4, the rest of the byte is ignored by wrapping.
+// https://www.nesdev.org/wiki/UxROM
+pub struct Mapper2 {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    prg_bank_offset: usize,
+    mirroring: Mirroring,
+    chr_is_ram: bool,
+}
+
+impl Mapper2 {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mirroring: Mirroring, chr_is_ram: bool) -> Self {
+        Mapper2 { prg_rom, chr_rom, prg_bank_offset: 0, mirroring, chr_is_ram }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        match addr {
+            0x8000..=0xBFFF => self.prg_bank_offset + (addr - 0x8000) as usize,
+            _ => mapper::last_bank_offset(0x4000, self.prg_rom.len()) + (addr - 0xC000) as usize,
+        }
+    }
+
+    fn prg_read(&self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_offset(addr) % self.prg_rom.len()]
+    }
+}
+
+impl Mapper for Mapper2 {
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn bank_mapping(&self) -> BankMapping {
+        let prg = [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| self.prg_offset(addr) % self.prg_rom.len());
+        BankMapping { prg, chr: [0, 0x1000] }
+    }
+
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => (addr >> 8) as u8, // no PRG RAM, open bus
+            0x8000..=0xFFFF => self.prg_read(addr),
+            _ => panic!("CPU READ to invalid address MAPPER 2")
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+        // Bus conflict: the ROM drives the bus too, so only bits both agree on land
+        let value = data & self.prg_read(addr);
+        self.prg_bank_offset = mapper::bank_offset(value as usize, 0x4000, self.prg_rom.len());
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        if addr < 0x2000 {
+            self.chr_rom[addr as usize % self.chr_rom.len()]
+        } else {
+            panic!("Invalid ppu read address for mapper2")
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x2000 {
+            panic!("Invalid ppu write address for mapper2")
+        }
+        // Writes to CHR ROM go nowhere
+        if self.chr_is_ram {
+            let len = self.chr_rom.len();
+            self.chr_rom[addr as usize % len] = data;
+        }
+    }
+}
+
+impl Snapshot for Mapper2 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.usize(self.prg_bank_offset);
+        if self.chr_is_ram {
+            out.bytes(&self.chr_rom);
+        }
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.prg_bank_offset = input.usize()?;
+        if self.chr_is_ram {
+            input.bytes_into(&mut self.chr_rom)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_switchable_and_fixed_banks() {
+        // 128KB, each 16KB bank filled with its number
+        let prg_rom = (0..8).flat_map(|bank| vec![bank; 0x4000]).collect();
+        let mut mapper = Mapper2::new(prg_rom, vec![0; 0x2000], Mirroring::VERTICAL, true);
+        assert_eq!((mapper.cpu_read(0x8000), mapper.cpu_read(0xC000)), (0, 7));
+
+        // Bank 5, written over a ROM byte that has the bits set
+        mapper.cpu_write(0xFFFF, 0x05);
+        assert_eq!((mapper.cpu_read(0xBFFF), mapper.cpu_read(0xFFFF)), (5, 7));
+
+        // Bus conflict: written over a byte of bank 7 (0b111) the value 0b1010 lands as 0b0010
+        mapper.cpu_write(0xC000, 0b1010);
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+
+        mapper.ppu_write(0x1234, 0x77);
+        assert_eq!(mapper.ppu_read(0x1234), 0x77);
+    }
+}
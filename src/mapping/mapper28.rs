@@ -0,0 +1,187 @@
+use crate::banks::BankMapping;
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// Action 53 (mapper 28), the homebrew multicart board. Each game sits in an outer
+// bank and sees its own NROM/CNROM/UNROM/AOROM style banking inside it, with up
+// to 8MB of PRG and 32KB of CHR RAM. A write to $5000-$5FFF picks a register,
+// writes to $8000-$FFFF go to it:
+//
+//   $00 CHR bank     ...M ..CC   CC = 8KB CHR RAM bank
+//   $01 inner bank   ...M PPPP   PPPP = 16KB/32KB bank inside the game
+//   $80 mode         ..SS PPMM   MM = mirroring, PP = PRG mode, SS = game size
+//   $81 outer bank   OOOO OOOO   32KB bank where the game starts
+//
+// M in registers $00 and $01 sets the one-screen page when MM is 0 or 1.
+// https://www.nesdev.org/wiki/Action_53_mapper
+pub struct Mapper28 {
+    pub prg_rom: Vec<u8>,
+    pub chr_ram: Vec<u8>,
+    selected: u8, // register written by $8000-$FFFF
+    chr_bank: u8,
+    inner_bank: u8,
+    mode: u8,
+    outer_bank: u8,
+}
+
+impl Mapper28 {
+    pub fn new(prg_rom: Vec<u8>) -> Self {
+        Mapper28 {
+            prg_rom,
+            chr_ram: vec![0; 0x8000],
+            selected: 0,
+            chr_bank: 0,
+            inner_bank: 0,
+            mode: 0,
+            outer_bank: 0xFF, // the menu lives in the last bank and starts from there
+        }
+    }
+
+    // 16KB bank seen at `addr` ($8000-$FFFF)
+    fn prg_bank(&self, addr: u16) -> usize {
+        let a14 = ((addr >> 14) & 1) as usize;
+        let outer = (self.outer_bank as usize) << 1;
+        let prg_mode = ((self.mode >> 2) & 0b11) as usize;
+
+        // UNROM style modes have one fixed half: the matching half of the outer bank
+        if prg_mode ^ a14 == 0b10 {
+            return outer | a14;
+        }
+
+        let inner = if prg_mode & 0b10 == 0 {
+            ((self.inner_bank as usize) << 1) | a14 // 32KB at a time
+        } else {
+            self.inner_bank as usize
+        };
+        // Game size picks how many low bits come from the inner bank
+        let inner_mask = [0b1, 0b11, 0b111, 0b1111][((self.mode >> 4) & 0b11) as usize];
+        (outer & !inner_mask) | (inner & inner_mask)
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        mapper::bank_offset(self.prg_bank(addr), 0x4000, self.prg_rom.len()) + (addr & 0x3FFF) as usize
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        ((self.chr_bank & 0b11) as usize) * 0x2000 + (addr & 0x1FFF) as usize
+    }
+
+    // Bit 4 of registers $00 and $01 picks the page in one-screen mode
+    fn set_one_screen_page(&mut self, value: u8) {
+        if self.mode & 0b10 == 0 {
+            self.mode = (self.mode & !1) | ((value >> 4) & 1);
+        }
+    }
+}
+
+impl Mapper for Mapper28 {
+    fn get_mirroring(&self) -> Mirroring {
+        match self.mode & 0b11 {
+            0 => Mirroring::SINGLELOWER,
+            1 => Mirroring::SINGLEUPPER,
+            2 => Mirroring::VERTICAL,
+            _ => Mirroring::HORIZONTAL,
+        }
+    }
+
+    fn bank_mapping(&self) -> BankMapping {
+        BankMapping {
+            prg: [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| self.prg_offset(addr)),
+            chr: [self.chr_offset(0x0000), self.chr_offset(0x1000)],
+        }
+    }
+
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => (addr >> 8) as u8, // no PRG RAM, open bus
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+            _ => panic!("CPU READ to invalid address MAPPER 28")
+        }
+    }
+
+    fn expansion_write(&mut self, addr: u16, data: u8) -> bool {
+        if addr >= 0x5000 {
+            self.selected = data & 0x81;
+        }
+        addr >= 0x5000
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return; // $6000-$7FFF has nothing on this board
+        }
+        match self.selected {
+            0x00 => {
+                self.chr_bank = data;
+                self.set_one_screen_page(data);
+            }
+            0x01 => {
+                self.inner_bank = data & 0x0F;
+                self.set_one_screen_page(data);
+            }
+            0x80 => self.mode = data & 0x3F,
+            _ => self.outer_bank = data,
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr_ram[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr_ram[offset] = data;
+    }
+}
+
+impl Snapshot for Mapper28 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.chr_ram);
+        for register in [self.selected, self.chr_bank, self.inner_bank, self.mode, self.outer_bank] {
+            out.u8(register);
+        }
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.chr_ram)?;
+        for register in [&mut self.selected, &mut self.chr_bank, &mut self.inner_bank, &mut self.mode, &mut self.outer_bank] {
+            *register = input.u8()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_register(mapper: &mut Mapper28, register: u8, value: u8) {
+        mapper.expansion_write(0x5000, register);
+        mapper.cpu_write(0x8000, value);
+    }
+
+    #[test]
+    fn test_game_banking_inside_outer_bank() {
+        // 512KB, each 16KB bank filled with its number
+        let mut mapper = Mapper28::new((0..32).flat_map(|bank| vec![bank; 0x4000]).collect());
+        // Power on: the last 32KB
+        assert_eq!((mapper.cpu_read(0x8000), mapper.cpu_read(0xC000)), (30, 31));
+
+        // A 128KB UNROM game starting at 32KB bank 4 (16KB bank 8)
+        write_register(&mut mapper, 0x81, 0x07); // outer bank points at the game's last 32KB
+        write_register(&mut mapper, 0x80, 0b10_1111); // 128KB, fixed $C000, horizontal
+        write_register(&mut mapper, 0x01, 2);
+        assert_eq!((mapper.cpu_read(0x8000), mapper.cpu_read(0xC000)), (10, 15));
+        assert_eq!(mapper.get_mirroring(), Mirroring::HORIZONTAL);
+
+        // 32KB NROM game in bank 1, one-screen page from bit 4
+        write_register(&mut mapper, 0x81, 0x01);
+        write_register(&mut mapper, 0x80, 0b00_0000);
+        write_register(&mut mapper, 0x00, 0b1_0010);
+        assert_eq!((mapper.cpu_read(0x8000), mapper.cpu_read(0xC000)), (2, 3));
+        assert_eq!(mapper.get_mirroring(), Mirroring::SINGLEUPPER);
+        mapper.ppu_write(0x0010, 0xAB);
+        assert_eq!(mapper.chr_ram[0x4010], 0xAB);
+    }
+}
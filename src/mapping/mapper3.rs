@@ -0,0 +1,116 @@
+use crate::banks::BankMapping;
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// CNROM (mapper 3): fixed 16 or 32KB PRG, and an 8KB CHR ROM bank picked by
+// writing $8000-$FFFF. Mirroring is soldered. Arkanoid, Gradius, Paperboy...
+// The original board has 2 bank bits, later ones more, so the value wraps.
+// https://www.nesdev.org/wiki/INES_Mapper_003
+pub struct Mapper3 {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    chr_bank_offset: usize,
+    mirroring: Mirroring,
+    chr_is_ram: bool,
+}
+
+impl Mapper3 {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mirroring: Mirroring, chr_is_ram: bool) -> Self {
+        Mapper3 { prg_rom, chr_rom, chr_bank_offset: 0, mirroring, chr_is_ram }
+    }
+
+    fn prg_read(&self, addr: u16) -> u8 {
+        self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()]
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        (self.chr_bank_offset + addr as usize) % self.chr_rom.len()
+    }
+}
+
+impl Mapper for Mapper3 {
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn bank_mapping(&self) -> BankMapping {
+        let prg = [0x0000, 0x2000, 0x4000, 0x6000].map(|offset| offset % self.prg_rom.len());
+        BankMapping { prg, chr: [self.chr_offset(0x0000), self.chr_offset(0x1000)] }
+    }
+
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => (addr >> 8) as u8, // no PRG RAM, open bus
+            0x8000..=0xFFFF => self.prg_read(addr),
+            _ => panic!("CPU READ to invalid address MAPPER 3")
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+        // Bus conflict: the ROM drives the bus too, so only bits both agree on land
+        let value = data & self.prg_read(addr);
+        self.chr_bank_offset = mapper::bank_offset(value as usize, 0x2000, self.chr_rom.len());
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        if addr < 0x2000 {
+            self.chr_rom[self.chr_offset(addr)]
+        } else {
+            panic!("Invalid ppu read address for mapper3")
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x2000 {
+            panic!("Invalid ppu write address for mapper3")
+        }
+        // A few homebrew boards have CHR RAM, writes to CHR ROM go nowhere
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr_rom[offset] = data;
+        }
+    }
+}
+
+impl Snapshot for Mapper3 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.usize(self.chr_bank_offset);
+        if self.chr_is_ram {
+            out.bytes(&self.chr_rom);
+        }
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.chr_bank_offset = input.usize()?;
+        if self.chr_is_ram {
+            input.bytes_into(&mut self.chr_rom)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chr_banks() {
+        // 32KB CHR, each 8KB bank filled with its number
+        let chr_rom = (0..4).flat_map(|bank| vec![bank; 0x2000]).collect();
+        let mut mapper = Mapper3::new(vec![0xFF; 0x8000], chr_rom, Mirroring::HORIZONTAL, false);
+        assert_eq!(mapper.ppu_read(0x0000), 0);
+
+        mapper.cpu_write(0x8000, 2);
+        assert_eq!((mapper.ppu_read(0x0000), mapper.ppu_read(0x1FFF)), (2, 2));
+        // Past the last bank wraps around
+        mapper.cpu_write(0x8000, 7);
+        assert_eq!(mapper.ppu_read(0x0000), 3);
+        // CHR ROM ignores writes
+        mapper.ppu_write(0x0000, 0x55);
+        assert_eq!(mapper.ppu_read(0x0000), 3);
+    }
+}
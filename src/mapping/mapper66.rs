@@ -0,0 +1,119 @@
+use crate::banks::BankMapping;
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// GxROM (mapper 66): writing $8000-$FFFF picks a 32KB PRG bank (bits 4-5) and an
+// 8KB CHR bank (bits 0-1) at once. Mirroring is soldered. Super Mario Bros. +
+// Duck Hunt, Dragon Power...
+// https://www.nesdev.org/wiki/GxROM
+pub struct Mapper66 {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    prg_bank_offset: usize,
+    chr_bank_offset: usize,
+    mirroring: Mirroring,
+    chr_is_ram: bool,
+}
+
+impl Mapper66 {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mirroring: Mirroring, chr_is_ram: bool) -> Self {
+        Mapper66 { prg_rom, chr_rom, prg_bank_offset: 0, chr_bank_offset: 0, mirroring, chr_is_ram }
+    }
+
+    fn prg_read(&self, addr: u16) -> u8 {
+        self.prg_rom[(self.prg_bank_offset + (addr - 0x8000) as usize) % self.prg_rom.len()]
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        (self.chr_bank_offset + addr as usize) % self.chr_rom.len()
+    }
+}
+
+impl Mapper for Mapper66 {
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn bank_mapping(&self) -> BankMapping {
+        let prg = [0x0000, 0x2000, 0x4000, 0x6000].map(|offset| (self.prg_bank_offset + offset) % self.prg_rom.len());
+        BankMapping { prg, chr: [self.chr_offset(0x0000), self.chr_offset(0x1000)] }
+    }
+
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => (addr >> 8) as u8, // no PRG RAM, open bus
+            0x8000..=0xFFFF => self.prg_read(addr),
+            _ => panic!("CPU READ to invalid address MAPPER 66")
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+        // Bus conflict: the ROM drives the bus too, so only bits both agree on land
+        let value = data & self.prg_read(addr);
+        self.prg_bank_offset = mapper::bank_offset(((value >> 4) & 0b11) as usize, 0x8000, self.prg_rom.len());
+        self.chr_bank_offset = mapper::bank_offset((value & 0b11) as usize, 0x2000, self.chr_rom.len());
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        if addr < 0x2000 {
+            self.chr_rom[self.chr_offset(addr)]
+        } else {
+            panic!("Invalid ppu read address for mapper66")
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x2000 {
+            panic!("Invalid ppu write address for mapper66")
+        }
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr_rom[offset] = data;
+        }
+    }
+}
+
+impl Snapshot for Mapper66 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.usize(self.prg_bank_offset);
+        out.usize(self.chr_bank_offset);
+        if self.chr_is_ram {
+            out.bytes(&self.chr_rom);
+        }
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.prg_bank_offset = input.usize()?;
+        self.chr_bank_offset = input.usize()?;
+        if self.chr_is_ram {
+            input.bytes_into(&mut self.chr_rom)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prg_and_chr_banks() {
+        // 128KB PRG and 32KB CHR, each bank filled with its number. PRG bytes are
+        // $FF except the bank number's own byte, so writes aren't lost to conflicts.
+        let prg_rom = (0..4u8).flat_map(|bank| [vec![0xFF; 0x7FFF], vec![bank]].concat()).collect();
+        let chr_rom = (0..4).flat_map(|bank| vec![bank; 0x2000]).collect();
+        let mut mapper = Mapper66::new(prg_rom, chr_rom, Mirroring::VERTICAL, false);
+        assert_eq!((mapper.cpu_read(0xFFFF), mapper.ppu_read(0x0000)), (0, 0));
+
+        mapper.cpu_write(0x8000, 0b0010_0011);
+        assert_eq!((mapper.cpu_read(0xFFFF), mapper.ppu_read(0x1FFF)), (2, 3));
+
+        // The ROM's 0s win over the written 1s: $02 & $33 is PRG 0, CHR 2
+        mapper.cpu_write(0xFFFF, 0b0011_0011);
+        assert_eq!((mapper.cpu_read(0xFFFF), mapper.ppu_read(0x0000)), (0, 2));
+    }
+}
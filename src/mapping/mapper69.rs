@@ -0,0 +1,262 @@
+use crate::banks::BankMapping;
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// Sunsoft FME-7 and 5B (mapper 69). Registers are written in two steps: the
+// command number to $8000-$9FFF, then its value to $A000-$BFFF.
+//
+//   $0-$7  1KB CHR bank at PPU $0000 + n * $400
+//   $8     what $6000-$7FFF shows: bits 0-5 a ROM bank, bit 6 RAM instead of
+//          ROM, bit 7 RAM enabled (disabled RAM reads as open bus)
+//   $9-$B  8KB PRG banks at $8000, $A000, $C000 ($E000 is the last bank)
+//   $C     mirroring: 0 vertical, 1 horizontal, 2 single lower, 3 single upper
+//   $D     IRQ control: bit 0 IRQ enable, bit 7 counter enable, acknowledges the IRQ
+//   $E/$F  IRQ counter low / high byte
+//
+// The counter counts down every CPU cycle while enabled, and raises an IRQ as it
+// wraps from $0000 to $FFFF if IRQs are enabled. Batman: Return of the Joker,
+// Gimmick!, Hebereke... The 5B's expansion audio at $C000-$FFFF isn't emulated.
+// https://www.nesdev.org/wiki/Sunsoft_FME-7
+pub struct Mapper69 {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub prg_ram: Vec<u8>,
+    chr_is_ram: bool,
+
+    command: u8,
+    chr_banks: [u8; 8],
+    prg_banks: [u8; 3],
+    low_bank: u8, // command $8
+    mirroring: Mirroring,
+
+    irq_enabled: bool,
+    counter_enabled: bool,
+    counter: u16,
+    irq: bool,
+}
+
+impl Mapper69 {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mirroring: Mirroring, chr_is_ram: bool) -> Self {
+        Mapper69 {
+            prg_rom,
+            chr_rom,
+            prg_ram: vec![0; 0x2000],
+            chr_is_ram,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 3],
+            low_bank: 0,
+            mirroring,
+            irq_enabled: false,
+            counter_enabled: false,
+            counter: 0,
+            irq: false,
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let window = (addr - 0x8000) as usize / 0x2000;
+        let base = match window {
+            0..=2 => mapper::bank_offset((self.prg_banks[window] & 0x3F) as usize, 0x2000, self.prg_rom.len()),
+            _ => mapper::last_bank_offset(0x2000, self.prg_rom.len()),
+        };
+        base + (addr & 0x1FFF) as usize
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / 0x400] as usize;
+        mapper::bank_offset(bank, 0x400, self.chr_rom.len()) + (addr & 0x3FF) as usize
+    }
+
+    fn ram_selected(&self) -> bool {
+        self.low_bank & 0b0100_0000 != 0
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.low_bank & 0b1100_0000 == 0b1100_0000
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = data,
+            0x8 => self.low_bank = data,
+            0x9..=0xB => self.prg_banks[(self.command - 0x9) as usize] = data,
+            0xC => {
+                self.mirroring = match data & 0b11 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::SINGLELOWER,
+                    _ => Mirroring::SINGLEUPPER,
+                };
+            }
+            0xD => {
+                self.irq_enabled = data & 0b0000_0001 != 0;
+                self.counter_enabled = data & 0b1000_0000 != 0;
+                self.irq = false;
+            }
+            0xE => self.counter = (self.counter & 0xFF00) | data as u16,
+            _ => self.counter = (self.counter & 0x00FF) | (data as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Mapper69 {
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn bank_mapping(&self) -> BankMapping {
+        let prg = [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| self.prg_offset(addr));
+        BankMapping { prg, chr: [self.chr_offset(0x0000), self.chr_offset(0x1000)] }
+    }
+
+    fn cpu_clock(&mut self, cycles: usize) {
+        if !self.counter_enabled {
+            return;
+        }
+        if cycles > self.counter as usize && self.irq_enabled {
+            self.irq = true;
+        }
+        self.counter = self.counter.wrapping_sub(cycles as u16);
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq
+    }
+
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.ram_enabled() => self.prg_ram[(addr - 0x6000) as usize],
+            0x6000..=0x7FFF if self.ram_selected() => (addr >> 8) as u8, // disabled RAM, open bus
+            0x6000..=0x7FFF => {
+                let bank = mapper::bank_offset((self.low_bank & 0x3F) as usize, 0x2000, self.prg_rom.len());
+                self.prg_rom[bank + (addr - 0x6000) as usize]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+            _ => panic!("CPU READ to invalid address MAPPER 69")
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.ram_enabled() => self.prg_ram[(addr - 0x6000) as usize] = data,
+            0x8000..=0x9FFF => self.command = data & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            _ => {} // ROM or disabled RAM at $6000, and the 5B's audio registers
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        if addr < 0x2000 {
+            self.chr_rom[self.chr_offset(addr)]
+        } else {
+            panic!("Invalid ppu read address for mapper69")
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x2000 {
+            panic!("Invalid ppu write address for mapper69")
+        }
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr_rom[offset] = data;
+        }
+    }
+}
+
+impl Snapshot for Mapper69 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            out.bytes(&self.chr_rom);
+        }
+        out.u8(self.command);
+        out.bytes(&self.chr_banks);
+        out.bytes(&self.prg_banks);
+        out.u8(self.low_bank);
+        out.mirroring(self.mirroring);
+        out.bool(self.irq_enabled);
+        out.bool(self.counter_enabled);
+        out.u16(self.counter);
+        out.bool(self.irq);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            input.bytes_into(&mut self.chr_rom)?;
+        }
+        self.command = input.u8()?;
+        input.bytes_into(&mut self.chr_banks)?;
+        input.bytes_into(&mut self.prg_banks)?;
+        self.low_bank = input.u8()?;
+        self.mirroring = input.mirroring()?;
+        self.irq_enabled = input.bool()?;
+        self.counter_enabled = input.bool()?;
+        self.counter = input.u16()?;
+        self.irq = input.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_register(mapper: &mut Mapper69, command: u8, value: u8) {
+        mapper.cpu_write(0x8000, command);
+        mapper.cpu_write(0xA000, value);
+    }
+
+    #[test]
+    fn test_banks_ram_and_irq() {
+        // 128KB PRG in 8KB banks and 64KB CHR in 1KB banks, each filled with its number
+        let prg_rom = (0..16).flat_map(|bank| vec![bank; 0x2000]).collect();
+        let chr_rom = (0..64).flat_map(|bank| vec![bank; 0x400]).collect();
+        let mut mapper = Mapper69::new(prg_rom, chr_rom, Mirroring::VERTICAL, false);
+        assert_eq!(mapper.cpu_read(0xE000), 15);
+
+        write_register(&mut mapper, 0x9, 5);
+        write_register(&mut mapper, 0xB, 7);
+        write_register(&mut mapper, 0x3, 40);
+        assert_eq!((mapper.cpu_read(0x8000), mapper.cpu_read(0xC000)), (5, 7));
+        assert_eq!((mapper.ppu_read(0x0C00), mapper.ppu_read(0x0800)), (40, 0));
+
+        // $6000: ROM bank, then RAM that only holds writes once enabled
+        write_register(&mut mapper, 0x8, 3);
+        assert_eq!(mapper.cpu_read(0x6000), 3);
+        write_register(&mut mapper, 0x8, 0b0100_0000);
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0x60);
+        write_register(&mut mapper, 0x8, 0b1100_0000);
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0x42);
+
+        write_register(&mut mapper, 0xC, 3);
+        assert_eq!(mapper.get_mirroring(), Mirroring::SINGLEUPPER);
+
+        // IRQ as the counter wraps past 0, acknowledged by writing the control
+        write_register(&mut mapper, 0xE, 100);
+        write_register(&mut mapper, 0xF, 0);
+        write_register(&mut mapper, 0xD, 0b1000_0001);
+        mapper.cpu_clock(100);
+        assert!(!mapper.irq_pending());
+        mapper.cpu_clock(1);
+        assert!(mapper.irq_pending());
+        assert_eq!(mapper.counter, 0xFFFF);
+        write_register(&mut mapper, 0xD, 0b1000_0000);
+        assert!(!mapper.irq_pending());
+        mapper.cpu_clock(0x10000);
+        assert!(!mapper.irq_pending()); // counting, but IRQs off
+    }
+}
@@ -0,0 +1,113 @@
+use crate::banks::BankMapping;
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// AxROM (mapper 7): writing $8000-$FFFF picks a 32KB PRG bank (bits 0-2) and
+// which 1KB of the console's VRAM all four nametables show (bit 4), so the
+// mirroring changes at runtime. CHR is 8KB of RAM. Battletoads, Marble Madness...
+// Games avoid bus conflicts on the boards that have them, so they're not emulated.
+// https://www.nesdev.org/wiki/AxROM
+pub struct Mapper7 {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    prg_bank_offset: usize,
+    mirroring: Mirroring,
+    chr_is_ram: bool,
+}
+
+impl Mapper7 {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, chr_is_ram: bool) -> Self {
+        Mapper7 {
+            prg_rom,
+            chr_rom,
+            prg_bank_offset: 0,
+            mirroring: Mirroring::SINGLELOWER,
+            chr_is_ram,
+        }
+    }
+}
+
+impl Mapper for Mapper7 {
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn bank_mapping(&self) -> BankMapping {
+        let prg = [0x0000, 0x2000, 0x4000, 0x6000].map(|offset| (self.prg_bank_offset + offset) % self.prg_rom.len());
+        BankMapping { prg, chr: [0, 0x1000] }
+    }
+
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => (addr >> 8) as u8, // no PRG RAM, open bus
+            0x8000..=0xFFFF => self.prg_rom[(self.prg_bank_offset + (addr - 0x8000) as usize) % self.prg_rom.len()],
+            _ => panic!("CPU READ to invalid address MAPPER 7")
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+        self.prg_bank_offset = mapper::bank_offset((data & 0b0000_0111) as usize, 0x8000, self.prg_rom.len());
+        self.mirroring = if data & 0b0001_0000 == 0 { Mirroring::SINGLELOWER } else { Mirroring::SINGLEUPPER };
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        if addr < 0x2000 {
+            self.chr_rom[addr as usize % self.chr_rom.len()]
+        } else {
+            panic!("Invalid ppu read address for mapper7")
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x2000 {
+            panic!("Invalid ppu write address for mapper7")
+        }
+        if self.chr_is_ram {
+            let len = self.chr_rom.len();
+            self.chr_rom[addr as usize % len] = data;
+        }
+    }
+}
+
+impl Snapshot for Mapper7 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.usize(self.prg_bank_offset);
+        out.mirroring(self.mirroring);
+        if self.chr_is_ram {
+            out.bytes(&self.chr_rom);
+        }
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.prg_bank_offset = input.usize()?;
+        self.mirroring = input.mirroring()?;
+        if self.chr_is_ram {
+            input.bytes_into(&mut self.chr_rom)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prg_banks_and_single_screen() {
+        // 256KB, each 32KB bank filled with its number
+        let prg_rom = (0..8).flat_map(|bank| vec![bank; 0x8000]).collect();
+        let mut mapper = Mapper7::new(prg_rom, vec![0; 0x2000], true);
+        assert_eq!((mapper.cpu_read(0x8000), mapper.get_mirroring()), (0, Mirroring::SINGLELOWER));
+
+        mapper.cpu_write(0x8000, 0b0001_0110);
+        assert_eq!((mapper.cpu_read(0x8000), mapper.cpu_read(0xFFFF)), (6, 6));
+        assert_eq!(mapper.get_mirroring(), Mirroring::SINGLEUPPER);
+
+        mapper.cpu_write(0xFFFF, 0b0000_0011);
+        assert_eq!((mapper.cpu_read(0xC000), mapper.get_mirroring()), (3, Mirroring::SINGLELOWER));
+    }
+}
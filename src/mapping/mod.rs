@@ -1,2 +1,112 @@
 pub mod mapper0;
-pub mod mapper1;
\ No newline at end of file
+pub mod mapper1;
+pub mod mapper2;
+pub mod mapper3;
+pub mod mapper7;
+pub mod mapper11;
+pub mod mapper28;
+pub mod mapper66;
+pub mod mapper69;
+pub mod mapper105;
+pub mod mapper184;
+pub mod mapper185;
+
+// What the emulator knows about each iNES mapper number: the boards people will
+// recognise it by, what the cart has besides ROM, and whether it can be played.
+// Rom::has_supported_mapper and generate_mapper go by this table, and -list-mappers
+// prints it. Common mappers that aren't written yet are listed too so the error
+// for them can name the board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Supported,
+    Partial, // runs, but some of the board isn't emulated (say what in notes)
+    Unsupported,
+}
+
+pub struct MapperInfo {
+    pub number: u8,
+    pub name: &'static str,
+    pub irq: bool,
+    pub prg_ram: bool,
+    pub chr_ram: bool, // the board can have CHR RAM instead of, or as well as, CHR ROM
+    pub status: Status,
+    pub notes: &'static str,
+}
+
+pub static MAPPERS: [MapperInfo; 16] = [
+    MapperInfo { number: 0, name: "NROM", irq: false, prg_ram: true, chr_ram: true, status: Status::Supported, notes: "" },
+    MapperInfo { number: 1, name: "MMC1 (SxROM)", irq: false, prg_ram: true, chr_ram: true, status: Status::Supported, notes: "including 512 KB SUROM" },
+    MapperInfo { number: 2, name: "UxROM", irq: false, prg_ram: false, chr_ram: true, status: Status::Supported, notes: "" },
+    MapperInfo { number: 3, name: "CNROM", irq: false, prg_ram: false, chr_ram: false, status: Status::Supported, notes: "" },
+    MapperInfo { number: 4, name: "MMC3 (TxROM)", irq: true, prg_ram: true, chr_ram: true, status: Status::Unsupported, notes: "" },
+    MapperInfo { number: 5, name: "MMC5 (ExROM)", irq: true, prg_ram: true, chr_ram: true, status: Status::Unsupported, notes: "" },
+    MapperInfo { number: 7, name: "AxROM", irq: false, prg_ram: false, chr_ram: true, status: Status::Supported, notes: "" },
+    MapperInfo { number: 9, name: "MMC2 (PxROM)", irq: false, prg_ram: false, chr_ram: false, status: Status::Unsupported, notes: "" },
+    MapperInfo { number: 11, name: "Color Dreams", irq: false, prg_ram: false, chr_ram: false, status: Status::Supported, notes: "" },
+    MapperInfo { number: 28, name: "Action 53", irq: false, prg_ram: false, chr_ram: true, status: Status::Supported, notes: "" },
+    MapperInfo { number: 30, name: "UNROM-512", irq: false, prg_ram: false, chr_ram: true, status: Status::Unsupported, notes: "" },
+    MapperInfo { number: 66, name: "GxROM", irq: false, prg_ram: false, chr_ram: false, status: Status::Supported, notes: "" },
+    MapperInfo { number: 69, name: "Sunsoft FME-7 / 5B", irq: true, prg_ram: true, chr_ram: false, status: Status::Partial, notes: "no 5B expansion audio" },
+    MapperInfo { number: 105, name: "NES-EVENT (Nintendo World Championships)", irq: true, prg_ram: true, chr_ram: true, status: Status::Supported, notes: "" },
+    MapperInfo { number: 184, name: "Sunsoft-1", irq: false, prg_ram: false, chr_ram: false, status: Status::Supported, notes: "" },
+    MapperInfo { number: 185, name: "CNROM with copy protection", irq: false, prg_ram: false, chr_ram: false, status: Status::Supported, notes: "" },
+];
+
+pub fn lookup(number: u8) -> Option<&'static MapperInfo> {
+    MAPPERS.iter().find(|info| info.number == number)
+}
+
+pub fn is_playable(number: u8) -> bool {
+    lookup(number).is_some_and(|info| info.status != Status::Unsupported)
+}
+
+// "Mapper 30 (UNROM-512) is not supported", without the name for ones we don't know
+pub fn unsupported_message(number: u8) -> String {
+    match lookup(number) {
+        Some(info) => format!("Mapper {} ({}) is not supported", number, info.name),
+        None => format!("Mapper {} is not supported", number),
+    }
+}
+
+// The table -list-mappers prints
+pub fn list() -> String {
+    let flag = |has: bool, name: &str| if has { name.to_string() } else { String::new() };
+    let mut out = format!("{:>6}  {:<42} {:<4} {:<8} {:<8} {}\n", "Mapper", "Board", "IRQ", "PRG RAM", "CHR RAM", "Status");
+    for info in MAPPERS.iter() {
+        let mut status = match info.status {
+            Status::Supported => "supported",
+            Status::Partial => "partial",
+            Status::Unsupported => "not supported",
+        }.to_string();
+        if !info.notes.is_empty() {
+            status += &format!(" ({})", info.notes);
+        }
+        out += &format!(
+            "{:>6}  {:<42} {:<4} {:<8} {:<8} {}\n",
+            info.number,
+            info.name,
+            flag(info.irq, "yes"),
+            flag(info.prg_ram, "yes"),
+            flag(info.chr_ram, "yes"),
+            status,
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        assert!(is_playable(0) && is_playable(185));
+        assert!(!is_playable(30) && !is_playable(250));
+        assert_eq!(unsupported_message(30), "Mapper 30 (UNROM-512) is not supported");
+        assert_eq!(unsupported_message(250), "Mapper 250 is not supported");
+
+        // Kept in order so the listing reads well
+        assert!(MAPPERS.windows(2).all(|pair| pair[0].number < pair[1].number));
+        assert_eq!(list().lines().count(), MAPPERS.len() + 1);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::bus::Mem;
+use crate::cpu::CPU;
+
+// Dumping memory to a file and putting it back, for diffing memory between runs
+// and building test fixtures. What to dump is a list of segments:
+//
+//   ram                  all 2KB of CPU RAM
+//   ram:0x300-0x3FF      part of a region (inclusive, offsets into the region)
+//   prg_ram,vram,oam     several regions, one after the other in the file
+//
+// Regions are ram, prg_ram (cartridge RAM, on carts that have it), vram (the 4KB
+// of nametable RAM as laid out in the console, before mirroring), oam and palette.
+// The file is just the segments' bytes back to back with no header, so any hex
+// diff tool can compare two of them; restoring needs the same segment list.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryRegion {
+    Ram,
+    PrgRam,
+    Vram,
+    Oam,
+    Palette,
+}
+
+impl MemoryRegion {
+    pub fn parse(value: &str) -> Result<MemoryRegion, String> {
+        match value {
+            "ram" => Ok(MemoryRegion::Ram),
+            "prg_ram" => Ok(MemoryRegion::PrgRam),
+            "vram" => Ok(MemoryRegion::Vram),
+            "oam" => Ok(MemoryRegion::Oam),
+            "palette" => Ok(MemoryRegion::Palette),
+            _ => Err(format!("Memory region must be ram, prg_ram, vram, oam or palette but got \"{}\"", value)),
+        }
+    }
+
+    // The whole region as it is now
+    fn read(self, cpu: &CPU) -> Result<Vec<u8>, String> {
+        match self {
+            MemoryRegion::Ram => Ok((0..0x0800).map(|addr| cpu.bus.mem_peek(addr)).collect()),
+            MemoryRegion::PrgRam => cpu.bus.cart_prg_ram().ok_or("The cartridge has no PRG RAM".to_string()),
+            MemoryRegion::Vram => Ok(cpu.bus.ppu.vram.to_vec()),
+            MemoryRegion::Oam => Ok(cpu.bus.ppu.oam_data.to_vec()),
+            MemoryRegion::Palette => Ok(cpu.bus.ppu.palette_table.to_vec()),
+        }
+    }
+
+    // Replaces the whole region, `data` is as long as read returned
+    fn write(self, cpu: &mut CPU, data: &[u8]) {
+        match self {
+            MemoryRegion::Ram => {
+                for (addr, value) in data.iter().enumerate() {
+                    cpu.bus.mem_poke(addr as u16, *value);
+                }
+            }
+            MemoryRegion::PrgRam => {
+                cpu.bus.load_cart_prg_ram(data);
+            }
+            MemoryRegion::Vram => cpu.bus.ppu.vram.copy_from_slice(data),
+            MemoryRegion::Oam => cpu.bus.ppu.oam_data.copy_from_slice(data),
+            MemoryRegion::Palette => cpu.bus.ppu.palette_table.copy_from_slice(data),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub region: MemoryRegion,
+    pub range: Option<(usize, usize)>, // first and last offset, None for the whole region
+}
+
+impl Segment {
+    // Parses a list like "ram:0x300-0x3FF,oam"
+    pub fn parse_list(spec: &str) -> Result<Vec<Segment>, String> {
+        let segments: Vec<Segment> = spec
+            .split(',')
+            .map(|segment| segment.trim())
+            .filter(|segment| !segment.is_empty())
+            .map(Segment::parse)
+            .collect::<Result<_, _>>()?;
+        if segments.is_empty() {
+            return Err("No memory segments given".to_string());
+        }
+        Ok(segments)
+    }
+
+    fn parse(segment: &str) -> Result<Segment, String> {
+        let Some((region, range)) = segment.split_once(':') else {
+            return Ok(Segment { region: MemoryRegion::parse(segment)?, range: None });
+        };
+        let bad_range = || format!("Expected a range like 0x300-0x3FF in \"{}\"", segment);
+        let (first, last) = range.split_once('-').ok_or_else(bad_range)?;
+        let (first, last) = (parse_offset(first).ok_or_else(bad_range)?, parse_offset(last).ok_or_else(bad_range)?);
+        if first > last {
+            return Err(bad_range());
+        }
+        Ok(Segment { region: MemoryRegion::parse(region)?, range: Some((first, last)) })
+    }
+
+    // Offsets of the segment within a region of `len` bytes
+    fn bounds(&self, len: usize) -> Result<std::ops::Range<usize>, String> {
+        match self.range {
+            None => Ok(0..len),
+            Some((first, last)) if last < len => Ok(first..last + 1),
+            Some(_) => Err(format!("{:?} is only {} bytes long", self.region, len)),
+        }
+    }
+}
+
+fn parse_offset(value: &str) -> Option<usize> {
+    match value.strip_prefix("0x").or(value.strip_prefix("$")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+// The segments' bytes back to back
+pub fn dump(cpu: &CPU, segments: &[Segment]) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    for segment in segments {
+        let region = segment.region.read(cpu)?;
+        bytes.extend_from_slice(&region[segment.bounds(region.len())?]);
+    }
+    Ok(bytes)
+}
+
+// Puts back what dump returned for the same segments. Nothing is written unless
+// the data is the right length for all of them.
+pub fn restore(cpu: &mut CPU, segments: &[Segment], data: &[u8]) -> Result<(), String> {
+    let mut regions = vec![];
+    let mut expected = 0;
+    for segment in segments {
+        let region = segment.region.read(cpu)?;
+        expected += segment.bounds(region.len())?.len();
+        regions.push(region);
+    }
+    if data.len() != expected {
+        return Err(format!("The segments hold {} bytes but the data is {} bytes", expected, data.len()));
+    }
+
+    let mut data = data;
+    for (segment, mut region) in segments.iter().zip(regions) {
+        let bounds = segment.bounds(region.len())?;
+        let (part, rest) = data.split_at(bounds.len());
+        region[bounds].copy_from_slice(part);
+        segment.region.write(cpu, &region);
+        data = rest;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::rom::Rom;
+
+    #[test]
+    fn test_dump_and_restore_segments() {
+        let bus = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        let mut cpu = CPU::new(bus);
+        cpu.bus.mem_poke(0x0301, 0xAB);
+        cpu.bus.ppu.oam_data[4] = 0x42;
+
+        let segments = Segment::parse_list("ram:0x300-0x303, oam").unwrap();
+        let saved = dump(&cpu, &segments).unwrap();
+        assert_eq!(saved.len(), 4 + 256);
+        assert_eq!(&saved[0..4], &[0, 0xAB, 0, 0]);
+
+        cpu.bus.mem_poke(0x0301, 0);
+        cpu.bus.ppu.oam_data[4] = 0;
+        restore(&mut cpu, &segments, &saved).unwrap();
+        assert_eq!(cpu.bus.mem_peek(0x0301), 0xAB);
+        assert_eq!(cpu.bus.ppu.oam_data[4], 0x42);
+
+        assert!(restore(&mut cpu, &segments, &saved[1..]).is_err());
+        assert!(dump(&cpu, &Segment::parse_list("oam:0-0x100").unwrap()).is_err());
+        assert!(Segment::parse_list("chr").is_err());
+        assert!(Segment::parse_list("ram:5-1").is_err());
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+// The microphone built into the Famicom's second controller. Games only see
+// whether it's picking up sound: bit 2 of $4016 reads 1 while it is. Zelda's Pols
+// Voice die when you shout at them, Kid Icarus and Takeshi no Chousenjou ask for
+// it too. The host can blow into it two ways:
+//
+//   key      hold a key (microphone_key, M unless set) to make noise
+//   capture  listen to the host's real microphone, loud enough (the peak level
+//            over microphone_threshold, 0.0 to 1.0) counts as noise
+//
+// The bit is handed to the bus once a frame so runs stay deterministic whatever
+// the capture thread is doing.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MicSource {
+    Off,
+    Key,
+    Capture,
+}
+
+impl MicSource {
+    pub fn parse(value: &str) -> Result<MicSource, String> {
+        match value {
+            "off" => Ok(MicSource::Off),
+            "key" => Ok(MicSource::Key),
+            "capture" => Ok(MicSource::Capture),
+            _ => Err(format!("Microphone must be off, key or capture but got \"{}\"", value)),
+        }
+    }
+}
+
+// Loudest sample in the last block the host microphone delivered. Written by the
+// audio capture thread, read by the emulator.
+#[derive(Clone, Default)]
+pub struct MicLevel(Arc<AtomicU32>);
+
+impl MicLevel {
+    pub fn new() -> Self {
+        MicLevel::default()
+    }
+
+    pub fn feed(&self, samples: &[f32]) {
+        let peak = samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        self.0.store(peak.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn level(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_level_is_the_last_blocks_peak() {
+        let level = MicLevel::new();
+        let capture = level.clone();
+        capture.feed(&[0.1, -0.6, 0.3]);
+        assert_eq!(level.level(), 0.6);
+        capture.feed(&[0.05, 0.0]);
+        assert_eq!(level.level(), 0.05);
+
+        assert_eq!(MicSource::parse("capture").unwrap(), MicSource::Capture);
+        assert!(MicSource::parse("loud").is_err());
+    }
+}
@@ -0,0 +1,694 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::ops::{Bound, RangeBounds};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::bus::{Bus, Mem};
+use crate::cpu::CPU;
+use crate::frame::{Frame, PixelFormat};
+use crate::hash::crc32_update;
+use crate::joypad::{Joypad, JoypadButton};
+use crate::perf::{PerfMonitor, PerfStats};
+use crate::palette::Palettes;
+use crate::ppu::{NesPPU, OamEntry};
+use crate::render;
+use crate::rom::Rom;
+use crate::scheduler::MASTER_CYCLES_PER_CPU_CYCLE;
+
+// One complete console. Everything an instance needs (RAM, PPU, mapper, the
+// frontend callback) is owned by it, so several can live side by side in one
+// process. The shared statics (opcode tables, palette) are read only.
+pub struct Nes<'call> {
+    pub cpu: CPU<'call>,
+    keyboard_map: KeyboardMap,
+    perf: PerfMonitor,
+    perf_frame: u64,
+    frontend_time: Rc<Cell<Duration>>, // time spent in the gameloop callback
+    hooks: Hooks<'call>,
+    hook_scanline: u16,
+}
+
+// How often a callback added with Nes::add_callback runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    Instruction, // before every instruction, like run_with_callback
+    Scanline, // after the instruction that ends a scanline
+    Frame, // after the instruction that ends a frame
+}
+
+type Hook<'call> = Box<dyn FnMut(&mut CPU<'call>) + 'call>;
+
+// Each list is checked for emptiness before anything else, so a granularity
+// nobody asked for costs a branch rather than a call
+#[derive(Default)]
+struct Hooks<'call> {
+    instruction: Vec<Hook<'call>>,
+    scanline: Vec<Hook<'call>>,
+    frame: Vec<Hook<'call>>,
+}
+
+impl<'a> Nes<'a> {
+    // Builds and powers on a console. The gameloop callback is called once per
+    // frame (on entering vblank) with the PPU to render and the joypad to update.
+    // Fails for roms whose mapper isn't supported.
+    pub fn new<'call, F>(rom: Rom, mut gameloop_callback: F) -> Result<Nes<'call>, String>
+    where
+        F: FnMut(&NesPPU, &mut Joypad) + 'call,
+    {
+        // Time the callback so the frontend's work can be told apart from emulation
+        let frontend_time = Rc::new(Cell::new(Duration::ZERO));
+        let callback_time = frontend_time.clone();
+        let bus = Bus::new(rom, move |ppu: &NesPPU, joypad1: &mut Joypad| {
+            let start = Instant::now();
+            gameloop_callback(ppu, joypad1);
+            callback_time.set(callback_time.get() + start.elapsed());
+        })?;
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+
+        Ok(Nes {
+            cpu,
+            keyboard_map: KeyboardMap::new(),
+            perf: PerfMonitor::new(),
+            perf_frame: 0,
+            frontend_time,
+            hooks: Hooks::default(),
+            hook_scanline: 0,
+        })
+    }
+
+    // Emulation speed over the last second or so, None until a couple of frames ran
+    pub fn perf_stats(&self) -> Option<PerfStats> {
+        self.perf.stats()
+    }
+
+    // Presses or releases whatever controller 1 button the key is mapped to (see
+    // NesBuilder::controller). Returns false for keys that aren't mapped.
+    pub fn key_event(&mut self, key: &str, pressed: bool) -> bool {
+        match self.keyboard_map.get(key) {
+            Some(button) => {
+                self.cpu.bus.joypad1_mut().set_button_pressed_status(*button, pressed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Holds `buttons` down for the next frame, for bots and other programs driving
+    // the game. They are merged with whatever the frontend has pressed (keys still
+    // work) and let go when the frame ends, so call this once per frame, e.g.:
+    //
+    //   loop {
+    //       nes.set_controller_state(ControllerPort::One, bot.decide(&nes))?;
+    //       nes.run_frame();
+    //   }
+    pub fn set_controller_state(&mut self, port: ControllerPort, buttons: JoypadButton) -> Result<(), String> {
+        match port {
+            ControllerPort::One => self.cpu.bus.joypad1_mut().inject(buttons),
+            ControllerPort::Two => return Err("Controller port 2 only takes the Zapper, not a standard controller".to_string()),
+        }
+        Ok(())
+    }
+
+    // Reads `addr` the way the CPU would see it, mirrors included, but without any of
+    // the side effects of a real read (no vblank flag clearing, no controller shift,
+    // no PPU buffer update). The supported way in for trainers, cheats and scripts.
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.cpu.bus.mem_peek(addr)
+    }
+
+    // peek over a whole range, e.g. nes.peek_range(0x0000..0x0800) for all of RAM
+    pub fn peek_range<R: RangeBounds<u16>>(&self, range: R) -> Vec<u8> {
+        let start = match range.start_bound() {
+            Bound::Included(&addr) => addr as u32,
+            Bound::Excluded(&addr) => addr as u32 + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&addr) => addr as u32 + 1,
+            Bound::Excluded(&addr) => addr as u32,
+            Bound::Unbounded => 0x10000,
+        };
+        (start..end).map(|addr| self.peek(addr as u16)).collect()
+    }
+
+    // Changes a byte of RAM ($0000-$1FFF, mirrors resolved) or of the cartridge's PRG
+    // RAM ($6000-$7FFF) behind the game's back. Registers and ROM can't be poked,
+    // writing them always does something.
+    pub fn poke(&mut self, addr: u16, val: u8) -> Result<(), String> {
+        if self.cpu.bus.mem_poke(addr, val) {
+            Ok(())
+        } else {
+            Err(format!("Can't poke ${:04X}, only RAM ($0000-$1FFF) and PRG RAM ($6000-$7FFF) can be poked", addr))
+        }
+    }
+
+    // The cartridge's whole PRG RAM, None when it has none. Unlike peeking $6000-$7FFF
+    // this works while the game has the RAM disabled.
+    pub fn prg_ram(&self) -> Option<Vec<u8>> {
+        self.cpu.bus.cart_prg_ram()
+    }
+
+    // PPU memory as the PPU sees it: pattern tables (through the mapper), nametables
+    // with the cart's mirroring, and palette RAM. Like peek, leaves $2006/$2007 alone.
+    pub fn peek_ppu(&self, addr: u16) -> u8 {
+        self.cpu.bus.ppu.peek_vram(addr)
+    }
+
+    // Pattern table pokes only stick on carts with CHR RAM
+    pub fn poke_ppu(&mut self, addr: u16, val: u8) {
+        self.cpu.bus.ppu.poke_vram(addr, val);
+    }
+
+    // Colors to draw with instead of the fixed palette, e.g. from ntsc_palette::generate
+    pub fn set_palettes(&mut self, palettes: Palettes) {
+        self.cpu.bus.ppu.set_palettes(palettes);
+    }
+
+    // All 64 sprites of the last completed frame, decoded (see OamEntry), for overlay
+    // tools and bots that want to know what's on screen without parsing OAM bytes
+    pub fn oam_snapshot(&self) -> Vec<OamEntry> {
+        self.cpu.bus.ppu.frame_sprites()
+    }
+
+    // Swaps cartridges with the power on, see Bus::swap_cartridge
+    pub fn swap_cartridge(&mut self, rom: &mut Rom) -> Result<(), String> {
+        self.cpu.bus.swap_cartridge(rom)
+    }
+
+    // See state_hash
+    pub fn state_hash(&self) -> u32 {
+        state_hash(&self.cpu)
+    }
+
+    // Runs `callback` at the given granularity from step() on, for debuggers, tools
+    // and frontends. Any number can be added at each granularity.
+    pub fn add_callback<F>(&mut self, granularity: Granularity, callback: F)
+    where
+        F: FnMut(&mut CPU<'a>) + 'a,
+    {
+        let hooks = match granularity {
+            Granularity::Instruction => &mut self.hooks.instruction,
+            Granularity::Scanline => &mut self.hooks.scanline,
+            Granularity::Frame => &mut self.hooks.frame,
+        };
+        hooks.push(Box::new(callback));
+    }
+
+    // Runs a single CPU instruction (and the PPU cycles that go with it)
+    pub fn step(&mut self) {
+        if !self.hooks.instruction.is_empty() {
+            for hook in self.hooks.instruction.iter_mut() {
+                hook(&mut self.cpu);
+            }
+        }
+
+        self.cpu.step();
+
+        let timing = self.cpu.bus.ppu.timing();
+        if !self.hooks.scanline.is_empty() && timing.scanline != self.hook_scanline {
+            self.hook_scanline = timing.scanline;
+            for hook in self.hooks.scanline.iter_mut() {
+                hook(&mut self.cpu);
+            }
+        }
+
+        if timing.frame != self.perf_frame {
+            self.perf_frame = timing.frame;
+            self.cpu.bus.joypad1_mut().inject(JoypadButton::empty());
+            let cycles = self.cpu.bus.scheduler().now() / MASTER_CYCLES_PER_CPU_CYCLE;
+            self.perf.record(Instant::now(), cycles, timing.frame, self.frontend_time.get());
+            for hook in self.hooks.frame.iter_mut() {
+                hook(&mut self.cpu);
+            }
+        }
+    }
+
+    // Runs forever, calling whatever was added with add_callback
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.step();
+        }
+    }
+
+    // Runs until the PPU finishes the current frame and returns its number. For
+    // frontends that own their loop (async tasks, GUI event loops, tests) instead of
+    // handing it to run_with_callback. The frame callback still runs on vblank.
+    pub fn run_frame(&mut self) -> u64 {
+        let frame = self.cpu.bus.ppu.timing().frame;
+        while self.cpu.bus.ppu.timing().frame == frame {
+            self.step();
+        }
+        frame
+    }
+
+    // Calls `callback` before every instruction, forever. Same as an Instruction
+    // callback followed by run().
+    pub fn run_with_callback<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(&mut CPU),
+    {
+        loop {
+            callback(&mut self.cpu);
+            self.step();
+        }
+    }
+}
+
+// CRC32 of everything that decides what the game does next: CPU registers, RAM,
+// PRG RAM, PPU memory and where the CPU and PPU are in the frame. Two consoles
+// that ran the same rom with the same input hash the same, so comparing hashes
+// finds where runs (netplay peers, replays) drifted apart. Peeks only, so
+// hashing doesn't change the state.
+pub fn state_hash(cpu: &CPU) -> u32 {
+    let timing = cpu.bus.ppu.timing();
+    let mut crc = crc32_update(0, &cpu.pc.to_le_bytes());
+    crc = crc32_update(crc, &[cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.sp, cpu.status]);
+    crc = crc32_update(crc, &cpu.bus.scheduler().now().to_le_bytes());
+    crc = crc32_update(crc, &timing.scanline.to_le_bytes());
+    crc = crc32_update(crc, &timing.dot.to_le_bytes());
+    let ram: Vec<u8> = (0..0x800).map(|addr| cpu.bus.mem_peek(addr)).collect();
+    crc = crc32_update(crc, &ram);
+    if let Some(prg_ram) = cpu.bus.cart_prg_ram() {
+        crc = crc32_update(crc, &prg_ram);
+    }
+    crc = crc32_update(crc, &cpu.bus.ppu.vram);
+    crc = crc32_update(crc, &cpu.bus.ppu.oam_data);
+    crc32_update(crc, &cpu.bus.ppu.palette_table)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerPort {
+    One,
+    Two,
+}
+
+type VideoSink<'call> = Box<dyn FnMut(&Frame) + 'call>;
+
+// Key name -> button. Names are whatever the frontend calls its keys.
+pub type KeyboardMap = HashMap<String, JoypadButton>;
+
+// The emulator's standard keys, named the way SDL names them (Keycode::name)
+pub fn default_keyboard_map() -> KeyboardMap {
+    [
+        ("Down", JoypadButton::DOWN),
+        ("Up", JoypadButton::UP),
+        ("Right", JoypadButton::RIGHT),
+        ("Left", JoypadButton::LEFT),
+        ("Space", JoypadButton::SELECT),
+        ("Return", JoypadButton::START),
+        ("A", JoypadButton::BUTTON_A),
+        ("S", JoypadButton::BUTTON_B),
+    ]
+    .into_iter()
+    .map(|(key, button)| (key.to_string(), button))
+    .collect()
+}
+
+// Sets up a console without wiring the Bus, CPU and PPU together by hand:
+//
+//   let mut nes = NesBuilder::new()
+//       .rom("smb.nes")
+//       .region(Region::Ntsc)
+//       .video_sink(|frame| show(frame))
+//       .pixel_format(PixelFormat::Rgba8888)
+//       .controller(ControllerPort::One, default_keyboard_map())
+//       .build()?;
+//   loop {
+//       nes.step();
+//       // on key events: nes.key_event(name, pressed);
+//   }
+pub struct NesBuilder<'call> {
+    rom_path: Option<String>,
+    rom: Option<Rom>,
+    region: Region,
+    video_sink: Option<VideoSink<'call>>,
+    pixel_format: PixelFormat,
+    palette_indices: bool,
+    controller: Option<(ControllerPort, KeyboardMap)>,
+}
+
+impl<'call> Default for NesBuilder<'call> {
+    fn default() -> Self {
+        NesBuilder::new()
+    }
+}
+
+impl<'call> NesBuilder<'call> {
+    pub fn new() -> Self {
+        NesBuilder {
+            rom_path: None,
+            rom: None,
+            region: Region::Ntsc,
+            video_sink: None,
+            pixel_format: PixelFormat::Rgb24,
+            palette_indices: false,
+            controller: None,
+        }
+    }
+
+    // Rom file to load when building
+    pub fn rom(mut self, path: &str) -> Self {
+        self.rom_path = Some(path.to_string());
+        self
+    }
+
+    // An already loaded (or patched) rom, used instead of a path
+    pub fn rom_image(mut self, rom: Rom) -> Self {
+        self.rom = Some(rom);
+        self
+    }
+
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = region;
+        self
+    }
+
+    // Called with every finished frame. Without a sink nothing is drawn at all.
+    pub fn video_sink<F>(mut self, sink: F) -> Self
+    where
+        F: FnMut(&Frame) + 'call,
+    {
+        self.video_sink = Some(Box::new(sink));
+        self
+    }
+
+    // How the frames handed to the video sink are laid out, RGB unless set
+    pub fn pixel_format(mut self, format: PixelFormat) -> Self {
+        self.pixel_format = format;
+        self
+    }
+
+    // Frames handed to the video sink also carry each pixel's palette index
+    // (Frame::indices), the exact output to compare with reference emulators
+    pub fn palette_indices(mut self) -> Self {
+        self.palette_indices = true;
+        self
+    }
+
+    pub fn controller(mut self, port: ControllerPort, keyboard_map: KeyboardMap) -> Self {
+        self.controller = Some((port, keyboard_map));
+        self
+    }
+
+    pub fn build(self) -> Result<Nes<'call>, String> {
+        let rom = match (self.rom, self.rom_path) {
+            (Some(rom), _) => rom,
+            (None, Some(path)) => {
+                let bytes = std::fs::read(&path).map_err(|e| format!("Could not read rom {}: {}", path, e))?;
+                Rom::new(&bytes)?
+            }
+            (None, None) => return Err("No rom given".to_string()),
+        };
+        if self.region != Region::Ntsc {
+            return Err(format!("{:?} consoles are not supported yet", self.region));
+        }
+
+        let keyboard_map = match self.controller {
+            Some((ControllerPort::Two, _)) => return Err("Controller port 2 only takes the Zapper, not a standard controller".to_string()),
+            Some((ControllerPort::One, keyboard_map)) => keyboard_map,
+            None => KeyboardMap::new(),
+        };
+
+        let mut video_sink = self.video_sink;
+        let mut frame = Frame::with_size(256, 240, self.pixel_format);
+        if self.palette_indices {
+            frame.keep_indices();
+        }
+        let mut nes = Nes::new(rom, move |ppu: &NesPPU, _joypad1: &mut Joypad| {
+            if let Some(video_sink) = video_sink.as_mut() {
+                render::render(ppu, &mut frame);
+                video_sink(&frame);
+            }
+        })?;
+        nes.keyboard_map = keyboard_map;
+        Ok(nes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::accuracy::AccuracyProfile;
+
+    fn nes_running(program: Vec<u8>) -> Nes<'static> {
+        let mut nes = Nes::new(Rom::_new_test(program).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.pc = 0x8000;
+        nes
+    }
+
+    #[test]
+    fn test_instances_are_independent() {
+        // LDA #$11 / STA $10 and LDA #$22 / STA $10
+        let mut nes_a = nes_running(vec![0xA9, 0x11, 0x85, 0x10]);
+        let mut nes_b = nes_running(vec![0xA9, 0x22, 0x85, 0x10]);
+
+        // Interleave the two consoles one instruction at a time
+        for _ in 0..2 {
+            nes_a.step();
+            nes_b.step();
+        }
+
+        assert_eq!(nes_a.cpu.bus.mem_read(0x10), 0x11);
+        assert_eq!(nes_b.cpu.bus.mem_read(0x10), 0x22);
+        assert_eq!(nes_a.cpu.reg_a, 0x11);
+        assert_eq!(nes_b.cpu.reg_a, 0x22);
+    }
+
+    #[test]
+    fn test_state_hash_follows_the_game() {
+        // Same rom, same state: same hash, and hashing changes nothing
+        let mut nes_a = nes_running(vec![0xA9, 0x11, 0x85, 0x10]);
+        let mut nes_b = nes_running(vec![0xA9, 0x11, 0x85, 0x10]);
+        nes_a.run_frame();
+        nes_b.run_frame();
+        assert_eq!(nes_a.state_hash(), nes_b.state_hash());
+        assert_eq!(nes_a.state_hash(), nes_b.state_hash());
+
+        // One byte of RAM apart
+        nes_b.poke(0x0300, 1).unwrap();
+        assert_ne!(nes_a.state_hash(), nes_b.state_hash());
+    }
+
+    #[test]
+    fn test_builder_wires_sink_and_keys() {
+        // LDA #$80 / STA $2000 (NMI on) then spin, with an RTI handler
+        let mut program = vec![0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x05, 0x80];
+        program.resize(0x4000, 0xEA);
+        program[0x3F00] = 0x40;
+        program[0x3FFA] = 0x00;
+        program[0x3FFB] = 0xBF;
+
+        let mut frames = 0;
+        let mut nes = NesBuilder::new()
+            .rom_image(Rom::_new_test(program).unwrap())
+            .video_sink(|_frame| frames += 1)
+            .controller(ControllerPort::One, default_keyboard_map())
+            .build()
+            .unwrap();
+        nes.cpu.pc = 0x8000;
+
+        assert!(nes.key_event("Return", true));
+        assert!(!nes.key_event("F12", true));
+        nes.cpu.bus.mem_write(0x4016, 1);
+        nes.cpu.bus.mem_write(0x4016, 0);
+        let buttons: Vec<u8> = (0..4).map(|_| nes.cpu.bus.mem_read(0x4016)).collect();
+        assert_eq!(buttons, vec![0, 0, 0, 1]); // A, B, Select, Start
+
+        while nes.cpu.bus.ppu.timing().frame < 3 {
+            nes.step();
+        }
+        drop(nes);
+        assert!(frames >= 2);
+    }
+
+    #[test]
+    fn test_perf_stats_after_a_few_frames() {
+        let mut nes = nes_running(vec![0x4C, 0x00, 0x80]); // JMP to itself
+        assert!(nes.perf_stats().is_none());
+        while nes.cpu.bus.ppu.timing().frame < 3 {
+            nes.step();
+        }
+
+        let stats = nes.perf_stats().unwrap();
+        assert!(stats.cycles_per_sec > 0.0 && stats.frames_per_sec > 0.0);
+        assert!(stats.host_utilization > 0.0 && stats.host_utilization <= 1.0);
+    }
+
+    #[test]
+    fn test_injected_buttons_last_one_frame() {
+        let mut nes = nes_running(vec![0x4C, 0x00, 0x80]); // JMP to itself
+        nes.cpu.bus.joypad1_mut().set_button_pressed_status(JoypadButton::START, true);
+        nes.set_controller_state(ControllerPort::One, JoypadButton::BUTTON_A | JoypadButton::RIGHT).unwrap();
+        assert!(nes.set_controller_state(ControllerPort::Two, JoypadButton::BUTTON_A).is_err());
+
+        let expected = JoypadButton::BUTTON_A | JoypadButton::RIGHT | JoypadButton::START;
+        assert_eq!(nes.cpu.bus.joypad1_mut().buttons(), expected);
+
+        assert_eq!(nes.run_frame(), 0);
+        assert_eq!(nes.cpu.bus.joypad1_mut().buttons(), JoypadButton::START);
+    }
+
+    #[test]
+    fn test_peek_and_poke_resolve_mirrors() {
+        let mut nes = nes_running(vec![0x4C, 0x00, 0x80]); // JMP to itself
+        nes.poke(0x0812, 0x42).unwrap(); // mirror of $0012
+        assert_eq!(nes.peek(0x0012), 0x42);
+        assert_eq!(nes.peek(0x1812), 0x42);
+        assert_eq!(nes.peek_range(0x0011..=0x0013), vec![0, 0x42, 0]);
+        assert_eq!(nes.peek_range(0x8000..0x8003), vec![0x4C, 0x00, 0x80]);
+        assert_eq!(nes.peek_range(0xFFFE..).len(), 2);
+        assert!(nes.poke(0x8000, 0).is_err());
+
+        // Cartridge RAM can be poked too, and read back whole
+        nes.poke(0x6002, 0x99).unwrap();
+        assert_eq!(nes.peek(0x6002), 0x99);
+        assert_eq!(nes.prg_ram().unwrap()[2], 0x99);
+
+        // Peeking $2002 leaves the vblank flag for the game to see
+        while nes.cpu.bus.ppu.timing().scanline != 242 {
+            nes.step();
+        }
+        assert_eq!(nes.peek(0x2002) & 0x80, 0x80);
+        assert_eq!(nes.peek(0x200A) & 0x80, 0x80);
+        assert_eq!(nes.cpu.bus.mem_read(0x2002) & 0x80, 0x80);
+    }
+
+    #[test]
+    fn test_accuracy_profiles_and_dummy_reads() {
+        // $2006 = $2000, then STA $2007,X with X = 0
+        let program = vec![
+            0xA9, 0x20, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20,
+            0xA2, 0x00, 0xA9, 0x55, 0x9D, 0x07, 0x20,
+        ];
+
+        // The dummy read of $2007 bumps the address before the write lands
+        let mut nes = nes_running(program.clone());
+        for _ in 0..7 {
+            nes.step();
+        }
+        assert_eq!((nes.peek_ppu(0x2000), nes.peek_ppu(0x2001)), (0x00, 0x55));
+
+        let mut nes = nes_running(program);
+        nes.cpu.bus.set_accuracy(AccuracyProfile::Fast.flags());
+        for _ in 0..7 {
+            nes.step();
+        }
+        assert_eq!((nes.peek_ppu(0x2000), nes.peek_ppu(0x2001)), (0x55, 0x00));
+    }
+
+    #[test]
+    fn test_run_frame_stops_at_the_frame_boundary() {
+        let mut nes = nes_running(vec![0x4C, 0x00, 0x80]); // JMP to itself
+        assert_eq!(nes.run_frame(), 0);
+        let timing = nes.cpu.bus.ppu.timing();
+        assert_eq!((timing.frame, timing.scanline), (1, 0));
+        assert_eq!(nes.run_frame(), 1);
+        assert_eq!(nes.cpu.bus.ppu.timing().frame, 2);
+    }
+
+    #[test]
+    fn test_callback_granularities() {
+        let counts = Rc::new(Cell::new((0, 0, 0)));
+        let mut nes = nes_running(vec![0x4C, 0x00, 0x80]); // JMP to itself
+        let instruction_counts = counts.clone();
+        nes.add_callback(Granularity::Instruction, move |_cpu| {
+            let (instructions, lines, frames) = instruction_counts.get();
+            instruction_counts.set((instructions + 1, lines, frames));
+        });
+        let scanline_counts = counts.clone();
+        nes.add_callback(Granularity::Scanline, move |_cpu| {
+            let (instructions, lines, frames) = scanline_counts.get();
+            scanline_counts.set((instructions, lines + 1, frames));
+        });
+        let frame_counts = counts.clone();
+        nes.add_callback(Granularity::Frame, move |cpu| {
+            assert_eq!(cpu.bus.ppu.timing().scanline, 0);
+            let (instructions, lines, frames) = frame_counts.get();
+            frame_counts.set((instructions, lines, frames + 1));
+        });
+
+        nes.run_frame();
+        nes.run_frame();
+        let (instructions, lines, frames) = counts.get();
+        assert!(instructions > 1000);
+        assert_eq!((lines, frames), (262 * 2, 2));
+    }
+
+    #[test]
+    fn test_oam_snapshot_is_the_last_frame() {
+        // Sprite 0 via $2003/$2004: y $20, tile $42, palette 2 + behind + flip H, x $30
+        let mut program = vec![0xA9, 0x00, 0x8D, 0x03, 0x20];
+        for byte in [0x20, 0x42, 0b0110_0010, 0x30] {
+            program.extend([0xA9, byte, 0x8D, 0x04, 0x20]);
+        }
+        let spin = 0x8000 + program.len() as u16;
+        program.extend([0x4C, spin as u8, (spin >> 8) as u8]);
+        let mut nes = nes_running(program);
+        assert_eq!(nes.oam_snapshot()[0].tile, 0); // nothing drawn yet
+
+        nes.run_frame();
+        let sprites = nes.oam_snapshot();
+        assert_eq!(sprites.len(), 64);
+        let expected = OamEntry {
+            index: 0,
+            x: 0x30,
+            y: 0x20,
+            tile: 0x42,
+            palette: 2,
+            behind_background: true,
+            flip_horizontal: true,
+            flip_vertical: false,
+            tall: false,
+        };
+        assert_eq!(sprites[0], expected);
+        assert_eq!(sprites[63].index, 63);
+
+        // A change after the frame ended waits for the next one
+        nes.cpu.bus.ppu.oam_data[1] = 0x43;
+        assert_eq!(nes.oam_snapshot()[0].tile, 0x42);
+        nes.run_frame();
+        assert_eq!(nes.oam_snapshot()[0].tile, 0x43);
+    }
+
+    #[test]
+    fn test_swapped_cartridge_keeps_the_console() {
+        // INC $10 / JMP $8000, swapped for a rom that starts with LDA #$FF
+        let mut nes = nes_running(vec![0xE6, 0x10, 0x4C, 0x00, 0x80]);
+        for _ in 0..10 {
+            nes.step();
+        }
+        nes.poke_ppu(0x2000, 0x42);
+        let mut cartridge = Rom::_new_test(vec![0xA9, 0xFF]).unwrap();
+        let other_crc = cartridge.crc;
+
+        nes.swap_cartridge(&mut cartridge).unwrap();
+        assert_eq!(cartridge.prg_rom[0], 0xE6); // the old one comes back out
+        assert_eq!(nes.cpu.bus.cart_crc(), other_crc);
+        assert_eq!((nes.peek(0x10), nes.peek_ppu(0x2000)), (5, 0x42));
+        assert_eq!(nes.peek(0x8000), 0xA9);
+
+        let mut unsupported = Rom::_new_test(vec![]).unwrap();
+        unsupported.mapper_id = 255;
+        assert!(nes.swap_cartridge(&mut unsupported).is_err());
+        assert_eq!(nes.cpu.bus.cart_crc(), other_crc);
+        assert_eq!(unsupported.mapper_id, 255); // and a rejected one is handed back
+    }
+
+    #[test]
+    fn test_builder_errors() {
+        assert!(NesBuilder::new().build().is_err());
+        let pal = NesBuilder::new().rom_image(Rom::_new_test(vec![]).unwrap()).region(Region::Pal);
+        assert!(pal.build().is_err());
+        let mut rom = Rom::_new_test(vec![]).unwrap();
+        rom.mapper_id = 250;
+        assert!(matches!(NesBuilder::new().rom_image(rom).build(), Err(e) if e == "Mapper 250 is not supported"));
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Desync detection for netplay. Both peers hash their console's state (see
+// nes::state_hash) every CHECKSUM_INTERVAL frames and send the hash over; when
+// the two hashes for the same frame differ the games have drifted apart and the
+// player is warned with the first frame that didn't match. Hashes for frames the
+// other side hasn't reached yet wait until it does.
+//
+// On the wire each hash is one line, "FRAME HASH" in decimal and hex:
+//
+//   600 1a2b3c4d
+pub const CHECKSUM_INTERVAL: u64 = 60;
+
+// Hashes kept for each side before the oldest are dropped, for a peer that
+// stopped answering
+const MAX_PENDING: usize = 256;
+
+pub struct ChecksumChannel {
+    interval: u64,
+    local: BTreeMap<u64, u32>,
+    remote: BTreeMap<u64, u32>,
+    desync: Option<u64>,
+    reported: bool,
+}
+
+impl ChecksumChannel {
+    pub fn new(interval: u64) -> Self {
+        ChecksumChannel { interval: interval.max(1), local: BTreeMap::new(), remote: BTreeMap::new(), desync: None, reported: false }
+    }
+
+    // Our hash at the end of `frame`. Returns it when it's one to send to the peer.
+    pub fn record_local(&mut self, frame: u64, hash: u32) -> Option<(u64, u32)> {
+        if !frame.is_multiple_of(self.interval) {
+            return None;
+        }
+        insert_bounded(&mut self.local, frame, hash);
+        self.compare(frame);
+        Some((frame, hash))
+    }
+
+    // A hash the peer sent
+    pub fn record_remote(&mut self, frame: u64, hash: u32) {
+        insert_bounded(&mut self.remote, frame, hash);
+        self.compare(frame);
+    }
+
+    fn compare(&mut self, frame: u64) {
+        if let (Some(local), Some(remote)) = (self.local.get(&frame), self.remote.get(&frame)) {
+            if local != remote && self.desync.is_none_or(|first| frame < first) {
+                self.desync = Some(frame);
+            }
+            self.local.remove(&frame);
+            self.remote.remove(&frame);
+        }
+    }
+
+    // First frame the two sides disagreed on, if they ever did
+    pub fn desync(&self) -> Option<u64> {
+        self.desync
+    }
+
+    // Like desync, but only the first time it's found, for showing a warning once
+    pub fn take_desync_warning(&mut self) -> Option<u64> {
+        if self.reported {
+            return None;
+        }
+        self.reported = self.desync.is_some();
+        self.desync
+    }
+}
+
+fn insert_bounded(hashes: &mut BTreeMap<u64, u32>, frame: u64, hash: u32) {
+    hashes.insert(frame, hash);
+    while hashes.len() > MAX_PENDING {
+        hashes.pop_first();
+    }
+}
+
+pub fn format_checksum(frame: u64, hash: u32) -> String {
+    format!("{} {:08x}", frame, hash)
+}
+
+pub fn parse_checksum(line: &str) -> Result<(u64, u32), String> {
+    let mut parts = line.split_whitespace();
+    let (Some(frame), Some(hash), None) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(format!("Bad checksum line '{}'", line));
+    };
+    let frame = frame.parse().map_err(|_| format!("Bad frame number '{}'", frame))?;
+    let hash = u32::from_str_radix(hash, 16).map_err(|_| format!("Bad hash '{}'", hash))?;
+    Ok((frame, hash))
+}
+
+// TCP connection to the peer the checksums go to. Reading happens on a
+// background thread, so checking for the peer's hashes never blocks a frame.
+pub struct ChecksumLink {
+    writer: Arc<Mutex<Option<TcpStream>>>,
+    incoming: Receiver<(u64, u32)>,
+}
+
+impl ChecksumLink {
+    // Connects to a peer listening on `addr`, or when nobody is there yet listens
+    // on it and waits for the peer in the background
+    pub fn open(addr: &str) -> Result<ChecksumLink, String> {
+        let (sender, incoming) = mpsc::channel();
+        let writer = Arc::new(Mutex::new(None));
+
+        let reader_writer = writer.clone();
+        let read = move |stream: TcpStream| {
+            *reader_writer.lock().unwrap() = stream.try_clone().ok();
+            for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                match parse_checksum(&line) {
+                    Ok(checksum) => {
+                        if sender.send(checksum).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => eprintln!("Netplay: {}", e),
+                }
+            }
+        };
+
+        match TcpStream::connect(addr) {
+            Ok(stream) => {
+                println!("Netplay checksums: connected to {}", addr);
+                thread::spawn(move || read(stream));
+            }
+            Err(_) => {
+                let listener = TcpListener::bind(addr).map_err(|e| format!("Could not listen for a netplay peer on {}: {}", addr, e))?;
+                println!("Netplay checksums: waiting for a peer on {}", addr);
+                thread::spawn(move || {
+                    if let Ok((stream, peer)) = listener.accept() {
+                        println!("Netplay checksums: {} connected", peer);
+                        read(stream);
+                    }
+                });
+            }
+        }
+
+        Ok(ChecksumLink { writer, incoming })
+    }
+
+    // Dropped while no peer is connected, there's nobody to compare with yet
+    pub fn send(&self, frame: u64, hash: u32) {
+        let mut writer = self.writer.lock().unwrap();
+        if let Some(stream) = writer.as_mut()
+            && writeln!(stream, "{}", format_checksum(frame, hash)).is_err()
+        {
+            *writer = None;
+        }
+    }
+
+    pub fn receive(&self) -> Vec<(u64, u32)> {
+        self.incoming.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_first_mismatch_is_reported() {
+        let mut channel = ChecksumChannel::new(60);
+        assert_eq!(channel.record_local(59, 1), None);
+        assert_eq!(channel.record_local(60, 0xAAAA), Some((60, 0xAAAA)));
+        channel.record_remote(60, 0xAAAA);
+        assert_eq!(channel.take_desync_warning(), None);
+
+        // The peer can be ahead: its hashes wait for ours
+        channel.record_remote(120, 2);
+        channel.record_remote(180, 3);
+        assert_eq!(channel.desync(), None);
+        channel.record_local(180, 4);
+        channel.record_local(120, 5);
+        assert_eq!(channel.desync(), Some(120));
+        assert_eq!(channel.take_desync_warning(), Some(120));
+        assert_eq!(channel.take_desync_warning(), None);
+
+        assert_eq!(parse_checksum(&format_checksum(600, 0x1A2B3C4D)), Ok((600, 0x1A2B3C4D)));
+        assert!(parse_checksum("600").is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use std::f32::consts::PI;
+
+// Works the palette out from the NTSC signal instead of using the fixed table in
+// palette.rs. The PPU makes each color as a square wave between two voltage levels,
+// with the hue picking the wave's phase against the color burst. Decoding averages
+// the wave over one full color cycle (12 samples), which gives each color cleanly
+// without the dot crawl a real TV shows. Emphasis bits dim the wave during the
+// parts of the cycle belonging to the other colors, so all 512 colors fall out.
+
+// Signal levels in volts for the 4 luma rows, low then high half of the wave
+const LEVELS: [f32; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+const ATTENUATION: f32 = 0.746;
+// Phase the decoder samples at so hue 0 lines up with the colors the PPU means
+const BURST_PHASE: f32 = 3.9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtscSettings {
+    pub hue: f32, // degrees
+    pub saturation: f32,
+    pub contrast: f32,
+    pub brightness: f32,
+}
+
+impl Default for NtscSettings {
+    fn default() -> Self {
+        NtscSettings { hue: 0.0, saturation: 1.0, contrast: 1.0, brightness: 0.0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteSource {
+    Builtin,
+    Ntsc,
+}
+
+impl PaletteSource {
+    pub fn parse(value: &str) -> Result<PaletteSource, String> {
+        match value {
+            "builtin" => Ok(PaletteSource::Builtin),
+            "ntsc" => Ok(PaletteSource::Ntsc),
+            _ => Err(format!("Palette must be builtin or ntsc but got \"{}\"", value)),
+        }
+    }
+}
+
+// Whether the wave for `color` is high at sample `phase` of the color cycle
+fn in_color_phase(color: usize, phase: usize) -> bool {
+    (color + phase) % 12 < 6
+}
+
+// One color, `index` being the 6 bit palette entry and `emphasis` the top 3 bits of $2001
+pub fn decode(index: u8, emphasis: u8, settings: &NtscSettings) -> (u8, u8, u8) {
+    let color = (index & 0x0F) as usize;
+    // $xE and $xF output black whatever the row
+    let level = if color > 13 { 1 } else { (index >> 4 & 0b11) as usize };
+    let low = if color == 0 { LEVELS[level + 4] } else { LEVELS[level] };
+    let high = if color > 12 { low } else { LEVELS[level + 4] };
+
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+    let hue = settings.hue / 30.0; // degrees to samples, 12 per cycle
+    for phase in 0..12 {
+        let mut signal = if in_color_phase(color, phase) { high } else { low };
+        if (emphasis & 0b001 != 0 && in_color_phase(0xC, phase))
+            || (emphasis & 0b010 != 0 && in_color_phase(0x4, phase))
+            || (emphasis & 0b100 != 0 && in_color_phase(0x8, phase))
+        {
+            signal *= ATTENUATION;
+        }
+        let signal = (signal - BLACK) / (WHITE - BLACK) / 12.0;
+        let angle = PI * (phase as f32 + BURST_PHASE + hue) / 6.0;
+        y += signal;
+        i += signal * angle.cos();
+        q += signal * angle.sin();
+    }
+
+    let y = y * settings.contrast + settings.brightness;
+    let (i, q) = (i * settings.saturation, q * settings.saturation);
+    let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    (
+        to_byte(y + 0.946882 * i + 0.623557 * q),
+        to_byte(y - 0.274788 * i - 0.635691 * q),
+        to_byte(y - 1.108545 * i + 1.709007 * q),
+    )
+}
+
+// All 512 colors, laid out like palette::EMPHASIS_PALETTES
+pub fn generate(settings: &NtscSettings) -> [[(u8, u8, u8); 64]; 8] {
+    let mut palettes = [[(0, 0, 0); 64]; 8];
+    for (emphasis, palette) in palettes.iter_mut().enumerate() {
+        for (index, color) in palette.iter_mut().enumerate() {
+            *color = decode(index as u8, emphasis as u8, settings);
+        }
+    }
+    palettes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generated_palette() {
+        let palettes = generate(&NtscSettings::default());
+        assert_eq!(palettes[0][0x0F], (0, 0, 0));
+        assert_eq!(palettes[0][0x20], (255, 255, 255));
+        // Grays have no color in them
+        let (r, g, b) = palettes[0][0x00];
+        assert!(r == g && g == b);
+
+        // Red, green and blue land where the fixed palette has them
+        let (r, g, b) = palettes[0][0x16];
+        assert!(r > g && r > b);
+        let (r, g, b) = palettes[0][0x1A];
+        assert!(g > r && g > b);
+        let (r, g, b) = palettes[0][0x12];
+        assert!(b > r && b > g);
+
+        // Red emphasis takes green and blue out of white
+        let (r, g, b) = palettes[0b001][0x30];
+        assert!(r > g && r > b);
+        assert!(palettes[0b111][0x30].0 < 255);
+    }
+
+    #[test]
+    fn test_knobs() {
+        let dull = decode(0x16, 0, &NtscSettings { saturation: 0.0, ..NtscSettings::default() });
+        assert!(dull.0 == dull.1 && dull.1 == dull.2);
+        let dark = decode(0x20, 0, &NtscSettings { brightness: -0.5, ..NtscSettings::default() });
+        assert!(dark.0 < 160);
+        // A half turn of hue swaps red for its opposite
+        let (r, g, b) = decode(0x16, 0, &NtscSettings { hue: 180.0, ..NtscSettings::default() });
+        assert!(r < g || r < b);
+    }
+}
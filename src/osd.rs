@@ -0,0 +1,167 @@
+use crate::frame::Frame;
+
+// On screen display: short text notices drawn over the game picture for a few seconds.
+// Text is drawn with a tiny built in 3x5 font (uppercase letters, digits, a bit of punctuation).
+
+pub const DEFAULT_MESSAGE_FRAMES: u32 = 180; // ~3 seconds at 60 fps
+
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 2;
+const TEXT_COLOR: (u8, u8, u8) = (0xFF, 0xFF, 0xFF);
+const SHADOW_COLOR: (u8, u8, u8) = (0x00, 0x00, 0x00);
+
+// Each glyph is 5 rows of 3 bits, bit 2 being the leftmost pixel
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '?' => [0b110, 0b001, 0b010, 0b000, 0b010],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '$' => [0b011, 0b110, 0b010, 0b011, 0b110],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        _ => [0b000; GLYPH_HEIGHT], // spaces and anything the font doesn't know
+    }
+}
+
+// Draws text with its top left corner at (x, y). Each character is 4 pixels wide
+// including spacing. A 1 pixel shadow keeps it readable on bright backgrounds.
+pub fn draw_text(frame: &mut Frame, x: usize, y: usize, text: &str, color: (u8, u8, u8)) {
+    for (i, c) in text.chars().enumerate() {
+        let rows = glyph(c);
+        let char_x = x + i * (GLYPH_WIDTH + 1);
+
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if (bits >> (GLYPH_WIDTH - 1 - col)) & 1 == 1 {
+                    frame.set_pixel(false, char_x + col + 1, y + row + 1, SHADOW_COLOR);
+                    frame.set_pixel(false, char_x + col, y + row, color);
+                }
+            }
+        }
+    }
+}
+
+struct OsdMessage {
+    text: String,
+    frames_left: u32,
+}
+
+pub struct Osd {
+    messages: Vec<OsdMessage>,
+}
+
+impl Default for Osd {
+    fn default() -> Self {
+        Osd::new()
+    }
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Osd { messages: vec![] }
+    }
+
+    // Shows a message for the default amount of time
+    pub fn notify(&mut self, text: &str) {
+        self.notify_for(text, DEFAULT_MESSAGE_FRAMES);
+    }
+
+    pub fn notify_for(&mut self, text: &str, frames: u32) {
+        println!("{}", text);
+        self.messages.push(OsdMessage { text: text.to_string(), frames_left: frames });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    // Draws every active message stacked up from the bottom left corner
+    // and ages them by one frame. Call once per rendered frame.
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let bottom = 240 - LINE_HEIGHT - 2;
+        for (i, message) in self.messages.iter().rev().enumerate() {
+            if (i + 1) * LINE_HEIGHT > bottom {
+                break;
+            }
+            draw_text(frame, 4, bottom - i * LINE_HEIGHT, &message.text, TEXT_COLOR);
+        }
+
+        for message in self.messages.iter_mut() {
+            message.frames_left = message.frames_left.saturating_sub(1);
+        }
+        self.messages.retain(|message| message.frames_left > 0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_messages_expire() {
+        let mut osd = Osd::new();
+        let mut frame = Frame::new();
+        osd.notify_for("HI", 2);
+
+        osd.draw(&mut frame);
+        assert!(!osd.is_empty());
+        osd.draw(&mut frame);
+        assert!(osd.is_empty());
+    }
+
+    #[test]
+    fn test_draw_text_sets_pixels() {
+        let mut frame = Frame::new();
+        draw_text(&mut frame, 0, 0, "I", TEXT_COLOR);
+
+        // Top row of 'I' is solid
+        assert_eq!(&frame.data[0..9], &[0xFF; 9]);
+    }
+}
@@ -1,3 +1,5 @@
+use once_cell::sync::Lazy;
+
 #[rustfmt::skip]
 
 pub static SYSTEM_PALLETE: [(u8,u8,u8); 64] = [
@@ -14,4 +16,51 @@ pub static SYSTEM_PALLETE: [(u8,u8,u8); 64] = [
    (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB), (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0),
    (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
    (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
-];
\ No newline at end of file
+];
+
+// Color emphasis ($2001 bits 5-7) darkens the channels that aren't emphasized.
+// Rather than doing that per pixel, the 8 combinations are worked out once, indexed
+// by the emphasis bits shifted down (bit 0 red, bit 1 green, bit 2 blue).
+const EMPHASIS_ATTENUATION: f32 = 0.75;
+
+// The 64 colors for each of the 8 emphasis combinations
+pub type Palettes = [[(u8, u8, u8); 64]; 8];
+
+pub static EMPHASIS_PALETTES: Lazy<Palettes> = Lazy::new(|| {
+    let mut palettes = [SYSTEM_PALLETE; 8];
+    for (emphasis, palette) in palettes.iter_mut().enumerate().skip(1) {
+        // A channel is dimmed when some other channel is emphasized and it isn't
+        let dim = |channel: u8, bit: usize| {
+            if emphasis & !(1 << bit) != 0 && emphasis & (1 << bit) == 0 {
+                (channel as f32 * EMPHASIS_ATTENUATION).round() as u8
+            } else {
+                channel
+            }
+        };
+        for color in palette.iter_mut() {
+            *color = (dim(color.0, 0), dim(color.1, 1), dim(color.2, 2));
+        }
+    }
+    palettes
+});
+
+// The 64 colors of the fixed palette as they look with `emphasis` (the top 3 bits
+// of $2001, shifted down). A generated palette can replace it, see NesPPU::colors.
+pub fn emphasis_palette(emphasis: u8) -> &'static [(u8, u8, u8); 64] {
+    &EMPHASIS_PALETTES[(emphasis & 0b111) as usize]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_emphasis_palettes() {
+        assert_eq!(emphasis_palette(0), &SYSTEM_PALLETE);
+        // Red emphasis dims green and blue of white
+        assert_eq!(emphasis_palette(0b001)[0x30], (0xFF, 0xBF, 0xBF));
+        // Every channel emphasized, none is dimmed
+        assert_eq!(emphasis_palette(0b111)[0x30], (0xFF, 0xFF, 0xFF));
+        assert_eq!(emphasis_palette(0b110)[0x30], (0xBF, 0xFF, 0xFF));
+    }
+}
@@ -0,0 +1,313 @@
+use std::path::{Path, PathBuf};
+
+use crate::hash::crc32;
+
+// Soft-patching support for the two common rom hack formats.
+// IPS: https://zerosoft.zophar.net/ips.php
+// BPS: https://www.romhacking.net/documents/746/
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+// Looks for a patch with the same stem as the rom sitting next to it (game.nes -> game.ips / game.bps)
+pub fn find_patch(rom_path: &Path) -> Option<PathBuf> {
+    ["ips", "bps"]
+        .iter()
+        .map(|ext| rom_path.with_extension(ext))
+        .find(|path| path.is_file())
+}
+
+// Applies a patch to the raw rom file, picking the format from the patch's magic bytes
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err("Patch is not an IPS or BPS file".to_string())
+    }
+}
+
+fn ips_read<'a>(patch: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], String> {
+    let bytes = patch.get(*pos..*pos + len).ok_or("IPS patch ended unexpectedly")?;
+    *pos += len;
+    Ok(bytes)
+}
+
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if !patch.starts_with(IPS_MAGIC) {
+        return Err("Patch is not an IPS file".to_string());
+    }
+
+    let mut output = rom.to_vec();
+    let mut pos = IPS_MAGIC.len();
+
+    loop {
+        let offset = ips_read(patch, &mut pos, 3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+        let offset = ((offset[0] as usize) << 16) | ((offset[1] as usize) << 8) | offset[2] as usize;
+
+        let size = ips_read(patch, &mut pos, 2)?;
+        let size = ((size[0] as usize) << 8) | size[1] as usize;
+
+        // A size of 0 marks a run length encoded record
+        let data = if size == 0 {
+            let run = ips_read(patch, &mut pos, 3)?;
+            let run_size = ((run[0] as usize) << 8) | run[1] as usize;
+            vec![run[2]; run_size]
+        } else {
+            ips_read(patch, &mut pos, size)?.to_vec()
+        };
+
+        if output.len() < offset + data.len() {
+            output.resize(offset + data.len(), 0);
+        }
+        output[offset..offset + data.len()].copy_from_slice(&data);
+    }
+
+    // Optional truncation extension after the EOF marker
+    if let Ok(truncate) = ips_read(patch, &mut pos, 3) {
+        let size = ((truncate[0] as usize) << 16) | ((truncate[1] as usize) << 8) | truncate[2] as usize;
+        output.truncate(size);
+    }
+
+    Ok(output)
+}
+
+fn bps_number(patch: &[u8], pos: &mut usize) -> Result<usize, String> {
+    let mut data: usize = 0;
+    let mut shift: usize = 1;
+    loop {
+        let byte = *patch.get(*pos).ok_or("BPS patch ended unexpectedly")?;
+        *pos += 1;
+        data = ((byte & 0x7F) as usize)
+            .checked_mul(shift)
+            .and_then(|value| data.checked_add(value))
+            .ok_or("BPS number is too large")?;
+        if byte & 0x80 != 0 {
+            return Ok(data);
+        }
+        shift = shift.checked_mul(0x80).ok_or("BPS number is too large")?;
+        data = data.checked_add(shift).ok_or("BPS number is too large")?;
+    }
+}
+
+// Moves a relative copy offset by a signed BPS delta (lowest bit is the sign)
+fn bps_relative(offset: isize, data: usize) -> Option<isize> {
+    let delta = isize::try_from(data >> 1).ok()?;
+    offset.checked_add(if data & 1 == 1 { -delta } else { delta })
+}
+
+// Bounds of a copy, None when it can't be a valid range
+fn bps_range(start: usize, length: usize) -> Option<std::ops::Range<usize>> {
+    Some(start..start.checked_add(length)?)
+}
+
+fn read_u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if !patch.starts_with(BPS_MAGIC) || patch.len() < BPS_MAGIC.len() + 12 {
+        return Err("Patch is not a BPS file".to_string());
+    }
+
+    // Footer holds source, target and patch checksums
+    let footer = patch.len() - 12;
+    let source_crc = read_u32_le(&patch[footer..]);
+    let target_crc = read_u32_le(&patch[footer + 4..]);
+    let patch_crc = read_u32_le(&patch[footer + 8..]);
+
+    if crc32(&patch[..footer + 8]) != patch_crc {
+        return Err("BPS patch is corrupt (checksum mismatch)".to_string());
+    }
+    if crc32(rom) != source_crc {
+        return Err("BPS patch was made for a different rom".to_string());
+    }
+
+    let mut pos = BPS_MAGIC.len();
+    let _source_size = bps_number(patch, &mut pos)?;
+    let target_size = bps_number(patch, &mut pos)?;
+    let metadata_size = bps_number(patch, &mut pos)?;
+    pos = pos.checked_add(metadata_size).ok_or("BPS metadata out of range")?;
+
+    // target_size is only the patch's word until the checksum at the end, so the
+    // output grows as it's written and never past it
+    let mut output: Vec<u8> = Vec::with_capacity(target_size.min(rom.len() * 4));
+    let mut source_rel: isize = 0;
+    let mut target_rel: isize = 0;
+
+    while pos < footer {
+        let command = bps_number(patch, &mut pos)?;
+        let length = (command >> 2) + 1;
+        if length > target_size - output.len() {
+            return Err("BPS patch writes past its target size".to_string());
+        }
+
+        match command & 0b11 {
+            // SourceRead: copy from the same offset in the source
+            0 => {
+                let range = bps_range(output.len(), length).ok_or("BPS source read out of range")?;
+                let bytes = rom.get(range).ok_or("BPS source read out of range")?;
+                output.extend_from_slice(bytes);
+            }
+            // TargetRead: copy literal bytes out of the patch
+            1 => {
+                let range = bps_range(pos, length).ok_or("BPS target read out of range")?;
+                let bytes = patch.get(range).ok_or("BPS target read out of range")?;
+                output.extend_from_slice(bytes);
+                pos += length;
+            }
+            // SourceCopy: copy from a relative offset in the source
+            2 => {
+                let data = bps_number(patch, &mut pos)?;
+                source_rel = bps_relative(source_rel, data).ok_or("BPS source copy out of range")?;
+                let start = usize::try_from(source_rel).map_err(|_| "BPS source copy out of range")?;
+                let range = bps_range(start, length).ok_or("BPS source copy out of range")?;
+                let bytes = rom.get(range).ok_or("BPS source copy out of range")?;
+                output.extend_from_slice(bytes);
+                source_rel += length as isize;
+            }
+            // TargetCopy: copy from earlier output (byte at a time, ranges may overlap)
+            _ => {
+                let data = bps_number(patch, &mut pos)?;
+                target_rel = bps_relative(target_rel, data).ok_or("BPS target copy out of range")?;
+                for _ in 0..length {
+                    let start = usize::try_from(target_rel).map_err(|_| "BPS target copy out of range")?;
+                    let byte = *output.get(start).ok_or("BPS target copy out of range")?;
+                    output.push(byte);
+                    target_rel += 1;
+                }
+            }
+        }
+    }
+
+    if output.len() != target_size || crc32(&output) != target_crc {
+        return Err("BPS patch produced an unexpected result (checksum mismatch)".to_string());
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ips_records_and_rle() {
+        let rom = vec![0u8; 8];
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend([0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]); // 2 bytes at offset 1
+        patch.extend([0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x04, 0xCC]); // 4 x 0xCC at offset 5
+        patch.extend(IPS_EOF);
+
+        let output = apply_ips(&rom, &patch).unwrap();
+
+        assert_eq!(output, vec![0, 0xAA, 0xBB, 0, 0, 0xCC, 0xCC, 0xCC, 0xCC]);
+    }
+
+    fn bps_encode(mut data: usize, out: &mut Vec<u8>) {
+        loop {
+            let x = (data & 0x7F) as u8;
+            data >>= 7;
+            if data == 0 {
+                out.push(0x80 | x);
+                break;
+            }
+            out.push(x);
+            data -= 1;
+        }
+    }
+
+    #[test]
+    fn test_bps_source_and_target_read() {
+        let rom = vec![1, 2, 3, 4];
+        let target = vec![1, 2, 9, 9, 4];
+
+        let mut patch = BPS_MAGIC.to_vec();
+        bps_encode(rom.len(), &mut patch);
+        bps_encode(target.len(), &mut patch);
+        bps_encode(0, &mut patch);
+        bps_encode((2 - 1) << 2, &mut patch); // SourceRead 2
+        bps_encode(((2 - 1) << 2) | 1, &mut patch); // TargetRead 2
+        patch.extend([9, 9]);
+        bps_encode(2, &mut patch); // SourceCopy 1 (length - 1 is 0) from offset 3
+        bps_encode(3 << 1, &mut patch);
+        patch.extend(crc32(&rom).to_le_bytes());
+        patch.extend(crc32(&target).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+
+        assert_eq!(apply_bps(&rom, &patch).unwrap(), target);
+        assert!(apply_bps(&[0, 0, 0, 0], &patch).is_err());
+    }
+
+    // Wraps SourceCopy commands in a patch with valid checksums
+    fn bps_source_copy(rom: &[u8], copies: &[(usize, usize)]) -> Vec<u8> {
+        let mut patch = BPS_MAGIC.to_vec();
+        bps_encode(rom.len(), &mut patch);
+        bps_encode(rom.len(), &mut patch);
+        bps_encode(0, &mut patch);
+        for (length, data) in copies {
+            bps_encode(((length - 1) << 2) | 2, &mut patch);
+            bps_encode(*data, &mut patch);
+        }
+        patch.extend(crc32(rom).to_le_bytes());
+        patch.extend(crc32(rom).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_bps_bad_relative_offsets_are_errors() {
+        let rom = vec![1, 2, 3, 4];
+        assert_eq!(apply_bps(&rom, &bps_source_copy(&rom, &[(4, 0)])).unwrap(), rom);
+
+        // One byte before the start of the source
+        assert!(apply_bps(&rom, &bps_source_copy(&rom, &[(4, (1 << 1) | 1)])).is_err());
+        // So far past the end that start + length overflows
+        assert!(apply_bps(&rom, &bps_source_copy(&rom, &[(4, usize::MAX - 1)])).is_err());
+        // Back to a valid offset, then a delta that overflows the running offset
+        assert!(apply_bps(&rom, &bps_source_copy(&rom, &[(2, 0), (2, usize::MAX - 1)])).is_err());
+
+        // A length that overflows a TargetRead range
+        let mut patch = BPS_MAGIC.to_vec();
+        bps_encode(rom.len(), &mut patch);
+        bps_encode(rom.len(), &mut patch);
+        bps_encode(0, &mut patch);
+        bps_encode(((usize::MAX >> 2) << 2) | 1, &mut patch);
+        patch.extend(crc32(&rom).to_le_bytes());
+        patch.extend(crc32(&rom).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        assert!(apply_bps(&rom, &patch).is_err());
+    }
+
+    #[test]
+    fn test_bps_huge_target_size_is_an_error() {
+        let rom = vec![1, 2, 3, 4];
+        let mut patch = BPS_MAGIC.to_vec();
+        bps_encode(rom.len(), &mut patch);
+        bps_encode(1 << 60, &mut patch);
+        bps_encode(0, &mut patch);
+        bps_encode((4 - 1) << 2, &mut patch); // SourceRead 4
+        patch.extend(crc32(&rom).to_le_bytes());
+        patch.extend(crc32(&rom).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        assert!(apply_bps(&rom, &patch).is_err());
+
+        // A TargetCopy that would repeat the output far past the target size
+        let mut patch = BPS_MAGIC.to_vec();
+        bps_encode(rom.len(), &mut patch);
+        bps_encode(rom.len(), &mut patch);
+        bps_encode(0, &mut patch);
+        bps_encode((1 - 1) << 2, &mut patch); // SourceRead 1
+        bps_encode(((1 << 40) << 2) | 3, &mut patch); // TargetCopy from offset 0
+        bps_encode(0, &mut patch);
+        patch.extend(crc32(&rom).to_le_bytes());
+        patch.extend(crc32(&rom).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        assert_eq!(apply_bps(&rom, &patch).unwrap_err(), "BPS patch writes past its target size");
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::frameskip::NTSC_FRAME_TIME;
+
+// Emulation speed statistics, averaged over the last WINDOW frames. Nes samples
+// the clock once per frame so frontends can show a performance HUD and benchmarks
+// can check throughput (see Nes::perf_stats).
+
+const WINDOW: usize = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PerfStats {
+    pub cycles_per_sec: f64, // emulated CPU cycles per real second
+    pub frames_per_sec: f64,
+    pub speed: f64, // 1.0 is a real NTSC console's speed
+    // Share of real time spent emulating (0 to 1). Time the frontend spends in its
+    // frame callback (drawing, waiting for vsync) doesn't count.
+    pub host_utilization: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Instant,
+    cycles: u64,
+    frames: u64,
+    frontend: Duration, // total time spent in the frame callback so far
+}
+
+pub struct PerfMonitor {
+    samples: VecDeque<Sample>,
+}
+
+impl Default for PerfMonitor {
+    fn default() -> Self {
+        PerfMonitor::new()
+    }
+}
+
+impl PerfMonitor {
+    pub fn new() -> Self {
+        PerfMonitor { samples: VecDeque::with_capacity(WINDOW + 1) }
+    }
+
+    // Called once per frame with the running totals
+    pub fn record(&mut self, at: Instant, cycles: u64, frames: u64, frontend: Duration) {
+        if self.samples.len() > WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample { at, cycles, frames, frontend });
+    }
+
+    // None until two frames have been seen
+    pub fn stats(&self) -> Option<PerfStats> {
+        let (first, last) = (self.samples.front()?, self.samples.back()?);
+        let wall = last.at.duration_since(first.at).as_secs_f64();
+        if wall <= 0.0 {
+            return None;
+        }
+
+        let frames_per_sec = (last.frames - first.frames) as f64 / wall;
+        let emulating = wall - (last.frontend - first.frontend).as_secs_f64();
+        Some(PerfStats {
+            cycles_per_sec: (last.cycles - first.cycles) as f64 / wall,
+            frames_per_sec,
+            speed: frames_per_sec * NTSC_FRAME_TIME.as_secs_f64(),
+            host_utilization: (emulating / wall).clamp(0.0, 1.0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stats_over_window() {
+        let mut monitor = PerfMonitor::new();
+        let start = Instant::now();
+        monitor.record(start, 0, 0, Duration::ZERO);
+        assert_eq!(monitor.stats(), None);
+
+        // 120 frames in 1 second, 250ms of it spent in the frontend
+        monitor.record(start + Duration::from_secs(1), 3_600_000, 120, Duration::from_millis(250));
+        let stats = monitor.stats().unwrap();
+        assert_eq!(stats.cycles_per_sec, 3_600_000.0);
+        assert_eq!(stats.frames_per_sec, 120.0);
+        assert!((stats.speed - 2.0).abs() < 0.01);
+        assert!((stats.host_utilization - 0.75).abs() < 1e-9);
+    }
+}
@@ -3,6 +3,9 @@ use crate::rom::Mirroring;
 use bitflags::bitflags;
 use crate::mapper::Mapper;
 use crate::rom::Rom;
+use crate::palette::{self, Palettes};
+use crate::state::{Snapshot, StateReader, StateWriter};
+use crate::vram_log::{VramLog, VramWrite};
 use std::{cell::RefCell, rc::Rc};
 
 // PPU Register -> Reg Title translation
@@ -13,44 +16,94 @@ Addr == 0x2006 == Helps provide CPU access to PPU memory
 Data == 0x2007 == ^^^^^
 */
 
+// Where the PPU currently is in the frame. This is the stable way for tools
+// (tracing, debuggers, scripts) to read PPU timing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PpuTiming {
+    pub scanline: u16,
+    pub dot: usize,
+    pub frame: u64,
+}
+
+// Where sprite zero hit was first set in a frame. The hit is only checked once
+// per line, so x is sprite zero's left edge rather than the exact overlapping pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpriteZeroHit {
+    pub x: u8,
+    pub scanline: u16,
+}
+
+// One sprite out of OAM, decoded. y is the OAM byte as the game wrote it, one
+// line above where the sprite's top row shows on hardware; $EF and up is off
+// the bottom, which is how games hide sprites.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OamEntry {
+    pub index: u8, // 0-63, sprite 0 first (and in front of the others)
+    pub x: u8,
+    pub y: u8,
+    pub tile: u8, // for 8x16 sprites bit 0 picks the pattern table
+    pub palette: u8, // 0-3, sprite palettes at $3F10-$3F1F
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    pub tall: bool, // 8x16, from $2000 when the frame was drawn
+}
+
 pub struct NesPPU {
     pub mapper: Rc<RefCell<dyn Mapper>>,
     pub palette_table: [u8; 32],
-    pub vram: [u8; 2048],
+    scanline_palettes: [[u8; 32]; 240], // palette as it was when each visible line started (for raster effects)
+    pub vram: [u8; 4096], // only the first 2kB is used unless the cart is four-screen
     pub oam_data: [u8; 256],
+    frame_oam: [u8; 256], // OAM as the last completed frame was drawn with
+    frame_tall_sprites: bool,
     internal_data_buf: u8, // Storage for 0x2007 reads
     oam_addr: u8, // OAM Address written by 0x2003 and used by 0x2004
 
-    pub cycles: usize,
-    pub scanline: u16,
-    pub trigger_nmi: bool, // Variable cpu reads to see if it should be interrupted
+    cycles: usize, // dot within the current scanline
+    scanline: u16,
+    frame: u64, // frames completed since power on
 
     addr: AddrRegister,
+    rendering_increment: bool, // $2007 during rendering steps v like the renderer does, see Accuracy
+    write_toggle: bool, // "w", shared by $2005 and $2006: false before the first write of a pair
     status: StatusRegister,
     pub scroll: ScrollRegister,
     mask: MaskRegister,
     pub ctrl: ControlRegister,
+
+    vram_log: Option<VramLog>, // $2007 writes, when logging is on
+    sprite_zero_hit: Option<SpriteZeroHit>, // first hit of the current frame
+    palettes: Option<Box<Palettes>>, // generated colors used instead of the fixed palette (see ntsc_palette.rs)
 }
 
 impl NesPPU {
 
     pub fn _new_empty_rom() -> Self {
-        let test_mapper = Rom::_new_test(vec![0; 5]).unwrap().generate_mapper();
+        let test_mapper = Rom::_new_test(vec![0; 5]).unwrap().generate_mapper().unwrap();
         NesPPU {
             mapper: test_mapper,
             internal_data_buf: 0,
             oam_addr: 0,
-            vram: [0; 2048],
+            vram: [0; 4096],
             oam_data: [0; 64 * 4],
+            frame_oam: [0; 64 * 4],
+            frame_tall_sprites: false,
             palette_table: [0; 32],
+            scanline_palettes: [[0; 32]; 240],
             cycles: 0,
             scanline: 0,
-            trigger_nmi: false,
+            frame: 0,
             addr: AddrRegister::new(),
+            rendering_increment: false,
+            write_toggle: false,
             status: StatusRegister::new(),
             scroll: ScrollRegister::new(),
             mask: MaskRegister::new(),
             ctrl: ControlRegister::new(),
+            vram_log: None,
+            sprite_zero_hit: None,
+            palettes: None,
         }
     }
 
@@ -60,54 +113,93 @@ impl NesPPU {
             mapper: mapper,
             internal_data_buf: 0,
             oam_addr: 0,
-            vram: [0; 2048],
+            vram: [0; 4096],
             oam_data: [0; 64 * 4],
+            frame_oam: [0; 64 * 4],
+            frame_tall_sprites: false,
             palette_table: [0; 32],
+            scanline_palettes: [[0; 32]; 240],
             cycles: 0,
             scanline: 0,
-            trigger_nmi: false,
+            frame: 0,
             addr: AddrRegister::new(),
+            rendering_increment: false,
+            write_toggle: false,
             status: StatusRegister::new(),
             scroll: ScrollRegister::new(),
             mask: MaskRegister::new(),
             ctrl: ControlRegister::new(),
+            vram_log: None,
+            sprite_zero_hit: None,
+            palettes: None,
         }
     }
 
-    pub fn tick(&mut self, cycles: usize) -> bool {
-        
-        self.cycles += cycles;
-        // println!("does ppu tick? cycles: {}", self.cycles);
-        if self.cycles >= 341 {
-            if self.is_sprite_0_hit(self.cycles) {
-                self.status.set_sprite_zero_hit(true);
-            }
+    // The reset button clears the registers a game sets up at boot, memory and
+    // the dot counters are left as they were
+    pub fn reset(&mut self) {
+        self.ctrl = ControlRegister::new();
+        self.mask = MaskRegister::new();
+        self.scroll = ScrollRegister::new();
+        self.write_toggle = false;
+        self.internal_data_buf = 0;
+    }
 
-            self.cycles -= 341;
-
-            self.scanline += 1;
-            // println!("ppu scanline: {} and status reg 0b{:08b}", self.scanline, self.status.bits());
-            // println!("current cpu SCANLINE: {}", self.scanline);
-            if self.scanline == 241 { // Trigger interupt at 241st scanline (offscreen)
-                self.status.set_vblank_started(true);
-                // println!("PPU: VBlank set scanline {})", self.scanline);
-                // println!("ppu set vblank status true AAAAAAAAAAAAAAAAAAAAAAAAAAAAAA");
-                self.status.set_sprite_zero_hit(false);
-                if self.ctrl.is_generate_nmi() {
-                    self.trigger_nmi = true;
-                }
-            }
+    // Starts over with `mapper` (the cartridge as it powers on) and empty memory.
+    // The dot counters carry on so the bus scheduler stays in step with them.
+    pub fn power_cycle(&mut self, mapper: Rc<RefCell<dyn Mapper>>) {
+        let (cycles, scanline, frame) = (self.cycles, self.scanline, self.frame);
+        let vram_log = self.vram_log.take();
+        *self = NesPPU::new(mapper);
+        (self.cycles, self.scanline, self.frame) = (cycles, scanline, frame);
+        self.vram_log = vram_log;
+    }
+
+    // Advances the dot counter within the current scanline. When the line
+    // actually ends is decided by the bus scheduler, see end_scanline.
+    pub fn tick(&mut self, dots: usize) {
+        self.cycles += dots;
+    }
 
-            if self.scanline >= 262 {
-                // Reset out scanlines
-                self.trigger_nmi = false;
-                self.scanline = 0;
-                self.status.set_vblank_started(false);
-                // println!("pp");
-                self.status.set_sprite_overflow(false);
-                self.status.set_sprite_zero_hit(false);
-                return true;
+    // Called by the scheduler once all 341 dots of the current scanline have elapsed.
+    // Returns true when the last scanline of the frame just finished.
+    pub fn end_scanline(&mut self) -> bool {
+        if self.is_sprite_0_hit(self.cycles) {
+            if self.sprite_zero_hit.is_none() && self.scanline < 240 {
+                self.sprite_zero_hit = Some(SpriteZeroHit { x: self.oam_data[3], scanline: self.scanline });
             }
+            self.status.set_sprite_zero_hit(true);
+        }
+
+        self.cycles = self.cycles.saturating_sub(341);
+
+        self.scanline += 1;
+        // println!("ppu scanline: {} and status reg 0b{:08b}", self.scanline, self.status.bits());
+        // println!("current cpu SCANLINE: {}", self.scanline);
+        if self.scanline == 241 { // Trigger interupt at 241st scanline (offscreen)
+            self.status.set_vblank_started(true);
+            self.status.set_sprite_zero_hit(false);
+            self.frame_oam = self.oam_data;
+            self.frame_tall_sprites = self.ctrl.is_sprite_size();
+        }
+
+        if self.scanline >= 262 {
+            // Reset out scanlines
+            self.scanline = 0;
+            self.frame += 1;
+            self.status.set_vblank_started(false);
+            // println!("pp");
+            self.status.set_sprite_overflow(false);
+            self.status.set_sprite_zero_hit(false);
+            self.sprite_zero_hit = None;
+            self.scanline_palettes[0] = self.palette_table;
+            return true;
+        }
+
+        // Games can rewrite the palette between lines (color bars, split screens)
+        // so remember what each line starts with for the renderer
+        if (self.scanline as usize) < self.scanline_palettes.len() {
+            self.scanline_palettes[self.scanline as usize] = self.palette_table;
         }
         
         return false;
@@ -122,56 +214,143 @@ impl NesPPU {
 
     // Handles 0x2006 write (updates addr 0x2007 reads or writes from)
     pub fn write_to_ppu_addr(&mut self, value: u8) {
-        // println!("Writing {:08b} to ppu addr 0x2006 register", value);
-        self.addr.update(value);
+        self.addr.update(value, self.write_toggle);
+        self.write_toggle = !self.write_toggle;
+        if !self.write_toggle {
+            self.ppu_addr_changed(); // the address only reaches the bus on the second write
+        }
     }
 
     // Handles 0x2000 writes
     pub fn write_to_ctrl(&mut self, value: u8) {
-        // println!("Writing {:08b} to ctrl 0x2000 register", value);
-        let prev_ctrl_status = self.ctrl.is_generate_nmi();
         self.ctrl.update(value);
-        if !prev_ctrl_status && self.ctrl.is_generate_nmi() && self.status.is_vblank_started() {
-            self.trigger_nmi = true;
+    }
+
+    // Starts recording $2007 writes, dropping any earlier log
+    pub fn start_vram_log(&mut self) {
+        self.vram_log = Some(VramLog::new());
+    }
+
+    pub fn vram_log(&self) -> Option<&VramLog> {
+        self.vram_log.as_ref()
+    }
+
+    // Where sprite zero hit happened this frame, if it has yet. Still set during
+    // vblank, so the frame callback can see it.
+    pub fn sprite_zero_hit(&self) -> Option<SpriteZeroHit> {
+        self.sprite_zero_hit
+    }
+
+    // The sprites the last completed frame was drawn with, copied from OAM as it
+    // ended, so they still match the picture after the game moves things in vblank
+    pub fn frame_sprites(&self) -> Vec<OamEntry> {
+        self.frame_oam
+            .chunks(4)
+            .enumerate()
+            .map(|(index, sprite)| OamEntry {
+                index: index as u8,
+                x: sprite[3],
+                y: sprite[0],
+                tile: sprite[1],
+                palette: sprite[2] & 0b11,
+                behind_background: sprite[2] & 0b0010_0000 != 0,
+                flip_horizontal: sprite[2] & 0b0100_0000 != 0,
+                flip_vertical: sprite[2] & 0b1000_0000 != 0,
+                tall: self.frame_tall_sprites,
+            })
+            .collect()
+    }
+
+    fn is_rendering(&self) -> bool {
+        let drawing_line = self.scanline < 240 || self.scanline == 261;
+        drawing_line && self.mask.intersects(MaskRegister::BACKGROUND_RENDERING | MaskRegister::SPRITE_RENDERING)
+    }
+
+    pub fn timing(&self) -> PpuTiming {
+        PpuTiming {
+            scanline: self.scanline,
+            dot: self.cycles,
+            frame: self.frame,
         }
     }
 
-    pub fn get_nmi_status(&mut self) -> bool {
-        if self.trigger_nmi == true {
-            self.trigger_nmi = false;
-            // println!("that this is true");
-            return true
+    // Palette RAM as it was when a visible scanline (0..240) started
+    pub fn scanline_palette(&self, line: usize) -> &[u8; 32] {
+        &self.scanline_palettes[line.min(self.scanline_palettes.len() - 1)]
+    }
+
+    // The PPU's NMI output: active while the vblank flag is set and $2000 has NMI
+    // enabled. The CPU reacts to it becoming active, not to it staying active, so
+    // one vblank gives one NMI unless the game toggles NMI enable (see Bus::poll_nmi).
+    pub fn nmi_line(&self) -> bool {
+        self.status.is_vblank_started() && self.ctrl.is_generate_nmi()
+    }
+
+    // Called upon 0x2007 writes or reads. While the PPU is drawing, v belongs to the
+    // renderer and an access bumps it the way the renderer would (coarse X and Y at
+    // once) instead of by 1 or 32. Only done in the accurate profile.
+    pub fn increment_vram_addr(&mut self) {
+        if self.rendering_increment && self.is_rendering() {
+            self.addr.rendering_increment();
         } else {
-            return false
+            self.addr.increment(self.ctrl.vram_addr_increment());
         }
     }
 
-    // Called upon 0x2007 writes or reads
-    pub fn increment_vram_addr(&mut self) {
-        self.addr.increment(self.ctrl.vram_addr_increment());
+    pub fn set_rendering_increment(&mut self, on: bool) {
+        self.rendering_increment = on;
+    }
+
+    // Every mapper access made by the PPU and the renderer goes through these.
+    // Each borrows the mapper for just the one call (see mapper.rs)
+    pub fn chr_read(&self, addr: u16) -> u8 {
+        self.mapper.borrow().ppu_read(addr)
+    }
+
+    fn chr_write(&mut self, addr: u16, data: u8) {
+        self.mapper.borrow_mut().ppu_write(addr, data);
+    }
+
+    // $2000-$3EFF. The mapper decides which part of VRAM each nametable is on every
+    // access, so mirroring changes show up straight away
+    fn nametable_read(&self, addr: u16) -> u8 {
+        self.mapper.borrow().nametable_read(addr, &self.vram)
+    }
+
+    fn nametable_write(&mut self, addr: u16, data: u8) {
+        self.mapper.borrow_mut().nametable_write(addr, data, &mut self.vram);
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.borrow().get_mirroring()
+    }
+
+    // Lets the mapper see the address lines move, see Mapper::on_ppu_addr
+    fn ppu_addr_changed(&self) {
+        self.mapper.borrow_mut().on_ppu_addr(self.addr.get());
     }
 
     // For read upon 0x2007
     pub fn read_data(&mut self) -> u8 {
         let addr = self.addr.get();
         self.increment_vram_addr();
+        self.ppu_addr_changed();
 
         match addr {
             0..=0x1FFF => {
                 let result = self.internal_data_buf;
-                self.internal_data_buf = self.mapper.borrow().ppu_read(addr);
+                self.internal_data_buf = self.chr_read(addr);
                 result
             },
-            0x2000..=0x2FFF => {
+            0x2000..=0x3EFF => {
                 let result = self.internal_data_buf;
-                self.internal_data_buf = self.vram[self.mirror_vram_addr(addr) as usize];
+                self.internal_data_buf = self.nametable_read(addr);
                 result  
             },
-            0x3000..=0x3EFF => panic!("Addr space 0x3000..=0x3EFF is not expected to be used. Attempted to read 0x{:04X}", addr),
-            0x3F00..=0x3FFF => {
-                self.palette_table[(addr - 0x3F00) as usize]
+            _ => {
+                // $3F00-$3FFF, the address register is only 14 bits
+                self.palette_table[palette_index(addr)]
             },
-            _ => panic!("Unexpected read access to mirrored space {}", addr),
         }
     }
 
@@ -183,15 +362,14 @@ impl NesPPU {
                 let result = self.internal_data_buf;
                 result
             },
-            0x2000..=0x2FFF => {
+            0x2000..=0x3EFF => {
                 let result = self.internal_data_buf;
                 result  
             },
-            0x3000..=0x3EFF => panic!("Addr space 0x3000..=0x3EFF is not expected to be used. Attempted to read 0x{:04X}", addr),
-            0x3F00..=0x3FFF => {
-                self.palette_table[(addr - 0x3F00) as usize]
+            _ => {
+                // $3F00-$3FFF, the address register is only 14 bits
+                self.palette_table[palette_index(addr)]
             },
-            _ => panic!("Unexpected read access to mirrored space {}", addr),
         }
     }
 
@@ -199,19 +377,44 @@ impl NesPPU {
     pub fn write_to_data(&mut self, data: u8) {
         let addr = self.addr.get();
         self.increment_vram_addr();
+        self.ppu_addr_changed();
+
+        let during_rendering = self.is_rendering();
+        if let Some(log) = self.vram_log.as_mut() {
+            log.record(VramWrite { frame: self.frame, scanline: self.scanline, dot: self.cycles, addr, data, during_rendering });
+        }
 
         match addr {
             0..=0x1FFF => {
-                self.mapper.borrow_mut().ppu_write(addr, data);
+                self.chr_write(addr, data);
             },
-            0x2000..=0x2FFF => {
-                self.vram[self.mirror_vram_addr(addr) as usize] = data
+            0x2000..=0x3EFF => {
+                self.nametable_write(addr, data)
             },
-            0x3000..=0x3EFF => panic!("Addr space 0x3000..=0x3EFF is not expected to be used. Attempted to read 0x{:04X}", addr),
-            0x3F00..=0x3FFF => {
-                self.palette_table[(addr - 0x3F00) as usize] = data
+            _ => {
+                // $3F00-$3FFF, the address register is only 14 bits
+                self.palette_table[palette_index(addr)] = data
             },
-            _ => panic!("Unexpected read access to mirrored space {}", addr),
+        }
+    }
+
+    // Reads PPU memory ($0000-$3FFF, mirrored above that) without touching the
+    // address register or the read buffer, for graphics tools and the debug viewers
+    pub fn peek_vram(&self, addr: u16) -> u8 {
+        match addr & 0x3FFF {
+            addr @ 0..=0x1FFF => self.chr_read(addr),
+            addr @ 0x2000..=0x3EFF => self.nametable_read(addr),
+            addr => self.palette_table[palette_index(addr)],
+        }
+    }
+
+    // Writes PPU memory like peek_vram reads it. Pattern writes go to the mapper, so
+    // they only stick on carts with CHR RAM.
+    pub fn poke_vram(&mut self, addr: u16, data: u8) {
+        match addr & 0x3FFF {
+            addr @ 0..=0x1FFF => self.chr_write(addr, data),
+            addr @ 0x2000..=0x3EFF => self.nametable_write(addr, data),
+            addr => self.palette_table[palette_index(addr)] = data,
         }
     }
 
@@ -222,9 +425,8 @@ impl NesPPU {
         }
         // println!("Read ppu status with result {}", self.status.bits());
 
-        // Reset 0x2005 0x2006 latches
-        self.addr.reset_latch();
-        self.scroll.reset_latch();
+        // The next 0x2005 or 0x2006 write is the first of a pair again
+        self.write_toggle = false;
         
         // Return output
         self.status.read()
@@ -234,9 +436,45 @@ impl NesPPU {
         self.status.peek()
     }
 
+    pub fn peek_mask(&self) -> u8 {
+        self.mask.bits()
+    }
+
+    // The color emphasis bits of $2001, shifted down to index palette::EMPHASIS_PALETTES
+    pub fn emphasis(&self) -> u8 {
+        self.mask.bits() >> 5
+    }
+
+    // Draws with `palettes` instead of the fixed palette from now on
+    pub fn set_palettes(&mut self, palettes: Palettes) {
+        self.palettes = Some(Box::new(palettes));
+    }
+
+    // The 64 colors as they look with `emphasis`, from the generated palettes if any
+    pub fn colors(&self, emphasis: u8) -> &[(u8, u8, u8); 64] {
+        match self.palettes.as_ref() {
+            Some(palettes) => &palettes[(emphasis & 0b111) as usize],
+            None => palette::emphasis_palette(emphasis),
+        }
+    }
+
+    // $2001 bit 0, colors lose their hue and only the brightness (column $x0) is left
+    pub fn greyscale(&self) -> bool {
+        self.mask.contains(MaskRegister::GREYSCALE)
+    }
+
+    // What the PPU puts out for palette entry `color`: the 6 bit color with
+    // greyscale applied, and the emphasis bits above it (a 9 bit value, the way
+    // 512 entry .pal files and reference emulators' index dumps are laid out)
+    pub fn output_index(&self, color: u8) -> u16 {
+        let color = if self.greyscale() { color & 0x30 } else { color & 0x3F };
+        (self.emphasis() as u16) << 6 | color as u16
+    }
+
     // Handles 0x2005 writes
     pub fn write_scroll(&mut self, data: u8) {
-        self.scroll.write(data);
+        self.scroll.write(data, self.write_toggle);
+        self.write_toggle = !self.write_toggle;
     }
 
     // Handles 0x2001 writes
@@ -252,14 +490,11 @@ impl NesPPU {
 
     // Handles 0x2004 reads
     pub fn oam_data_read(&self) -> u8 {
-        println!("Reading OAM DATA from 0x{:02X}", self.oam_addr);
-        println!("Read OAM DATA 0x{:02X}", self.oam_data[self.oam_addr as usize]);
         self.oam_data[self.oam_addr as usize]
     }
 
     // Handles 0x2004 writes
     pub fn oam_data_write(&mut self, data: u8) {
-        println!("Writing OAM DATA 0x{:02X} to 0x{:02X}", data, self.oam_addr);
         self.oam_data[self.oam_addr as usize] = data;
         self.oam_addr = self.oam_addr.wrapping_add(1);
     }
@@ -271,33 +506,80 @@ impl NesPPU {
         }
     }
 
-    // See section 6.1 of textbook on screen-state mirroring
-    fn mirror_vram_addr(&self, addr: u16) -> u16 {
-        let mirrored_vram = addr & 0b10111111111111; // Mirrors down 3000-3EFF to regular ranges
-        let vram_index = mirrored_vram - 0x2000; // Screens can start at 0x2000 so reduct to start from 0
-        let name_table = vram_index / 0x400; // Create an index for each mirrored chunk
-        match (&self.mapper.borrow().get_mirroring(), name_table) {
-            (Mirroring::VERTICAL, 2) | (Mirroring::VERTICAL, 3) => vram_index - 0x800,
-            (Mirroring::HORIZONTAL, 2) | (Mirroring::HORIZONTAL, 1) => vram_index - 0x400,
-            (Mirroring::HORIZONTAL, 3) => vram_index - 0x800,
-            (Mirroring::SINGLELOWER, 1) | (Mirroring::SINGLEUPPER, 2) => vram_index - 0x400,
-            (Mirroring::SINGLELOWER, 2) | (Mirroring::SINGLEUPPER, 3) => vram_index - 0x800,
-            (Mirroring::SINGLELOWER, 3) => vram_index - 0xC00,
-            _ => vram_index,
-        }
+}
+
+impl Snapshot for NesPPU {
+    // Memory, registers and where the beam is. The VRAM log is left alone.
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.palette_table);
+        out.bytes(self.scanline_palettes.as_flattened());
+        out.bytes(&self.vram);
+        out.bytes(&self.oam_data);
+        out.bytes(&self.frame_oam);
+        out.bool(self.frame_tall_sprites);
+        out.u8(self.internal_data_buf);
+        out.u8(self.oam_addr);
+        out.usize(self.cycles);
+        out.u16(self.scanline);
+        out.u64(self.frame);
+        out.u8(self.addr.value.0);
+        out.u8(self.addr.value.1);
+        out.bool(self.write_toggle);
+        out.u8(self.status.bits());
+        out.u8(self.scroll.x_val);
+        out.u8(self.scroll.y_val);
+        out.u8(self.mask.bits());
+        out.u8(self.ctrl.bits());
+        out.bool(self.sprite_zero_hit.is_some());
+        let hit = self.sprite_zero_hit.unwrap_or(SpriteZeroHit { x: 0, scanline: 0 });
+        out.u8(hit.x);
+        out.u16(hit.scanline);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.palette_table)?;
+        input.bytes_into(self.scanline_palettes.as_flattened_mut())?;
+        input.bytes_into(&mut self.vram)?;
+        input.bytes_into(&mut self.oam_data)?;
+        input.bytes_into(&mut self.frame_oam)?;
+        self.frame_tall_sprites = input.bool()?;
+        self.internal_data_buf = input.u8()?;
+        self.oam_addr = input.u8()?;
+        self.cycles = input.usize()?;
+        self.scanline = input.u16()?;
+        self.frame = input.u64()?;
+        self.addr.value = (input.u8()?, input.u8()?);
+        self.write_toggle = input.bool()?;
+        self.status = StatusRegister::from_bits_truncate(input.u8()?);
+        self.scroll.x_val = input.u8()?;
+        self.scroll.y_val = input.u8()?;
+        self.mask = MaskRegister::from_bits_truncate(input.u8()?);
+        self.ctrl = ControlRegister::from_bits_truncate(input.u8()?);
+        let hit = input.bool()?;
+        let hit_at = SpriteZeroHit { x: input.u8()?, scanline: input.u16()? };
+        self.sprite_zero_hit = if hit { Some(hit_at) } else { None };
+        Ok(())
     }
 }
 
-pub struct AddrRegister { // hi ptr tracks if we've received 1 of 2 bytes yet
+// Palette RAM is 32 bytes mirrored up to $3FFF, and the backdrop entries of the
+// sprite palettes ($3F10/$3F14/$3F18/$3F1C) are the background ones
+fn palette_index(addr: u16) -> usize {
+    let index = (addr & 0x1F) as usize;
+    if index & 0x13 == 0x10 { index & 0x0F } else { index }
+}
+
+// $2005 and $2006 each take two writes, with one toggle between them deciding
+// which write is which (NesPPU::write_toggle). Interleaving the two registers, like
+// games do for mid-frame scroll changes, depends on the toggle being shared.
+pub struct AddrRegister {
     value: (u8, u8),
-    hi_ptr: bool
 }
 
 impl AddrRegister {
     pub fn new() -> Self {
         AddrRegister {
             value: (0, 0),
-            hi_ptr: true,
         }
     }
 
@@ -308,22 +590,28 @@ impl AddrRegister {
 
     // Grabs and returns the 2 byte address stored in value
     pub fn get(&self) -> u16 {
+        self.raw() & 0x3FFF
+    }
+
+    // All 15 bits, as a loopy v register: yyy NN YYYYY XXXXX (fine Y, nametable,
+    // coarse Y, coarse X). Only rendering_increment sets the top bit.
+    fn raw(&self) -> u16 {
         ((self.value.0 as u16) << 8) | (self.value.1 as u16)
     }
 
-    // Called when something gets loaded into the 0x2006 register
-    pub fn update(&mut self, data: u8) {
-        if self.hi_ptr {
+    // Called when something gets loaded into the 0x2006 register, the high byte
+    // first and the low byte on the second write of the pair
+    pub fn update(&mut self, data: u8, second_write: bool) {
+        if !second_write {
             self.value.0 = data;
         } else {
             self.value.1 = data;
         }
 
         // Everything above 0x3FFF is mirrored so mirror down if ever above
-        if self.get() > 0x3FFF {
-            self.set(self.get() & 0b11111111111111);
+        if self.raw() > 0x3FFF {
+            self.set(self.raw() & 0b11111111111111);
         }
-        self.hi_ptr = !self.hi_ptr;
     }
 
     pub fn increment(&mut self, inc: u8) {
@@ -332,13 +620,36 @@ impl AddrRegister {
         if lo > self.value.1 {
             self.value.0 = self.value.0.wrapping_add(1);
         }
-        if self.get() > 0x3fff {
-            self.set(self.get() & 0b11111111111111); //mirror down addr above 0x3fff
+        if self.raw() > 0x3fff {
+            self.set(self.raw() & 0b11111111111111); //mirror down addr above 0x3fff
         }
     }
 
-    pub fn reset_latch(&mut self) {
-        self.hi_ptr = true;
+    // Coarse X and Y increments together, wrapping into the next nametable like
+    // scrolling does
+    pub fn rendering_increment(&mut self) {
+        let mut v = self.raw();
+        if v & 0x001F == 31 {
+            v = (v & !0x001F) ^ 0x0400;
+        } else {
+            v += 1;
+        }
+
+        if v & 0x7000 != 0x7000 {
+            v += 0x1000;
+        } else {
+            v &= !0x7000;
+            let coarse_y = match (v & 0x03E0) >> 5 {
+                29 => {
+                    v ^= 0x0800;
+                    0
+                }
+                31 => 0,
+                y => y + 1,
+            };
+            v = (v & !0x03E0) | (coarse_y << 5);
+        }
+        self.set(v);
     }
 }
 
@@ -525,10 +836,9 @@ impl MaskRegister {
     }
 }
 
-pub struct ScrollRegister { // hi ptr tracks if we've received 1 of 2 bytes yet
+pub struct ScrollRegister { // x on the first write of a pair, y on the second
     x_val: u8,
     y_val: u8,
-    latch: bool
 }
 
 impl ScrollRegister {
@@ -536,33 +846,42 @@ impl ScrollRegister {
         ScrollRegister {
             x_val: 0,
             y_val: 0,
-            latch: true,
         }
     }
 
-    pub fn write(&mut self, data: u8) {
-        if self.latch {
+    pub fn write(&mut self, data: u8, second_write: bool) {
+        if !second_write {
             self.x_val = data;
         } else {
             self.y_val = data;
         }
-
-        self.latch = !self.latch;
     }
 
     pub fn read(&self) -> (u8, u8) {
         (self.x_val, self.y_val)
     }
 
-    pub fn reset_latch(&mut self) {
-        self.latch = true;
-    }
-
 }
 
 #[cfg(test)]
 pub mod test {
     use super::*;
+    use crate::mapping::mapper0::Mapper0;
+    use crate::mapping::mapper7::Mapper7;
+
+    #[test]
+    fn test_timing_tracks_scanlines_and_frames() {
+        let mut ppu = NesPPU::_new_empty_rom();
+        ppu.tick(345);
+        ppu.end_scanline();
+        assert_eq!(ppu.timing(), PpuTiming { scanline: 1, dot: 4, frame: 0 });
+
+        for _ in 1..262 {
+            ppu.end_scanline();
+        }
+        assert_eq!(ppu.timing().scanline, 0);
+        assert_eq!(ppu.timing().frame, 1);
+    }
 
     #[test]
     fn test_ppu_vram_writes() {
@@ -654,7 +973,8 @@ pub mod test {
     //   [0x2800 a ] [0x2C00 b ]
     #[test]
     fn test_vram_vertical_mirror() {
-        let test_mapper = Rom::_new_test(vec![0; 5]).unwrap().generate_mapper();
+        // The test rom's header says horizontal, so build a vertically mirrored cart
+        let test_mapper = Rc::new(RefCell::new(Mapper0::new(vec![0; 0x4000], vec![0; 0x2000], Mirroring::VERTICAL, true)));
         let mut ppu = NesPPU::new(test_mapper);
 
         ppu.write_to_ppu_addr(0x20);
@@ -680,6 +1000,72 @@ pub mod test {
         assert_eq!(ppu.read_data(), 0x77); //read from B
     }
 
+    #[test]
+    fn test_peek_and_poke_vram() {
+        let mut ppu = NesPPU::_new_empty_rom();
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0x00);
+
+        // Horizontal mirroring, and $3000 mirrors $2000
+        ppu.poke_vram(0x2405, 0x66);
+        assert_eq!(ppu.peek_vram(0x2005), 0x66);
+        assert_eq!(ppu.peek_vram(0x3005), 0x66);
+
+        // Palette mirrors, $3F10 is the backdrop
+        ppu.poke_vram(0x3F10, 0x0F);
+        ppu.poke_vram(0x3F31, 0x21);
+        assert_eq!(ppu.palette_table[0], 0x0F);
+        assert_eq!(ppu.peek_vram(0x3F11), 0x21);
+        assert_eq!(ppu.peek_vram(0x7F11), 0x21);
+
+        // The test rom has CHR RAM
+        ppu.poke_vram(0x0010, 0x12);
+        assert_eq!(ppu.peek_vram(0x0010), 0x12);
+
+        assert_eq!(ppu.addr.get(), 0x2100);
+    }
+
+    #[test]
+    fn test_scroll_and_addr_share_the_write_toggle() {
+        let mut ppu = NesPPU::_new_empty_rom();
+        // A $2005 write leaves the toggle on the second write, so $2006 takes the low byte
+        ppu.write_scroll(0x10);
+        ppu.write_to_ppu_addr(0x34);
+        assert_eq!(ppu.addr.get(), 0x0034);
+        assert_eq!(ppu.scroll.read(), (0x10, 0x00));
+
+        // And the other way around, $2005 after one $2006 write sets y
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_scroll(0x48);
+        assert_eq!(ppu.scroll.read(), (0x10, 0x48));
+
+        // $2002 resets it for both
+        ppu.write_scroll(0x20);
+        ppu.read_status();
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+        assert_eq!(ppu.addr.get(), 0x2305);
+    }
+
+    #[test]
+    fn test_ppudata_during_rendering_steps_v() {
+        let mut ppu = NesPPU::_new_empty_rom();
+        ppu.set_rendering_increment(true);
+        ppu.write_mask(0b0000_1000); // background on, line 0 is being drawn
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x1F); // coarse X 31
+        ppu.write_to_data(0x55);
+        // Coarse X wraps into the next nametable, fine Y (the top 3 bits) goes from 2 to 3
+        assert_eq!(ppu.addr.get(), 0x3400);
+
+        // Outside the accurate profile it's a plain increment
+        ppu.set_rendering_increment(false);
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x1F);
+        ppu.write_to_data(0x55);
+        assert_eq!(ppu.addr.get(), 0x2020);
+    }
+
     #[test]
     fn test_read_status_resets_latch() {
         let mut ppu = NesPPU::_new_empty_rom();
@@ -715,6 +1101,35 @@ pub mod test {
         // assert_eq!(ppu.addr.read(), 0x0306)
     }
 
+    #[test]
+    fn test_single_screen_follows_the_mapper() {
+        let mapper = Rc::new(RefCell::new(Mapper7::new(vec![0; 0x8000], vec![0; 0x2000], true)));
+        let mut ppu = NesPPU::new(mapper.clone());
+
+        // All four nametables are the lower 1KB...
+        ppu.poke_vram(0x2C05, 0x66);
+        assert_eq!((ppu.vram[0x005], ppu.peek_vram(0x2005)), (0x66, 0x66));
+
+        // ...until the game picks the upper one, then all four are that
+        mapper.borrow_mut().cpu_write(0x8000, 0x10);
+        ppu.poke_vram(0x2005, 0x77);
+        assert_eq!(ppu.vram[0x405], 0x77);
+        assert_eq!((ppu.peek_vram(0x2405), ppu.peek_vram(0x2805), ppu.peek_vram(0x2C05)), (0x77, 0x77, 0x77));
+    }
+
+    #[test]
+    fn test_four_screen_nametables_are_separate() {
+        let mapper = Rc::new(RefCell::new(Mapper0::new(vec![0; 0x4000], vec![0; 0x2000], Mirroring::FOURSCREEN, true)));
+        let mut ppu = NesPPU::new(mapper);
+        for (table, base) in [0x2000, 0x2400, 0x2800, 0x2C00].into_iter().enumerate() {
+            ppu.write_to_ppu_addr((base >> 8) as u8);
+            ppu.write_to_ppu_addr(0x05);
+            ppu.write_to_data(table as u8 + 1);
+        }
+        assert_eq!([ppu.vram[0x005], ppu.vram[0x405], ppu.vram[0x805], ppu.vram[0xC05]], [1, 2, 3, 4]);
+        assert_eq!(ppu.peek_vram(0x3C05), 4); // $3000-$3EFF mirror $2000-$2EFF
+    }
+
     #[test]
     fn test_read_status_resets_vblank() {
         let mut ppu = NesPPU::_new_empty_rom();
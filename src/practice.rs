@@ -0,0 +1,119 @@
+use crate::cpu::CPU;
+use crate::joypad::JoypadButton;
+
+// Practice mode, for drilling one hard part of a game: F1 marks a checkpoint (a
+// save state kept in memory, see state.rs) and F2 puts the game straight back
+// there, as many times as it takes. The OSD counts the attempts since the
+// checkpoint was marked. "practice = input" also shows the buttons held each
+// frame, for checking inputs against a route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PracticeMode {
+    Off,
+    On,
+    Input, // on, with the input display
+}
+
+impl PracticeMode {
+    pub fn parse(value: &str) -> Result<PracticeMode, String> {
+        match value {
+            "off" => Ok(PracticeMode::Off),
+            "on" => Ok(PracticeMode::On),
+            "input" => Ok(PracticeMode::Input),
+            _ => Err(format!("Practice mode must be off, on or input but got \"{}\"", value)),
+        }
+    }
+}
+
+// Buttons in the order the input display shows them
+const INPUT_LABELS: [(JoypadButton, &str); 8] = [
+    (JoypadButton::UP, "U"),
+    (JoypadButton::DOWN, "D"),
+    (JoypadButton::LEFT, "L"),
+    (JoypadButton::RIGHT, "R"),
+    (JoypadButton::SELECT, "SE"),
+    (JoypadButton::START, "ST"),
+    (JoypadButton::BUTTON_B, "B"),
+    (JoypadButton::BUTTON_A, "A"),
+];
+
+#[derive(Default)]
+pub struct Practice {
+    checkpoint: Option<Vec<u8>>,
+    attempts: u32, // counting the one in progress
+}
+
+impl Practice {
+    pub fn new() -> Self {
+        Practice::default()
+    }
+
+    pub fn mark(&mut self, cpu: &CPU) {
+        self.checkpoint = Some(cpu.save_state());
+        self.attempts = 1;
+    }
+
+    // Back to the checkpoint, Ok(false) when none has been marked yet
+    pub fn retry(&mut self, cpu: &mut CPU) -> Result<bool, String> {
+        let Some(checkpoint) = self.checkpoint.as_ref() else {
+            return Ok(false);
+        };
+        cpu.load_state(checkpoint)?;
+        self.attempts += 1;
+        Ok(true)
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    // The attempt counter, empty until a checkpoint is marked
+    pub fn overlay_text(&self) -> String {
+        if self.checkpoint.is_none() {
+            return String::new();
+        }
+        format!("ATTEMPT {}", self.attempts)
+    }
+}
+
+// "U - L - - ST B -": held buttons by name, released ones as dashes
+pub fn input_text(buttons: JoypadButton) -> String {
+    let labels: Vec<String> = INPUT_LABELS
+        .iter()
+        .map(|(button, label)| if buttons.contains(*button) { label.to_string() } else { "-".repeat(label.len()) })
+        .collect();
+    labels.join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::rom::Rom;
+
+    #[test]
+    fn test_retry_goes_back_to_the_checkpoint() {
+        // INC $10 / JMP $8000
+        let mut nes = Nes::new(Rom::_new_test(vec![0xE6, 0x10, 0x4C, 0x00, 0x80]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.pc = 0x8000;
+        let mut practice = Practice::new();
+        assert!(!practice.retry(&mut nes.cpu).unwrap());
+        assert_eq!(practice.overlay_text(), "");
+
+        for _ in 0..10 {
+            nes.step();
+        }
+        practice.mark(&nes.cpu);
+        let marked = nes.cpu.save_state();
+        for _ in 0..1000 {
+            nes.step();
+        }
+        assert_ne!(nes.cpu.mem_peek(0x10), 5);
+
+        assert!(practice.retry(&mut nes.cpu).unwrap());
+        assert_eq!(nes.cpu.mem_peek(0x10), 5);
+        assert_eq!(nes.cpu.save_state(), marked);
+        assert_eq!(practice.overlay_text(), "ATTEMPT 2");
+
+        assert_eq!(input_text(JoypadButton::UP | JoypadButton::START | JoypadButton::BUTTON_B), "U - - - -- ST B -");
+    }
+}
@@ -0,0 +1,85 @@
+use bitflags::bitflags;
+
+// Per game compatibility switches for carts that need something the general
+// emulation doesn't do by default. Looked up by the same CRC32 as romdb.rs when a
+// rom is loaded, and can be overridden from the config file ("quirks = ...").
+// QUIRKS_TABLE stays empty until entries have been checked against real dumps.
+
+bitflags! {
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct Quirks: u8 {
+        // Cart has its own 2kB of nametable RAM, whatever the header says
+        const FOUR_SCREEN       = 0b0000_0001;
+        // Honour the MMC1B PRG RAM disable bit (MMC1A boards leave RAM always enabled)
+        const MMC1_RAM_DISABLE  = 0b0000_0010;
+        // Controller reads return the open bus $40 in the upper bits instead of 0
+        const JOYPAD_OPEN_BUS   = 0b0000_0100;
+    }
+}
+
+const QUIRK_NAMES: [(&str, Quirks); 3] = [
+    ("four_screen", Quirks::FOUR_SCREEN),
+    ("mmc1_ram_disable", Quirks::MMC1_RAM_DISABLE),
+    ("joypad_open_bus", Quirks::JOYPAD_OPEN_BUS),
+];
+
+impl Quirks {
+    // Parses a comma separated list of quirk names, "none" for an empty set
+    pub fn parse(value: &str) -> Result<Quirks, String> {
+        let mut quirks = Quirks::empty();
+        if value == "none" {
+            return Ok(quirks);
+        }
+
+        for name in value.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+            let (_, quirk) = QUIRK_NAMES
+                .iter()
+                .find(|(quirk_name, _)| *quirk_name == name)
+                .ok_or(format!("Unknown quirk \"{}\"", name))?;
+            quirks |= *quirk;
+        }
+        Ok(quirks)
+    }
+}
+
+pub struct QuirkEntry {
+    pub crc: u32,
+    pub name: &'static str,
+    pub quirks: Quirks,
+}
+
+// Only games that have been checked against a known good dump belong here
+pub static QUIRKS_TABLE: [QuirkEntry; 0] = [];
+
+pub fn lookup(crc: u32) -> Quirks {
+    lookup_in(&QUIRKS_TABLE, crc)
+}
+
+fn lookup_in(table: &[QuirkEntry], crc: u32) -> Quirks {
+    table
+        .iter()
+        .find(|entry| entry.crc == crc)
+        .map_or(Quirks::empty(), |entry| entry.quirks)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_quirk_list() {
+        assert_eq!(Quirks::parse("none").unwrap(), Quirks::empty());
+        assert_eq!(
+            Quirks::parse("four_screen, joypad_open_bus").unwrap(),
+            Quirks::FOUR_SCREEN | Quirks::JOYPAD_OPEN_BUS
+        );
+        assert!(Quirks::parse("four_screens").is_err());
+    }
+
+    #[test]
+    fn test_lookup_by_crc() {
+        let table = [QuirkEntry { crc: 0x12345678, name: "Test", quirks: Quirks::FOUR_SCREEN }];
+        assert_eq!(lookup_in(&table, 0x12345678), Quirks::FOUR_SCREEN);
+        assert_eq!(lookup_in(&table, 0), Quirks::empty());
+    }
+}
@@ -0,0 +1,474 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::bus::Mem;
+use crate::cpu::CPU;
+use crate::frame::Frame;
+use crate::joypad::JoypadButton;
+use crate::memdump::{self, Segment};
+use crate::savestate::{StateSlots, SLOT_COUNT, STATE_DIR};
+use crate::script_input::{ConsoleEvent, ScriptAction, ScriptCommand, ScriptInput};
+
+// Small embedded HTTP server for remote control and inspection of a running game.
+// Connections are handled on a background thread; anything that needs the console
+// is passed to the emulator thread as a query and answered between instructions.
+//
+//   GET  /registers                    cpu registers and ppu timing as JSON
+//   GET  /memory?addr=0x0300&len=16    bytes from the cpu address space as a JSON array
+//   GET  /joypad                       controller 1's buttons and shift register as JSON
+//   POST /input?press=a,start&release=b
+//   POST /reset                        press the reset button on the next frame
+//   POST /power                        power cycle on the next frame
+//   GET  /screenshot                   last drawn frame as a BMP
+//   POST /dump?segments=ram,oam&file=NAME      memory to a file in dumps/ (see memdump.rs)
+//   POST /restore?segments=ram,oam&file=NAME   and back
+//   POST /state/save?slot=3            save state slot, the same ones F5/F6 use (see savestate.rs)
+//   POST /state/load?slot=3
+//
+// Only plain HTTP/1.1 with one request per connection is spoken, which is
+// enough for curl, scripts and a browser dashboard polling the endpoints.
+// Only the GET endpoints may be read from other origins, POSTs that a browser
+// sends from another origin are refused, and dump files stay inside DUMP_DIR,
+// so a web page can't use the server to drive the game or write or read files.
+// Connections are taken one at a time, so a client that stops sending or sends
+// endless headers is cut off after CLIENT_TIMEOUT or MAX_HEAD_BYTES.
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_HEAD_BYTES: u64 = 8 * 1024;
+const MAX_MEMORY_READ: usize = 0x10000;
+const DUMP_DIR: &str = "dumps";
+
+enum Query {
+    Registers,
+    Memory { addr: u16, len: usize },
+    Joypad,
+    Dump { segments: Vec<Segment>, path: PathBuf },
+    Restore { segments: Vec<Segment>, path: PathBuf },
+    SaveState { slot: u8 },
+    LoadState { slot: u8 },
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(body: String) -> Self {
+        Response { status: "200 OK", content_type: "application/json", body: body.into_bytes() }
+    }
+
+    fn error(status: &'static str, message: &str) -> Self {
+        Response { status, content_type: "text/plain", body: message.as_bytes().to_vec() }
+    }
+}
+
+// Latest drawn frame, shared between the frame callback and the server thread.
+// Only the pixels are copied each frame, encoding happens when a screenshot is asked for.
+#[derive(Clone)]
+pub struct ScreenShare {
+    frame: Arc<Mutex<Frame>>,
+}
+
+impl ScreenShare {
+    pub fn publish(&self, frame: &Frame) {
+        self.frame.lock().unwrap().data.copy_from_slice(&frame.data);
+    }
+}
+
+pub struct RemoteServer {
+    queries: Receiver<(Query, Sender<Result<String, String>>)>,
+    input: Option<ScriptInput>,
+    screen: ScreenShare,
+}
+
+impl RemoteServer {
+    // Starts listening, e.g. on "127.0.0.1:8080"
+    pub fn start(addr: &str) -> Result<RemoteServer, String> {
+        let listener = TcpListener::bind(addr).map_err(|e| format!("Could not start remote server on {}: {}", addr, e))?;
+        let (query_sender, queries) = mpsc::channel();
+        let (input_sender, input_receiver) = mpsc::channel();
+        let screen = ScreenShare { frame: Arc::new(Mutex::new(Frame::new())) };
+
+        let server_screen = screen.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = handle_connection(stream, &query_sender, &input_sender, &server_screen) {
+                    eprintln!("Remote server: {}", e);
+                }
+            }
+        });
+
+        println!("Remote control server listening on http://{}", addr);
+        Ok(RemoteServer { queries, input: Some(ScriptInput::from_channel(input_receiver)), screen })
+    }
+
+    // Button presses from /input, to be applied once per frame like a script
+    pub fn take_input(&mut self) -> Option<ScriptInput> {
+        self.input.take()
+    }
+
+    pub fn screen(&self) -> ScreenShare {
+        self.screen.clone()
+    }
+
+    // Answers any waiting queries. Call between instructions.
+    // Returns whether a state was loaded, which battery saves need to know.
+    pub fn answer_queries(&self, cpu: &mut CPU) -> bool {
+        let mut state_loaded = false;
+        for (query, reply) in self.queries.try_iter() {
+            state_loaded |= matches!(query, Query::LoadState { .. });
+            let _ = reply.send(answer(query, cpu));
+        }
+        state_loaded
+    }
+}
+
+fn answer(query: Query, cpu: &mut CPU) -> Result<String, String> {
+    match query {
+        Query::Registers => {
+            let timing = cpu.bus.ppu.timing();
+            Ok(format!(
+                "{{\"pc\":{},\"a\":{},\"x\":{},\"y\":{},\"sp\":{},\"status\":{},\"scanline\":{},\"dot\":{},\"frame\":{}}}",
+                cpu.pc, cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.sp, cpu.status, timing.scanline, timing.dot, timing.frame
+            ))
+        }
+        Query::Memory { addr, len } => {
+            let bytes: Vec<String> = (0..len)
+                .map(|i| cpu.bus.mem_peek(addr.wrapping_add(i as u16)).to_string())
+                .collect();
+            Ok(format!("[{}]", bytes.join(",")))
+        }
+        Query::Joypad => {
+            let snapshot = cpu.bus.joypad1().snapshot();
+            let names = |buttons: JoypadButton| {
+                let labels: Vec<String> = buttons.labels().iter().map(|label| format!("\"{}\"", label)).collect();
+                format!("[{}]", labels.join(","))
+            };
+            Ok(format!(
+                "{{\"buttons\":{},\"latched\":{},\"strobe\":{},\"index\":{}}}",
+                names(snapshot.buttons),
+                names(snapshot.latched),
+                snapshot.strobe,
+                snapshot.index
+            ))
+        }
+        Query::Dump { segments, path } => {
+            let bytes = memdump::dump(cpu, &segments)?;
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+            }
+            std::fs::write(&path, &bytes).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+            Ok(format!("{{\"bytes\":{}}}", bytes.len()))
+        }
+        Query::Restore { segments, path } => {
+            let bytes = std::fs::read(&path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+            memdump::restore(cpu, &segments, &bytes)?;
+            Ok(format!("{{\"bytes\":{}}}", bytes.len()))
+        }
+        Query::SaveState { slot } => {
+            let mut slots = StateSlots::new(Path::new(STATE_DIR), cpu.bus.cart_crc());
+            slots.select(slot);
+            slots.save(cpu)?;
+            Ok(format!("{{\"slot\":{}}}", slot))
+        }
+        Query::LoadState { slot } => {
+            let mut slots = StateSlots::new(Path::new(STATE_DIR), cpu.bus.cart_crc());
+            if !slots.load(cpu, slot)? {
+                return Err(format!("State slot {} is empty", slot));
+            }
+            Ok(format!("{{\"slot\":{}}}", slot))
+        }
+    }
+}
+
+fn parse_number(value: &str) -> Option<usize> {
+    match value.strip_prefix("0x").or(value.strip_prefix("$")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+// Dump files are named relative to DUMP_DIR and can't climb out of it
+fn dump_path(file: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(file);
+    let inside = relative.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if file.is_empty() || !inside {
+        return Err(format!("Dump file \"{}\" must be a relative path inside {}", file, DUMP_DIR));
+    }
+    Ok(Path::new(DUMP_DIR).join(relative))
+}
+
+fn query_param<'a>(params: &'a str, key: &str) -> Option<&'a str> {
+    params
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value)
+}
+
+fn route(
+    method: &str,
+    path: &str,
+    params: &str,
+    queries: &Sender<(Query, Sender<Result<String, String>>)>,
+    input: &Sender<ScriptCommand>,
+    screen: &ScreenShare,
+) -> Response {
+    let ask = |query: Query| {
+        let (reply_sender, reply) = mpsc::channel();
+        queries.send((query, reply_sender)).ok();
+        match reply.recv_timeout(QUERY_TIMEOUT) {
+            Ok(Ok(body)) => Response::json(body),
+            Ok(Err(e)) => Response::error("400 Bad Request", &e),
+            Err(_) => Response::error("503 Service Unavailable", "Emulator did not answer"),
+        }
+    };
+
+    match (method, path) {
+        ("GET", "/registers") => ask(Query::Registers),
+        ("GET", "/joypad") => ask(Query::Joypad),
+        ("GET", "/memory") => {
+            let addr = query_param(params, "addr").and_then(parse_number);
+            let len = query_param(params, "len").map_or(Some(1), parse_number);
+            match (addr, len) {
+                (Some(addr), Some(len)) if addr <= 0xFFFF && len <= MAX_MEMORY_READ => {
+                    ask(Query::Memory { addr: addr as u16, len })
+                }
+                _ => Response::error("400 Bad Request", "Expected addr=ADDRESS and optional len=COUNT"),
+            }
+        }
+        ("POST", "/dump") | ("POST", "/restore") => {
+            let segments = Segment::parse_list(query_param(params, "segments").unwrap_or(""));
+            match (segments, query_param(params, "file").map(dump_path)) {
+                (Ok(segments), Some(Ok(file))) if path == "/dump" => ask(Query::Dump { segments, path: file }),
+                (Ok(segments), Some(Ok(file))) => ask(Query::Restore { segments, path: file }),
+                (Err(e), _) | (_, Some(Err(e))) => Response::error("400 Bad Request", &e),
+                (_, None) => Response::error("400 Bad Request", "Expected file=NAME"),
+            }
+        }
+        ("POST", "/input") => {
+            for (key, pressed) in [("press", true), ("release", false)] {
+                for name in query_param(params, key).unwrap_or("").split(',').filter(|name| !name.is_empty()) {
+                    let Some(button) = JoypadButton::from_label(name) else {
+                        return Response::error("400 Bad Request", &format!("Unknown button \"{}\"", name));
+                    };
+                    // Frame 0 is always in the past so it applies on the next frame
+                    input.send(ScriptCommand { frame: 0, action: ScriptAction::Button { button, pressed } }).ok();
+                }
+            }
+            Response::json("{\"ok\":true}".to_string())
+        }
+        ("POST", "/reset") | ("POST", "/power") => {
+            let event = if path == "/reset" { ConsoleEvent::Reset } else { ConsoleEvent::PowerCycle };
+            input.send(ScriptCommand { frame: 0, action: ScriptAction::Console(event) }).ok();
+            Response::json("{\"ok\":true}".to_string())
+        }
+        ("GET", "/screenshot") => Response {
+            status: "200 OK",
+            content_type: "image/bmp",
+            body: screen.frame.lock().unwrap().to_bmp(),
+        },
+        ("POST", "/state/save") | ("POST", "/state/load") => {
+            match query_param(params, "slot").and_then(parse_number) {
+                Some(slot) if slot < SLOT_COUNT as usize && path == "/state/save" => ask(Query::SaveState { slot: slot as u8 }),
+                Some(slot) if slot < SLOT_COUNT as usize => ask(Query::LoadState { slot: slot as u8 }),
+                _ => Response::error("400 Bad Request", &format!("Expected slot=0 to {}", SLOT_COUNT - 1)),
+            }
+        }
+        _ => Response::error("404 Not Found", "Unknown endpoint"),
+    }
+}
+
+// Dashboards on other origins may read the GET endpoints but not see or drive the POST ones
+fn response_head(method: &str, response: &Response) -> String {
+    let cors = if method == "GET" { "Access-Control-Allow-Origin: *\r\n" } else { "" };
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+        response.status, response.content_type, response.body.len(), cors
+    )
+}
+
+// Browsers send simple POSTs to any origin and only hide the answer, so a POST
+// whose Origin isn't this server is refused before it reaches an endpoint.
+// Tools like curl send no Origin and are let through.
+fn same_origin(origin: Option<&str>, host: Option<&str>) -> bool {
+    let Some(origin) = origin else {
+        return true;
+    };
+    let origin_host = origin.strip_prefix("http://").or_else(|| origin.strip_prefix("https://"));
+    match (origin_host, host) {
+        (Some(origin_host), Some(host)) => origin_host.eq_ignore_ascii_case(host),
+        _ => false,
+    }
+}
+
+fn serve(
+    method: &str,
+    target: &str,
+    origin: Option<&str>,
+    host: Option<&str>,
+    queries: &Sender<(Query, Sender<Result<String, String>>)>,
+    input: &Sender<ScriptCommand>,
+    screen: &ScreenShare,
+) -> Response {
+    if method == "POST" && !same_origin(origin, host) {
+        return Response::error("403 Forbidden", "POSTs from other origins are refused");
+    }
+    let (path, params) = target.split_once('?').unwrap_or((target, ""));
+    route(method, path, params, queries, input, screen)
+}
+
+struct RequestHead {
+    request_line: String,
+    origin: Option<String>,
+    host: Option<String>,
+}
+
+// Reads up to the blank line ending the headers, giving up past MAX_HEAD_BYTES.
+// Only Origin and Host are kept, to turn away POSTs from web pages.
+fn read_head(reader: impl BufRead) -> Result<RequestHead, String> {
+    let mut reader = reader.take(MAX_HEAD_BYTES);
+    let mut head = RequestHead { request_line: String::new(), origin: None, host: None };
+    reader.read_line(&mut head.request_line).map_err(|e| e.to_string())?;
+    let mut header = String::new();
+    loop {
+        if reader.read_line(&mut header).map_err(|e| e.to_string())? == 0 {
+            return Err(format!("Request headers cut off or longer than {} bytes", MAX_HEAD_BYTES));
+        }
+        if header.trim() == "" {
+            return Ok(head);
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("origin") {
+                head.origin = Some(value.trim().to_string());
+            } else if name.trim().eq_ignore_ascii_case("host") {
+                head.host = Some(value.trim().to_string());
+            }
+        }
+        header.clear();
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    queries: &Sender<(Query, Sender<Result<String, String>>)>,
+    input: &Sender<ScriptCommand>,
+    screen: &ScreenShare,
+) -> Result<(), String> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT)).map_err(|e| e.to_string())?;
+    let RequestHead { request_line, origin, host } =
+        read_head(BufReader::new(stream.try_clone().map_err(|e| e.to_string())?))?;
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let target = parts.next().unwrap_or("");
+
+    let response = serve(method, target, origin.as_deref(), host.as_deref(), queries, input, screen);
+
+    let mut stream = stream;
+    stream.write_all(response_head(method, &response).as_bytes()).map_err(|e| e.to_string())?;
+    stream.write_all(&response.body).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_params() {
+        assert_eq!(query_param("addr=0x300&len=4", "len"), Some("4"));
+        assert_eq!(query_param("addr=0x300", "len"), None);
+        assert_eq!(parse_number("0x300"), Some(0x300));
+        assert_eq!(parse_number("$10"), Some(0x10));
+        assert_eq!(parse_number("12"), Some(12));
+    }
+
+    #[test]
+    fn test_input_and_unknown_routes() {
+        let (queries, _query_receiver) = mpsc::channel();
+        let (input, input_receiver) = mpsc::channel();
+        let screen = ScreenShare { frame: Arc::new(Mutex::new(Frame::new())) };
+
+        let response = route("POST", "/input", "press=a,start", &queries, &input, &screen);
+        assert_eq!(response.status, "200 OK");
+        assert_eq!(input_receiver.try_iter().count(), 2);
+
+        assert_eq!(route("POST", "/input", "press=z", &queries, &input, &screen).status, "400 Bad Request");
+        assert_eq!(route("GET", "/nope", "", &queries, &input, &screen).status, "404 Not Found");
+        assert_eq!(route("POST", "/dump", "segments=chr&file=out.bin", &queries, &input, &screen).status, "400 Bad Request");
+        assert_eq!(route("POST", "/restore", "segments=ram", &queries, &input, &screen).status, "400 Bad Request");
+        assert_eq!(route("POST", "/state/save", "slot=10", &queries, &input, &screen).status, "400 Bad Request");
+        assert_eq!(route("POST", "/state/load", "", &queries, &input, &screen).status, "400 Bad Request");
+    }
+
+    #[test]
+    fn test_dump_files_stay_in_the_dump_dir() {
+        assert_eq!(dump_path("run1/ram.bin").unwrap(), Path::new(DUMP_DIR).join("run1/ram.bin"));
+        assert!(dump_path("").is_err());
+        assert!(dump_path("/etc/passwd").is_err());
+        assert!(dump_path("../ram.bin").is_err());
+        assert!(dump_path("run1/../../ram.bin").is_err());
+
+        // Rejected before the emulator is even asked
+        let (queries, query_receiver) = mpsc::channel();
+        let (input, _input_receiver) = mpsc::channel();
+        let screen = ScreenShare { frame: Arc::new(Mutex::new(Frame::new())) };
+        for file in ["/tmp/ram.bin", "../ram.bin"] {
+            let params = format!("segments=ram&file={}", file);
+            assert_eq!(route("POST", "/dump", &params, &queries, &input, &screen).status, "400 Bad Request");
+            assert_eq!(route("POST", "/restore", &params, &queries, &input, &screen).status, "400 Bad Request");
+        }
+        assert_eq!(query_receiver.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_read_head() {
+        let head = read_head("POST /reset HTTP/1.1\r\nHost: localhost:8080\r\nOrigin: http://evil.example\r\n\r\n".as_bytes()).unwrap();
+        assert_eq!(head.request_line.trim(), "POST /reset HTTP/1.1");
+        assert_eq!(head.host.as_deref(), Some("localhost:8080"));
+        assert_eq!(head.origin.as_deref(), Some("http://evil.example"));
+
+        // Cut off before the blank line, or headers that never end
+        assert!(read_head("GET /registers HTTP/1.1\r\nHost: localhost\r\n".as_bytes()).is_err());
+        let endless = format!("GET /registers HTTP/1.1\r\n{}", "X-Padding: aaaaaaaaaaaaaaaa\r\n".repeat(1000));
+        assert!(read_head(endless.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_only_gets_allow_other_origins() {
+        let response = Response::json("{}".to_string());
+        assert!(response_head("GET", &response).contains("Access-Control-Allow-Origin: *"));
+        assert!(!response_head("POST", &response).contains("Access-Control-Allow-Origin"));
+    }
+
+    #[test]
+    fn test_posts_from_other_origins_are_refused() {
+        let (queries, query_receiver) = mpsc::channel();
+        let (input, input_receiver) = mpsc::channel();
+        let screen = ScreenShare { frame: Arc::new(Mutex::new(Frame::new())) };
+        let host = Some("127.0.0.1:8080");
+
+        for origin in ["http://evil.example", "null", "http://127.0.0.1:9000"] {
+            let response = serve("POST", "/input?press=a", Some(origin), host, &queries, &input, &screen);
+            assert_eq!(response.status, "403 Forbidden");
+            let response = serve("POST", "/dump?segments=ram&file=ram.bin", Some(origin), host, &queries, &input, &screen);
+            assert_eq!(response.status, "403 Forbidden");
+        }
+        assert_eq!(input_receiver.try_iter().count(), 0);
+        assert_eq!(query_receiver.try_iter().count(), 0);
+
+        // The server's own pages and clients that send no Origin still get through
+        assert_eq!(serve("POST", "/input?press=a", Some("http://127.0.0.1:8080"), host, &queries, &input, &screen).status, "200 OK");
+        assert_eq!(serve("POST", "/input?press=b", None, host, &queries, &input, &screen).status, "200 OK");
+        assert_eq!(input_receiver.try_iter().count(), 2);
+    }
+}
@@ -4,8 +4,6 @@ use std::vec;
 
 use crate::ppu::NesPPU;
 use crate::frame::Frame;
-use crate::palette::{SYSTEM_PALLETE};
-use crate::rom::Mirroring;
 
 struct Rect {
     x1: usize,
@@ -27,6 +25,7 @@ impl Rect {
 
 fn render_name_table(ppu: &NesPPU, frame: &mut Frame, name_table: &[u8], view_port: Rect, shift_x: isize, shift_y: isize) {
     let bank = ppu.ctrl.get_background_bank_val();
+    let colors = ppu.colors(ppu.emphasis());
 
     let attribute_table = &name_table[0x3C0..0x400]; // Stores palette table information from the name table/screen ram
 
@@ -37,29 +36,33 @@ fn render_name_table(ppu: &NesPPU, frame: &mut Frame, name_table: &[u8], view_po
         let x_offset: usize = i % 32;
         let y_offset: usize = i / 32;
 
-        let palette = bg_pallette(ppu, attribute_table, x_offset, y_offset);
         
         let mut tile: Vec<u8> = vec![];
         let index_range = (bank + (tile_id * 16)) as usize..=(bank + (tile_id * 16) + 15) as usize;
         for i in index_range {
-            tile.push(ppu.mapper.borrow().ppu_read(i as u16));
+            tile.push(ppu.chr_read(i as u16));
         }
 
         for y in 0..=7 {
             let mut lower = tile[y];
             let mut upper = tile[y+8];
 
+            // Use the palette the screen line this row lands on was drawn with
+            let screen_y = ((y_offset * 8 + y) as isize + shift_y).clamp(0, 239) as usize;
+            let palette = bg_pallette(ppu.scanline_palette(screen_y), attribute_table, x_offset, y_offset);
+
             for x in (0..=7).rev() {
                 let pal_id = (1 & upper) << 1 | (1 & lower);
                 lower = lower >> 1;
                 upper = upper >> 1;
-                let color = match pal_id {
-                    0 => SYSTEM_PALLETE[palette[0] as usize],
-                    1 => SYSTEM_PALLETE[palette[1] as usize],
-                    2 => SYSTEM_PALLETE[palette[2] as usize],
-                    3 => SYSTEM_PALLETE[palette[3] as usize],
+                let index = match pal_id {
+                    0 => ppu.output_index(palette[0]),
+                    1 => ppu.output_index(palette[1]),
+                    2 => ppu.output_index(palette[2]),
+                    3 => ppu.output_index(palette[3]),
                     _ => panic!("Somehow got invalid sprite color id???")
                 };
+                let color = colors[(index & 0x3F) as usize];
 
                 let trans = if pal_id == 0 {
                     true
@@ -71,33 +74,65 @@ fn render_name_table(ppu: &NesPPU, frame: &mut Frame, name_table: &[u8], view_po
                 let pixel_y = (y_offset * 8) + y;
 
                 if (pixel_x >= view_port.x1) && (pixel_x < view_port.x2) && (pixel_y >= view_port.y1) && (pixel_y < view_port.y2) {
-                    frame.set_pixel(trans, ((pixel_x as isize) + shift_x) as usize, ((pixel_y as isize) + shift_y) as usize, color);
+                    frame.set_indexed_pixel(trans, ((pixel_x as isize) + shift_x) as usize, ((pixel_y as isize) + shift_y) as usize, index, color);
                 }
             }
         }
     }
 }
 
+// Draws the 256 tiles of one pattern table (0 or 1) as a 16x16 grid with a gap
+// between tiles. Reads through the mapper, so CHR RAM shows the tiles the game
+// has written so far and bank switched CHR shows the banks mapped right now.
+pub fn render_pattern_table(ppu: &NesPPU, table: u16, frame: &mut Frame) {
+    let colors = ppu.colors(0);
+    let shades = [colors[0x01], colors[0x27], colors[0x23], colors[0x30]];
+
+    for tile_index in 0..256u16 {
+        let x_offset = (tile_index % 16) as usize * 9;
+        let y_offset = (tile_index / 16) as usize * 9;
+        let base = table * 0x1000 + tile_index * 16;
+
+        for y in 0..=7 {
+            let mut lower = ppu.chr_read(base + y);
+            let mut upper = ppu.chr_read(base + y + 8);
+
+            for x in (0..=7).rev() {
+                let pal_id = (1 & upper) << 1 | (1 & lower);
+                lower >>= 1;
+                upper >>= 1;
+                frame.set_pixel(pal_id == 0, x + x_offset, y as usize + y_offset, shades[pal_id as usize]);
+            }
+        }
+    }
+}
+
+// Draws all four nametables ($2000, $2400, $2800, $2C00) through the cart's
+// mirroring, unscrolled, into a 512x480 frame: the whole area the game can
+// scroll around in, with mirrored ones showing twice.
+pub fn render_nametables(ppu: &NesPPU, frame: &mut Frame) {
+    for table in 0..4u16 {
+        let base = 0x2000 + table * 0x400;
+        let name_table = read_nametable(ppu, base);
+        let shift_x = (table % 2) as isize * 256;
+        let shift_y = (table / 2) as isize * 240;
+        render_name_table(ppu, frame, &name_table, Rect::new(0, 0, 256, 240), shift_x, shift_y);
+    }
+}
+
+fn read_nametable(ppu: &NesPPU, base: u16) -> Vec<u8> {
+    (base..base + 0x400).map(|addr| ppu.peek_vram(addr)).collect()
+}
+
 pub fn render(ppu: &NesPPU, frame: &mut Frame) {
     let scroll = ppu.scroll.read();
 
-    let (main_nametable, other_nametable) = match (&ppu.mapper.borrow().get_mirroring(), ppu.ctrl.read_nametable()) {
-        (Mirroring::VERTICAL, 0x2000) | (Mirroring::VERTICAL, 0x2800) | (Mirroring::HORIZONTAL, 0x2000) | (Mirroring::HORIZONTAL, 0x2400) => {
-            // println!("Base == A | Second == B");
-            (&ppu.vram[0..0x400], &ppu.vram[0x400..0x800])
-        }
-        (Mirroring::VERTICAL, 0x2400) | (Mirroring::VERTICAL, 0x2C00) | (Mirroring::HORIZONTAL, 0x2800) | (Mirroring::HORIZONTAL, 0x2C00) => {
-            // println!("Base == A | Second == B");
-            (&ppu.vram[0x400..0x800], &ppu.vram[0..0x400])
-        }
-        (Mirroring::SINGLELOWER, _) => {
-            (&ppu.vram[0..0x400], &ppu.vram[0..0x400])
-        }
-        (Mirroring::SINGLEUPPER, _) => {
-            (&ppu.vram[0x400..0x800], &ppu.vram[0x400..0x800])
-        }
-        (_,_) => panic!("Unsupported mirroring type?")
-    };
+    // The nametable the scroll starts in and its neighbour the way we scroll, read
+    // through the mapper so they're whatever its mirroring makes them this frame
+    let base = ppu.ctrl.read_nametable();
+    let neighbour = if scroll.0 > 0 { base ^ 0x400 } else { base ^ 0x800 };
+    let main_nametable = &read_nametable(ppu, base);
+    let other_nametable = &read_nametable(ppu, neighbour);
 
     // println!("Scroll x == {} | Scroll y == {}", scroll.0, scroll.1);
 
@@ -129,8 +164,8 @@ pub fn render(ppu: &NesPPU, frame: &mut Frame) {
     } 
     // If we aren't scrolling in a direction we don't need to do any extra screen rendering!
 
-    // Render sprites
-    for i in (0..ppu.oam_data.len()).step_by(4).rev() {
+    // Render sprites, lowest index first since it wins where they overlap
+    for i in (0..ppu.oam_data.len()).step_by(4) {
         let tile_y: usize = ppu.oam_data[i] as usize;
         let tile_index: u16 = ppu.oam_data[i+1] as u16;
         let tile_attr = ppu.oam_data[i+2];
@@ -165,7 +200,6 @@ fn render_8x8_sprite(ppu: &NesPPU, frame: &mut Frame, tile_y: usize, tile_index:
     };
 
     let palette_index = tile_attr & 0b11;
-    let sprite_palette = sprite_palette(ppu, palette_index);
 
     // Select bank based off ctrl register
     let bank = if ppu.ctrl.is_sprite_pattern_addr() {
@@ -177,9 +211,10 @@ fn render_8x8_sprite(ppu: &NesPPU, frame: &mut Frame, tile_y: usize, tile_index:
     let mut tile: Vec<u8> = vec![];
     let index_range = (bank + (tile_index * 16)) as usize..=(bank + (tile_index * 16) + 15) as usize;
     for i in index_range {
-        tile.push(ppu.mapper.borrow().ppu_read(i as u16));
+        tile.push(ppu.chr_read(i as u16));
     }
-    render_sprite_tile(&tile, tile_x, tile_y, frame, &sprite_palette, tile_prio, flip_vertical, flip_horizontal);
+    let sprite = SpriteTile { x: tile_x, y: tile_y, palette_index, prio: tile_prio, flip_vert: flip_vertical, flip_hori: flip_horizontal };
+    render_sprite_tile(ppu, frame, &tile, &sprite);
 }
 
 fn render_8x16_sprite(ppu: &NesPPU, frame: &mut Frame, tile_y: usize, tile_index: u16, tile_attr: u8, tile_x: usize) {
@@ -203,7 +238,6 @@ fn render_8x16_sprite(ppu: &NesPPU, frame: &mut Frame, tile_y: usize, tile_index
     };
 
     let palette_index = tile_attr & 0b11;
-    let sprite_palette = sprite_palette(ppu, palette_index);
 
     // Select bank based off last bit of tile index
     let bank = if (tile_index &0b0000_0001) == 1 {
@@ -216,37 +250,55 @@ fn render_8x16_sprite(ppu: &NesPPU, frame: &mut Frame, tile_y: usize, tile_index
     let mut tile2: Vec<u8> = vec![];
     let index_range = (bank + (tile_index * 16)) as usize..=(bank + (tile_index * 16) + 15) as usize;
     for i in index_range {
-        tile1.push(ppu.mapper.borrow().ppu_read(i as u16));
-        tile2.push(ppu.mapper.borrow().ppu_read((i + 16) as u16));
+        tile1.push(ppu.chr_read(i as u16));
+        tile2.push(ppu.chr_read((i + 16) as u16));
     }
 
+    let top = SpriteTile { x: tile_x, y: tile_y, palette_index, prio: tile_prio, flip_vert: flip_vertical, flip_hori: flip_horizontal };
+    let bottom = SpriteTile { y: tile_y + 8, ..top };
     if !flip_vertical {
-        render_sprite_tile(&tile1, tile_x, tile_y, frame, &sprite_palette, tile_prio, flip_vertical, flip_horizontal);
-        render_sprite_tile(&tile2, tile_x, tile_y + 8, frame, &sprite_palette, tile_prio, flip_vertical, flip_horizontal);
+        render_sprite_tile(ppu, frame, &tile1, &top);
+        render_sprite_tile(ppu, frame, &tile2, &bottom);
     } else {
-        render_sprite_tile(&tile2, tile_x, tile_y, frame, &sprite_palette, tile_prio, flip_vertical, flip_horizontal);
-        render_sprite_tile(&tile1, tile_x, tile_y + 8, frame, &sprite_palette, tile_prio, flip_vertical, flip_horizontal);
+        render_sprite_tile(ppu, frame, &tile2, &top);
+        render_sprite_tile(ppu, frame, &tile1, &bottom);
     }
     
     
 }
 
-fn render_sprite_tile(tile: &Vec<u8>, tile_x: usize, tile_y: usize, frame: &mut Frame, sprite_palette: &[u8; 4], tile_prio: bool, flip_vert: bool, flip_hori: bool) {
+// Where and how one 8x8 sprite tile is drawn, true prio = above the background
+struct SpriteTile {
+    x: usize,
+    y: usize,
+    palette_index: u8,
+    prio: bool,
+    flip_vert: bool,
+    flip_hori: bool,
+}
+
+fn render_sprite_tile(ppu: &NesPPU, frame: &mut Frame, tile: &[u8], sprite: &SpriteTile) {
+    let SpriteTile { x: tile_x, y: tile_y, palette_index, prio: tile_prio, flip_vert, flip_hori } = *sprite;
+    let colors = ppu.colors(ppu.emphasis());
     for y in 0..=7usize {
         let mut lower = tile[y];
         let mut upper = tile[y+8];
 
+        let screen_y = if flip_vert { tile_y + 7 - y } else { tile_y + y };
+        let sprite_palette = sprite_palette(ppu.scanline_palette(screen_y), palette_index);
+
         'outer: for x in (0..=7usize).rev() {
             let pal_id = (1 & upper) << 1 | (1 & lower);
             lower = lower >> 1;
             upper = upper >> 1;
-            let color = match pal_id {
+            let index = match pal_id {
                 0 => continue 'outer,
-                1 => SYSTEM_PALLETE[sprite_palette[1] as usize],
-                2 => SYSTEM_PALLETE[sprite_palette[2] as usize],
-                3 => SYSTEM_PALLETE[sprite_palette[3] as usize],
+                1 => ppu.output_index(sprite_palette[1]),
+                2 => ppu.output_index(sprite_palette[2]),
+                3 => ppu.output_index(sprite_palette[3]),
                 _ => panic!("Somehow got invalid sprite color id???")
             };
+            let color = colors[(index & 0x3F) as usize];
 
             let trans = if pal_id == 0 {
                 true
@@ -255,16 +307,16 @@ fn render_sprite_tile(tile: &Vec<u8>, tile_x: usize, tile_y: usize, frame: &mut
             };
 
             match (flip_hori, flip_vert) {
-                (false, false) => frame.check_and_set(trans, tile_prio, tile_x + x,tile_y + y, color),
-                (true, false) => frame.check_and_set(trans, tile_prio, tile_x + 7 -x,tile_y + y, color),
-                (false, true) => frame.check_and_set(trans, tile_prio, tile_x + x,tile_y + 7 - y, color),
-                (true, true) => frame.check_and_set(trans, tile_prio, tile_x + 7 - x,tile_y + 7 - y, color),
+                (false, false) => frame.check_and_set(trans, tile_prio, tile_x + x,tile_y + y, index, color),
+                (true, false) => frame.check_and_set(trans, tile_prio, tile_x + 7 -x,tile_y + y, index, color),
+                (false, true) => frame.check_and_set(trans, tile_prio, tile_x + x,tile_y + 7 - y, index, color),
+                (true, true) => frame.check_and_set(trans, tile_prio, tile_x + 7 - x,tile_y + 7 - y, index, color),
             }
         }
     }
 }
 
-fn bg_pallette(ppu: &NesPPU, attribute_table: &[u8], tile_column: usize, tile_row: usize) -> [u8;4] {
+fn bg_pallette(palette_table: &[u8; 32], attribute_table: &[u8], tile_column: usize, tile_row: usize) -> [u8;4] {
 
     // Start at attr table of name table 1 and shift to the 4x4 meta tile
     // corresponding to the calculation made in attr_table_index
@@ -285,20 +337,259 @@ fn bg_pallette(ppu: &NesPPU, attribute_table: &[u8], tile_column: usize, tile_ro
     // add 1 since first palette table entry is a single stable value for all palettes
     let palette_start_index = 1 + (palette_index as usize) * 4; 
     [
-        ppu.palette_table[0],
-        ppu.palette_table[palette_start_index],
-        ppu.palette_table[palette_start_index+1],
-        ppu.palette_table[palette_start_index+2]
+        palette_table[0],
+        palette_table[palette_start_index],
+        palette_table[palette_start_index+1],
+        palette_table[palette_start_index+2]
     ]
 }
 
 
-fn sprite_palette(ppu: &NesPPU, palette_index: u8) -> [u8;4] {
+fn sprite_palette(palette_table: &[u8; 32], palette_index: u8) -> [u8;4] {
     let start = 0x11 + (palette_index * 4) as usize;
     [
         0,
-        ppu.palette_table[start as usize],
-        ppu.palette_table[start+1 as usize],
-        ppu.palette_table[start+2 as usize]
+        palette_table[start],
+        palette_table[start + 1],
+        palette_table[start + 2]
     ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::rom::Rom;
+    use crate::frame::PixelFormat;
+    use crate::palette::{self, SYSTEM_PALLETE};
+    use crate::ppu::NesPPU;
+
+    const GREEN: usize = 0x2A; // background color 1
+    const RED: usize = 0x16; // sprite palette 0 color 1
+    const BLUE: usize = 0x12; // sprite palette 1 color 1
+
+    // Tile 1 is solid color 1, tile 2 only has its left half (color 1). The top
+    // left background tile is tile 1, the rest of the screen is the backdrop.
+    fn sprite_scene(sprites: &[(u8, u8, u8, u8)]) -> Frame {
+        let mut ppu = NesPPU::_new_empty_rom();
+        for row in 0..8 {
+            ppu.poke_vram(0x10 + row, 0xFF);
+            ppu.poke_vram(0x20 + row, 0xF0);
+        }
+        ppu.poke_vram(0x2000, 0x01);
+        ppu.poke_vram(0x3F00, 0x0F);
+        ppu.poke_vram(0x3F01, GREEN as u8);
+        ppu.poke_vram(0x3F11, RED as u8);
+        ppu.poke_vram(0x3F15, BLUE as u8);
+        for (i, &(y, tile, attr, x)) in sprites.iter().enumerate() {
+            ppu.oam_data[i * 4..i * 4 + 4].copy_from_slice(&[y, tile, attr, x]);
+        }
+        // A whole frame so every line picks up the palette
+        for _ in 0..262 {
+            ppu.end_scanline();
+        }
+
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        frame
+    }
+
+    #[test]
+    fn test_sprite_behind_background() {
+        let frame = sprite_scene(&[(0, 1, 0x20, 0), (0, 1, 0x20, 16)]);
+        // Hidden by an opaque background pixel, shown over the backdrop
+        assert_eq!(frame.pixel(3, 3), SYSTEM_PALLETE[GREEN]);
+        assert_eq!(frame.pixel(19, 3), SYSTEM_PALLETE[RED]);
+        assert_eq!(frame.pixel(30, 3), SYSTEM_PALLETE[0x0F]);
+
+        let frame = sprite_scene(&[(0, 1, 0x00, 0)]);
+        assert_eq!(frame.pixel(3, 3), SYSTEM_PALLETE[RED]);
+    }
+
+    #[test]
+    fn test_overlapping_sprites() {
+        // Lower OAM index wins, whichever was written to the frame last
+        let frame = sprite_scene(&[(40, 1, 0x00, 40), (40, 1, 0x01, 44)]);
+        assert_eq!(frame.pixel(42, 40), SYSTEM_PALLETE[RED]);
+        assert_eq!(frame.pixel(46, 47), SYSTEM_PALLETE[RED]);
+        assert_eq!(frame.pixel(49, 40), SYSTEM_PALLETE[BLUE]);
+
+        let frame = sprite_scene(&[(40, 1, 0x01, 44), (40, 1, 0x00, 40)]);
+        assert_eq!(frame.pixel(46, 40), SYSTEM_PALLETE[BLUE]);
+
+        // A behind sprite still beats a later front one: over the background the
+        // background shows (the "mushroom out of a block" trick), over the
+        // backdrop the behind sprite does
+        let frame = sprite_scene(&[(0, 1, 0x20, 0), (0, 1, 0x01, 0)]);
+        assert_eq!(frame.pixel(3, 3), SYSTEM_PALLETE[GREEN]);
+        let frame = sprite_scene(&[(0, 1, 0x20, 80), (0, 1, 0x01, 80)]);
+        assert_eq!(frame.pixel(83, 3), SYSTEM_PALLETE[RED]);
+    }
+
+    #[test]
+    fn test_transparent_sprite_pixels() {
+        // Color 0 of a sprite shows what's under it: background...
+        let frame = sprite_scene(&[(0, 2, 0x00, 0)]);
+        assert_eq!(frame.pixel(2, 2), SYSTEM_PALLETE[RED]);
+        assert_eq!(frame.pixel(6, 2), SYSTEM_PALLETE[GREEN]);
+
+        // ...or the next sprite down, even from a sprite with a lower index
+        let frame = sprite_scene(&[(100, 2, 0x00, 100), (100, 1, 0x01, 100)]);
+        assert_eq!(frame.pixel(101, 101), SYSTEM_PALLETE[RED]);
+        assert_eq!(frame.pixel(106, 101), SYSTEM_PALLETE[BLUE]);
+        assert_eq!(frame.pixel(110, 101), SYSTEM_PALLETE[0x0F]);
+    }
+
+    #[test]
+    fn test_palette_indices() {
+        let mut ppu = NesPPU::_new_empty_rom();
+        ppu.poke_vram(0x3F00, GREEN as u8);
+        ppu.write_mask(0b0010_0001); // red emphasis and greyscale
+        for _ in 0..262 {
+            ppu.end_scanline();
+        }
+
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        assert_eq!(frame.indices(), None);
+
+        // Greyscale keeps the brightness column of $2A, emphasis goes on top
+        frame.keep_indices();
+        render(&ppu, &mut frame);
+        assert_eq!(frame.index(100, 100), Some(0b001 << 6 | 0x20));
+        assert_eq!(frame.pixel(100, 100), palette::emphasis_palette(0b001)[0x20]);
+    }
+
+    #[test]
+    fn test_each_ppu_draws_with_its_own_palette() {
+        let backdrop = |ppu: &mut NesPPU| {
+            ppu.poke_vram(0x3F00, GREEN as u8);
+            for _ in 0..262 {
+                ppu.end_scanline();
+            }
+            let mut frame = Frame::new();
+            render(ppu, &mut frame);
+            frame.pixel(100, 100)
+        };
+
+        let mut generated = *palette::EMPHASIS_PALETTES;
+        generated[0][GREEN] = (1, 2, 3);
+        let mut ntsc = NesPPU::_new_empty_rom();
+        ntsc.set_palettes(generated);
+        let mut fixed = NesPPU::_new_empty_rom();
+
+        assert_eq!(backdrop(&mut ntsc), (1, 2, 3));
+        assert_eq!(backdrop(&mut fixed), SYSTEM_PALLETE[GREEN]);
+    }
+
+    // Writes a tile into CHR RAM through $2006/$2007 at power on, like homebrew
+    // that builds its graphics at runtime, then puts it on screen
+    fn chr_ram_program() -> Vec<u8> {
+        let mut program = vec![
+            0xAD, 0x02, 0x20, // LDA $2002
+            0xA9, 0x00,       // LDA #$00
+            0x8D, 0x06, 0x20, // STA $2006
+            0xA9, 0x10,       // LDA #$10 (tile 1)
+            0x8D, 0x06, 0x20, // STA $2006
+        ];
+        // Tile 1: low plane solid, high plane empty, so every pixel is color 1
+        for byte in [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0] {
+            program.extend([0xA9, byte, 0x8D, 0x07, 0x20]); // LDA #byte, STA $2007
+        }
+        program.extend([
+            0xA9, 0x20,       // LDA #$20
+            0x8D, 0x06, 0x20, // STA $2006
+            0xA9, 0x00,       // LDA #$00
+            0x8D, 0x06, 0x20, // STA $2006
+            0xA9, 0x01,       // LDA #$01
+            0x8D, 0x07, 0x20, // STA $2007 (top left nametable tile = tile 1)
+            0xA9, 0x3F,       // LDA #$3F
+            0x8D, 0x06, 0x20, // STA $2006
+            0xA9, 0x01,       // LDA #$01
+            0x8D, 0x06, 0x20, // STA $2006
+            0xA9, 0x2A,       // LDA #$2A
+            0x8D, 0x07, 0x20, // STA $2007 (background color 1 = green)
+            0xA9, 0x00,       // LDA #$00
+            0x8D, 0x05, 0x20, // STA $2005
+            0x8D, 0x05, 0x20, // STA $2005
+        ]);
+        let here = 0x8000 + program.len() as u16;
+        program.extend([0x4C, here as u8, (here >> 8) as u8]); // JMP here
+        program
+    }
+
+    #[test]
+    fn test_chr_ram_tiles_render() {
+        let mut nes = Nes::new(Rom::_new_test(chr_ram_program()).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.pc = 0x8000;
+        nes.run_frame();
+        nes.run_frame();
+
+        let green = palette::SYSTEM_PALLETE[0x2A];
+        let mut frame = Frame::new();
+        render(&nes.cpu.bus.ppu, &mut frame);
+        assert_eq!(&frame.data[0..3], &[green.0, green.1, green.2]);
+        let past_tile = 8 * 3;
+        assert_ne!(&frame.data[past_tile..past_tile + 3], &[green.0, green.1, green.2]);
+
+        // The pattern table viewer shows the tile the game wrote, tile 0 is still blank
+        let mut tiles = Frame::new();
+        render_pattern_table(&nes.cpu.bus.ppu, 0, &mut tiles);
+        let shade = palette::SYSTEM_PALLETE[0x27];
+        assert_eq!(&tiles.data[9 * 3..9 * 3 + 3], &[shade.0, shade.1, shade.2]);
+        let blank = palette::SYSTEM_PALLETE[0x01];
+        assert_eq!(&tiles.data[0..3], &[blank.0, blank.1, blank.2]);
+
+        // The test rom mirrors horizontally, so $2400 shows the tile again
+        let mut nametables = Frame::with_size(512, 480, PixelFormat::Rgba8888);
+        render_nametables(&nes.cpu.bus.ppu, &mut nametables);
+        assert_eq!(nametables.pixel(0, 0), green);
+        assert_eq!(nametables.pixel(256, 0), green);
+        assert_ne!(nametables.pixel(0, 240), green);
+    }
+
+    #[test]
+    fn test_raster_color_bars() {
+        // Rewrites the backdrop color roughly once per scanline, like a demo's color bars
+        let program = vec![
+            0xA2, 0x00,       // LDX #$00
+            0xAD, 0x02, 0x20, // loop: LDA $2002 (reset the address latch)
+            0xA9, 0x3F,       // LDA #$3F
+            0x8D, 0x06, 0x20, // STA $2006
+            0xA9, 0x00,       // LDA #$00
+            0x8D, 0x06, 0x20, // STA $2006
+            0x8A,             // TXA
+            0x29, 0x3F,       // AND #$3F
+            0x8D, 0x07, 0x20, // STA $2007 (backdrop color = X)
+            0xE8,             // INX
+            0xA0, 0x14,       // LDY #$14
+            0x88,             // delay: DEY
+            0xD0, 0xFD,       // BNE delay
+            0x4C, 0x02, 0x80, // JMP loop
+        ];
+        let mut nes = Nes::new(Rom::_new_test(program).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.pc = 0x8000;
+
+        // Run until the second frame has been fully drawn so every line was sampled
+        while nes.cpu.bus.ppu.timing().frame < 1 || nes.cpu.bus.ppu.timing().scanline < 241 {
+            nes.step();
+        }
+
+        let mut frame = Frame::new();
+        render(&nes.cpu.bus.ppu, &mut frame);
+
+        let pixel = |x: usize, y: usize| {
+            let base = (y * 256 + x) * 3;
+            (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+        };
+
+        let mut bar_colors = std::collections::HashSet::new();
+        for y in 0..240 {
+            // Each line is one solid bar...
+            assert_eq!(pixel(0, y), pixel(255, y), "line {} changed color mid line", y);
+            bar_colors.insert(pixel(0, y));
+        }
+        // ...but the bars differ down the screen instead of using one palette for the frame
+        assert!(bar_colors.len() > 20, "only {} colors on screen", bar_colors.len());
+    }
 }
\ No newline at end of file
@@ -1,9 +1,21 @@
-use crate::{mapper::Mapper, mapping::mapper1::Mapper1};
+use crate::{mapper::{self, Mapper}, mapping::{self, mapper1::Mapper1}};
 use crate::mapping::mapper0::Mapper0;
+use crate::mapping::mapper2::Mapper2;
+use crate::mapping::mapper3::Mapper3;
+use crate::mapping::mapper7::Mapper7;
+use crate::mapping::mapper11::Mapper11;
+use crate::mapping::mapper28::Mapper28;
+use crate::mapping::mapper66::Mapper66;
+use crate::mapping::mapper69::Mapper69;
+use crate::mapping::mapper105::Mapper105;
+use crate::mapping::mapper184::Mapper184;
+use crate::mapping::mapper185::Mapper185;
+use crate::hash::{crc32, crc32_update};
+use crate::quirks::{self, Quirks};
 
 use std::{cell::RefCell, rc::Rc};
 
-const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+pub const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
 
@@ -16,17 +28,22 @@ pub enum Mirroring {
     FOURSCREEN
 }
 
+#[derive(Clone)]
 pub struct Rom {
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
     pub mapper_id: u8,
     pub screen_mirroring: Mirroring,
-    pub is_chr_ram: bool
+    pub is_chr_ram: bool,
+    pub has_battery: bool, // PRG RAM is battery backed and kept in a .sav file
+    pub crc: u32, // CRC32 of the PRG + CHR data (header and trainer excluded)
+    pub quirks: Quirks, // from the quirks table, can be replaced before generating the mapper
+    pub dip_switches: u8, // for carts that have them, set before generating the mapper
 }
 
 impl Rom {
     pub fn new(raw: &Vec<u8>) -> Result<Rom, String> {
-        if &raw[0..4] != NES_TAG {
+        if raw.len() < 16 || raw[0..4] != NES_TAG[..] {
             return Err("File is not in iNES file format".to_string());
         }
 
@@ -52,6 +69,7 @@ impl Rom {
 
 
         let skip_trainer = raw[6] & 0b100 != 0;
+        let has_battery = raw[6] & 0b10 != 0;
 
         let mut prg_rom_start = 16;
         if skip_trainer {
@@ -62,6 +80,13 @@ impl Rom {
         println!("PRG ROM INFORMATION: start: {} size: {}", prg_rom_start, prg_rom_size);
         println!("CHR ROM INFORMATION: start: {} size: {}", chr_rom_start, chr_rom_size);
 
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err(format!(
+                "File is truncated: the header asks for {} bytes of PRG and {} of CHR but the file is {} bytes",
+                prg_rom_size, chr_rom_size, raw.len()
+            ));
+        }
+
         let mut is_chr_ram: bool = false;
 
         let prg_rom = raw[prg_rom_start..(prg_rom_start+prg_rom_size)].to_vec();
@@ -72,12 +97,24 @@ impl Rom {
             raw[chr_rom_start..(chr_rom_start+chr_rom_size)].to_vec()
         };
 
+        mapper::check_rom_sizes(prg_rom.len(), chr_rom.len())?;
+
+        let crc = if is_chr_ram {
+            crc32(&prg_rom)
+        } else {
+            crc32_update(crc32(&prg_rom), &chr_rom)
+        };
+
         Ok(Rom {
             prg_rom: prg_rom,
             chr_rom: chr_rom,
             is_chr_ram: is_chr_ram,
+            has_battery,
             mapper_id,
-            screen_mirroring
+            screen_mirroring,
+            crc,
+            quirks: quirks::lookup(crc),
+            dip_switches: 0,
         })
     }
 
@@ -85,8 +122,8 @@ impl Rom {
         let mut output_raw = NES_TAG.to_vec(); // NES FILE RECOGNITION
         output_raw.push(0x01); // Rom has only 1 16kB ROM bank
         output_raw.push(0x00); // Rom has no CHR rom banks (ppu data)
-        output_raw.push(0b1111_0000); // Byte 6 (bit 2 set to 0 for NO trainer)
-        output_raw.push(0b1111_0000); // Byte 7 (last 4 bits tell EMU we're on iNES 1.0)
+        output_raw.push(0b0000_0000); // Byte 6 (bit 2 set to 0 for NO trainer, upper nibble is mapper 0)
+        output_raw.push(0b0000_0000); // Byte 7 (last 4 bits tell EMU we're on iNES 1.0)
         output_raw.push(0x00);
         output_raw.push(0x00);
         output_raw.extend(std::iter::repeat(0).take(6)); // Add 6 0s for reserved
@@ -104,24 +141,71 @@ impl Rom {
         Self::new(&output_raw)
     }
 
-    pub fn generate_mapper(&self) -> Rc<RefCell<dyn Mapper>> {
+    // Whether generate_mapper can build this rom's mapper (keep the mapping registry
+    // in sync with it)
+    pub fn has_supported_mapper(&self) -> bool {
+        mapping::is_playable(self.mapper_id)
+    }
+
+    // Errors for mapper ids the emulator doesn't have a board for
+    pub fn generate_mapper(&self) -> Result<Rc<RefCell<dyn Mapper>>, String> {
         println!("Generating mapper with mode: {}", self.mapper_id);
+        let mirroring = if self.quirks.contains(Quirks::FOUR_SCREEN) {
+            Mirroring::FOURSCREEN
+        } else {
+            self.screen_mirroring
+        };
         let mapper: Rc<RefCell<dyn Mapper>>  = match self.mapper_id {
             0 => Rc::new(RefCell::new(Mapper0::new(
                 self.prg_rom.clone(),
                 self.chr_rom.clone(),
-                self.screen_mirroring,
+                mirroring,
                 self.is_chr_ram,
             ))),
             1 => Rc::new(RefCell::new(Mapper1::new(
                 self.prg_rom.clone(),
                 self.chr_rom.clone(),
-                self.screen_mirroring,
+                mirroring,
                 self.is_chr_ram
+            ).with_ram_disable(self.quirks.contains(Quirks::MMC1_RAM_DISABLE)))),
+            2 => Rc::new(RefCell::new(Mapper2::new(
+                self.prg_rom.clone(),
+                self.chr_rom.clone(),
+                mirroring,
+                self.is_chr_ram,
+            ))),
+            3 => Rc::new(RefCell::new(Mapper3::new(
+                self.prg_rom.clone(),
+                self.chr_rom.clone(),
+                mirroring,
+                self.is_chr_ram,
+            ))),
+            7 => Rc::new(RefCell::new(Mapper7::new(self.prg_rom.clone(), self.chr_rom.clone(), self.is_chr_ram))),
+            11 => Rc::new(RefCell::new(Mapper11::new(
+                self.prg_rom.clone(),
+                self.chr_rom.clone(),
+                mirroring,
+                self.is_chr_ram,
+            ))),
+            28 => Rc::new(RefCell::new(Mapper28::new(self.prg_rom.clone()))),
+            66 => Rc::new(RefCell::new(Mapper66::new(
+                self.prg_rom.clone(),
+                self.chr_rom.clone(),
+                mirroring,
+                self.is_chr_ram,
+            ))),
+            69 => Rc::new(RefCell::new(Mapper69::new(
+                self.prg_rom.clone(),
+                self.chr_rom.clone(),
+                mirroring,
+                self.is_chr_ram,
             ))),
-            _ => panic!("Unsupported mapper selected {}", self.mapper_id)
+            105 => Rc::new(RefCell::new(Mapper105::new(self.prg_rom.clone(), self.dip_switches))),
+            184 => Rc::new(RefCell::new(Mapper184::new(self.prg_rom.clone(), self.chr_rom.clone(), mirroring))),
+            185 => Rc::new(RefCell::new(Mapper185::new(self.prg_rom.clone(), self.chr_rom.clone(), mirroring))),
+            _ => return Err(mapping::unsupported_message(self.mapper_id)),
         };
-        mapper
+        Ok(mapper)
     }
 }
 
@@ -243,4 +327,63 @@ pub mod test {
             Result::Err(str) => assert_eq!(str, "NES2.0 format is not supported"),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_truncated_rom_is_rejected() {
+        let _test_rom = _create_rom(_TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x00, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE], // header says 2 banks
+            chr_rom: vec![],
+        });
+        match Rom::new(&_test_rom) {
+            Ok(_) => panic!("should not load a truncated rom"),
+            Err(e) => assert!(e.starts_with("File is truncated")),
+        }
+        assert!(Rom::new(&vec![0x4E, 0x45]).is_err());
+    }
+
+    // MMC1 rom whose 16 KB PRG banks are each filled with their own number
+    fn _mmc1_rom(prg_banks: u8) -> Rom {
+        let _test_rom = _create_rom(_TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, prg_banks, 0x00, 0x10, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: (0..prg_banks).flat_map(|bank| vec![bank; PRG_ROM_PAGE_SIZE]).collect(),
+            chr_rom: vec![],
+        });
+        Rom::new(&_test_rom).unwrap()
+    }
+
+    fn _mmc1_write(mapper: &Rc<RefCell<dyn Mapper>>, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mapper.borrow_mut().cpu_write(addr, (value >> bit) & 1);
+        }
+    }
+
+    #[test]
+    fn test_odd_size_prg_wraps() {
+        // 48 KB: three banks, the last one fixed at $C000
+        let mapper = _mmc1_rom(3).generate_mapper().unwrap();
+        assert_eq!(mapper.borrow().cpu_read(0xC000), 2);
+
+        _mmc1_write(&mapper, 0xE000, 5); // past the end, wraps to bank 1
+        assert_eq!(mapper.borrow().cpu_read(0x8000), 1);
+        _mmc1_write(&mapper, 0xE000, 15);
+        assert_eq!(mapper.borrow().cpu_read(0x8000), 0);
+    }
+
+    #[test]
+    fn test_512k_prg_uses_outer_bank() {
+        let mapper = _mmc1_rom(32).generate_mapper().unwrap();
+        assert_eq!(mapper.borrow().cpu_read(0xC000), 15);
+
+        _mmc1_write(&mapper, 0xA000, 0x10); // upper 256 KB
+        assert_eq!(mapper.borrow().cpu_read(0xC000), 31);
+        _mmc1_write(&mapper, 0xE000, 2);
+        assert_eq!(mapper.borrow().cpu_read(0x8000), 18);
+    }
+}
@@ -0,0 +1,22 @@
+use crate::rom::Mirroring;
+
+// Small built-in database of known good dumps, keyed by the CRC32 of the rom data
+// without its 16 byte iNES header (the same key No-Intro and NesCartDB use).
+// Only holds what the emulator needs to second guess a header.
+pub struct RomInfo {
+    pub crc: u32,
+    pub name: &'static str,
+    pub mapper_id: u8,
+    // None when the mapper drives mirroring itself and the header bit is ignored
+    pub mirroring: Option<Mirroring>,
+}
+
+pub static ROM_DATABASE: [RomInfo; 3] = [
+    RomInfo { crc: 0x3337EC46, name: "Super Mario Bros. (World)", mapper_id: 0, mirroring: Some(Mirroring::VERTICAL) },
+    RomInfo { crc: 0x3FE272FB, name: "Legend of Zelda, The (USA)", mapper_id: 1, mirroring: None },
+    RomInfo { crc: 0x1394F57E, name: "Tetris (USA)", mapper_id: 1, mirroring: None },
+];
+
+pub fn lookup(crc: u32) -> Option<&'static RomInfo> {
+    ROM_DATABASE.iter().find(|info| info.crc == crc)
+}
@@ -0,0 +1,176 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// A clock for rom hacks and homebrew that want the time of day (day/night cycles,
+// daily events). Retail carts never had one, so it sits in the disabled APU test
+// range next to the entropy register and is only on the bus when asked for: with
+// -rtc, or per game with a ROMNAME.rtc file next to the rom holding the source.
+//
+// Writing anything to RTC_LATCH copies the current time into the registers, so a
+// rom reading several of them gets one consistent time. Values are plain binary:
+//   $4019 second   $401A minute   $401B hour (0-23)   $401C day (1-31)
+//   $401D month    $401E year - 2000                  $401F weekday (0 = Sunday)
+//
+// The source is either the wall clock ("wall", "wall+120" for minutes from UTC,
+// there's no timezone database to ask) or emulated time: a fixed start given in
+// seconds since 1970 that moves on with the CPU's cycles, so movies and tests see
+// the same time on every run whatever the real clock says.
+
+pub const RTC_LATCH: u16 = 0x4019;
+pub const RTC_LAST_REGISTER: u16 = 0x401F;
+const CPU_CYCLES_PER_SECOND: u64 = 1_789_773;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcSource {
+    Wall { utc_offset_minutes: i64 },
+    Emulated { start: u64 },
+}
+
+impl RtcSource {
+    pub fn parse(value: &str) -> Result<RtcSource, String> {
+        let value = value.trim();
+        if let Some(offset) = value.strip_prefix("wall") {
+            let utc_offset_minutes = match offset {
+                "" => 0,
+                offset => offset
+                    .strip_prefix('+')
+                    .unwrap_or(offset)
+                    .parse()
+                    .map_err(|_| format!("Expected minutes from UTC after \"wall\" but got \"{}\"", offset))?,
+            };
+            return Ok(RtcSource::Wall { utc_offset_minutes });
+        }
+        value
+            .parse()
+            .map(|start| RtcSource::Emulated { start })
+            .map_err(|_| format!("Clock must be wall, wall+MINUTES or seconds since 1970 but got \"{}\"", value))
+    }
+}
+
+// ROMNAME.rtc next to the rom, if the game has asked for a clock
+pub fn find_rtc_file(rom_path: &Path) -> Option<PathBuf> {
+    Some(rom_path.with_extension("rtc")).filter(|path| path.is_file())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub weekday: u8, // 0 = Sunday
+}
+
+impl DateTime {
+    pub fn from_unix(seconds: i64) -> DateTime {
+        let days = seconds.div_euclid(86400);
+        let of_day = seconds.rem_euclid(86400);
+
+        // Days to a civil date, from Howard Hinnant's date algorithms
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let day_of_era = z.rem_euclid(146097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153; // March is 0
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year,
+            month: month as u8,
+            day: day as u8,
+            hour: (of_day / 3600) as u8,
+            minute: (of_day / 60 % 60) as u8,
+            second: (of_day % 60) as u8,
+            weekday: (days + 4).rem_euclid(7) as u8, // 1970-01-01 was a Thursday
+        }
+    }
+}
+
+pub struct RtcDevice {
+    source: RtcSource,
+    latched: [u8; 7], // $4019-$401F
+}
+
+impl RtcDevice {
+    pub fn new(source: RtcSource) -> Self {
+        RtcDevice { source, latched: [0; 7] }
+    }
+
+    pub fn source(&self) -> RtcSource {
+        self.source
+    }
+
+    // The time now, in seconds since 1970, `cpu_cycles` after power on
+    pub fn now(&self, cpu_cycles: u64) -> i64 {
+        match self.source {
+            RtcSource::Wall { utc_offset_minutes } => {
+                let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+                since_epoch as i64 + utc_offset_minutes * 60
+            }
+            RtcSource::Emulated { start } => (start + cpu_cycles / CPU_CYCLES_PER_SECOND) as i64,
+        }
+    }
+
+    // Called upon RTC_LATCH writes
+    pub fn latch(&mut self, cpu_cycles: u64) {
+        let time = DateTime::from_unix(self.now(cpu_cycles));
+        self.latched = [
+            time.second,
+            time.minute,
+            time.hour,
+            time.day,
+            time.month,
+            (time.year - 2000).clamp(0, 255) as u8,
+            time.weekday,
+        ];
+    }
+
+    // Called upon reads of $4019-$401F
+    pub fn read(&self, addr: u16) -> u8 {
+        self.latched[(addr - RTC_LATCH) as usize]
+    }
+}
+
+impl Snapshot for RtcDevice {
+    // The latched registers. Emulated time moves on from the CPU's saved cycles;
+    // the wall clock is whatever time it is when the state is loaded.
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.latched);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.latched)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_emulated_time_is_deterministic() {
+        // 2024-02-29 23:59:59, a Thursday
+        let mut rtc = RtcDevice::new(RtcSource::parse("1709251199").unwrap());
+        rtc.latch(0);
+        let registers: Vec<u8> = (RTC_LATCH..=RTC_LAST_REGISTER).map(|addr| rtc.read(addr)).collect();
+        assert_eq!(registers, vec![59, 59, 23, 29, 2, 24, 4]);
+
+        // A second of CPU cycles later it's March
+        rtc.latch(CPU_CYCLES_PER_SECOND);
+        assert_eq!(rtc.read(0x401C), 1);
+        assert_eq!(rtc.read(0x401D), 3);
+        assert_eq!(rtc.read(0x401F), 5);
+
+        assert_eq!(DateTime::from_unix(0).year, 1970);
+        assert_eq!(RtcSource::parse("wall-300").unwrap(), RtcSource::Wall { utc_offset_minutes: -300 });
+        assert_eq!(RtcSource::parse("wall+60").unwrap(), RtcSource::Wall { utc_offset_minutes: 60 });
+        assert!(RtcSource::parse("noon").is_err());
+    }
+}
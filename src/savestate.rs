@@ -0,0 +1,195 @@
+use std::path::{Path, PathBuf};
+
+use crate::cpu::CPU;
+
+// Save state files. Each rom gets 10 numbered slots in the states directory, named
+// by the rom's CRC32 so renaming or moving the rom keeps its states:
+//
+//   states/1A2B3C4D.0.state ... states/1A2B3C4D.9.state
+//   states/1A2B3C4D.last        the slot saved or loaded last, picked at start
+//
+// F3/F4 pick the slot, F5 saves to it and F6 loads it (the format is in state.rs).
+// The slots always belong to the cartridge that's in, so with a swapped cartridge
+// F5 and F6 use that cartridge's states and leave the first one's alone.
+// A state is written to a temporary file first and then renamed over the old one,
+// so quitting or crashing halfway through never leaves a broken state behind.
+// Loading checks the state before touching the console, see CPU::load_state.
+pub const SLOT_COUNT: u8 = 10;
+pub const STATE_DIR: &str = "states";
+
+pub struct StateSlots {
+    dir: PathBuf,
+    crc: u32,
+    selected: u8,
+    last_used: Option<u8>,
+}
+
+impl StateSlots {
+    pub fn new(dir: &Path, crc: u32) -> Self {
+        let mut slots = StateSlots { dir: dir.to_path_buf(), crc, selected: 0, last_used: None };
+        slots.last_used = slots.read_last_used();
+        slots.selected = slots.last_used.unwrap_or(0);
+        slots
+    }
+
+    fn read_last_used(&self) -> Option<u8> {
+        std::fs::read_to_string(self.last_used_path())
+            .ok()
+            .and_then(|text| text.trim().parse().ok())
+            .filter(|&slot| slot < SLOT_COUNT)
+    }
+
+    // Switches to another cartridge's states, keeping the selected slot number
+    fn use_cartridge(&mut self, crc: u32) {
+        if crc != self.crc {
+            self.crc = crc;
+            self.last_used = self.read_last_used();
+        }
+    }
+
+    pub fn path(&self, slot: u8) -> PathBuf {
+        self.dir.join(format!("{:08X}.{}.state", self.crc, slot))
+    }
+
+    fn last_used_path(&self) -> PathBuf {
+        self.dir.join(format!("{:08X}.last", self.crc))
+    }
+
+    pub fn selected(&self) -> u8 {
+        self.selected
+    }
+
+    pub fn last_used(&self) -> Option<u8> {
+        self.last_used
+    }
+
+    pub fn select(&mut self, slot: u8) {
+        self.selected = slot % SLOT_COUNT;
+    }
+
+    pub fn select_next(&mut self) -> u8 {
+        self.selected = (self.selected + 1) % SLOT_COUNT;
+        self.selected
+    }
+
+    pub fn select_previous(&mut self) -> u8 {
+        self.selected = (self.selected + SLOT_COUNT - 1) % SLOT_COUNT;
+        self.selected
+    }
+
+    pub fn is_empty(&self, slot: u8) -> bool {
+        !self.path(slot).exists()
+    }
+
+    // Saves to the selected slot and returns it
+    pub fn save(&mut self, cpu: &CPU) -> Result<u8, String> {
+        self.use_cartridge(cpu.bus.cart_crc());
+        let slot = self.selected;
+        std::fs::create_dir_all(&self.dir).map_err(|e| format!("Could not create {}: {}", self.dir.display(), e))?;
+        save(cpu, &self.path(slot))?;
+        self.remember(slot)?;
+        Ok(slot)
+    }
+
+    // Ok(false) when nothing was saved to the slot
+    pub fn load(&mut self, cpu: &mut CPU, slot: u8) -> Result<bool, String> {
+        if slot >= SLOT_COUNT {
+            return Err(format!("State slots go from 0 to {} but got {}", SLOT_COUNT - 1, slot));
+        }
+        self.use_cartridge(cpu.bus.cart_crc());
+        if self.is_empty(slot) {
+            return Ok(false);
+        }
+        load(cpu, &self.path(slot))?;
+        self.selected = slot;
+        self.remember(slot)?;
+        Ok(true)
+    }
+
+    fn remember(&mut self, slot: u8) -> Result<(), String> {
+        self.last_used = Some(slot);
+        let path = self.last_used_path();
+        std::fs::write(&path, format!("{}\n", slot)).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+}
+
+pub fn save(cpu: &CPU, path: &Path) -> Result<(), String> {
+    let partial = path.with_extension("state.tmp");
+    std::fs::write(&partial, cpu.save_state()).map_err(|e| format!("Could not write state {}: {}", partial.display(), e))?;
+    std::fs::rename(&partial, path).map_err(|e| format!("Could not write state {}: {}", path.display(), e))
+}
+
+pub fn load(cpu: &mut CPU, path: &Path) -> Result<(), String> {
+    let state = std::fs::read(path).map_err(|e| format!("Could not read state {}: {}", path.display(), e))?;
+    cpu.load_state(&state)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::rom::Rom;
+
+    #[test]
+    fn test_slots_round_trip_and_remember_the_last_one() {
+        let dir = std::env::temp_dir().join(format!("savestate_test_{}", std::process::id())).join("states");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // INC $10 / JMP $8000
+        let mut nes = Nes::new(Rom::_new_test(vec![0xE6, 0x10, 0x4C, 0x00, 0x80]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.pc = 0x8000;
+        let crc = nes.cpu.bus.cart_crc();
+        let mut slots = StateSlots::new(&dir, crc);
+        assert_eq!((slots.selected(), slots.last_used()), (0, None));
+        assert!(slots.path(3).ends_with(format!("{:08X}.3.state", crc)));
+        assert_eq!(slots.select_previous(), 9);
+        assert_eq!(slots.select_next(), 0);
+        assert!(!slots.load(&mut nes.cpu, 0).unwrap());
+
+        slots.select_next();
+        slots.select_next();
+        nes.run_frame();
+        assert_eq!(slots.save(&nes.cpu).unwrap(), 2);
+        let saved = nes.cpu.save_state();
+        nes.run_frame();
+        assert!(!slots.path(2).with_extension("state.tmp").exists());
+
+        // A new session starts on the last slot used
+        let mut slots = StateSlots::new(&dir, crc);
+        assert_eq!((slots.selected(), slots.last_used()), (2, Some(2)));
+        assert!(slots.load(&mut nes.cpu, 2).unwrap());
+        assert_eq!(nes.cpu.save_state(), saved);
+        assert!(slots.load(&mut nes.cpu, SLOT_COUNT).is_err());
+        assert_eq!(StateSlots::new(&dir, 0x99).last_used(), None); // another rom's slots
+
+        std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_slots_follow_a_swapped_cartridge() {
+        let dir = std::env::temp_dir().join(format!("savestate_swap_test_{}", std::process::id())).join("states");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut nes = Nes::new(Rom::_new_test(vec![0xE6, 0x10, 0x4C, 0x00, 0x80]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.pc = 0x8000;
+        let first_crc = nes.cpu.bus.cart_crc();
+        let mut slots = StateSlots::new(&dir, first_crc);
+        slots.save(&nes.cpu).unwrap();
+        let first_state = std::fs::read(slots.path(0)).unwrap();
+
+        // The other cartridge saves to and loads from its own slot 0
+        let mut other = Rom::_new_test(vec![0xA9, 0xFF]).unwrap();
+        let other_crc = other.crc;
+        nes.swap_cartridge(&mut other).unwrap();
+        assert!(!slots.load(&mut nes.cpu, 0).unwrap());
+        slots.save(&nes.cpu).unwrap();
+        assert!(slots.path(0).ends_with(format!("{:08X}.0.state", other_crc)));
+        assert_eq!(std::fs::read(dir.join(format!("{:08X}.0.state", first_crc))).unwrap(), first_state);
+
+        // Back to the first cartridge, whose state still loads
+        nes.swap_cartridge(&mut other).unwrap();
+        assert!(slots.load(&mut nes.cpu, 0).unwrap());
+
+        std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// Central event queue keyed on the NTSC master clock (~21.477 MHz).
+// Every component's clock is a divider of it, so events from the CPU, PPU,
+// APU and mapper IRQ counters can all be ordered on one timeline:
+//   1 CPU cycle = 12 master cycles, 1 PPU dot = 4 master cycles
+// Components schedule an event for the master cycle it should fire on and
+// the bus dispatches it once the CPU has run far enough.
+
+pub const MASTER_CYCLES_PER_CPU_CYCLE: u64 = 12;
+pub const MASTER_CYCLES_PER_PPU_DOT: u64 = 4;
+pub const PPU_DOTS_PER_SCANLINE: u64 = 341;
+pub const MASTER_CYCLES_PER_SCANLINE: u64 = PPU_DOTS_PER_SCANLINE * MASTER_CYCLES_PER_PPU_DOT;
+pub const SCANLINES_PER_FRAME: u64 = 262;
+
+// Everything that can be scheduled. New timed features (mapper IRQs, APU
+// frame counter, DMC fetches) add a variant here and a match arm in Bus::advance;
+// Bus::tick stops its batches on the event's cycle so nothing needs to hand
+// roll offsets inside an instruction's cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EventKind {
+    PpuScanlineEnd,
+    ApuFrameIrq, // only splits the batch so the IRQ is seen on its own cycle
+    DmcFetch, // the DMC's sample buffer empties, the bus fetches its next byte
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Event {
+    pub at: u64, // master cycle the event fires on (first field so the heap orders by it)
+    pub kind: EventKind,
+}
+
+pub struct Scheduler {
+    now: u64,
+    events: BinaryHeap<Reverse<Event>>,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler {
+            now: 0,
+            events: BinaryHeap::new(),
+        }
+    }
+
+    // Current master clock cycle
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn schedule(&mut self, at: u64, kind: EventKind) {
+        self.events.push(Reverse(Event { at, kind }));
+    }
+
+    // Schedule an event relative to the current master cycle
+    pub fn schedule_in(&mut self, delay: u64, kind: EventKind) {
+        self.schedule(self.now + delay, kind);
+    }
+
+    // Drops every pending event of the given kind (e.g. a mapper IRQ that got disabled)
+    pub fn cancel(&mut self, kind: EventKind) {
+        self.events.retain(|event| event.0.kind != kind);
+    }
+
+    // Master cycle of the next pending event of a kind, if any
+    pub fn next_of(&self, kind: EventKind) -> Option<u64> {
+        self.events.iter().filter(|event| event.0.kind == kind).map(|event| event.0.at).min()
+    }
+
+    // Master cycle of the earliest pending event, if any
+    pub fn next_at(&self) -> Option<u64> {
+        self.events.peek().map(|event| event.0.at)
+    }
+
+    pub fn advance(&mut self, master_cycles: u64) {
+        self.now += master_cycles;
+    }
+
+    // Pops the earliest event that is due at the current master cycle.
+    // Call in a loop until it returns None.
+    pub fn pop_due(&mut self) -> Option<Event> {
+        match self.events.peek() {
+            Some(Reverse(event)) if event.at <= self.now => self.events.pop().map(|event| event.0),
+            _ => None,
+        }
+    }
+}
+
+impl Snapshot for Scheduler {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.u64(self.now);
+        out.u32(self.events.len() as u32);
+        for Reverse(event) in self.events.iter() {
+            out.u64(event.at);
+            out.u8(event.kind as u8);
+        }
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.now = input.u64()?;
+        self.events.clear();
+        for _ in 0..input.u32()? {
+            let at = input.u64()?;
+            let kind = match input.u8()? {
+                0 => EventKind::PpuScanlineEnd,
+                1 => EventKind::ApuFrameIrq,
+                2 => EventKind::DmcFetch,
+                kind => return Err(format!("Save state has an unknown event {}", kind)),
+            };
+            self.schedule(at, kind);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_events_fire_in_order_when_due() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(30, EventKind::PpuScanlineEnd);
+        scheduler.schedule(10, EventKind::PpuScanlineEnd);
+
+        scheduler.advance(5);
+        assert_eq!(scheduler.pop_due(), None);
+
+        scheduler.advance(30);
+        assert_eq!(scheduler.pop_due().unwrap().at, 10);
+        assert_eq!(scheduler.pop_due().unwrap().at, 30);
+        assert_eq!(scheduler.pop_due(), None);
+    }
+
+    #[test]
+    fn test_cancel_and_schedule_in() {
+        let mut scheduler = Scheduler::new();
+        scheduler.advance(100);
+        scheduler.schedule_in(MASTER_CYCLES_PER_SCANLINE, EventKind::PpuScanlineEnd);
+        assert_eq!(scheduler.next_of(EventKind::PpuScanlineEnd), Some(100 + MASTER_CYCLES_PER_SCANLINE));
+
+        scheduler.cancel(EventKind::PpuScanlineEnd);
+        assert_eq!(scheduler.next_of(EventKind::PpuScanlineEnd), None);
+        assert_eq!(scheduler.next_at(), None);
+    }
+}
@@ -0,0 +1,131 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::frame::Frame;
+use crate::joypad::Joypad;
+use crate::nes::{Granularity, Nes};
+use crate::ppu::NesPPU;
+use crate::render;
+use crate::rom::Rom;
+use crate::script_input::ScriptInput;
+
+// Headless screenshots, e.g. for thumbnails of a rom library. The game runs without a
+// window (optionally driven by an input script) and the requested frame is drawn.
+//
+// For graphics research a capture can also be saved raw: the PPU's output index of
+// every pixel (see NesPPU::output_index) as little endian u16s row by row, and a
+// JSON sidecar with what decided the picture, so it can be reproduced exactly:
+//
+//   {"rom_crc":"1A2B3C4D","frame":300,"scroll_x":0,"scroll_y":0,"ctrl":144,"mask":30}
+
+// The PPU state a capture was drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureInfo {
+    pub rom_crc: u32,
+    pub frame: u64,
+    pub scroll: (u8, u8), // $2005
+    pub ctrl: u8, // $2000
+    pub mask: u8, // $2001
+}
+
+impl CaptureInfo {
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"rom_crc\":\"{:08X}\",\"frame\":{},\"scroll_x\":{},\"scroll_y\":{},\"ctrl\":{},\"mask\":{}}}\n",
+            self.rom_crc, self.frame, self.scroll.0, self.scroll.1, self.ctrl, self.mask
+        )
+    }
+}
+
+// Runs the rom until `frame_number` frames have been drawn and returns that frame
+pub fn capture_frame(rom: Rom, frame_number: u64, input: Option<ScriptInput>) -> Result<Frame, String> {
+    Ok(capture(rom, frame_number, input)?.0)
+}
+
+// capture_frame, with the palette indices kept in the frame and the PPU state it
+// was drawn with
+pub fn capture(rom: Rom, frame_number: u64, mut input: Option<ScriptInput>) -> Result<(Frame, CaptureInfo), String> {
+    let rom_crc = rom.crc;
+    if let Some(input) = input.as_mut() {
+        input.read_to_end();
+    }
+
+    // Resets and power cycles from the script happen as their frame ends
+    let console_event = Rc::new(Cell::new(None));
+    let due_event = console_event.clone();
+    let mut nes = Nes::new(rom, move |ppu: &NesPPU, joypad1: &mut Joypad| {
+        if let Some(input) = input.as_mut()
+            && let Some(event) = input.apply(ppu.timing().frame, joypad1)
+        {
+            due_event.set(Some(event));
+        }
+    })?;
+    nes.add_callback(Granularity::Frame, move |cpu| {
+        if let Some(event) = console_event.take() {
+            event.perform(cpu);
+        }
+    });
+
+    // The frame counter ticks over at the end of vblank, so the wanted frame is
+    // complete once its own vblank begins
+    loop {
+        let timing = nes.cpu.bus.ppu.timing();
+        if timing.frame > frame_number || (timing.frame == frame_number && timing.scanline >= 241) {
+            break;
+        }
+        nes.step();
+    }
+
+    let ppu = &nes.cpu.bus.ppu;
+    let mut frame = Frame::new();
+    frame.keep_indices();
+    render::render(ppu, &mut frame);
+    let info = CaptureInfo {
+        rom_crc,
+        frame: frame_number,
+        scroll: ppu.scroll.read(),
+        ctrl: ppu.ctrl.bits(),
+        mask: ppu.peek_mask(),
+    };
+    Ok((frame, info))
+}
+
+// The raw capture: each pixel's output index, u16 little endian, row by row
+pub fn indices_bytes(frame: &Frame) -> Vec<u8> {
+    frame.indices().unwrap_or_default().iter().flat_map(|index| index.to_le_bytes()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::palette::SYSTEM_PALLETE;
+
+    #[test]
+    fn test_capture_shows_backdrop_written_by_rom() {
+        // Sets the backdrop color to $21 then spins
+        let mut program = vec![
+            0xA9, 0x3F, 0x8D, 0x06, 0x20, // LDA #$3F / STA $2006
+            0xA9, 0x00, 0x8D, 0x06, 0x20, // LDA #$00 / STA $2006
+            0xA9, 0x21, 0x8D, 0x07, 0x20, // LDA #$21 / STA $2007
+            0x4C, 0x0F, 0x80,             // JMP to itself
+        ];
+        // Reset vector at $8000 (the 16kB bank is mirrored up to $FFFC)
+        program.resize(0x4000, 0xEA);
+        program[0x3FFC] = 0x00;
+        program[0x3FFD] = 0x80;
+
+        let frame = capture_frame(Rom::_new_test(program.clone()).unwrap(), 2, None).unwrap();
+        let color = SYSTEM_PALLETE[0x21];
+        assert_eq!(&frame.data[0..3], &[color.0, color.1, color.2]);
+
+        // The raw capture has the index itself, and the sidecar the registers
+        let rom = Rom::_new_test(program).unwrap();
+        let rom_crc = rom.crc;
+        let (frame, info) = capture(rom, 2, None).unwrap();
+        let raw = indices_bytes(&frame);
+        assert_eq!(raw.len(), 256 * 240 * 2);
+        assert_eq!(&raw[0..2], &[0x21, 0x00]);
+        assert_eq!(info, CaptureInfo { rom_crc, frame: 2, scroll: (0, 0), ctrl: 0, mask: 0 });
+        assert!(info.to_json().starts_with(&format!("{{\"rom_crc\":\"{:08X}\",\"frame\":2,", rom_crc)));
+    }
+}
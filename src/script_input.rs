@@ -0,0 +1,252 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::cpu::CPU;
+use crate::joypad::{Joypad, JoypadButton};
+use crate::startup::StartupState;
+
+// Controller input driven by text commands, one per line, read from stdin or a
+// file / named pipe. Lets external scripts and CI jobs play the game:
+//
+//   frame 120 press start
+//   frame 125 release start
+//   frame 900 reset
+//   frame 1500 power
+//   # comments and blank lines are ignored
+//
+// Commands are applied at the start of the given frame. Commands for frames that
+// already passed are applied straight away.
+//
+// reset presses the console's reset button and power switches it off and on, for
+// movies whose route depends on one. Both happen as the given frame ends (when
+// the frame counter ticks over at the end of vblank) so they land on the same
+// cycle on every run. Frame numbers keep counting through either. What's kept:
+//   reset  RAM, VRAM, cartridge RAM and mapper registers; A, X and Y
+//   power  battery backed cartridge RAM only, everything else starts from scratch
+//
+// A movie recorded with random startup memory starts with the state it was
+// recorded with, as printed by the frontend:
+//
+//   startup random:1234
+//
+// That's a power cycle into that state as frame 0 ends (see startup.rs), so the
+// rest of the script plays back on the same memory whatever the config says.
+
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptCommand {
+    pub frame: u64,
+    pub action: ScriptAction,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ScriptAction {
+    Button { button: JoypadButton, pressed: bool },
+    Console(ConsoleEvent),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleEvent {
+    Reset,
+    PowerCycle,
+    Startup(StartupState),
+}
+
+impl ConsoleEvent {
+    // Call as the frame the command was for ends
+    pub fn perform(self, cpu: &mut CPU) {
+        match self {
+            ConsoleEvent::Reset => cpu.reset_button(),
+            ConsoleEvent::PowerCycle => cpu.power_cycle(),
+            ConsoleEvent::Startup(state) => {
+                cpu.bus.set_startup_state(state);
+                cpu.power_cycle();
+            }
+        }
+    }
+}
+
+// Parses one line. Ok(None) for blank lines and comments.
+pub fn parse_command(line: &str) -> Result<Option<ScriptCommand>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if let ["startup", state] = words[..] {
+        let state = StartupState::parse(state)?;
+        return Ok(Some(ScriptCommand { frame: 0, action: ScriptAction::Console(ConsoleEvent::Startup(state)) }));
+    }
+    if !(3..=4).contains(&words.len()) || words[0] != "frame" {
+        return Err(format!("Expected \"frame N press|release BUTTON\" or \"frame N reset|power\" but got \"{}\"", line));
+    }
+
+    let frame: u64 = words[1].parse().map_err(|_| format!("Bad frame number \"{}\"", words[1]))?;
+    let action = match (words[2], words.get(3)) {
+        ("press", Some(label)) | ("release", Some(label)) => ScriptAction::Button {
+            button: JoypadButton::from_label(label).ok_or(format!("Unknown button \"{}\"", label))?,
+            pressed: words[2] == "press",
+        },
+        ("reset", None) => ScriptAction::Console(ConsoleEvent::Reset),
+        ("power", None) => ScriptAction::Console(ConsoleEvent::PowerCycle),
+        (other, _) => return Err(format!("Unknown action \"{}\" in \"{}\"", other, line)),
+    };
+
+    Ok(Some(ScriptCommand { frame, action }))
+}
+
+pub struct ScriptInput {
+    receiver: Receiver<ScriptCommand>,
+    pending: Vec<ScriptCommand>,
+}
+
+impl ScriptInput {
+    // "-" reads from stdin, anything else is opened as a file or named pipe
+    pub fn open(path: &str) -> Result<ScriptInput, String> {
+        if path == "-" {
+            return Ok(ScriptInput::from_reader(BufReader::new(std::io::stdin())));
+        }
+        let file = File::open(path).map_err(|e| format!("Could not open input script {}: {}", path, e))?;
+        Ok(ScriptInput::from_reader(BufReader::new(file)))
+    }
+
+    // Lines are read on their own thread so a script that hasn't written
+    // anything yet never stalls the emulator
+    pub fn from_reader<R: BufRead + Send + 'static>(reader: R) -> ScriptInput {
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let Ok(line) = line else { break };
+                match parse_command(&line) {
+                    Ok(Some(command)) => {
+                        if sender.send(command).is_err() {
+                            break; // emulator closed
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => eprintln!("Input script: {}", e),
+                }
+            }
+        });
+
+        ScriptInput::from_channel(receiver)
+    }
+
+    // Commands sent by something else in the process (e.g. the remote control server)
+    pub fn from_channel(receiver: Receiver<ScriptCommand>) -> ScriptInput {
+        ScriptInput { receiver, pending: vec![] }
+    }
+
+    // Blocks until the script's writer is done (end of file / pipe closed) and queues
+    // every command. Headless runs use this so results don't depend on thread timing.
+    pub fn read_to_end(&mut self) {
+        self.pending.extend(self.receiver.iter());
+    }
+
+    // Call once per frame before the game reads the controller. Returns the reset or
+    // power cycle due, if any, for the caller to perform as the frame ends (the
+    // last one wins when a frame has several).
+    pub fn apply(&mut self, frame: u64, joypad: &mut Joypad) -> Option<ConsoleEvent> {
+        self.pending.extend(self.receiver.try_iter());
+
+        // Keep the order commands were written in for the same frame
+        self.pending.sort_by_key(|command| command.frame);
+        let due = self.pending.partition_point(|command| command.frame <= frame);
+        let mut console_event = None;
+        for command in self.pending.drain(..due) {
+            match command.action {
+                ScriptAction::Button { button, pressed } => joypad.set_button_pressed_status(button, pressed),
+                ScriptAction::Console(event) => console_event = Some(event),
+            }
+        }
+        console_event
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::rom::Rom;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_parse_command() {
+        let command = parse_command("frame 120 press A").unwrap().unwrap();
+        assert_eq!(command.frame, 120);
+        let ScriptAction::Button { button, pressed } = command.action else { panic!("not a button") };
+        assert_eq!(button.bits(), JoypadButton::BUTTON_A.bits());
+        assert!(pressed);
+        assert!(matches!(parse_command("frame 9 power").unwrap().unwrap().action, ScriptAction::Console(ConsoleEvent::PowerCycle)));
+        let startup = parse_command("startup random:77").unwrap().unwrap();
+        assert_eq!(startup.frame, 0);
+        assert!(matches!(startup.action, ScriptAction::Console(ConsoleEvent::Startup(StartupState::Random(77)))));
+
+        assert!(parse_command("# nothing").unwrap().is_none());
+        assert!(parse_command("frame x press a").is_err());
+        assert!(parse_command("frame 1 hold a").is_err());
+        assert!(parse_command("frame 1 reset a").is_err());
+        assert!(parse_command("frame 1 press").is_err());
+    }
+
+    // Reads all 8 buttons the way a game does
+    fn buttons(joypad: &mut Joypad) -> u8 {
+        joypad.write(1);
+        joypad.write(0);
+        (0..8).fold(0, |bits, i| bits | (joypad.read() << i))
+    }
+
+    #[test]
+    fn test_commands_apply_on_their_frame() {
+        let script = "frame 2 press start\nframe 4 release start\n";
+        let mut input = ScriptInput::from_reader(Cursor::new(script));
+        let mut joypad = Joypad::new();
+
+        // Wait for the reader thread to parse both lines
+        while input.pending.len() < 2 {
+            input.pending.extend(input.receiver.recv());
+        }
+
+        input.apply(1, &mut joypad);
+        assert_eq!(buttons(&mut joypad), 0);
+        input.apply(2, &mut joypad);
+        assert_eq!(buttons(&mut joypad), JoypadButton::START.bits());
+        input.apply(4, &mut joypad);
+        assert_eq!(buttons(&mut joypad), 0);
+    }
+
+    #[test]
+    fn test_reset_keeps_memory_and_power_cycle_clears_it() {
+        let bus = Bus::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+        cpu.mem_write(0x0300, 0x42);
+        cpu.bus.ppu.oam_data[0] = 0x17;
+        cpu.reg_y = 5;
+        cpu.status = 0;
+
+        ConsoleEvent::Reset.perform(&mut cpu);
+        assert_eq!(cpu.mem_peek(0x0300), 0x42);
+        assert_eq!(cpu.bus.ppu.oam_data[0], 0x17);
+        assert_eq!(cpu.reg_y, 5);
+        assert_eq!(cpu.sp, 0xFC);
+        assert_eq!(cpu.status & 0b0000_0100, 0b0000_0100);
+
+        let frame = cpu.bus.ppu.timing().frame;
+        ConsoleEvent::PowerCycle.perform(&mut cpu);
+        assert_eq!(cpu.mem_peek(0x0300), 0);
+        assert_eq!(cpu.bus.ppu.oam_data[0], 0);
+        assert_eq!((cpu.reg_y, cpu.sp), (0, 0xFF));
+        assert_eq!(cpu.bus.ppu.timing().frame, frame);
+
+        // Powering on into a seeded state fills memory the same way each time
+        ConsoleEvent::Startup(StartupState::Random(5)).perform(&mut cpu);
+        let ram: Vec<u8> = (0..0x0800).map(|addr| cpu.mem_peek(addr)).collect();
+        ConsoleEvent::PowerCycle.perform(&mut cpu);
+        assert!((0..0x0800).all(|addr| cpu.mem_peek(addr) == ram[addr as usize]));
+        assert!(ram.iter().any(|&byte| byte != 0));
+    }
+}
@@ -0,0 +1,219 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::frame::{Frame, PixelFormat};
+
+// Spectator mode: streams the picture to anyone who connects, so a friend can
+// watch a game live. Viewers only receive, nothing they send is ever read, so
+// they can't touch the game. See examples/spectate.rs for a viewer.
+//
+// Each frame is one message:
+//
+//   "NESF"               magic
+//   kind       u8        0 = key frame, 1 = delta from the previous frame
+//   width      u16 LE
+//   height     u16 LE
+//   format     u8        0 = RGB, 1 = RGBA
+//   length     u32 LE    bytes of payload
+//   payload              (count, byte) pairs, count 1-255
+//
+// The pixels are XORed with the previous frame (all zero for a key frame) and
+// run length encoded, so the parts of the screen that didn't change cost almost
+// nothing. A viewer gets a key frame when it joins and deltas after that.
+//
+// Encoding and sending run on their own thread. When it falls behind (a slow
+// viewer) frames are dropped rather than slowing the game down, and a viewer
+// that can't keep up at all is disconnected.
+const MAGIC: &[u8; 4] = b"NESF";
+const HEADER_SIZE: usize = 14;
+const KEY_FRAME: u8 = 0;
+const DELTA_FRAME: u8 = 1;
+const WRITE_TIMEOUT: Duration = Duration::from_millis(250);
+const QUEUED_FRAMES: usize = 2;
+
+struct Viewer {
+    stream: TcpStream,
+    needs_key_frame: bool,
+}
+
+pub struct SpectatorServer {
+    frames: SyncSender<Frame>,
+}
+
+impl SpectatorServer {
+    // Starts listening for viewers, e.g. on "0.0.0.0:9000"
+    pub fn start(addr: &str) -> Result<SpectatorServer, String> {
+        let listener = TcpListener::bind(addr).map_err(|e| format!("Could not start spectator server on {}: {}", addr, e))?;
+        let viewers: Arc<Mutex<Vec<Viewer>>> = Arc::new(Mutex::new(vec![]));
+
+        let accept_viewers = viewers.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+                let _ = stream.set_nodelay(true);
+                if let Ok(peer) = stream.peer_addr() {
+                    println!("Spectator {} is watching", peer);
+                }
+                accept_viewers.lock().unwrap().push(Viewer { stream, needs_key_frame: true });
+            }
+        });
+
+        let (frames, queue) = mpsc::sync_channel(QUEUED_FRAMES);
+        thread::spawn(move || send_frames(queue, viewers));
+
+        println!("Spectator server listening on {}", addr);
+        Ok(SpectatorServer { frames })
+    }
+
+    // Hands a drawn frame to the sending thread, dropped if it's still busy
+    pub fn publish(&self, frame: &Frame) {
+        let mut copy = Frame::with_size(frame.width(), frame.height(), frame.format());
+        copy.data.copy_from_slice(&frame.data);
+        let _ = self.frames.try_send(copy); // when behind the viewers get a later one
+    }
+}
+
+fn send_frames(queue: Receiver<Frame>, viewers: Arc<Mutex<Vec<Viewer>>>) {
+    let mut previous: Option<Frame> = None;
+    for frame in queue {
+        let mut viewers = viewers.lock().unwrap();
+        if viewers.is_empty() {
+            previous = None;
+            continue;
+        }
+        // A size or format change is a fresh start for everyone
+        let previous_data = previous
+            .as_ref()
+            .filter(|last| (last.width(), last.height(), last.format()) == (frame.width(), frame.height(), frame.format()))
+            .map(|last| last.data.as_slice());
+        let delta = previous_data.map(|last| encode(&frame, Some(last)));
+        let mut key = None;
+
+        viewers.retain_mut(|viewer| {
+            let message = match (&delta, viewer.needs_key_frame) {
+                (Some(delta), false) => delta,
+                _ => key.get_or_insert_with(|| encode(&frame, None)),
+            };
+            viewer.needs_key_frame = false;
+            viewer.stream.write_all(message).is_ok()
+        });
+        previous = Some(frame);
+    }
+}
+
+// One message for `frame`, a delta from `previous` or a key frame without one
+pub fn encode(frame: &Frame, previous: Option<&[u8]>) -> Vec<u8> {
+    let mut payload = vec![];
+    let mut run: Option<(u8, u8)> = None;
+    for (i, &byte) in frame.data.iter().enumerate() {
+        let value = byte ^ previous.map_or(0, |previous| previous[i]);
+        run = match run {
+            Some((count, run_value)) if run_value == value && count < 255 => Some((count + 1, value)),
+            Some((count, run_value)) => {
+                payload.extend([count, run_value]);
+                Some((1, value))
+            }
+            None => Some((1, value)),
+        };
+    }
+    if let Some((count, value)) = run {
+        payload.extend([count, value]);
+    }
+
+    let mut message = Vec::with_capacity(HEADER_SIZE + payload.len());
+    message.extend(MAGIC);
+    message.push(if previous.is_some() { DELTA_FRAME } else { KEY_FRAME });
+    message.extend((frame.width() as u16).to_le_bytes());
+    message.extend((frame.height() as u16).to_le_bytes());
+    message.push(match frame.format() {
+        PixelFormat::Rgb24 => 0,
+        PixelFormat::Rgba8888 => 1,
+    });
+    message.extend((payload.len() as u32).to_le_bytes());
+    message.extend(payload);
+    message
+}
+
+// Reads the next message from a spectator stream into `frame`, replacing it on
+// key frames and size changes and applying deltas to it otherwise
+pub fn read_frame(stream: &mut impl Read, frame: &mut Option<Frame>) -> Result<(), String> {
+    let mut header = [0; HEADER_SIZE];
+    stream.read_exact(&mut header).map_err(stream_error)?;
+    if &header[0..4] != MAGIC {
+        return Err("Not a spectator stream".to_string());
+    }
+    let kind = header[4];
+    let width = u16::from_le_bytes([header[5], header[6]]) as usize;
+    let height = u16::from_le_bytes([header[7], header[8]]) as usize;
+    let format = match header[9] {
+        0 => PixelFormat::Rgb24,
+        1 => PixelFormat::Rgba8888,
+        other => return Err(format!("Unknown pixel format {}", other)),
+    };
+    let length = u32::from_le_bytes([header[10], header[11], header[12], header[13]]) as usize;
+    let mut payload = vec![0; length];
+    stream.read_exact(&mut payload).map_err(stream_error)?;
+
+    let same_shape = frame.as_ref().is_some_and(|frame| (frame.width(), frame.height(), frame.format()) == (width, height, format));
+    if kind == KEY_FRAME || !same_shape {
+        if kind == DELTA_FRAME {
+            return Err("Delta frame without the frame it's based on".to_string());
+        }
+        let mut key = Frame::with_size(width, height, format);
+        key.data.fill(0);
+        *frame = Some(key);
+    }
+    let data = &mut frame.as_mut().unwrap().data;
+
+    let mut i = 0;
+    for pair in payload.chunks_exact(2) {
+        let (count, value) = (pair[0] as usize, pair[1]);
+        if i + count > data.len() {
+            return Err("Frame data runs past the end of the picture".to_string());
+        }
+        data[i..i + count].iter_mut().for_each(|byte| *byte ^= value);
+        i += count;
+    }
+    if i != data.len() {
+        return Err(format!("Frame data covers {} of {} bytes", i, data.len()));
+    }
+    Ok(())
+}
+
+fn stream_error(e: io::Error) -> String {
+    format!("Spectator stream: {}", e)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_key_and_delta_frames_round_trip() {
+        let mut first = Frame::new();
+        first.set_pixel(false, 10, 10, (1, 2, 3));
+        let mut second = Frame::new();
+        second.data.copy_from_slice(&first.data);
+        second.set_pixel(false, 200, 100, (0xFF, 0x80, 0x00));
+
+        let key = encode(&first, None);
+        let delta = encode(&second, Some(&first.data));
+        // A couple of changed pixels on a mostly empty screen is a few hundred bytes
+        assert!(delta.len() < 2000, "{}", delta.len());
+
+        let mut stream: &[u8] = &[key, delta].concat();
+        let mut frame = None;
+        read_frame(&mut stream, &mut frame).unwrap();
+        assert_eq!(frame.as_ref().unwrap().data, first.data);
+        read_frame(&mut stream, &mut frame).unwrap();
+        assert_eq!(frame.as_ref().unwrap().data, second.data);
+
+        // A viewer can't start on a delta
+        let mut stream: &[u8] = &encode(&second, Some(&first.data));
+        assert!(read_frame(&mut stream, &mut None).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use crate::frame::Frame;
+use crate::ppu::{NesPPU, SpriteZeroHit};
+
+// Debug view for sprite zero hit, which status bar splits and a lot of other
+// timing tricks wait on. Marks where the hit happened on screen and logs the
+// stretches of frames where it never happened at all.
+
+const MARK_COLOR: (u8, u8, u8) = (0xFF, 0x00, 0xFF);
+
+fn mark(frame: &mut Frame, x: i32, y: i32) {
+    if (0..256).contains(&x) && (0..240).contains(&y) {
+        frame.set_pixel(false, x as usize, y as usize, MARK_COLOR);
+    }
+}
+
+// Dotted line along the hit's scanline and a box around the pixel
+pub fn draw_marker(frame: &mut Frame, hit: SpriteZeroHit) {
+    let (x, y) = (hit.x as i32, hit.scanline as i32);
+    for line_x in (0..256).step_by(2) {
+        mark(frame, line_x, y);
+    }
+    for d in -2..=2 {
+        mark(frame, x + d, y - 2);
+        mark(frame, x + d, y + 2);
+        mark(frame, x - 2, y + d);
+        mark(frame, x + 2, y + d);
+    }
+}
+
+#[derive(Default)]
+pub struct SpriteZeroMonitor {
+    missing_since: Option<u64>, // first frame of the current run without a hit
+}
+
+impl SpriteZeroMonitor {
+    pub fn new() -> Self {
+        SpriteZeroMonitor { missing_since: None }
+    }
+
+    // Call once per frame after the visible lines are drawn. Returns a line for the
+    // log when a run of frames without a hit starts or ends.
+    pub fn check(&mut self, ppu: &NesPPU) -> Option<String> {
+        let frame = ppu.timing().frame;
+        match (ppu.sprite_zero_hit(), self.missing_since) {
+            (None, None) => {
+                self.missing_since = Some(frame);
+                Some(format!("Sprite zero hit missing from frame {}", frame))
+            }
+            (Some(hit), Some(since)) => {
+                self.missing_since = None;
+                Some(format!(
+                    "Sprite zero hit back on frame {} at ({},{}), missing for {} frames",
+                    frame, hit.x, hit.scanline, frame - since
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ppu::NesPPU;
+
+    // Runs to the start of vblank, where the frame callback would look
+    fn run_to_vblank(ppu: &mut NesPPU) {
+        loop {
+            ppu.tick(341);
+            ppu.end_scanline();
+            if ppu.timing().scanline == 241 {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn test_hit_recorded_and_missing_frames_logged() {
+        let mut ppu = NesPPU::_new_empty_rom();
+        let mut monitor = SpriteZeroMonitor::new();
+        ppu.write_mask(0b0001_1000);
+
+        // Sprite zero below the screen: no hit
+        let mut oam = [0xFF; 256];
+        ppu.oam_dma_write(&oam);
+        run_to_vblank(&mut ppu);
+        assert_eq!(monitor.check(&ppu), Some("Sprite zero hit missing from frame 0".to_string()));
+        run_to_vblank(&mut ppu);
+        assert_eq!(monitor.check(&ppu), None);
+
+        // Sprite zero at (16, 30)
+        oam[0] = 30;
+        oam[3] = 16;
+        ppu.oam_dma_write(&oam);
+        run_to_vblank(&mut ppu);
+        assert_eq!(ppu.sprite_zero_hit(), Some(SpriteZeroHit { x: 16, scanline: 30 }));
+        assert_eq!(monitor.check(&ppu), Some("Sprite zero hit back on frame 2 at (16,30), missing for 2 frames".to_string()));
+
+        let mut frame = Frame::new();
+        draw_marker(&mut frame, ppu.sprite_zero_hit().unwrap());
+        let pixel = (30 * 256 + 16) * 3;
+        assert_eq!(&frame.data[pixel..pixel + 3], &[0xFF, 0x00, 0xFF]);
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt;
+
+use crate::entropy::EntropyDevice;
+
+// What CPU RAM, VRAM and OAM hold when the console is switched on. Real consoles
+// come up with whatever the chips settled on, mostly but not quite random, and a
+// few games only show their bugs (or only work) with some of those values. The
+// config picks one ("startup = zero|pattern|random|random:SEED", or -startup):
+//
+//   zero      all 0, what the emulator always did and what most games expect
+//   pattern   4 bytes of $00 then 4 of $FF over and over, like some consoles
+//   random    random bytes from a seed, the same seed fills memory the same way
+//
+// "random" on its own picks a new seed every launch. The frontend prints it, and
+// an input script can start with "startup random:SEED" so a movie plays back on
+// the memory it was recorded with (see script_input.rs).
+//
+// A power cycle fills memory the same way again, so it stays reproducible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupState {
+    Zeroed,
+    Pattern,
+    Random(u64),
+}
+
+impl StartupState {
+    pub fn parse(value: &str) -> Result<StartupState, String> {
+        match value.split_once(':') {
+            None if value == "zero" => Ok(StartupState::Zeroed),
+            None if value == "pattern" => Ok(StartupState::Pattern),
+            None if value == "random" => Ok(StartupState::Random(EntropyDevice::new(None, None).seed())),
+            Some(("random", seed)) => seed
+                .parse()
+                .map(StartupState::Random)
+                .map_err(|_| format!("Expected a seed after \"random:\" but got \"{}\"", seed)),
+            _ => Err(format!("Startup state must be zero, pattern, random or random:SEED but got \"{}\"", value)),
+        }
+    }
+
+    // Fills RAM, VRAM and OAM in that order
+    pub fn fill(&self, ram: &mut [u8], vram: &mut [u8], oam: &mut [u8]) {
+        match self {
+            StartupState::Zeroed => {
+                for memory in [ram, vram, oam] {
+                    memory.fill(0);
+                }
+            }
+            StartupState::Pattern => {
+                for memory in [ram, vram, oam] {
+                    for (i, byte) in memory.iter_mut().enumerate() {
+                        *byte = if i & 0b100 == 0 { 0x00 } else { 0xFF };
+                    }
+                }
+            }
+            StartupState::Random(seed) => {
+                let mut source = EntropyDevice::new(Some(*seed), None);
+                for memory in [ram, vram, oam] {
+                    memory.iter_mut().for_each(|byte| *byte = source.read());
+                }
+            }
+        }
+    }
+}
+
+// The same words parse takes, a random state with its seed
+impl fmt::Display for StartupState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StartupState::Zeroed => write!(f, "zero"),
+            StartupState::Pattern => write!(f, "pattern"),
+            StartupState::Random(seed) => write!(f, "random:{}", seed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_states_fill_memory() {
+        let (mut ram, mut vram, mut oam) = ([0x55; 2048], [0x55; 2048], [0x55; 256]);
+        StartupState::parse("pattern").unwrap().fill(&mut ram, &mut vram, &mut oam);
+        assert_eq!(&ram[0..9], &[0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0]);
+        assert_eq!(oam[7], 0xFF);
+
+        StartupState::Zeroed.fill(&mut ram, &mut vram, &mut oam);
+        assert!(ram.iter().chain(&vram).chain(&oam).all(|&byte| byte == 0));
+
+        // A seed fills the same way every time and round trips through its name
+        let state = StartupState::parse("random:1234").unwrap();
+        assert_eq!(StartupState::parse(&state.to_string()).unwrap(), state);
+        let (mut ram2, mut vram2, mut oam2) = ([0; 2048], [0; 2048], [0; 256]);
+        state.fill(&mut ram, &mut vram, &mut oam);
+        state.fill(&mut ram2, &mut vram2, &mut oam2);
+        assert_eq!((ram, vram, oam), (ram2, vram2, oam2));
+        assert!(ram.iter().any(|&byte| byte != 0));
+
+        assert!(matches!(StartupState::parse("random").unwrap(), StartupState::Random(_)));
+        assert!(StartupState::parse("random:x").is_err());
+        assert!(StartupState::parse("ones").is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use crate::rom::Mirroring;
+
+// Save states: the whole console (CPU, RAM, PPU, APU, DMA, the scheduler's
+// events and the cartridge's registers and RAM) written to a flat byte buffer and
+// read back in the same order. Each part is a Snapshot, the top is CPU::save_state
+// and savestate.rs keeps states in files.
+//
+// What's left out is what isn't the console's: the rom itself (a state only loads
+// on the game it came from, checked by CRC), what the player is doing (buttons
+// held, keys down, the Zapper's aim and trigger, the microphone hearing
+// something), the frontend's callbacks and debug logs, and the audio resampler's
+// filters. Devices the frontend plugs in (entropy, clock, Zapper, keyboard) are
+// saved when they're there, and a state only loads with the same ones plugged in.
+//
+// Numbers are little endian, byte arrays are prefixed with their length. Reading
+// a state checks every length, so a damaged or mismatched one fails to load
+// instead of leaving the console half restored with garbage.
+pub const STATE_MAGIC: &[u8; 4] = b"NESS";
+pub const STATE_VERSION: u8 = 3;
+
+// A part of the console that goes into save states. load_state reads back exactly
+// what save_state wrote, in the same order, and fails rather than guess.
+pub trait Snapshot {
+    fn save_state(&self, out: &mut StateWriter);
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.data.extend(bytes);
+    }
+
+    pub fn mirroring(&mut self, mirroring: Mirroring) {
+        self.u8(match mirroring {
+            Mirroring::VERTICAL => 0,
+            Mirroring::HORIZONTAL => 1,
+            Mirroring::SINGLELOWER => 2,
+            Mirroring::SINGLEUPPER => 3,
+            Mirroring::FOURSCREEN => 4,
+        });
+    }
+
+    // A part that may not be plugged in
+    pub fn optional<T: Snapshot>(&mut self, part: Option<&T>) {
+        self.bool(part.is_some());
+        if let Some(part) = part {
+            part.save_state(self);
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("Save state is cut short".to_string());
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, String> {
+        Ok(self.u64()? as usize)
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    // Fills `out`, which must be the size that was saved
+    pub fn bytes_into(&mut self, out: &mut [u8]) -> Result<(), String> {
+        let bytes = self.bytes()?;
+        if bytes.len() != out.len() {
+            return Err(format!("Save state has {} bytes where {} were expected", bytes.len(), out.len()));
+        }
+        out.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn mirroring(&mut self) -> Result<Mirroring, String> {
+        match self.u8()? {
+            0 => Ok(Mirroring::VERTICAL),
+            1 => Ok(Mirroring::HORIZONTAL),
+            2 => Ok(Mirroring::SINGLELOWER),
+            3 => Ok(Mirroring::SINGLEUPPER),
+            4 => Ok(Mirroring::FOURSCREEN),
+            value => Err(format!("Save state has an unknown mirroring {}", value)),
+        }
+    }
+
+    // Reads back what StateWriter::optional wrote, into a part that must be
+    // plugged in if and only if it was when the state was saved
+    pub fn optional<T: Snapshot>(&mut self, part: Option<&mut T>, name: &str) -> Result<(), String> {
+        match (self.bool()?, part) {
+            (true, Some(part)) => part.load_state(self),
+            (false, None) => Ok(()),
+            (true, None) => Err(format!("Save state has a {} and this console doesn't", name)),
+            (false, Some(_)) => Err(format!("Save state has no {} and this console does", name)),
+        }
+    }
+
+    pub fn finish(&self) -> Result<(), String> {
+        if !self.data.is_empty() {
+            return Err(format!("Save state has {} bytes left over", self.data.len()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::entropy::EntropyDevice;
+    use crate::nes::Nes;
+    use crate::rom::Rom;
+    use crate::rtc::{RtcDevice, RtcSource};
+
+    fn run(nes: &mut Nes, steps: usize) {
+        for _ in 0..steps {
+            nes.step();
+        }
+    }
+
+    #[test]
+    fn test_loaded_state_runs_the_same() {
+        // INC $10 / LDA $10 / STA $2006 / JMP $8000, so RAM, the PPU and the clocks all move
+        let program = vec![0xE6, 0x10, 0xA5, 0x10, 0x8D, 0x06, 0x20, 0x4C, 0x00, 0x80];
+        let mut nes = Nes::new(Rom::_new_test(program).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.pc = 0x8000;
+        run(&mut nes, 5000);
+        let state = nes.cpu.save_state();
+        run(&mut nes, 50000);
+        let later = nes.cpu.save_state();
+
+        nes.cpu.load_state(&state).unwrap();
+        assert_eq!(nes.cpu.save_state(), state);
+        run(&mut nes, 50000);
+        assert_eq!(nes.cpu.save_state(), later);
+
+        // A cut short state, or one from another game, leaves the console alone
+        assert!(nes.cpu.load_state(&state[..state.len() - 1]).is_err());
+        assert_eq!(nes.cpu.save_state(), later);
+        let mut other = Nes::new(Rom::_new_test(vec![0xEA]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        assert!(other.cpu.load_state(&state).unwrap_err().contains("another game"));
+        assert!(nes.cpu.load_state(b"not a state").is_err());
+    }
+
+    #[test]
+    fn test_devices_come_back_with_the_state() {
+        // LDA $4018 / STA $10 / STA $4019 / LDA $401A / STA $11 / JMP $8000: random
+        // bytes and the clock's minutes both end up in RAM
+        let program = vec![0xAD, 0x18, 0x40, 0x85, 0x10, 0x8D, 0x19, 0x40, 0xAD, 0x1A, 0x40, 0x85, 0x11, 0x4C, 0x00, 0x80];
+        let mut nes = Nes::new(Rom::_new_test(program.clone()).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.bus.attach_entropy(EntropyDevice::new(Some(1234), None));
+        nes.cpu.bus.attach_rtc(RtcDevice::new(RtcSource::Emulated { start: 1709251170 }));
+        nes.cpu.pc = 0x8000;
+        run(&mut nes, 5000);
+        let state = nes.cpu.save_state();
+        run(&mut nes, 50000);
+        let later = nes.state_hash();
+
+        nes.cpu.load_state(&state).unwrap();
+        run(&mut nes, 50000);
+        assert_eq!(nes.state_hash(), later);
+
+        // Not without the same devices plugged in
+        let mut plain = Nes::new(Rom::_new_test(program).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        assert!(plain.cpu.load_state(&state).unwrap_err().contains("entropy device"));
+    }
+}
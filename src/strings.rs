@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+
+// Text the player sees on screen, kept apart from the code that shows it so it can
+// be translated. Code asks for a Message and fills in its {0}, {1}... slots; the
+// catalog for the configured locale ("locale = de") supplies the wording.
+//
+// English is built in. Any other locale is read from lang/LOCALE.txt, one
+// "key = text" line per message (keys are the names in Message::key). Messages a
+// translation leaves out stay in English, and slots may be reordered. The OSD font
+// only has uppercase ASCII for now, so other scripts need glyphs added in osd.rs.
+//
+// Debug readouts (the latency and bank panels, console output) stay in English.
+
+pub const DEFAULT_LOCALE: &str = "en";
+pub const LANG_DIR: &str = "lang";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Message {
+    PatchApplied, // {0} patch file name
+    PatchFailed, // {0} patch file name, {1} error
+    MacroRecording,
+    MacroRecorded, // {0} frame count
+    MacroEmpty,
+    NoMacroYet,
+    GameStuck, // {0}-{1} address range of the loop
+    InputKeyboard,
+    InputGamepad, // {0} gamepad name
+    BugReportSaved, // {0} file name
+    BugReportFailed, // {0} error
+    FamilyKeyboardOn,
+    FamilyKeyboardOff,
+    NetplayDesync, // {0} first frame that differed
+    CheckpointMarked,
+    NoCheckpoint,
+    StateLoadFailed, // {0} error
+    StateSaved, // {0} slot
+    StateSaveFailed, // {0} error
+    StateLoaded, // {0} slot
+    StateSlotSelected, // {0} slot
+    StateSlotLastUsed, // {0} slot
+    StateSlotEmpty, // {0} slot
+    CartridgeSwapped, // {0} rom path
+    CartridgeSwapFailed, // {0} error
+}
+
+const ALL_MESSAGES: [Message; 25] = [
+    Message::PatchApplied,
+    Message::PatchFailed,
+    Message::MacroRecording,
+    Message::MacroRecorded,
+    Message::MacroEmpty,
+    Message::NoMacroYet,
+    Message::GameStuck,
+    Message::InputKeyboard,
+    Message::InputGamepad,
+    Message::BugReportSaved,
+    Message::BugReportFailed,
+    Message::FamilyKeyboardOn,
+    Message::FamilyKeyboardOff,
+    Message::NetplayDesync,
+    Message::CheckpointMarked,
+    Message::NoCheckpoint,
+    Message::StateLoadFailed,
+    Message::StateSaved,
+    Message::StateSaveFailed,
+    Message::StateLoaded,
+    Message::StateSlotSelected,
+    Message::StateSlotLastUsed,
+    Message::StateSlotEmpty,
+    Message::CartridgeSwapped,
+    Message::CartridgeSwapFailed,
+];
+
+impl Message {
+    pub fn key(self) -> &'static str {
+        match self {
+            Message::PatchApplied => "patch_applied",
+            Message::PatchFailed => "patch_failed",
+            Message::MacroRecording => "macro_recording",
+            Message::MacroRecorded => "macro_recorded",
+            Message::MacroEmpty => "macro_empty",
+            Message::NoMacroYet => "no_macro_yet",
+            Message::GameStuck => "game_stuck",
+            Message::InputKeyboard => "input_keyboard",
+            Message::InputGamepad => "input_gamepad",
+            Message::BugReportSaved => "bug_report_saved",
+            Message::BugReportFailed => "bug_report_failed",
+            Message::FamilyKeyboardOn => "family_keyboard_on",
+            Message::FamilyKeyboardOff => "family_keyboard_off",
+            Message::NetplayDesync => "netplay_desync",
+            Message::CheckpointMarked => "checkpoint_marked",
+            Message::NoCheckpoint => "no_checkpoint",
+            Message::StateLoadFailed => "state_load_failed",
+            Message::StateSaved => "state_saved",
+            Message::StateSaveFailed => "state_save_failed",
+            Message::StateLoaded => "state_loaded",
+            Message::StateSlotSelected => "state_slot_selected",
+            Message::StateSlotLastUsed => "state_slot_last_used",
+            Message::StateSlotEmpty => "state_slot_empty",
+            Message::CartridgeSwapped => "cartridge_swapped",
+            Message::CartridgeSwapFailed => "cartridge_swap_failed",
+        }
+    }
+
+    fn english(self) -> &'static str {
+        match self {
+            Message::PatchApplied => "Applied patch {0}",
+            Message::PatchFailed => "Could not apply patch {0}: {1}",
+            Message::MacroRecording => "Recording macro",
+            Message::MacroRecorded => "Macro recorded, {0} frames",
+            Message::MacroEmpty => "Nothing was pressed, no macro recorded",
+            Message::NoMacroYet => "No macro recorded yet (F9)",
+            Message::GameStuck => "Game looks stuck at {0}-{1}",
+            Message::InputKeyboard => "Controller 1: keyboard",
+            Message::InputGamepad => "Controller 1: {0}",
+            Message::BugReportSaved => "Bug report saved to {0}",
+            Message::BugReportFailed => "Could not save the bug report: {0}",
+            Message::FamilyKeyboardOn => "Keys go to the Family BASIC keyboard",
+            Message::FamilyKeyboardOff => "Keys go to controller 1",
+            Message::NetplayDesync => "Netplay desync since frame {0}",
+            Message::CheckpointMarked => "Checkpoint marked, F2 to retry",
+            Message::NoCheckpoint => "No checkpoint yet (F1)",
+            Message::StateLoadFailed => "Could not load the state: {0}",
+            Message::StateSaved => "State saved to slot {0}",
+            Message::StateSaveFailed => "Could not save the state: {0}",
+            Message::StateLoaded => "State loaded from slot {0}",
+            Message::StateSlotSelected => "State slot {0}",
+            Message::StateSlotLastUsed => "State slot {0} (last used)",
+            Message::StateSlotEmpty => "State slot {0} is empty",
+            Message::CartridgeSwapped => "Swapped in {0}",
+            Message::CartridgeSwapFailed => "Could not swap cartridges: {0}",
+        }
+    }
+}
+
+pub struct Catalog {
+    translations: HashMap<Message, String>,
+}
+
+impl Catalog {
+    pub fn english() -> Self {
+        Catalog { translations: HashMap::new() }
+    }
+
+    // The catalog for `locale`, read from `dir`/LOCALE.txt unless it's English
+    pub fn load(locale: &str, dir: &Path) -> Result<Catalog, String> {
+        if locale == DEFAULT_LOCALE {
+            return Ok(Catalog::english());
+        }
+        let path = dir.join(format!("{}.txt", locale));
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("Could not read strings {}: {}", path.display(), e))?;
+        Catalog::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Catalog, String> {
+        let mut translations = HashMap::new();
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(format!("Line {} is not in \"key = text\" form", line_num + 1))?;
+            let message = ALL_MESSAGES.iter().find(|message| message.key() == key.trim())
+                .ok_or(format!("Line {} has unknown message \"{}\"", line_num + 1, key.trim()))?;
+            translations.insert(*message, value.trim().to_string());
+        }
+        Ok(Catalog { translations })
+    }
+
+    // The message with its slots filled from `args` in order
+    pub fn text(&self, message: Message, args: &[&dyn Display]) -> String {
+        let template = self.translations.get(&message).map_or(message.english(), |text| text.as_str());
+        let mut text = template.to_string();
+        for (i, arg) in args.iter().enumerate() {
+            text = text.replace(&format!("{{{}}}", i), &arg.to_string());
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_translation_falls_back_to_english() {
+        let english = Catalog::english();
+        assert_eq!(english.text(Message::MacroRecorded, &[&12]), "Macro recorded, 12 frames");
+
+        // Slots can move around, missing messages stay English
+        let catalog = Catalog::parse("# test\ngame_stuck = Von {1} bis {0} haengt es\n").unwrap();
+        assert_eq!(catalog.text(Message::GameStuck, &[&"$8000", &"$8010"]), "Von $8010 bis $8000 haengt es");
+        assert_eq!(catalog.text(Message::MacroRecording, &[]), "Recording macro");
+
+        assert!(Catalog::parse("no_such_message = x").is_err());
+        assert!(Catalog::parse("macro_empty").is_err());
+        assert!(Catalog::load(DEFAULT_LOCALE, Path::new("nowhere")).is_ok());
+        assert!(Catalog::load("xx", Path::new("nowhere")).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+// Dynamic rate control (see https://docs.libretro.com/development/cores/dynamic-rate-control/).
+// The NES runs at ~60.1 fps while most displays run at 60 Hz and sound cards never
+// consume samples at exactly the rate asked for. Over a long session that drift
+// either drains the audio buffer (crackles) or fills it until frames must be dropped.
+//
+// Instead the audio resampling ratio is nudged by at most +-max_delta depending on how
+// full the output buffer is: a nearly empty buffer produces slightly more samples per
+// emulated frame, a nearly full one slightly fewer, so the buffer hovers around half full.
+
+pub const DEFAULT_MAX_DELTA: f64 = 0.005; // +-0.5%, small enough that the pitch change isn't audible
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncSettings {
+    pub dynamic_rate: bool,
+    pub max_delta: f64,
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        SyncSettings {
+            dynamic_rate: true,
+            max_delta: DEFAULT_MAX_DELTA,
+        }
+    }
+}
+
+pub struct RateController {
+    settings: SyncSettings,
+    ratio: f64,
+}
+
+impl RateController {
+    pub fn new(settings: SyncSettings) -> Self {
+        RateController { settings, ratio: 1.0 }
+    }
+
+    // Last ratio handed out, 1.0 means no adjustment
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    // Call once per frame with the audio buffer's fill level. Returns the multiplier to apply
+    // to the number of output samples generated per emulated sample.
+    pub fn update(&mut self, queued: usize, capacity: usize) -> f64 {
+        self.ratio = if !self.settings.dynamic_rate || capacity == 0 {
+            1.0
+        } else {
+            let fill = (queued as f64 / capacity as f64).min(1.0);
+            1.0 + (1.0 - 2.0 * fill) * self.settings.max_delta
+        };
+        self.ratio
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ratio_follows_buffer_fill() {
+        let mut controller = RateController::new(SyncSettings::default());
+
+        assert_eq!(controller.update(500, 1000), 1.0);
+        assert!((controller.update(0, 1000) - (1.0 + DEFAULT_MAX_DELTA)).abs() < 1e-9);
+        assert!((controller.update(1000, 1000) - (1.0 - DEFAULT_MAX_DELTA)).abs() < 1e-9);
+
+        // Never goes past the limit even if the buffer overflows
+        assert!(controller.update(5000, 1000) >= 1.0 - DEFAULT_MAX_DELTA - 1e-9);
+    }
+
+    #[test]
+    fn test_disabled_controller_never_adjusts() {
+        let settings = SyncSettings { dynamic_rate: false, ..SyncSettings::default() };
+        let mut controller = RateController::new(settings);
+        assert_eq!(controller.update(0, 1000), 1.0);
+    }
+}
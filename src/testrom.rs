@@ -0,0 +1,150 @@
+use std::fmt;
+
+use crate::bus::{Bus, Mem};
+use crate::nes::Nes;
+
+// blargg's test rom output convention (https://github.com/christopherpow/nes-test-roms):
+//   $6000       status: $80 = still running, $81 = press reset, $00-$7F = finished with that result code
+//   $6001-$6003 signature $DE $B0 $61, written once the rest is valid
+//   $6004+      zero terminated text describing the results
+
+pub const TEST_STATUS_ADDR: u16 = 0x6000;
+const TEST_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const TEST_TEXT_ADDR: u16 = 0x6004;
+const TEST_TEXT_MAX: u16 = 0x1FFC; // text can run up to the end of PRG RAM
+const RESET_DELAY_FRAMES: u64 = 6; // roms ask for at least 100ms before the reset
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestStatus {
+    Running,
+    NeedsReset,
+    Finished(u8), // 0 means every test passed
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestOutput {
+    pub status: TestStatus,
+    pub text: String,
+}
+
+impl TestOutput {
+    pub fn passed(&self) -> bool {
+        self.status == TestStatus::Finished(0)
+    }
+}
+
+impl fmt::Display for TestOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.text.trim_end())?;
+        match self.status {
+            TestStatus::Running => write!(f, "Result: still running"),
+            TestStatus::NeedsReset => write!(f, "Result: waiting for reset"),
+            TestStatus::Finished(0) => write!(f, "Result: passed"),
+            TestStatus::Finished(code) => write!(f, "Result: failed with code {}", code),
+        }
+    }
+}
+
+// Decodes the test output in PRG RAM. None until the rom has written the signature.
+pub fn read_test_output(bus: &Bus) -> Option<TestOutput> {
+    let signature = [
+        bus.mem_peek(TEST_STATUS_ADDR + 1),
+        bus.mem_peek(TEST_STATUS_ADDR + 2),
+        bus.mem_peek(TEST_STATUS_ADDR + 3),
+    ];
+    if signature != TEST_SIGNATURE {
+        return None;
+    }
+
+    let status = match bus.mem_peek(TEST_STATUS_ADDR) {
+        0x80 => TestStatus::Running,
+        0x81 => TestStatus::NeedsReset,
+        code => TestStatus::Finished(code),
+    };
+
+    let text: Vec<u8> = (0..TEST_TEXT_MAX)
+        .map(|i| bus.mem_peek(TEST_TEXT_ADDR + i))
+        .take_while(|&byte| byte != 0)
+        .collect();
+
+    Some(TestOutput {
+        status,
+        text: String::from_utf8_lossy(&text).to_string(),
+    })
+}
+
+// Runs a console until its test rom reports a result or max_frames pass, pressing reset
+// whenever the rom asks for it. Returns the last output seen, None if the rom never used
+// the $6000 convention.
+pub fn run_test_rom(nes: &mut Nes, max_frames: u64) -> Option<TestOutput> {
+    let mut output = None;
+    let mut reset_at: Option<u64> = None;
+    let mut frame = nes.cpu.bus.ppu.timing().frame;
+
+    while frame < max_frames {
+        nes.step();
+
+        // Checking once a frame is plenty, the roms take seconds to run
+        if nes.cpu.bus.ppu.timing().frame == frame {
+            continue;
+        }
+        frame = nes.cpu.bus.ppu.timing().frame;
+
+        output = read_test_output(&nes.cpu.bus);
+        match output.as_ref().map(|output| output.status) {
+            Some(TestStatus::Finished(_)) => break,
+            Some(TestStatus::NeedsReset) => match reset_at {
+                None => reset_at = Some(frame + RESET_DELAY_FRAMES),
+                Some(at) if frame >= at => {
+                    nes.cpu.reset();
+                    reset_at = None;
+                }
+                Some(_) => {}
+            },
+            _ => {}
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::Rom;
+
+    // Writes bytes to $6000+ with LDA #imm / STA abs pairs, then spins
+    fn reporting_rom(bytes: &[(u16, u8)]) -> Rom {
+        let mut program = vec![];
+        for &(addr, value) in bytes {
+            program.extend([0xA9, value, 0x8D, addr as u8, (addr >> 8) as u8]);
+        }
+        let end = 0x8000 + program.len() as u16;
+        program.extend([0x4C, end as u8, (end >> 8) as u8]); // JMP to itself
+        Rom::_new_test(program).unwrap()
+    }
+
+    #[test]
+    fn test_decodes_finished_output() {
+        let rom = reporting_rom(&[
+            (0x6004, b'o'), (0x6005, b'k'), (0x6006, 0),
+            (0x6001, 0xDE), (0x6002, 0xB0), (0x6003, 0x61),
+            (0x6000, 0x00),
+        ]);
+        let mut nes = Nes::new(rom, |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.pc = 0x8000;
+
+        let output = run_test_rom(&mut nes, 10).unwrap();
+        assert!(output.passed());
+        assert_eq!(output.text, "ok");
+    }
+
+    #[test]
+    fn test_no_signature_means_no_output() {
+        let rom = reporting_rom(&[(0x6000, 0x00)]);
+        let mut nes = Nes::new(rom, |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.pc = 0x8000;
+
+        assert_eq!(run_test_rom(&mut nes, 3), None);
+    }
+}
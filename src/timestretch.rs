@@ -0,0 +1,131 @@
+use std::f32::consts::PI;
+
+// What happens to the sound when the game runs faster or slower than normal (fast
+// forward, slow motion). Simply playing the samples faster or slower shifts the
+// pitch with the speed, which makes slow motion a low rumble and fast forward a
+// squeak. Time stretching instead cuts the sound into short overlapping grains and
+// spaces them out (or packs them together), so it keeps its pitch and stays
+// intelligible at the cost of a slight echo.
+
+const GRAIN: usize = 1024; // ~23ms at 44.1kHz, long enough to keep bass notes intact
+const HOP: usize = GRAIN / 2; // 50% overlap, where Hann windows sum to exactly 1
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeedAudioMode {
+    Stretch,
+    Pitch,
+}
+
+impl SpeedAudioMode {
+    pub fn parse(value: &str) -> Result<SpeedAudioMode, String> {
+        match value {
+            "stretch" => Ok(SpeedAudioMode::Stretch),
+            "pitch" => Ok(SpeedAudioMode::Pitch),
+            _ => Err(format!("Speed audio must be stretch or pitch but got \"{}\"", value)),
+        }
+    }
+}
+
+// Turns the samples made at some emulation speed into samples for normal speed
+// playback. Streams: call process with each new batch of samples.
+pub struct TimeStretcher {
+    mode: SpeedAudioMode,
+    window: Vec<f32>,
+    pending: Vec<f32>, // input not used up yet
+    position: f64, // where the next grain (or resampled sample) starts in pending
+    overlap: Vec<f32>, // second half of the last grain, added to the next one
+}
+
+impl TimeStretcher {
+    pub fn new(mode: SpeedAudioMode) -> Self {
+        // Periodic Hann window
+        let window = (0..GRAIN).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / GRAIN as f32).cos()).collect();
+        TimeStretcher { mode, window, pending: vec![], position: 0.0, overlap: vec![0.0; HOP] }
+    }
+
+    // `speed` is the emulation speed the samples were made at: 2.0 for double speed
+    // fast forward (about half as many samples come out), 0.5 for slow motion
+    pub fn process(&mut self, input: &[f32], speed: f64) -> Vec<f32> {
+        self.pending.extend_from_slice(input);
+        let output = match self.mode {
+            SpeedAudioMode::Stretch => self.stretch(speed),
+            SpeedAudioMode::Pitch => self.resample(speed),
+        };
+
+        let used = (self.position as usize).min(self.pending.len());
+        self.pending.drain(..used);
+        self.position -= used as f64;
+        output
+    }
+
+    fn stretch(&mut self, speed: f64) -> Vec<f32> {
+        let mut output = vec![];
+        while self.position as usize + GRAIN <= self.pending.len() {
+            let start = self.position as usize;
+            let grain = &self.pending[start..start + GRAIN];
+            for i in 0..HOP {
+                output.push(self.overlap[i] + grain[i] * self.window[i]);
+                self.overlap[i] = grain[HOP + i] * self.window[HOP + i];
+            }
+            self.position += HOP as f64 * speed;
+        }
+        output
+    }
+
+    fn resample(&mut self, speed: f64) -> Vec<f32> {
+        let mut output = vec![];
+        while (self.position as usize) + 1 < self.pending.len() {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            output.push(self.pending[index] * (1.0 - fraction) + self.pending[index + 1] * fraction);
+            self.position += speed;
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A steady tone, `period` samples per cycle
+    fn tone(len: usize, period: f32) -> Vec<f32> {
+        (0..len).map(|i| (2.0 * PI * i as f32 / period).sin()).collect()
+    }
+
+    // Samples between rising zero crossings, averaged
+    fn period(samples: &[f32]) -> f32 {
+        let crossings: Vec<usize> = (1..samples.len()).filter(|&i| samples[i - 1] < 0.0 && samples[i] >= 0.0).collect();
+        (crossings[crossings.len() - 1] - crossings[0]) as f32 / (crossings.len() - 1) as f32
+    }
+
+    #[test]
+    fn test_stretch_keeps_pitch() {
+        let mut stretcher = TimeStretcher::new(SpeedAudioMode::Stretch);
+        // A period that fits the grain spacing, so the grains line up seamlessly
+        let slow = stretcher.process(&tone(44_100, 64.0), 0.5);
+        assert!((slow.len() as f64 / 88_200.0 - 1.0).abs() < 0.05);
+        assert!((period(&slow[GRAIN..]) - 64.0).abs() < 0.5);
+
+        let mut stretcher = TimeStretcher::new(SpeedAudioMode::Stretch);
+        let fast = stretcher.process(&tone(44_100, 64.0), 2.0);
+        assert!((fast.len() as f64 / 22_050.0 - 1.0).abs() < 0.05);
+        assert!((period(&fast[GRAIN..]) - 64.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_pitch_mode_shifts_pitch() {
+        let mut stretcher = TimeStretcher::new(SpeedAudioMode::Pitch);
+        let fast = stretcher.process(&tone(44_100, 100.0), 2.0);
+        assert!((fast.len() as f64 / 22_050.0 - 1.0).abs() < 0.01);
+        assert!((period(&fast) - 50.0).abs() < 1.0);
+
+        // Streaming in pieces gives the same samples as one batch
+        let mut pieces = TimeStretcher::new(SpeedAudioMode::Pitch);
+        let input = tone(1000, 100.0);
+        let mut streamed = pieces.process(&input[..333], 2.0);
+        streamed.extend(pieces.process(&input[333..], 2.0));
+        let mut whole = TimeStretcher::new(SpeedAudioMode::Pitch);
+        assert_eq!(streamed, whole.process(&input, 2.0));
+    }
+}
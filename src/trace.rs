@@ -80,43 +80,36 @@ pub fn trace(cpu: &CPU) -> String {
 
             let address = cpu.mem_peek_u16(begin + 1);
 
+            // JMP operands are printed as part of the full line like every other instruction
             if ops.addr == 0x4C {
-                return format!("#${:02x}", address);
+                format!("${:04x}", address)
             } else if ops.addr == 0x6C {
-                return format!("${:04x} = {:02x}", mem_addr, stored_value);
-            }
-
-            match ops.addressing_mode {
-                AddressingMode::NoneAddressing => {
-                    if ops.addr == 0x6c {
-                        //jmp indirect
-                        let jmp_addr = if address & 0x00FF == 0x00FF {
-                            let lo = cpu.mem_peek(address);
-                            let hi = cpu.mem_peek(address & 0xFF00);
-                            (hi as u16) << 8 | (lo as u16)
-                        } else {
-                            cpu.mem_peek_u16(address)
-                        };
-
-                        // let jmp_addr = cpu.mem_read_u16(address);
-                        format!("(${:04x}) = {:04x}", address, jmp_addr)
-                    } else {
-                        format!("${:04x}", address)
-                    }
+                // jmp indirect (with the 6502 page wrap bug)
+                let jmp_addr = if address & 0x00FF == 0x00FF {
+                    let lo = cpu.mem_peek(address);
+                    let hi = cpu.mem_peek(address & 0xFF00);
+                    (hi as u16) << 8 | (lo as u16)
+                } else {
+                    cpu.mem_peek_u16(address)
+                };
+                format!("(${:04x}) = {:04x}", address, jmp_addr)
+            } else {
+                match ops.addressing_mode {
+                    AddressingMode::NoneAddressing => format!("${:04x}", address),
+                    AddressingMode::Absolute => format!("${:04x} = {:02x}", mem_addr, stored_value),
+                    AddressingMode::Absolute_X => format!(
+                        "${:04x},X @ {:04x} = {:02x}",
+                        address, mem_addr, stored_value
+                    ),
+                    AddressingMode::Absolute_Y => format!(
+                        "${:04x},Y @ {:04x} = {:02x}",
+                        address, mem_addr, stored_value
+                    ),
+                    _ => panic!(
+                        "unexpected addressing mode {:?} has ops-len 3. code {:02x}",
+                        ops.addressing_mode, ops.addr
+                    ),
                 }
-                AddressingMode::Absolute => format!("${:04x} = {:02x}", mem_addr, stored_value),
-                AddressingMode::Absolute_X => format!(
-                    "${:04x},X @ {:04x} = {:02x}",
-                    address, mem_addr, stored_value
-                ),
-                AddressingMode::Absolute_Y => format!(
-                    "${:04x},Y @ {:04x} = {:02x}",
-                    address, mem_addr, stored_value
-                ),
-                _ => panic!(
-                    "unexpected addressing mode {:?} has ops-len 3. code {:02x}",
-                    ops.addressing_mode, ops.addr
-                ),
             }
         }
         _ => String::from(""),
@@ -131,9 +124,10 @@ pub fn trace(cpu: &CPU) -> String {
         .trim()
         .to_string();
 
+    let timing = cpu.bus.ppu.timing();
     format!(
         "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x} | PPU: L: {} CYC: {}",
-        asm_str, cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.status, cpu.sp, cpu.bus.ppu.scanline, cpu.bus.ppu.cycles
+        asm_str, cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.status, cpu.sp, timing.scanline, timing.dot
     )
     .to_ascii_uppercase()
 }
\ No newline at end of file
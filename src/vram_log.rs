@@ -0,0 +1,162 @@
+use std::fmt;
+
+// Optional log of every $2007 write, for tracking down garbage tiles. Each write
+// is kept with where the PPU was in the frame and what the address means
+// (which nametable tile, attribute block, pattern row or palette entry).
+//
+// Saved logs are one write per line:
+//
+//   FRAME SCANLINE DOT ADDR DATA  description
+//   12 241 8 2084 24  NT0 tile (4,4)
+//
+// Only the first five fields are read back (see VramLog::parse), the rest is for
+// people. `cargo run --example vram_replay` plays a log back one frame at a time.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VramTarget {
+    Pattern { table: u8, tile: u8, row: u8 }, // row 0-7 low plane, 8-15 high plane
+    Nametable { table: u8, x: u8, y: u8 }, // tile coordinates, 32x30
+    Attribute { table: u8, x: u8, y: u8 }, // 32x32 pixel blocks, 8x8
+    Palette(u8),
+}
+
+impl VramTarget {
+    pub fn of(addr: u16) -> VramTarget {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x1FFF => VramTarget::Pattern {
+                table: (addr / 0x1000) as u8,
+                tile: ((addr / 16) & 0xFF) as u8,
+                row: (addr % 16) as u8,
+            },
+            0x2000..=0x3EFF => {
+                let table = ((addr - 0x2000) / 0x400 % 4) as u8;
+                let offset = (addr - 0x2000) % 0x400;
+                if offset < 0x3C0 {
+                    VramTarget::Nametable { table, x: (offset % 32) as u8, y: (offset / 32) as u8 }
+                } else {
+                    let offset = offset - 0x3C0;
+                    VramTarget::Attribute { table, x: (offset % 8) as u8, y: (offset / 8) as u8 }
+                }
+            }
+            _ => VramTarget::Palette((addr % 32) as u8),
+        }
+    }
+}
+
+impl fmt::Display for VramTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VramTarget::Pattern { table, tile, row } => write!(f, "PT{} tile ${:02X} row {}", table, tile, row),
+            VramTarget::Nametable { table, x, y } => write!(f, "NT{} tile ({},{})", table, x, y),
+            VramTarget::Attribute { table, x, y } => write!(f, "AT{} block ({},{})", table, x, y),
+            VramTarget::Palette(index) => write!(f, "PAL {}", index),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VramWrite {
+    pub frame: u64,
+    pub scanline: u16,
+    pub dot: usize,
+    pub addr: u16,
+    pub data: u8,
+    pub during_rendering: bool, // the PPU was drawing, so the write also corrupts its address
+}
+
+impl VramWrite {
+    pub fn target(&self) -> VramTarget {
+        VramTarget::of(self.addr)
+    }
+}
+
+impl fmt::Display for VramWrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {} {:04X} {:02X}  {}", self.frame, self.scanline, self.dot, self.addr, self.data, self.target())?;
+        if self.during_rendering {
+            write!(f, " DURING RENDERING")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct VramLog {
+    writes: Vec<VramWrite>,
+}
+
+impl VramLog {
+    pub fn new() -> Self {
+        VramLog { writes: vec![] }
+    }
+
+    pub fn record(&mut self, write: VramWrite) {
+        self.writes.push(write);
+    }
+
+    pub fn writes(&self) -> &[VramWrite] {
+        &self.writes
+    }
+
+    pub fn to_text(&self) -> String {
+        self.writes.iter().map(|write| format!("{}\n", write)).collect()
+    }
+
+    pub fn parse(text: &str) -> Result<VramLog, String> {
+        let mut log = VramLog::new();
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let bad_line = || format!("Bad VRAM log line {}: \"{}\"", number + 1, line);
+            let fields: Vec<&str> = line.split_whitespace().take(5).collect();
+            if fields.len() < 5 {
+                return Err(bad_line());
+            }
+            log.record(VramWrite {
+                frame: fields[0].parse().map_err(|_| bad_line())?,
+                scanline: fields[1].parse().map_err(|_| bad_line())?,
+                dot: fields[2].parse().map_err(|_| bad_line())?,
+                addr: u16::from_str_radix(fields[3], 16).map_err(|_| bad_line())?,
+                data: u8::from_str_radix(fields[4], 16).map_err(|_| bad_line())?,
+                during_rendering: line.ends_with("DURING RENDERING"),
+            });
+        }
+        Ok(log)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ppu::NesPPU;
+
+    #[test]
+    fn test_targets() {
+        assert_eq!(VramTarget::of(0x2084), VramTarget::Nametable { table: 0, x: 4, y: 4 });
+        assert_eq!(VramTarget::of(0x2FC9), VramTarget::Attribute { table: 3, x: 1, y: 1 });
+        assert_eq!(VramTarget::of(0x1413), VramTarget::Pattern { table: 1, tile: 0x41, row: 3 });
+        assert_eq!(VramTarget::of(0x3F11), VramTarget::Palette(0x11));
+        assert_eq!(VramTarget::of(0x2484).to_string(), "NT1 tile (4,4)");
+    }
+
+    #[test]
+    fn test_ppu_logs_data_writes() {
+        let mut ppu = NesPPU::_new_empty_rom();
+        ppu.start_vram_log();
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x84);
+        ppu.write_to_data(0x24);
+        ppu.write_to_data(0x25);
+
+        let log = ppu.vram_log().unwrap();
+        assert_eq!(log.writes().len(), 2);
+        assert_eq!(log.writes()[1].target(), VramTarget::Nametable { table: 0, x: 5, y: 4 });
+
+        let text = log.to_text();
+        assert_eq!(text.lines().next().unwrap(), "0 0 0 2084 24  NT0 tile (4,4)");
+        assert_eq!(VramLog::parse(&text).unwrap().writes(), log.writes());
+        assert!(VramLog::parse("12 241 x 2084 24").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::{Duration, Instant};
+
+use crate::frameskip::NTSC_FRAME_TIME;
+
+// How frames are handed to the display and what keeps the game at the right speed.
+//
+//   on       -> wait for vblank on every present, the display sets the pace (the old
+//               behaviour, right on 60 Hz monitors)
+//   off      -> present straight away and pace with the software frame limiter. Lowest
+//               latency, but can tear
+//   adaptive -> like on, but a late frame is shown straight away instead of waiting a
+//               whole refresh (needs an OpenGL renderer, falls back to on otherwise)
+//   paced    -> vsync for tear free presents, and the frame limiter for the game's
+//               speed. Each finished frame goes out on the next refresh, a bit like
+//               triple buffering, so 120/144 Hz monitors neither speed the game up
+//               nor stutter it
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VsyncMode {
+    On,
+    Off,
+    Adaptive,
+    Paced,
+}
+
+impl VsyncMode {
+    pub fn parse(value: &str) -> Result<VsyncMode, String> {
+        match value {
+            "on" => Ok(VsyncMode::On),
+            "off" => Ok(VsyncMode::Off),
+            "adaptive" => Ok(VsyncMode::Adaptive),
+            "paced" => Ok(VsyncMode::Paced),
+            _ => Err(format!("Vsync must be on, off, adaptive or paced but got \"{}\"", value)),
+        }
+    }
+
+    pub fn uses_vsync(self) -> bool {
+        self != VsyncMode::Off
+    }
+
+    // Whether the frame limiter rather than the display keeps time
+    pub fn uses_limiter(self) -> bool {
+        matches!(self, VsyncMode::Off | VsyncMode::Paced)
+    }
+}
+
+// Keeps frames NTSC_FRAME_TIME apart by sleeping until a running deadline, so small
+// oversleeps don't add up the way sleeping a fixed time after each frame would
+pub struct FrameLimiter {
+    frame_time: Duration,
+    deadline: Option<Instant>,
+}
+
+impl Default for FrameLimiter {
+    fn default() -> Self {
+        FrameLimiter::new(NTSC_FRAME_TIME)
+    }
+}
+
+impl FrameLimiter {
+    pub fn new(frame_time: Duration) -> Self {
+        FrameLimiter { frame_time, deadline: None }
+    }
+
+    // Works out how long to wait at `now` and moves the deadline on a frame
+    fn next_wait(&mut self, now: Instant) -> Duration {
+        let deadline = self.deadline.unwrap_or(now);
+        // More than a frame behind (a pause, a slow stretch): start over instead of
+        // rushing through frames to catch up
+        if now > deadline + self.frame_time {
+            self.deadline = Some(now + self.frame_time);
+            return Duration::ZERO;
+        }
+        self.deadline = Some(deadline + self.frame_time);
+        deadline.saturating_duration_since(now)
+    }
+
+    // Call once per frame, returns when the next frame should start
+    pub fn wait(&mut self) {
+        let wait = self.next_wait(Instant::now());
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_modes() {
+        assert_eq!(VsyncMode::parse("paced").unwrap(), VsyncMode::Paced);
+        assert!(VsyncMode::parse("maybe").is_err());
+        assert!(VsyncMode::Paced.uses_vsync() && VsyncMode::Paced.uses_limiter());
+        assert!(!VsyncMode::Off.uses_vsync());
+        assert!(!VsyncMode::On.uses_limiter());
+    }
+
+    #[test]
+    fn test_limiter_keeps_a_steady_deadline() {
+        let frame = Duration::from_millis(16);
+        let mut limiter = FrameLimiter::new(frame);
+        let start = Instant::now();
+
+        assert_eq!(limiter.next_wait(start), Duration::ZERO);
+        // Frame finished early, wait out the rest of it
+        assert_eq!(limiter.next_wait(start + Duration::from_millis(10)), Duration::from_millis(6));
+        // Overslept by 2ms, the frame after is shortened to make up for it
+        assert_eq!(limiter.next_wait(start + Duration::from_millis(34)), Duration::ZERO);
+        assert_eq!(limiter.next_wait(start + Duration::from_millis(40)), Duration::from_millis(8));
+        // Far behind, start over
+        assert_eq!(limiter.next_wait(start + Duration::from_millis(200)), Duration::ZERO);
+        assert_eq!(limiter.next_wait(start + Duration::from_millis(201)), Duration::from_millis(15));
+    }
+}
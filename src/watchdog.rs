@@ -0,0 +1,147 @@
+use crate::cpu::CPU;
+use crate::trace::trace;
+
+// Watchdog for hung games. If the CPU spends many frames looping over a few bytes
+// of code without touching a single PPU/APU register, the game has almost certainly
+// locked up (usually waiting on hardware behaviour the emulator doesn't provide).
+// Rather than spinning silently forever, the watchdog reports it once along with a
+// short trace of the loop.
+
+pub const STUCK_FRAMES: u32 = 180; // ~3 seconds
+const LOOP_SPAN: u16 = 32; // how many bytes of code count as a "small" loop
+const TRACE_LINES: usize = 8;
+
+pub struct WatchdogWarning {
+    pub pc_min: u16,
+    pub pc_max: u16,
+    pub frames: u32,
+    pub trace: Vec<String>,
+}
+
+
+pub struct Watchdog {
+    frame: u64,
+    pc_min: u16,
+    pc_max: u16,
+    register_writes: u64,
+    stuck_frames: u32,
+    capturing: bool,
+    reported: bool,
+    trace: Vec<String>,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Watchdog::new()
+    }
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Watchdog {
+            frame: 0,
+            pc_min: u16::MAX,
+            pc_max: 0,
+            register_writes: 0,
+            stuck_frames: 0,
+            capturing: false,
+            reported: false,
+            trace: vec![],
+        }
+    }
+
+    // Call before every instruction (from a Granularity::Instruction callback).
+    // Returns a warning the first time a hang is detected.
+    pub fn check(&mut self, cpu: &CPU) -> Option<WatchdogWarning> {
+        // Once tripped, the next few instructions are the loop itself so trace those
+        if self.capturing {
+            self.trace.push(trace(cpu));
+            if self.trace.len() < TRACE_LINES {
+                return None;
+            }
+            self.capturing = false;
+            self.reported = true;
+            return Some(WatchdogWarning {
+                pc_min: self.pc_min,
+                pc_max: self.pc_max,
+                frames: self.stuck_frames,
+                trace: std::mem::take(&mut self.trace),
+            });
+        }
+
+        let frame = cpu.bus.ppu.timing().frame;
+        if frame != self.frame {
+            self.frame = frame;
+            self.end_frame(cpu.bus.register_writes());
+            if self.capturing {
+                return None;
+            }
+        }
+
+        self.pc_min = self.pc_min.min(cpu.pc);
+        self.pc_max = self.pc_max.max(cpu.pc);
+        None
+    }
+
+    fn end_frame(&mut self, register_writes: u64) {
+        let stuck = self.pc_max.saturating_sub(self.pc_min) < LOOP_SPAN && register_writes == self.register_writes;
+        self.register_writes = register_writes;
+
+        if !stuck {
+            self.stuck_frames = 0;
+            self.reported = false;
+        } else {
+            self.stuck_frames += 1;
+            if self.stuck_frames >= STUCK_FRAMES && !self.reported {
+                // Keep the loop's range for the report and start tracing
+                self.capturing = true;
+                return;
+            }
+        }
+
+        self.pc_min = u16::MAX;
+        self.pc_max = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Mem;
+    use crate::nes::Nes;
+    use crate::rom::Rom;
+
+    fn run_until_warning(program: Vec<u8>, frames: u64) -> Option<WatchdogWarning> {
+        let mut nes = Nes::new(Rom::_new_test(program).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.pc = 0x8000;
+        let mut watchdog = Watchdog::new();
+
+        while nes.cpu.bus.ppu.timing().frame < frames {
+            if let Some(warning) = watchdog.check(&nes.cpu) {
+                return Some(warning);
+            }
+            nes.step();
+        }
+        None
+    }
+
+    #[test]
+    fn test_trips_on_silent_loop() {
+        // loop: JMP loop
+        let warning = run_until_warning(vec![0x4C, 0x00, 0x80], STUCK_FRAMES as u64 + 5).unwrap();
+
+        assert_eq!((warning.pc_min, warning.pc_max), (0x8000, 0x8000));
+        assert_eq!(warning.trace.len(), TRACE_LINES);
+        assert!(warning.trace[0].starts_with("8000"), "{}", warning.trace[0]);
+    }
+
+    #[test]
+    fn test_register_writes_keep_it_quiet() {
+        // loop: STA $2005 / JMP loop
+        assert!(run_until_warning(vec![0x8D, 0x05, 0x20, 0x4C, 0x00, 0x80], STUCK_FRAMES as u64 + 5).is_none());
+
+        let mut nes = Nes::new(Rom::_new_test(vec![]).unwrap(), |_ppu, _joypad1| {}).unwrap();
+        nes.cpu.bus.mem_write(0x2001, 0);
+        assert_eq!(nes.cpu.bus.register_writes(), 1);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::frame::Frame;
+use crate::ppu::NesPPU;
+use crate::render;
+use crate::state::{Snapshot, StateReader, StateWriter};
+
+// The Zapper light gun on controller port 2, aimed with the mouse. It answers on
+// $4017:
+//
+//   bit 3  light sensed, 0 while the gun sees a bright spot (inverted)
+//   bit 4  trigger, 1 while it's pulled
+//
+// The real gun's photodiode lights up as the beam passes the spot it's aimed at
+// and stays lit for a few dozen lines. Here the picture the PPU is drawing is
+// rendered once per frame, on the first read, and the gun sees light from the
+// line it's aimed at until LIGHT_LINES further down, if the pixels around its
+// aim are bright. Duck Hunt and friends draw a black frame and then white boxes
+// over the targets, and check for light while the frame is drawn.
+pub const LIGHT_LINES: u16 = 20;
+
+const AIM_RADIUS: isize = 2; // the gun sees a small spot, not a single pixel
+const BRIGHT: u32 = 0xC0 * 3; // sum of R, G and B
+
+pub struct Zapper {
+    aim: Option<(usize, usize)>, // None when pointing off the screen
+    trigger: bool,
+    picture: Frame,
+    picture_frame: Option<u64>, // the PPU frame `picture` was rendered from
+}
+
+impl Default for Zapper {
+    fn default() -> Self {
+        Zapper::new()
+    }
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper { aim: None, trigger: false, picture: Frame::new(), picture_frame: None }
+    }
+
+    pub fn aim(&mut self, at: Option<(usize, usize)>) {
+        self.aim = at;
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    // $4017 read, rendering the frame being drawn first if it hasn't been yet
+    pub fn read(&mut self, ppu: &NesPPU) -> u8 {
+        let timing = ppu.timing();
+        if self.picture_frame != Some(timing.frame) {
+            render::render(ppu, &mut self.picture);
+            self.picture_frame = Some(timing.frame);
+        }
+        self.bits(timing.scanline)
+    }
+
+    // Like read, but looking at whatever picture the last read rendered
+    pub fn peek(&self, ppu: &NesPPU) -> u8 {
+        self.bits(ppu.timing().scanline)
+    }
+
+    fn bits(&self, scanline: u16) -> u8 {
+        let light = self.sees_light(scanline);
+        ((!light as u8) << 3) | ((self.trigger as u8) << 4)
+    }
+
+    fn sees_light(&self, scanline: u16) -> bool {
+        let Some((x, y)) = self.aim else {
+            return false;
+        };
+        if self.picture_frame.is_none() || !(y as u16..y as u16 + LIGHT_LINES).contains(&scanline) {
+            return false;
+        }
+        let (width, height) = (self.picture.width() as isize, self.picture.height() as isize);
+        (-AIM_RADIUS..=AIM_RADIUS).any(|dy| {
+            (-AIM_RADIUS..=AIM_RADIUS).any(|dx| {
+                let (px, py) = (x as isize + dx, y as isize + dy);
+                if px < 0 || py < 0 || px >= width || py >= height {
+                    return false;
+                }
+                let (r, g, b) = self.picture.pixel(px as usize, py as usize);
+                r as u32 + g as u32 + b as u32 >= BRIGHT
+            })
+        })
+    }
+}
+
+impl Snapshot for Zapper {
+    // The picture the light sensor looks at, so reads after a load see what they
+    // saw before. Where it's aimed and the trigger are up to the player.
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bool(self.picture_frame.is_some());
+        out.u64(self.picture_frame.unwrap_or(0));
+        out.bytes(&self.picture.data);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        let rendered = input.bool()?;
+        let frame = input.u64()?;
+        input.bytes_into(&mut self.picture.data)?;
+        self.picture_frame = if rendered { Some(frame) } else { None };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_light_follows_the_beam_and_the_picture() {
+        let mut ppu = NesPPU::_new_empty_rom();
+        let mut zapper = Zapper::new();
+        assert_eq!(zapper.read(&ppu), 0b0000_1000); // not aimed at the screen
+
+        // A white screen, aimed at line 0 while the beam is on it
+        ppu.palette_table[0] = 0x30;
+        zapper.aim(Some((100, 0)));
+        zapper.set_trigger(true);
+        assert_eq!(zapper.peek(&ppu), 0b0001_1000); // the last picture was black
+        ppu.tick(341);
+        ppu.end_scanline();
+        ppu.end_scanline(); // still the same frame, so no new render
+        assert_eq!(zapper.read(&ppu), 0b0001_1000);
+
+        let mut fresh = Zapper::new();
+        fresh.aim(Some((100, 0)));
+        assert_eq!(fresh.read(&ppu), 0b0000_0000);
+
+        // The beam has moved on
+        for _ in 0..LIGHT_LINES {
+            ppu.end_scanline();
+        }
+        assert_eq!(fresh.read(&ppu), 0b0000_1000);
+    }
+}